@@ -0,0 +1,69 @@
+// Construction benchmark over a realistic balance distribution.
+// Most accounts of an exchange hold small or repeated balances: here four in five
+// are below 1024, a quarter of those zero, and the rest spread up to a million.
+// Run with `cargo bench --bench construction`, and with --no-default-features to
+// compare against the build without the small value table. The trusted rebuild
+// times from_leaves_prehashed against new over the same IdAndValue leafs.
+
+use merkle_sum_tree::{Leaf, LeafHashMode, MerkleSumTree, TrustedBuildOpts};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::time::{Duration, Instant};
+
+const LEAFS: usize = 1 << 14;
+const RUNS: usize = 5;
+
+fn balances(mode: LeafHashMode) -> Vec<Leaf> {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    (0..LEAFS)
+        .map(|i| {
+            let value = match rng.gen_range(0, 20) {
+                0..=3 => 0,
+                4..=15 => rng.gen_range(1, 1024),
+                _ => rng.gen_range(1024, 1_000_000),
+            };
+            mode.leaf(format!("account-{}", i), value)
+        })
+        .collect()
+}
+
+//Median and best of RUNS builds
+fn time(label: &str, build: impl Fn() -> MerkleSumTree) {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            let tree = build();
+            let elapsed = started.elapsed();
+            assert!(tree.get_root().is_some());
+            elapsed
+        })
+        .collect();
+    times.sort();
+    println!(
+        "{} of {} leafs: median {:?}, best {:?} over {} runs",
+        label,
+        LEAFS,
+        times[RUNS / 2],
+        times[0],
+        RUNS
+    );
+}
+
+fn main() {
+    let leafs = balances(LeafHashMode::IdOnly);
+    time("construction", || {
+        MerkleSumTree::new(leafs.clone()).expect("the balances fit")
+    });
+
+    let mode = LeafHashMode::IdAndValue;
+    let hashed = balances(mode);
+    time("untrusted rebuild", || {
+        MerkleSumTree::builder()
+            .leaf_hash_mode(mode)
+            .build(hashed.clone())
+            .expect("the balances fit")
+    });
+    let opts = TrustedBuildOpts::new().leaf_hash_mode(mode);
+    time("trusted rebuild", || {
+        MerkleSumTree::from_leaves_prehashed(hashed.clone(), opts).expect("the balances fit")
+    });
+}
@@ -0,0 +1,106 @@
+// Converts the MiMC round constants to Montgomery-form limbs at build time.
+// The decimal strings in src/constants.rs are parsed here once and written to
+// OUT_DIR as an array of Fr limb literals, so DEFAULT_CONSTS is a plain static
+// with no init cost. A constant that is not a field element fails the build.
+// The values 0..SMALL_VALUES are written the same way, for the small value table.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+include!("src/constants.rs");
+
+//Must match the PrimeFieldModulus of Fr in src/mimc_sponge.rs
+const MODULUS: &str =
+    "28948022309329048855892746252171976963363056481941647379679742748393362948097";
+
+//Must match SMALL_VALUES_LEN in src/mimc_sponge.rs
+const SMALL_VALUES: u64 = 1024;
+
+//Little-endian limbs of a decimal integer, None on a bad digit or overflow
+fn parse_decimal(digits: &str) -> Option<[u64; 4]> {
+    if digits.is_empty() {
+        return None;
+    }
+    let mut limbs = [0u64; 4];
+    for c in digits.chars() {
+        let mut carry = u128::from(c.to_digit(10)?);
+        for limb in limbs.iter_mut() {
+            let current = u128::from(*limb) * 10 + carry;
+            *limb = current as u64;
+            carry = current >> 64;
+        }
+        if carry != 0 {
+            return None;
+        }
+    }
+    Some(limbs)
+}
+
+fn less_than(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    a.iter().rev().cmp(b.iter().rev()) == std::cmp::Ordering::Less
+}
+
+fn sub_assign(a: &mut [u64; 4], b: &[u64; 4]) {
+    let mut borrow = false;
+    for (x, y) in a.iter_mut().zip(b) {
+        let (d, b1) = x.overflowing_sub(*y);
+        let (d, b2) = d.overflowing_sub(u64::from(borrow));
+        *x = d;
+        borrow = b1 || b2;
+    }
+}
+
+//value * 2^256 mod modulus, by 256 modular doublings; the modulus is below 2^255
+fn to_montgomery(mut value: [u64; 4], modulus: &[u64; 4]) -> [u64; 4] {
+    for _ in 0..256 {
+        let mut carry = 0;
+        for limb in value.iter_mut() {
+            let next = *limb >> 63;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+        if !less_than(&value, modulus) {
+            sub_assign(&mut value, modulus);
+        }
+    }
+    value
+}
+
+//An array literal of Fr in Montgomery form, one per value
+fn fr_array(values: &[[u64; 4]], modulus: &[u64; 4]) -> String {
+    let mut out = String::from("[\n");
+    for value in values {
+        let limbs = to_montgomery(*value, modulus);
+        out.push_str(&format!(
+            "    Fr([{:#018x}, {:#018x}, {:#018x}, {:#018x}]),\n",
+            limbs[0], limbs[1], limbs[2], limbs[3]
+        ));
+    }
+    out.push(']');
+    out
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/constants.rs");
+    let modulus = parse_decimal(MODULUS).expect("the modulus is a 256-bit integer");
+    let constants: Vec<[u64; 4]> = C_STR
+        .iter()
+        .enumerate()
+        .map(|(i, constant)| match parse_decimal(constant) {
+            Some(value) if less_than(&value, &modulus) => value,
+            _ => panic!("MiMC constant {} is not a field element: {}", i, constant),
+        })
+        .collect();
+    let small: Vec<[u64; 4]> = (0..SMALL_VALUES).map(|value| [value, 0, 0, 0]).collect();
+    let out_dir = env::var("OUT_DIR").expect("cargo sets OUT_DIR");
+    let out_dir = Path::new(&out_dir);
+    fs::write(
+        out_dir.join("mimc_constants.rs"),
+        fr_array(&constants, &modulus),
+    )
+    .expect("OUT_DIR is writable");
+    fs::write(out_dir.join("small_values.rs"), fr_array(&small, &modulus))
+        .expect("OUT_DIR is writable");
+}
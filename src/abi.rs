@@ -0,0 +1,283 @@
+//Solidity ABI encoding of inclusion proofs, with the ethereum feature
+
+use crate::{
+    default_exponent, fr_to_value, path_index, value_to_fr, Endianness, Fr, InclusionProof, Leaf,
+    MerkleError, Neighbor, Node, Position,
+};
+
+const WORD: usize = 32;
+const HEAD_WORDS: usize = 7;
+
+impl InclusionProof {
+    pub fn to_abi_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        let root = match &self.root {
+            Some(root) => root.clone(),
+            None => self.compute_root()?,
+        };
+        let count = self.path.len();
+        if count > WORD * 8 {
+            return Err(MerkleError::InvalidLength {
+                expected: WORD * 8,
+                actual: count,
+            });
+        }
+        let mut bits = [0u8; WORD];
+        for (step, neighbor) in self.path.iter().enumerate() {
+            if neighbor.position == Position::Left {
+                if let Some(byte) = bits.get_mut(WORD - 1 - step / 8) {
+                    *byte |= 1 << (step % 8);
+                }
+            }
+        }
+        let siblings_offset = HEAD_WORDS * WORD;
+        let values_offset = siblings_offset + (count + 1) * WORD;
+        let mut bytes = Vec::with_capacity(values_offset + (count + 1) * WORD);
+        bytes.extend_from_slice(&hash_word(&self.leaf.node));
+        bytes.extend_from_slice(&value_word(self.leaf.node.value));
+        bytes.extend_from_slice(&usize_word(siblings_offset));
+        bytes.extend_from_slice(&usize_word(values_offset));
+        bytes.extend_from_slice(&bits);
+        bytes.extend_from_slice(&hash_word(&root));
+        bytes.extend_from_slice(&value_word(root.value));
+        bytes.extend_from_slice(&usize_word(count));
+        for neighbor in &self.path {
+            bytes.extend_from_slice(&hash_word(&neighbor.node));
+        }
+        bytes.extend_from_slice(&usize_word(count));
+        for neighbor in &self.path {
+            bytes.extend_from_slice(&value_word(neighbor.node.value));
+        }
+        Ok(bytes)
+    }
+
+    //Strict inverse of to_abi_bytes: canonical offsets, no trailing bytes. The leaf
+    //gets an empty id and the proof is stamped with the encoded root, without epoch
+    pub fn from_abi_bytes(bytes: &[u8]) -> Result<InclusionProof, MerkleError> {
+        let word = |index: usize| -> Result<&[u8], MerkleError> {
+            bytes
+                .get(index * WORD..(index + 1) * WORD)
+                .ok_or(MerkleError::InvalidLength {
+                    expected: (index + 1) * WORD,
+                    actual: bytes.len(),
+                })
+        };
+        let count = word_to_usize(word(HEAD_WORDS)?)?;
+        if count > WORD * 8 {
+            return Err(MerkleError::InvalidContainer(
+                "path longer than pathBits".to_string(),
+            ));
+        }
+        let expected_len = (HEAD_WORDS + 2 + 2 * count) * WORD;
+        if bytes.len() != expected_len {
+            return Err(MerkleError::InvalidLength {
+                expected: expected_len,
+                actual: bytes.len(),
+            });
+        }
+        let siblings_offset = word_to_usize(word(2)?)?;
+        let values_offset = word_to_usize(word(3)?)?;
+        let values_at = HEAD_WORDS + 1 + count;
+        if siblings_offset != HEAD_WORDS * WORD
+            || values_offset != values_at * WORD
+            || word_to_usize(word(values_at)?)? != count
+        {
+            return Err(MerkleError::InvalidContainer(
+                "non-canonical ABI offsets".to_string(),
+            ));
+        }
+        let bits = word(4)?;
+        let mut path = Vec::with_capacity(count);
+        for step in 0..count {
+            let hash = Fr::from_bytes(word(HEAD_WORDS + 1 + step)?, Endianness::Big)?;
+            let value = word_to_value(word(values_at + 1 + step)?)?;
+            let left = bits
+                .get(WORD - 1 - step / 8)
+                .is_some_and(|byte| byte & (1 << (step % 8)) != 0);
+            path.push(Neighbor {
+                position: if left {
+                    Position::Left
+                } else {
+                    Position::Right
+                },
+                node: Node::new(hash, value),
+            });
+        }
+        let used = match count {
+            0 => 0,
+            _ => (count - 1) / 8 + 1,
+        };
+        let (high, low) = bits.split_at(WORD - used);
+        //Bits of the top used byte past the last step
+        let unused = low
+            .first()
+            .map_or(0, |byte| (byte >> ((count.max(1) - 1) % 8)) >> 1);
+        if high.iter().any(|byte| *byte != 0) || (used > 0 && unused != 0) {
+            return Err(MerkleError::InvalidContainer(
+                "pathBits above the path length".to_string(),
+            ));
+        }
+        Ok(InclusionProof {
+            leaf: Leaf {
+                id: "".into(),
+                node: Node::new(
+                    Fr::from_bytes(word(0)?, Endianness::Big)?,
+                    word_to_value(word(1)?)?,
+                ),
+            },
+            index: path_index(&path),
+            path,
+            root: Some(Node::new(
+                Fr::from_bytes(word(5)?, Endianness::Big)?,
+                word_to_value(word(6)?)?,
+            )),
+            epoch: None,
+            exponent: default_exponent(),
+        })
+    }
+}
+
+fn hash_word(node: &Node) -> [u8; WORD] {
+    node.hash.to_bytes(Endianness::Big)
+}
+
+fn value_word(value: i32) -> [u8; WORD] {
+    value_to_fr(value).to_bytes(Endianness::Big)
+}
+
+fn usize_word(value: usize) -> [u8; WORD] {
+    let mut word = [0u8; WORD];
+    word.split_at_mut(WORD - 8)
+        .1
+        .copy_from_slice(&(value as u64).to_be_bytes());
+    word
+}
+
+fn word_to_usize(word: &[u8]) -> Result<usize, MerkleError> {
+    let (high, low) = word.split_at(WORD - 8);
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(low);
+    match high.iter().all(|byte| *byte == 0) {
+        true => usize::try_from(u64::from_be_bytes(bytes))
+            .map_err(|_| MerkleError::InvalidContainer("ABI length too large".to_string())),
+        false => Err(MerkleError::InvalidContainer(
+            "ABI length too large".to_string(),
+        )),
+    }
+}
+
+fn word_to_value(word: &[u8]) -> Result<i32, MerkleError> {
+    let fr = Fr::from_bytes(word, Endianness::Big)?;
+    fr_to_value(fr).ok_or_else(|| MerkleError::InvalidFieldElement(format!("{:?}", fr)))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::MerkleSumTree;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn abi_layout_is_pinned() {
+        let proof = InclusionProof {
+            leaf: Leaf {
+                id: "alice".into(),
+                node: Node::new(Fr::from(0xaau64), 10),
+            },
+            index: 2,
+            path: vec![
+                Neighbor {
+                    position: Position::Right,
+                    node: Node::new(Fr::from(0xbbu64), -1),
+                },
+                Neighbor {
+                    position: Position::Left,
+                    node: Node::new(Fr::from(0xccu64), 51),
+                },
+            ],
+            root: Some(Node::new(Fr::from(0xddu64), 60)),
+            epoch: Some(3),
+            exponent: default_exponent(),
+        };
+        let bytes = proof.to_abi_bytes().unwrap();
+        let words: Vec<String> = bytes.chunks(WORD).map(hex).collect();
+        let small = |value: &str| format!("{:0>64}", value);
+        let minus_one = "30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000000";
+        assert_eq!(
+            words,
+            vec![
+                small("aa"),
+                small("0a"),
+                small("e0"),
+                small("140"),
+                small("02"),
+                small("dd"),
+                small("3c"),
+                small("02"),
+                small("bb"),
+                small("cc"),
+                small("02"),
+                minus_one.to_string(),
+                small("33"),
+            ]
+        );
+
+        //The leaf id and epoch are not encoded, everything else comes back
+        let decoded = InclusionProof::from_abi_bytes(&bytes).unwrap();
+        assert_eq!(decoded.get_leaf().get_id(), "");
+        assert_eq!(decoded.get_path(), proof.get_path());
+        assert_eq!(decoded.get_root(), proof.get_root());
+        assert_eq!(decoded.to_abi_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn tree_proofs_round_trip() {
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), -20),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        for index in 0..3 {
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            let decoded = InclusionProof::from_abi_bytes(&proof.to_abi_bytes().unwrap()).unwrap();
+            assert_eq!(decoded.get_root(), tree.get_root());
+            assert_eq!(decoded.compute_root().unwrap(), tree.get_root().unwrap());
+        }
+    }
+
+    #[test]
+    fn malformed_encodings_are_refused() {
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+        ])
+        .unwrap();
+        let bytes = tree.get_proof(0).unwrap().unwrap().to_abi_bytes().unwrap();
+        assert!(InclusionProof::from_abi_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut longer = bytes.clone();
+        longer.extend_from_slice(&[0; WORD]);
+        assert!(InclusionProof::from_abi_bytes(&longer).is_err());
+        let mut offset = bytes.clone();
+        offset[2 * WORD + WORD - 1] = 0xc0;
+        assert!(matches!(
+            InclusionProof::from_abi_bytes(&offset),
+            Err(MerkleError::InvalidContainer(_))
+        ));
+        let mut stray_bit = bytes.clone();
+        stray_bit[4 * WORD + WORD - 1] |= 0b10;
+        assert!(matches!(
+            InclusionProof::from_abi_bytes(&stray_bit),
+            Err(MerkleError::InvalidContainer(_))
+        ));
+        let mut modulus = bytes;
+        modulus[..WORD].copy_from_slice(&[0xff; WORD]);
+        assert!(matches!(
+            InclusionProof::from_abi_bytes(&modulus),
+            Err(MerkleError::InvalidFieldElement(_))
+        ));
+    }
+}
@@ -0,0 +1,228 @@
+//Free slots and the order push hands them out in
+
+use crate::{seeded_rng, MerkleSumTree};
+use rand::Rng;
+use std::fmt;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AllocationStrategy {
+    //Lowest free slot, then lowest dummy slot
+    #[default]
+    FirstFree,
+    //Highest free slot, then highest dummy slot
+    LastFree,
+    //Uniform free slot, or dummy slot when none is free, derived from the seed
+    Random([u8; 32]),
+}
+
+//The Random seed is never printed
+impl fmt::Debug for AllocationStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocationStrategy::FirstFree => f.write_str("FirstFree"),
+            AllocationStrategy::LastFree => f.write_str("LastFree"),
+            AllocationStrategy::Random(_) => f.write_str("Random(..)"),
+        }
+    }
+}
+
+impl MerkleSumTree {
+    pub fn free_slot_count(&self) -> usize {
+        self.iter_free_slots().count()
+    }
+
+    //Free slots in ascending order, each holding the empty leaf
+    pub fn iter_free_slots(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut previous = None;
+        self.zero_index.iter().copied().filter(move |index| {
+            let fresh = previous.is_none_or(|previous| *index > previous);
+            previous = Some(*index);
+            fresh
+                && !self.retired.contains(index)
+                && matches!(self.leafs.get(*index), Some(leaf) if self.is_empty_leaf(leaf))
+        })
+    }
+
+    pub fn get_allocation_strategy(&self) -> AllocationStrategy {
+        self.allocation
+    }
+
+    pub fn set_allocation_strategy(&mut self, strategy: AllocationStrategy) {
+        self.allocation = strategy;
+    }
+
+    //Slot the next push writes, None when the tree has to grow
+    pub(crate) fn allocate(&self) -> Option<usize> {
+        match self.allocation {
+            AllocationStrategy::FirstFree => self
+                .iter_free_slots()
+                .next()
+                .or_else(|| self.dummies.first().copied()),
+            AllocationStrategy::LastFree => self
+                .iter_free_slots()
+                .last()
+                .or_else(|| self.dummies.last().copied()),
+            AllocationStrategy::Random(seed) => {
+                let free: Vec<usize> = self.iter_free_slots().collect();
+                let candidates = if free.is_empty() {
+                    self.dummies.iter().copied().collect()
+                } else {
+                    free
+                };
+                draw(seed, self.epoch, &candidates)
+            }
+        }
+    }
+}
+
+fn draw(seed: [u8; 32], epoch: u64, candidates: &[usize]) -> Option<usize> {
+    let count = candidates.len() as u64;
+    if count == 0 {
+        return None;
+    }
+    let mut rng = seeded_rng(seed);
+    rng.set_counter(0, epoch.wrapping_add(1));
+    let zone = u64::MAX - u64::MAX % count;
+    loop {
+        let value = (u64::from(rng.next_u32()) << 32) | u64::from(rng.next_u32());
+        if value < zone {
+            return candidates.get((value % count) as usize).copied();
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::Leaf;
+
+    fn tree(strategy: AllocationStrategy) -> MerkleSumTree {
+        let mut tree = MerkleSumTree::builder()
+            .allocation_strategy(strategy)
+            .build(users(6, 1, 0))
+            .unwrap();
+        tree.remove_many(&[1, 4]).unwrap();
+        tree
+    }
+
+    #[test]
+    fn strategies_pick_their_end() {
+        let mut first = tree(AllocationStrategy::FirstFree);
+        assert_eq!(
+            first.iter_free_slots().collect::<Vec<_>>(),
+            vec![1, 4, 6, 7]
+        );
+        assert_eq!(first.free_slot_count(), 4);
+        assert_eq!(first.push(Leaf::new("a".to_string(), 1)).unwrap(), 1);
+        assert_eq!(first.push(Leaf::new("b".to_string(), 1)).unwrap(), 4);
+
+        let mut last = tree(AllocationStrategy::LastFree);
+        assert_eq!(last.get_allocation_strategy(), AllocationStrategy::LastFree);
+        for expected in [7, 6, 4, 1, 8] {
+            assert_eq!(
+                last.push(Leaf::new(format!("n{}", expected), 1)).unwrap(),
+                expected
+            );
+        }
+        assert_eq!(last.free_slot_count(), 7);
+        assert_eq!(last.validate(), Ok(()));
+
+        //Not serialized, a loaded tree falls back to FirstFree
+        let json = serde_json::to_string(&last).unwrap();
+        let mut loaded: MerkleSumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            loaded.get_allocation_strategy(),
+            AllocationStrategy::FirstFree
+        );
+        loaded.set_allocation_strategy(AllocationStrategy::LastFree);
+        assert_eq!(loaded.push(Leaf::new("z".to_string(), 1)).unwrap(), 15);
+    }
+
+    #[test]
+    fn random_slots_follow_the_seed() {
+        let strategy = AllocationStrategy::Random([7; 32]);
+        let mut tree = MerkleSumTree::builder()
+            .allocation_strategy(strategy)
+            .build((0..8).map(|i| Leaf::new(format!("user{}", i), i)).collect())
+            .unwrap();
+        tree.remove_many(&[1, 2, 4, 5, 6]).unwrap();
+        let mut layout = vec![];
+        for id in ["a", "b", "c"] {
+            layout.push(tree.push(Leaf::new(id.to_string(), 1)).unwrap());
+        }
+        assert_eq!(layout, vec![1, 5, 2]);
+        assert_eq!(tree.get_index_by_id("b"), Some(5));
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(format!("{:?}", strategy), "Random(..)");
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(!json.contains("[7,7,7"));
+
+        //The grown tree takes the first new slot, dummies are drawn like free slots
+        tree.push(Leaf::new("d".to_string(), 1)).unwrap();
+        tree.push(Leaf::new("e".to_string(), 1)).unwrap();
+        assert_eq!(tree.push(Leaf::new("f".to_string(), 1)).unwrap(), 8);
+    }
+
+    #[test]
+    fn random_slots_are_spread() {
+        let mut tree = MerkleSumTree::builder()
+            .allocation_strategy(AllocationStrategy::Random([3; 32]))
+            .build(
+                (0..64)
+                    .map(|i| Leaf::new(format!("user{}", i), i))
+                    .collect(),
+            )
+            .unwrap();
+        tree.remove_many(&(1..64).collect::<Vec<_>>()).unwrap();
+        let slots: Vec<usize> = (0..32)
+            .map(|i| tree.push(Leaf::new(format!("new{}", i), 1)).unwrap())
+            .collect();
+        assert_eq!(&slots[..4], &[11, 1, 58, 63]);
+        let sequential = slots
+            .windows(2)
+            .filter(|pair| pair[1] == pair[0] + 1)
+            .count();
+        assert!(sequential < 8, "{:?}", slots);
+        assert!(slots.iter().filter(|slot| **slot >= 32).count() >= 8);
+        assert!(slots.iter().filter(|slot| **slot < 32).count() >= 8);
+        assert_eq!(tree.free_slot_count(), 31);
+    }
+
+    #[test]
+    fn free_slots_skip_retired_and_dummy_slots() {
+        let mut append_only = MerkleSumTree::builder()
+            .append_only(true)
+            .build(vec![
+                Leaf::new("alice".to_string(), 1),
+                Leaf::new("bob".to_string(), 2),
+                Leaf::new("carol".to_string(), 3),
+            ])
+            .unwrap();
+        append_only.remove(1).unwrap();
+        assert_eq!(append_only.iter_free_slots().collect::<Vec<_>>(), vec![3]);
+
+        let mut padded = MerkleSumTree::builder()
+            .dummy_padding([9; 32])
+            .allocation_strategy(AllocationStrategy::LastFree)
+            .build(vec![
+                Leaf::new("alice".to_string(), 1),
+                Leaf::new("bob".to_string(), 2),
+                Leaf::new("carol".to_string(), 3),
+            ])
+            .unwrap();
+        assert_eq!(padded.free_slot_count(), 0);
+        assert_eq!(padded.push(Leaf::new("dave".to_string(), 4)).unwrap(), 3);
+
+        let mut tree = tree(AllocationStrategy::FirstFree);
+        for index in tree.iter_free_slots().collect::<Vec<_>>() {
+            assert!(tree.get_leaf(index).unwrap().is_none());
+        }
+        //A stale entry in the zero index is never handed out
+        tree.zero_index.insert(1, 1);
+        tree.zero_index.insert(0, 0);
+        assert_eq!(tree.iter_free_slots().collect::<Vec<_>>(), vec![1, 4, 6, 7]);
+        assert!(tree.validate().is_err());
+    }
+}
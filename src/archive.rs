@@ -0,0 +1,142 @@
+//Parallel import of tree archives
+
+use crate::layout::{coordinates, level_count};
+use crate::progress::ProgressSink;
+use crate::sharded::stitched_nodes;
+use crate::{CancellationToken, MerkleError, MerkleSumTree, ProgressCallback};
+
+impl MerkleSumTree {
+    pub fn import_archive_parallel(
+        archive: &[u8],
+        threads: usize,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        Self::import_archive_parallel_with(
+            archive,
+            threads,
+            ProgressSink::default(),
+            CancellationToken::default(),
+        )
+    }
+
+    //import_archive_parallel reporting the rebuild and the comparison, the imported
+    //tree keeps reporting to progress
+    pub fn import_archive_parallel_with_progress(
+        archive: &[u8],
+        threads: usize,
+        progress: ProgressCallback,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        Self::import_archive_parallel_with(
+            archive,
+            threads,
+            ProgressSink::new(Some(progress)),
+            CancellationToken::default(),
+        )
+    }
+
+    //import_archive_parallel stopping once token is cancelled, the imported tree keeps it
+    pub fn import_archive_parallel_cancellable(
+        archive: &[u8],
+        threads: usize,
+        token: CancellationToken,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        Self::import_archive_parallel_with(archive, threads, ProgressSink::default(), token)
+    }
+
+    fn import_archive_parallel_with(
+        archive: &[u8],
+        threads: usize,
+        progress: ProgressSink,
+        cancel: CancellationToken,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        let mut tree =
+            MerkleSumTree::deserialize(&mut serde_json::Deserializer::from_slice(archive))?;
+        tree.progress = progress;
+        tree.cancel = cancel;
+        tree.ids = MerkleSumTree::index_ids(&mut tree.leafs, &tree.dummies, &tree.empty);
+        let leaf_count = tree.leafs.len();
+        if leaf_count.is_power_of_two() {
+            let (nodes, chunk_size) = stitched_nodes(
+                &tree.leafs,
+                threads,
+                tree.mimc_exponent,
+                &tree.progress,
+                &tree.cancel,
+            )?;
+            let (nodes, mismatched) = tree.compare_nodes(nodes);
+            let chunk_levels = level_count(chunk_size);
+            let diverged = mismatched
+                .iter()
+                .filter_map(|index| coordinates(leaf_count, *index))
+                .filter(|(level, _)| *level < chunk_levels)
+                .map(|(level, offset)| (offset << level) / chunk_size)
+                .min();
+            if let Some(chunk) = diverged {
+                return Err(MerkleError::ChunkDiverged {
+                    chunk,
+                    first_leaf: chunk * chunk_size,
+                    leaf_count: chunk_size,
+                });
+            }
+            tree.validate_reconciled(Ok((nodes, mismatched)))
+                .map_err(MerkleError::InvalidTree)?;
+        } else {
+            tree.validate().map_err(MerkleError::InvalidTree)?;
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::Leaf;
+
+    fn archive() -> Vec<u8> {
+        let leafs = users(27, 5, 60);
+        serde_json::to_vec(&MerkleSumTree::new(leafs).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parallel_imports_match_sequential_imports() {
+        let archive = archive();
+        let sequential: MerkleSumTree = serde_json::from_slice(&archive).unwrap();
+        for threads in [0, 1, 3, 4, 64] {
+            let parallel = MerkleSumTree::import_archive_parallel(&archive, threads).unwrap();
+            assert_eq!(
+                serde_json::to_vec(&parallel).unwrap(),
+                serde_json::to_vec(&sequential).unwrap()
+            );
+            assert_eq!(parallel.get_index_by_id("user9"), Some(9));
+        }
+    }
+
+    #[test]
+    fn divergent_chunks_are_named() {
+        let mut tree: MerkleSumTree = serde_json::from_slice(&archive()).unwrap();
+        //A balance edited after the nodes were hashed, in the second of four chunks
+        tree.leafs[10] = Leaf::new("user10".to_string(), 1000);
+        let archive = serde_json::to_vec(&tree).unwrap();
+        assert!(serde_json::from_slice::<MerkleSumTree>(&archive).is_err());
+        assert!(matches!(
+            MerkleSumTree::import_archive_parallel(&archive, 4),
+            Err(MerkleError::ChunkDiverged {
+                chunk: 1,
+                first_leaf: 8,
+                leaf_count: 8
+            })
+        ));
+
+        //A corrupted root lies above every chunk
+        let mut tree: MerkleSumTree = serde_json::from_slice(&archive()).unwrap();
+        let bottom = tree.get_nodes()[0].clone();
+        let root = tree.nodes.locate(tree.nodes.len() - 1).unwrap();
+        *tree.nodes.get_mut(root).unwrap() = bottom;
+        let archive = serde_json::to_vec(&tree).unwrap();
+        assert!(matches!(
+            MerkleSumTree::import_archive_parallel(&archive, 4),
+            Err(MerkleError::InvalidTree(_))
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+//Signed root attestations, only built with the signing feature
+
+use crate::{InclusionProof, MerkleError, MerkleSumTree, RootCommitment};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+const DOMAIN: &[u8] = b"merkle-sum-tree/root-attestation/v1";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootAttestation {
+    commitment: RootCommitment,
+    context: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl RootAttestation {
+    //Sign the current root and epoch, context names what the root is for
+    pub fn sign(
+        tree: &MerkleSumTree,
+        key: &SigningKey,
+        context: &[u8],
+    ) -> Result<RootAttestation, MerkleError> {
+        let commitment = tree.get_commitment().ok_or(MerkleError::EmptyTree)?;
+        let mut attestation = RootAttestation {
+            commitment,
+            context: context.to_vec(),
+            signature: vec![],
+        };
+        attestation.signature = key.sign(&attestation.message()).to_bytes().to_vec();
+        Ok(attestation)
+    }
+
+    pub fn verify(&self, public_key: &VerifyingKey) -> bool {
+        match Signature::from_slice(&self.signature) {
+            Ok(signature) => public_key
+                .verify_strict(&self.message(), &signature)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    //Chain proof -> root -> signature
+    pub fn verify_proof(
+        &self,
+        proof: &InclusionProof,
+        public_key: &VerifyingKey,
+    ) -> Result<bool, MerkleError> {
+        if !self.verify(public_key) {
+            return Ok(false);
+        }
+        Ok(self.commitment.matches(&proof.compute_root()?))
+    }
+
+    pub fn get_commitment(&self) -> RootCommitment {
+        self.commitment
+    }
+
+    pub fn get_context(&self) -> Vec<u8> {
+        self.context.clone()
+    }
+
+    fn message(&self) -> Vec<u8> {
+        let mut message = DOMAIN.to_vec();
+        message.extend_from_slice(&self.commitment.to_bytes());
+        message.extend_from_slice(&(self.context.len() as u64).to_le_bytes());
+        message.extend_from_slice(&self.context);
+        message
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::{Fr, Leaf};
+    use ff::Field;
+
+    fn signed() -> (MerkleSumTree, SigningKey, RootAttestation) {
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+        ])
+        .unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let attestation = RootAttestation::sign(&tree, &key, b"reserves 2024-01").unwrap();
+        (tree, key, attestation)
+    }
+
+    #[test]
+    fn attestations_verify_and_round_trip() {
+        let (tree, key, attestation) = signed();
+        let public_key = key.verifying_key();
+        assert!(attestation.verify(&public_key));
+        assert_eq!(Some(attestation.get_commitment()), tree.get_commitment());
+
+        let json = serde_json::to_string(&attestation).unwrap();
+        let restored: RootAttestation = serde_json::from_str(&json).unwrap();
+        assert!(restored.verify(&public_key));
+
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        assert!(attestation.verify_proof(&proof, &public_key).unwrap());
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(!attestation.verify(&other));
+        assert!(!attestation.verify_proof(&proof, &other).unwrap());
+    }
+
+    #[test]
+    fn altering_any_covered_field_breaks_the_signature() {
+        let (_, key, attestation) = signed();
+        let public_key = key.verifying_key();
+
+        let commitment = attestation.get_commitment();
+        for altered_commitment in [
+            RootCommitment::new(Fr::ONE, commitment.get_sum(), commitment.get_epoch()),
+            RootCommitment::new(
+                commitment.get_hash(),
+                commitment.get_sum() + 1,
+                commitment.get_epoch(),
+            ),
+            RootCommitment::new(
+                commitment.get_hash(),
+                commitment.get_sum(),
+                commitment.get_epoch() + 1,
+            ),
+        ] {
+            let mut altered = attestation.clone();
+            altered.commitment = altered_commitment;
+            assert!(!altered.verify(&public_key));
+        }
+
+        let mut altered = attestation.clone();
+        altered.context.push(b'!');
+        assert!(!altered.verify(&public_key));
+
+        let mut altered = attestation;
+        altered.signature.truncate(10);
+        assert!(!altered.verify(&public_key));
+    }
+}
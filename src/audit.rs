@@ -0,0 +1,291 @@
+//Audit reports summarizing a tree for publication
+
+use crate::{
+    value_to_fr, EmptyLeafSpec, Fr, Leaf, LeafHashMode, MerkleError, MerkleSumTree, MimcSponge,
+    RootCommitment,
+};
+use ff::Field;
+use serde::{Deserialize, Serialize};
+
+pub const HASHER: &str = "mimc-sponge-220-x5";
+pub const VALUE_ENCODING: &str = "i32-field-negation";
+pub const ARITY: usize = 2;
+
+//HASHER with the round exponent of the tree
+fn hasher_name(exponent: u64) -> String {
+    format!("mimc-sponge-220-x{}", exponent)
+}
+
+//Field order is part of the format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditReport {
+    root_hash: String,
+    root_sum: i32,
+    leaf_count: usize,
+    capacity: usize,
+    height: usize,
+    hasher: String,
+    value_encoding: String,
+    arity: usize,
+    epoch: u64,
+    options_digest: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plain_root: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    canonical: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    leaf_hash_mode: LeafHashMode,
+    #[serde(default, skip_serializing_if = "is_default")]
+    empty_leaf: EmptyLeafSpec,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+//The name a leaf hash mode is digested under
+fn leaf_hash_name(mode: LeafHashMode) -> &'static str {
+    match mode {
+        LeafHashMode::IdOnly => "id-only",
+        LeafHashMode::IdAndValue => "id-and-value",
+    }
+}
+
+impl MerkleSumTree {
+    pub fn audit_report(&self) -> Result<AuditReport, MerkleError> {
+        let root = self.get_root().ok_or(MerkleError::EmptyTree)?;
+        Ok(AuditReport {
+            root_hash: format!("{:#x}", root.get_hash()),
+            root_sum: root.get_value(),
+            leaf_count: self.non_empty_leafs(true).count(),
+            capacity: self.leafs.len(),
+            height: self.height,
+            hasher: hasher_name(self.mimc_exponent),
+            value_encoding: VALUE_ENCODING.to_string(),
+            arity: ARITY,
+            epoch: self.epoch,
+            options_digest: options_digest(&hasher_name(self.mimc_exponent), VALUE_ENCODING, ARITY),
+            plain_root: self.get_plain_root().map(|root| format!("{:#x}", root)),
+            canonical: self.canonical,
+        })
+    }
+}
+
+fn options_digest(
+    hasher: &str,
+    value_encoding: &str,
+    arity: usize,
+    leaf_hash_mode: LeafHashMode,
+    empty_leaf: &EmptyLeafSpec,
+) -> String {
+    let id_hash = |id: &str| Leaf::new(id.to_string(), 0).get_node().get_hash();
+    let mut arr = vec![
+        id_hash(hasher),
+        id_hash(value_encoding),
+        Fr::from(arity as u64),
+    ];
+    if !is_default(&leaf_hash_mode) || !is_default(empty_leaf) {
+        arr.extend([
+            id_hash(leaf_hash_name(leaf_hash_mode)),
+            empty_leaf.get_leaf().get_node().get_hash(),
+            value_to_fr(empty_leaf.get_value()),
+        ]);
+    }
+    let digest = MimcSponge::default().single_hash(&arr, Fr::ZERO);
+    format!("{:#x}", digest)
+}
+
+impl AuditReport {
+    pub fn get_root_hash(&self) -> String {
+        self.root_hash.clone()
+    }
+
+    pub fn get_root_sum(&self) -> i32 {
+        self.root_sum
+    }
+
+    //Occupied slots, dummy padding included
+    pub fn get_leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_hasher(&self) -> String {
+        self.hasher.clone()
+    }
+
+    pub fn get_value_encoding(&self) -> String {
+        self.value_encoding.clone()
+    }
+
+    pub fn get_arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn get_options_digest(&self) -> String {
+        self.options_digest.clone()
+    }
+
+    //Plain Merkle root, when the tree maintains one
+    pub fn get_plain_root(&self) -> Option<String> {
+        self.plain_root.clone()
+    }
+
+    //Whether the root is independent of the order the leafs were supplied in
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    pub fn get_leaf_hash_mode(&self) -> LeafHashMode {
+        self.leaf_hash_mode
+    }
+
+    pub fn get_empty_leaf(&self) -> EmptyLeafSpec {
+        self.empty_leaf.clone()
+    }
+
+    //Root hash, root sum and epoch of the report as a commitment
+    pub fn get_commitment(&self) -> Result<RootCommitment, MerkleError> {
+        Ok(RootCommitment::new(
+            self.root_hash.parse()?,
+            i64::from(self.root_sum),
+            self.epoch,
+        ))
+    }
+
+    //Cross-check against a commitment computed independently, and the digest against the options
+    pub fn verify_report_against(&self, commitment: &RootCommitment) -> bool {
+        self.get_commitment().ok().as_ref() == Some(commitment)
+            && self.options_digest
+                == options_digest(
+                    &self.hasher,
+                    &self.value_encoding,
+                    self.arity,
+                    self.leaf_hash_mode,
+                    &self.empty_leaf,
+                )
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+
+    fn small_tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, 20, 30])).unwrap()
+    }
+
+    #[test]
+    fn report_json_snapshot() {
+        let report = small_tree().audit_report().unwrap();
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            concat!(
+                "{\"root_hash\":\"0x330a5a305bfadcef363c836d936f883547a27a367698a6037969bb6129f0c7b1\",",
+                "\"root_sum\":60,\"leaf_count\":3,\"capacity\":4,\"height\":3,",
+                "\"hasher\":\"mimc-sponge-220-x5\",\"value_encoding\":\"i32-field-negation\",",
+                "\"arity\":2,\"epoch\":0,",
+                "\"options_digest\":\"0x1c196a6cd6eade68068513c6b07404110f248faa6281de809b745ee168483c9b\"}"
+            )
+        );
+    }
+
+    #[test]
+    fn reports_cross_check_against_a_root() {
+        let mut tree = small_tree();
+        let report = tree.audit_report().unwrap();
+        let root = tree.get_commitment().unwrap();
+        assert_eq!(report.get_commitment().unwrap(), root);
+        assert!(report.verify_report_against(&root));
+
+        let json = serde_json::to_string(&report).unwrap();
+        let published: AuditReport = serde_json::from_str(&json).unwrap();
+        assert!(published.verify_report_against(&root));
+
+        let mut relabeled = published;
+        relabeled.arity = 4;
+        assert!(!relabeled.verify_report_against(&root));
+
+        tree.push(Leaf::new("dave".to_string(), 40)).unwrap();
+        assert!(!report.verify_report_against(&tree.get_commitment().unwrap()));
+        assert_eq!(tree.audit_report().unwrap().get_epoch(), 1);
+    }
+
+    #[test]
+    fn reports_name_the_tree_exponent() {
+        let default = small_tree().audit_report().unwrap();
+        assert_eq!(default.get_hasher(), HASHER);
+
+        let tree = MerkleSumTree::builder()
+            .mimc_exponent(7)
+            .build(vec![
+                Leaf::new("alice".to_string(), 10),
+                Leaf::new("bob".to_string(), 20),
+                Leaf::new("carol".to_string(), 30),
+            ])
+            .unwrap();
+        let root = tree.get_commitment().unwrap();
+        let report = tree.audit_report().unwrap();
+        assert_eq!(report.get_hasher(), "mimc-sponge-220-x7");
+        assert_ne!(report.get_options_digest(), default.get_options_digest());
+        assert!(report.verify_report_against(&root));
+
+        let mut relabeled = report;
+        relabeled.hasher = HASHER.to_string();
+        assert!(!relabeled.verify_report_against(&root));
+    }
+
+    #[test]
+    fn reports_cover_the_leaf_hash_mode_and_empty_leaf() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ];
+        let default = small_tree().audit_report().unwrap();
+        let keyed = MerkleSumTree::builder()
+            .leaf_hash_mode(LeafHashMode::IdAndValue)
+            .build(leafs.clone())
+            .unwrap();
+        let sentinel = EmptyLeafSpec::new("<empty>".to_string(), 0);
+        let padded = MerkleSumTree::builder()
+            .empty_leaf(sentinel.clone())
+            .build(leafs)
+            .unwrap();
+
+        let keyed_report = keyed.audit_report().unwrap();
+        let padded_report = padded.audit_report().unwrap();
+        assert_eq!(keyed_report.get_leaf_hash_mode(), LeafHashMode::IdAndValue);
+        assert_eq!(padded_report.get_empty_leaf(), sentinel);
+        let digests: std::collections::HashSet<String> = [&default, &keyed_report, &padded_report]
+            .iter()
+            .map(|report| report.get_options_digest())
+            .collect();
+        assert_eq!(digests.len(), 3);
+
+        let json = serde_json::to_string(&keyed_report).unwrap();
+        assert!(json.ends_with(",\"leaf_hash_mode\":\"IdAndValue\"}"));
+        let published: AuditReport = serde_json::from_str(&json).unwrap();
+        assert!(published.verify_report_against(&keyed.get_commitment().unwrap()));
+        let mut relabeled = published;
+        relabeled.leaf_hash_mode = LeafHashMode::IdOnly;
+        assert!(!relabeled.verify_report_against(&keyed.get_commitment().unwrap()));
+
+        let mut unpadded = padded_report;
+        unpadded.empty_leaf = EmptyLeafSpec::default();
+        assert!(!unpadded.verify_report_against(&padded.get_commitment().unwrap()));
+    }
+}
@@ -0,0 +1,474 @@
+//Mutations of many slots with one recompute pass
+
+use crate::layout::{level_count, NodeIndex};
+use crate::metrics::MutationKind;
+use crate::wal::{LogKind, Logger};
+use crate::{intern, Leaf, MerkleError, MerkleSumTree, Node};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+impl MerkleSumTree {
+    //Empty every slot in indices, return how many held a leaf
+    pub fn remove_many(&mut self, indices: &[usize]) -> Result<usize, MerkleError> {
+        let slots = self.distinct_slots(indices)?;
+        let removed = slots
+            .iter()
+            .filter(
+                |index| matches!(self.leafs.get(**index), Some(leaf) if !self.is_empty_leaf(leaf)),
+            )
+            .count();
+        let empty = self.empty.get_leaf();
+        let writes = indices
+            .iter()
+            .map(|index| (*index, empty.clone()))
+            .collect();
+        self.write_leafs(writes, MutationKind::Remove)?;
+        Ok(removed)
+    }
+
+    //Move the leaf at from to the free slot to, a dummy slot counts as free
+    pub fn move_leaf(&mut self, from: usize, to: usize) -> Result<(), MerkleError> {
+        self.relocate(from, to, false)
+    }
+
+    //Exchange the leafs of two slots, either may be free
+    pub fn swap_leafs(&mut self, from: usize, to: usize) -> Result<(), MerkleError> {
+        self.relocate(from, to, true)
+    }
+
+    fn relocate(&mut self, from: usize, to: usize, swap: bool) -> Result<(), MerkleError> {
+        let leaf = self
+            .get_leaf(from)
+            .ok_or(MerkleError::IndexOutOfBounds(from))?;
+        let target = self.get_leaf(to).ok_or(MerkleError::IndexOutOfBounds(to))?;
+        if self.is_empty_leaf(&leaf) || self.is_dummy(from) {
+            return Err(MerkleError::EmptySlot(from));
+        }
+        if from == to {
+            return Ok(());
+        }
+        let occupied = !self.is_empty_leaf(&target) && !self.is_dummy(to);
+        //An append-only slot never changes hands, so only a move to an unused slot is allowed
+        if occupied && (!swap || self.append_only) {
+            return Err(MerkleError::SlotOccupied {
+                index: to,
+                id: target.get_id(),
+            });
+        }
+        if self.append_only && self.retired.contains(&to) {
+            return Err(MerkleError::SlotRetired(to));
+        }
+        let vacated = if occupied {
+            target
+        } else {
+            self.empty.get_leaf()
+        };
+        self.write_leafs(vec![(to, leaf), (from, vacated)], MutationKind::SetLeaf)
+    }
+
+    //Pack the occupied slots to the front in index order, return (old, new) per moved leaf
+    pub fn defragment(&mut self) -> Result<Vec<(usize, usize)>, MerkleError> {
+        //Retired slots stay empty, any other free or dummy slot can take a leaf
+        let targets = (0..self.leafs.len()).filter(|index| !self.retired.contains(index));
+        let moves: Vec<(usize, usize)> = self
+            .non_empty_leafs(false)
+            .map(|(index, _)| index)
+            .zip(targets)
+            .filter(|(old, new)| old != new)
+            .collect();
+        let mut writes = vec![];
+        for (old, new) in &moves {
+            let leaf = self
+                .get_leaf(*old)
+                .ok_or(MerkleError::IndexOutOfBounds(*old))?;
+            let current = self
+                .get_leaf(*new)
+                .ok_or(MerkleError::IndexOutOfBounds(*new))?;
+            if self.append_only && !self.is_dummy(*new) {
+                self.check_slot_reuse(&current, &leaf, *new)?;
+            }
+            writes.push((*new, leaf));
+        }
+        let filled: BTreeSet<usize> = moves.iter().map(|(_, new)| *new).collect();
+        for (old, _) in &moves {
+            if !filled.contains(old) {
+                writes.push((*old, self.empty.get_leaf()));
+            }
+        }
+        self.write_leafs(writes, MutationKind::SetLeaf)?;
+        self.trim_buffers();
+        Ok(moves)
+    }
+
+    //Write distinct (index, leaf) pairs as if one by one in order, in a single pass
+    pub(crate) fn write_leafs(
+        &mut self,
+        writes: Vec<(usize, Leaf)>,
+        kind: MutationKind,
+    ) -> Result<(), MerkleError> {
+        let started = self.metrics.start();
+        if writes.is_empty() {
+            return Ok(());
+        }
+        if let Some((index, _)) = writes.iter().find(|(index, _)| *index >= self.leafs.len()) {
+            return Err(MerkleError::IndexOutOfBounds(*index));
+        }
+        let writes: Vec<(usize, Leaf)> = writes
+            .into_iter()
+            .map(|(index, leaf)| (index, self.leaf_hash_mode.normalize(leaf)))
+            .collect();
+        let indices: Vec<usize> = writes.iter().map(|(index, _)| *index).collect();
+        if !matches!(self.log, Logger::Off) {
+            let mut staged = self.staged();
+            for (index, leaf) in writes {
+                self.cancel.check()?;
+                let kind = if self.is_empty_leaf(&leaf) {
+                    LogKind::Remove
+                } else {
+                    LogKind::SetLeaf
+                };
+                staged.write_leaf(leaf, index, kind)?;
+            }
+            return self.commit_staged(staged, &indices);
+        }
+
+        let nodes: BTreeMap<usize, Node> = writes
+            .iter()
+            .map(|(index, leaf)| (*index, leaf.get_node()))
+            .collect();
+        let updates = self.ancestor_updates(&nodes)?;
+        let mut changes = vec![];
+        for (index, mut leaf) in writes {
+            let was_dummy = self.dummies.remove(&index);
+            let emptied = self.is_empty_leaf(&leaf);
+            leaf.id = intern(&self.ids, &leaf.id);
+            let id = Arc::clone(&leaf.id);
+            let current = match self.leafs.get_mut(index) {
+                Some(slot) => std::mem::replace(slot, leaf),
+                None => continue,
+            };
+            let was_empty = self.is_empty_leaf(&current);
+            if emptied && !was_empty {
+                if let Err(pos) = self.zero_index.binary_search(&index) {
+                    self.zero_index.insert(pos, index);
+                }
+                if self.append_only && !was_dummy {
+                    self.retired.insert(index);
+                }
+            } else if !emptied && was_empty {
+                if let Ok(pos) = self.zero_index.binary_search(&index) {
+                    self.zero_index.remove(pos);
+                }
+            }
+            if !was_empty {
+                if let Some(ids) = self.ids.get_mut(&current.id) {
+                    ids.remove(&index);
+                    if ids.is_empty() {
+                        self.ids.remove(&current.id);
+                    }
+                }
+            }
+            if !emptied {
+                self.next_slot = self.next_slot.max(index + 1);
+                self.ids.entry(id).or_default().insert(index);
+            }
+            changes.push((index, current));
+        }
+        self.metrics.hashes(updates.len());
+        self.metrics
+            .mutation(kind, nodes.len() + updates.len(), started);
+        let leaf_nodes = nodes
+            .into_iter()
+            .map(|(index, node)| (NodeIndex::new(0, index), node));
+        self.apply_nodes(leaf_nodes.chain(updates));
+        self.refresh_plain(indices.iter().copied());
+        self.refresh_keccak(indices.iter().copied());
+        self.canonical = self.canonical
+            && indices
+                .iter()
+                .all(|index| self.canonical_after_write(*index));
+        self.epoch += indices.len() as u64;
+        self.notify(changes);
+        Ok(())
+    }
+
+    //indices as a set, refused whole when one is out of range or repeated
+    pub(crate) fn distinct_slots(&self, indices: &[usize]) -> Result<BTreeSet<usize>, MerkleError> {
+        let mut slots = BTreeSet::new();
+        let (mut out_of_range, mut duplicates) = (vec![], vec![]);
+        for &index in indices {
+            if index >= self.leafs.len() {
+                out_of_range.push(index);
+            } else if !slots.insert(index) {
+                duplicates.push(index);
+            }
+        }
+        if !out_of_range.is_empty() || !duplicates.is_empty() {
+            return Err(MerkleError::InvalidIndices {
+                out_of_range,
+                duplicates,
+            });
+        }
+        Ok(slots)
+    }
+
+    //Every ancestor of the written leafs, rehashed once, without touching the tree
+    pub(crate) fn ancestor_updates(
+        &self,
+        writes: &BTreeMap<usize, Node>,
+    ) -> Result<Vec<(NodeIndex, Node)>, MerkleError> {
+        let leaf_count = self.leafs.len();
+        let params = self.get_hash_params();
+        let mut overlay: HashMap<NodeIndex, Node> = writes
+            .iter()
+            .map(|(index, node)| (NodeIndex::new(0, *index), node.clone()))
+            .collect();
+        let mut offsets: BTreeSet<usize> = writes.keys().copied().collect();
+        let mut updates = vec![];
+        for level in 1..level_count(leaf_count) {
+            offsets = offsets.iter().map(|offset| offset / 2).collect();
+            for &offset in &offsets {
+                self.cancel.check_at(updates.len())?;
+                let left = self.overlaid(&overlay, NodeIndex::new(level - 1, 2 * offset))?;
+                let right = self.overlaid(&overlay, NodeIndex::new(level - 1, 2 * offset + 1))?;
+                let parent = Self::build_parent_with(&params, left, right)?;
+                let at = NodeIndex::new(level, offset);
+                overlay.insert(at, parent.clone());
+                updates.push((at, parent));
+            }
+        }
+        Ok(updates)
+    }
+
+    fn overlaid(
+        &self,
+        overlay: &HashMap<NodeIndex, Node>,
+        at: NodeIndex,
+    ) -> Result<Node, MerkleError> {
+        match overlay.get(&at) {
+            Some(node) => Ok(node.clone()),
+            None => self.node_at(at),
+        }
+    }
+
+    pub(crate) fn apply_nodes<I: IntoIterator<Item = (NodeIndex, Node)>>(&mut self, nodes: I) {
+        for (at, node) in nodes {
+            if let Some(slot) = self.nodes.get_mut(at) {
+                *slot = node;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::MutationLog;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    fn tree(count: i32) -> MerkleSumTree {
+        MerkleSumTree::new(users(count, 3, 20)).unwrap()
+    }
+
+    #[test]
+    fn remove_many_matches_sequential_removal() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for round in 0..40 {
+            let count = rng.gen_range(1, 70);
+            let mut batched = tree(count);
+            //Empty a few slots first so some removals hit empty leafs
+            batched.remove(0).unwrap();
+            let mut sequential = batched.clone();
+            let slots = batched.get_leafs().len();
+            let mut indices: Vec<usize> = (0..slots).filter(|_| rng.gen_weighted_bool(3)).collect();
+            rng.shuffle(&mut indices);
+            let occupied = indices
+                .iter()
+                .filter(|index| !batched.get_leafs()[**index].is_none())
+                .count();
+            assert_eq!(
+                batched.remove_many(&indices).unwrap(),
+                occupied,
+                "round {}",
+                round
+            );
+            for index in &indices {
+                sequential.remove(*index).unwrap();
+            }
+            assert_eq!(batched.get_commitment(), sequential.get_commitment());
+            assert_eq!(batched.get_nodes(), sequential.get_nodes());
+            assert_eq!(batched.validate(), Ok(()));
+            for index in &indices {
+                assert!(batched.get_proof(*index).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_batches_change_nothing() {
+        let mut tree = tree(6);
+        let before = tree.get_commitment();
+        match tree.remove_many(&[1, 9, 2, 1, 2, 8]) {
+            Err(MerkleError::InvalidIndices {
+                out_of_range,
+                duplicates,
+            }) => {
+                assert_eq!(out_of_range, vec![9, 8]);
+                assert_eq!(duplicates, vec![1, 2]);
+            }
+            other => panic!("expected invalid indices, got {:?}", other),
+        }
+        assert_eq!(tree.get_commitment(), before);
+        assert_eq!(tree.remove_many(&[]).unwrap(), 0);
+        assert_eq!(tree.get_commitment(), before);
+        assert_eq!(tree.get_index_by_id("user1"), Some(1));
+
+        //Append-only trees retire the freed slots
+        let mut append_only = MerkleSumTree::builder()
+            .append_only(true)
+            .build(vec![
+                Leaf::new("alice".to_string(), 1),
+                Leaf::new("bob".to_string(), 2),
+            ])
+            .unwrap();
+        assert_eq!(append_only.remove_many(&[0, 1]).unwrap(), 2);
+        assert_eq!(append_only.get_retired(), vec![0, 1]);
+        assert_eq!(append_only.get_index_by_id("bob"), None);
+    }
+
+    #[test]
+    fn moves_match_a_set_and_a_remove() {
+        //1 to 0 shares every ancestor but the leaf level, 1 to 14 only the root
+        for (from, to) in [(1, 0), (1, 14), (12, 2)] {
+            let mut moved = tree(13);
+            moved.remove(to).unwrap();
+            let mut sequential = moved.clone();
+            let leaf = moved.get_leaf(from).unwrap();
+            moved.move_leaf(from, to).unwrap();
+            sequential.set_leaf(leaf.clone(), to).unwrap();
+            sequential.remove(from).unwrap();
+            assert_eq!(moved.get_commitment(), sequential.get_commitment());
+            assert_eq!(moved.validate(), Ok(()));
+            assert_eq!(moved.get_index_by_id(&leaf.get_id()), Some(to));
+            assert!(moved.iter_free_slots().any(|index| index == from));
+            assert!(!moved.iter_free_slots().any(|index| index == to));
+            assert_eq!(moved.get_proof(to).unwrap().unwrap().get_leaf(), leaf);
+        }
+    }
+
+    #[test]
+    fn swaps_exchange_both_slots() {
+        let mut tree = tree(8);
+        let before = tree.get_root_sum();
+        tree.swap_leafs(1, 6).unwrap();
+        assert_eq!(tree.get_index_by_id("user1"), Some(6));
+        assert_eq!(tree.get_index_by_id("user6"), Some(1));
+        assert_eq!(tree.get_root_sum(), before);
+        assert_eq!(tree.validate(), Ok(()));
+        tree.swap_leafs(6, 1).unwrap();
+        assert_eq!(tree.get_root(), self::tree(8).get_root());
+        assert_eq!(tree.get_epoch(), 4);
+    }
+
+    #[test]
+    fn refused_moves_change_nothing() {
+        let mut tree = tree(5);
+        let before = tree.get_commitment();
+        assert!(matches!(
+            tree.move_leaf(1, 2),
+            Err(MerkleError::SlotOccupied { index: 2, .. })
+        ));
+        assert!(matches!(
+            tree.move_leaf(6, 1),
+            Err(MerkleError::EmptySlot(6))
+        ));
+        assert!(matches!(
+            tree.move_leaf(1, 8),
+            Err(MerkleError::IndexOutOfBounds(8))
+        ));
+        assert_eq!(tree.get_commitment(), before);
+
+        let mut append_only = MerkleSumTree::builder()
+            .append_only(true)
+            .build(vec![
+                Leaf::new("alice".to_string(), 1),
+                Leaf::new("bob".to_string(), 2),
+                Leaf::new("carol".to_string(), 3),
+            ])
+            .unwrap();
+        assert!(matches!(
+            append_only.swap_leafs(0, 1),
+            Err(MerkleError::SlotOccupied { index: 1, .. })
+        ));
+        append_only.move_leaf(0, 3).unwrap();
+        assert_eq!(append_only.get_retired(), vec![0]);
+        assert!(matches!(
+            append_only.move_leaf(1, 0),
+            Err(MerkleError::SlotRetired(0))
+        ));
+        assert_eq!(append_only.validate(), Ok(()));
+    }
+
+    #[test]
+    fn defragment_packs_like_a_fresh_build() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for _ in 0..20 {
+            let mut tree = tree(rng.gen_range(1, 40));
+            let slots = tree.get_leafs().len();
+            let removed: Vec<usize> = (0..slots).filter(|_| rng.gen_weighted_bool(2)).collect();
+            tree.remove_many(&removed).unwrap();
+            let before = tree.clone();
+            let moves = tree.defragment().unwrap();
+
+            let mut packed: Vec<Leaf> = before
+                .non_empty_leafs(false)
+                .map(|(_, leaf)| leaf.clone())
+                .collect();
+            let occupied = packed.len();
+            packed.resize(slots, before.get_empty_leaf().get_leaf());
+            let fresh = MerkleSumTree::new(packed).unwrap();
+            assert_eq!(tree.get_root(), fresh.get_root());
+            assert_eq!(tree.get_leafs(), fresh.get_leafs());
+            assert_eq!(
+                tree.iter_free_slots().collect::<Vec<_>>(),
+                (occupied..slots).collect::<Vec<_>>()
+            );
+            assert_eq!(tree.validate(), Ok(()));
+            for (old, new) in moves {
+                assert!(new < old);
+                assert_eq!(tree.get_leaf(new), before.get_leaf(old));
+            }
+            assert!(tree.defragment().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn defragment_keeps_retired_slots_empty() {
+        let mut tree = MerkleSumTree::builder()
+            .append_only(true)
+            .build(vec![
+                Leaf::new("alice".to_string(), 1),
+                Leaf::new("bob".to_string(), 2),
+                Leaf::new("carol".to_string(), 3),
+            ])
+            .unwrap();
+        tree.remove(1).unwrap();
+        let before = tree.get_commitment();
+        assert!(tree.defragment().unwrap().is_empty());
+        assert_eq!(tree.get_commitment(), before);
+    }
+
+    #[test]
+    fn logged_trees_take_the_staged_path() {
+        let mut logged = tree(5);
+        let mut plain = logged.clone();
+        logged.set_mutation_log(MutationLog::new(vec![]));
+        assert_eq!(logged.remove_many(&[3, 0, 4]).unwrap(), 3);
+        assert_eq!(plain.remove_many(&[3, 0, 4]).unwrap(), 3);
+        assert_eq!(logged.get_commitment(), plain.get_commitment());
+        assert_eq!(logged.get_index_by_id("user3"), None);
+        assert!(logged.take_mutation_log().is_some());
+    }
+}
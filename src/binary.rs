@@ -0,0 +1,462 @@
+//Compact binary form of inclusion proofs, for on-chain payloads and QR codes
+
+use crate::{
+    default_exponent, is_default_exponent, path_index, Endianness, Fr, InclusionProof, Leaf,
+    MerkleError, Neighbor, Node, Position,
+};
+
+pub const PROOF_VERSION: u8 = 1;
+//Longest path any proof format takes
+pub const MAX_PROOF_LEVELS: usize = 64;
+
+const HASH_BYTES: usize = 32;
+const VALUE_BYTES: usize = 4;
+
+//Bytes of a proof with an id of id_len bytes and a path of path_len neighbors
+pub(crate) fn binary_size(id_len: usize, path_len: usize) -> usize {
+    1 + 2
+        + id_len
+        + HASH_BYTES
+        + VALUE_BYTES
+        + 1
+        + path_len.div_ceil(8)
+        + path_len * (HASH_BYTES + VALUE_BYTES)
+}
+
+//PathTooLong for a parsed path above MAX_PROOF_LEVELS
+pub(crate) fn check_levels(levels: usize) -> Result<(), MerkleError> {
+    match levels > MAX_PROOF_LEVELS {
+        true => Err(MerkleError::PathTooLong {
+            levels,
+            max: MAX_PROOF_LEVELS,
+        }),
+        false => Ok(()),
+    }
+}
+
+fn invalid(reason: &str) -> MerkleError {
+    MerkleError::InvalidProof(reason.to_string())
+}
+
+//Cursor over the input, running past the end is an InvalidLength error
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], MerkleError> {
+        if self.bytes.len() < count {
+            return Err(MerkleError::InvalidLength {
+                expected: count,
+                actual: self.bytes.len(),
+            });
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn node(&mut self, field: &str) -> Result<Node, MerkleError> {
+        let hash = Fr::from_bytes(self.take(HASH_BYTES)?, Endianness::Little).map_err(|_| {
+            MerkleError::InvalidFieldElement(format!("{} hash is not below the modulus", field))
+        })?;
+        let mut value = [0u8; VALUE_BYTES];
+        value.copy_from_slice(self.take(VALUE_BYTES)?);
+        Ok(Node::new(hash, i32::from_le_bytes(value)))
+    }
+}
+
+impl InclusionProof {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        if !is_default_exponent(&self.exponent) {
+            return Err(MerkleError::InvalidProof(format!(
+                "exponent {} has no binary form",
+                self.exponent
+            )));
+        }
+        //The index travels as the bitmap, it must be the one the path walks
+        self.check_index()?;
+        let id = self.leaf.get_id_str().as_bytes();
+        let id_len = u16::try_from(id.len()).map_err(|_| MerkleError::InvalidLength {
+            expected: usize::from(u16::MAX),
+            actual: id.len(),
+        })?;
+        if self.path.len() > MAX_PROOF_LEVELS {
+            return Err(MerkleError::InvalidLength {
+                expected: MAX_PROOF_LEVELS,
+                actual: self.path.len(),
+            });
+        }
+        let mut bytes = Vec::with_capacity(binary_size(id.len(), self.path.len()));
+        bytes.push(PROOF_VERSION);
+        bytes.extend_from_slice(&id_len.to_le_bytes());
+        bytes.extend_from_slice(id);
+        push_node(&mut bytes, &self.leaf.node);
+        bytes.push(self.path.len() as u8);
+        let mut bitmap = vec![0u8; self.path.len().div_ceil(8)];
+        for (step, neighbor) in self.path.iter().enumerate() {
+            if neighbor.position == Position::Left {
+                if let Some(byte) = bitmap.get_mut(step / 8) {
+                    *byte |= 1 << (step % 8);
+                }
+            }
+        }
+        bytes.extend_from_slice(&bitmap);
+        for neighbor in &self.path {
+            push_node(&mut bytes, &neighbor.node);
+        }
+        Ok(bytes)
+    }
+
+    //Inverse of to_bytes, refusing anything but exactly one well-formed proof
+    pub fn from_bytes(bytes: &[u8]) -> Result<InclusionProof, MerkleError> {
+        let mut reader = Reader { bytes };
+        match reader.take(1)? {
+            [PROOF_VERSION] => {}
+            other => {
+                return Err(MerkleError::InvalidProof(format!(
+                    "unknown version {:?}",
+                    other
+                )))
+            }
+        }
+        let mut id_len = [0u8; 2];
+        id_len.copy_from_slice(reader.take(2)?);
+        let id = reader.take(usize::from(u16::from_le_bytes(id_len)))?;
+        let id = std::str::from_utf8(id).map_err(|_| invalid("id is not UTF-8"))?;
+        let node = reader.node("leaf")?;
+        let path_len = usize::from(*reader.take(1)?.first().unwrap_or(&0));
+        check_levels(path_len)?;
+        let bitmap = reader.take(path_len.div_ceil(8))?;
+        let bit = |step: usize| {
+            bitmap
+                .get(step / 8)
+                .is_some_and(|byte| (byte >> (step % 8)) & 1 == 1)
+        };
+        if (path_len..bitmap.len() * 8).any(bit) {
+            return Err(invalid("position bits above the path length"));
+        }
+        let mut path = Vec::with_capacity(path_len);
+        for step in 0..path_len {
+            let position = match bit(step) {
+                true => Position::Left,
+                false => Position::Right,
+            };
+            path.push(Neighbor::new(
+                position,
+                reader.node(&format!("neighbor {}", step))?,
+            ));
+        }
+        if !reader.bytes.is_empty() {
+            return Err(MerkleError::InvalidLength {
+                expected: bytes.len() - reader.bytes.len(),
+                actual: bytes.len(),
+            });
+        }
+        Ok(InclusionProof {
+            leaf: Leaf {
+                id: id.into(),
+                node,
+            },
+            index: path_index(&path),
+            path,
+            root: None,
+            epoch: None,
+            exponent: default_exponent(),
+        })
+    }
+}
+
+impl InclusionProof {
+    //Deterministic bytes of every field, equal exactly when the proofs are equal.
+    //Lengths, the index, the epoch and the exponent are u64 little-endian; each
+    //neighbor is a position byte (1 on the left), its hash and value; an absent
+    //stamped root or epoch is a 0 byte, a present one 1 and the field
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let id = self.leaf.get_id_str().as_bytes();
+        let mut bytes = Vec::with_capacity(
+            8 * 5
+                + id.len()
+                + (HASH_BYTES + VALUE_BYTES) * (self.path.len() + 2)
+                + self.path.len()
+                + 2,
+        );
+        bytes.extend_from_slice(&(id.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(id);
+        push_node(&mut bytes, &self.leaf.node);
+        bytes.extend_from_slice(&(self.index as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.path.len() as u64).to_le_bytes());
+        for neighbor in &self.path {
+            bytes.push(u8::from(neighbor.position == Position::Left));
+            push_node(&mut bytes, &neighbor.node);
+        }
+        match &self.root {
+            Some(root) => {
+                bytes.push(1);
+                push_node(&mut bytes, root);
+            }
+            None => bytes.push(0),
+        }
+        match self.epoch {
+            Some(epoch) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&epoch.to_le_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(&self.exponent.to_le_bytes());
+        bytes
+    }
+}
+
+fn push_node(bytes: &mut Vec<u8>, node: &Node) {
+    bytes.extend_from_slice(&node.hash.to_bytes(Endianness::Little));
+    bytes.extend_from_slice(&node.value.to_le_bytes());
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::{MerkleSumTree, MAX_HEIGHT};
+
+    fn proofs(count: i32) -> Vec<InclusionProof> {
+        let leafs = (0..count)
+            .map(|i| Leaf::new(format!("user-ü{}", i), i * 7 - 50))
+            .collect();
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        (0..count as usize)
+            .map(|index| tree.get_proof(index).unwrap().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn proofs_round_trip_through_bytes() {
+        for count in [1, 2, 3, 9, 40] {
+            for proof in proofs(count) {
+                let bytes = proof.to_bytes().unwrap();
+                assert_eq!(
+                    bytes.len(),
+                    binary_size(proof.get_leaf().get_id().len(), proof.get_path().len())
+                );
+                assert_eq!(InclusionProof::from_bytes(&bytes).unwrap(), proof);
+            }
+        }
+        //Height 1, the leaf is the root
+        let single = &proofs(1)[0];
+        assert!(single.get_path().is_empty());
+        assert_eq!(single.to_bytes().unwrap()[0], PROOF_VERSION);
+
+        //Deepest path the height cap allows, every neighbor on the left
+        let neighbor = Neighbor::new(Position::Left, Node::new(-Fr::ONE, i32::MIN));
+        let deepest = InclusionProof {
+            leaf: Leaf::new("max".to_string(), i32::MAX),
+            index: usize::MAX >> 1,
+            path: vec![neighbor.clone(); MAX_HEIGHT - 1],
+            root: None,
+            epoch: None,
+            exponent: default_exponent(),
+        };
+        let bytes = deepest.to_bytes().unwrap();
+        assert_eq!(InclusionProof::from_bytes(&bytes).unwrap(), deepest);
+
+        //The format takes MAX_PROOF_LEVELS levels on every platform, index 0 fits any
+        let right = Neighbor::new(Position::Right, neighbor.node);
+        let mut widest = deepest;
+        widest.index = 0;
+        widest.path = vec![right.clone(); MAX_PROOF_LEVELS];
+        let bytes = widest.to_bytes().unwrap();
+        assert_eq!(InclusionProof::from_bytes(&bytes).unwrap(), widest);
+
+        let mut too_deep = widest;
+        too_deep.path.push(right);
+        assert!(matches!(
+            too_deep.to_bytes(),
+            Err(MerkleError::InvalidLength { .. })
+        ));
+    }
+
+    #[test]
+    fn malformed_bytes_are_told_apart() {
+        let proof = proofs(5).remove(3);
+        let bytes = proof.to_bytes().unwrap();
+        let id_len = proof.get_leaf().get_id().len();
+        let leaf_hash = 3 + id_len;
+        let bitmap = leaf_hash + HASH_BYTES + VALUE_BYTES + 1;
+
+        let mut version = bytes.clone();
+        version[0] = 2;
+        let mut unknown_bit = bytes.clone();
+        unknown_bit[bitmap] |= 0x80;
+        let mut not_utf8 = bytes.clone();
+        not_utf8[3] = 0xff;
+        for bad in [version, unknown_bit, not_utf8] {
+            assert!(matches!(
+                InclusionProof::from_bytes(&bad),
+                Err(MerkleError::InvalidProof(_))
+            ));
+        }
+
+        let mut modulus = bytes.clone();
+        modulus[leaf_hash..leaf_hash + HASH_BYTES].copy_from_slice(&[0xff; HASH_BYTES]);
+        let mut neighbor = bytes.clone();
+        neighbor[bitmap + 1..bitmap + 1 + HASH_BYTES].copy_from_slice(&[0xff; HASH_BYTES]);
+        for bad in [modulus, neighbor] {
+            assert!(matches!(
+                InclusionProof::from_bytes(&bad),
+                Err(MerkleError::InvalidFieldElement(_))
+            ));
+        }
+
+        let mut long_id = bytes.clone();
+        long_id[1] = 200;
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        for bad in [vec![], bytes[..bytes.len() - 1].to_vec(), long_id] {
+            assert!(matches!(
+                InclusionProof::from_bytes(&bad),
+                Err(MerkleError::InvalidLength { .. })
+            ));
+        }
+        assert!(matches!(
+            InclusionProof::from_bytes(&trailing),
+            Err(MerkleError::InvalidLength { expected, actual })
+                if expected == bytes.len() && actual == bytes.len() + 1
+        ));
+
+        let mut deep = bytes.clone();
+        deep[bitmap - 1] = MAX_PROOF_LEVELS as u8 + 1;
+        assert!(matches!(
+            InclusionProof::from_bytes(&deep),
+            Err(MerkleError::PathTooLong { levels, max })
+                if levels == MAX_PROOF_LEVELS + 1 && max == MAX_PROOF_LEVELS
+        ));
+
+        let mut seven = proof;
+        seven.exponent = 7;
+        assert!(matches!(
+            seven.to_bytes(),
+            Err(MerkleError::InvalidProof(_))
+        ));
+    }
+
+    #[test]
+    fn truncations_and_bit_flips_never_panic() {
+        let proof = proofs(9).remove(6);
+        let bytes = proof.to_bytes().unwrap();
+        for end in 0..bytes.len() {
+            assert!(matches!(
+                InclusionProof::from_bytes(&bytes[..end]),
+                Err(MerkleError::InvalidLength { .. })
+            ));
+        }
+        //A flipped hash bit either leaves a field element, read back as such, or
+        //lands on or above the modulus
+        let id_len = proof.get_leaf().get_id().len();
+        let bitmap = 3 + id_len + HASH_BYTES + VALUE_BYTES + 1;
+        let hashes =
+            (3 + id_len..3 + id_len + HASH_BYTES).chain(bitmap + 1..bitmap + 1 + HASH_BYTES);
+        for byte in hashes {
+            for bit in 0..8 {
+                let mut flipped = bytes.clone();
+                flipped[byte] ^= 1 << bit;
+                match InclusionProof::from_bytes(&flipped) {
+                    Ok(parsed) => {
+                        assert_ne!(parsed, proof);
+                        assert_eq!(parsed.to_bytes().unwrap(), flipped);
+                    }
+                    Err(err) => assert!(matches!(err, MerkleError::InvalidFieldElement(_))),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn path_lengths_at_the_bounds() {
+        let empty = InclusionProof {
+            leaf: Leaf::new("alice".to_string(), 1),
+            index: 0,
+            path: vec![],
+            root: None,
+            epoch: None,
+            exponent: default_exponent(),
+        };
+        let bytes = empty.to_bytes().unwrap();
+        assert_eq!(InclusionProof::from_bytes(&bytes).unwrap(), empty);
+
+        //Path lengths of 64 and 65 with every byte they announce present
+        let node = Node::new(Fr::ONE, 0);
+        let announced = |levels: usize| {
+            let mut long = bytes[..bytes.len() - 1].to_vec();
+            long.push(levels as u8);
+            long.extend(vec![0; levels.div_ceil(8)]);
+            for _ in 0..levels {
+                push_node(&mut long, &node);
+            }
+            InclusionProof::from_bytes(&long)
+        };
+        let widest = announced(MAX_PROOF_LEVELS).unwrap();
+        assert_eq!(widest.get_path().len(), 64);
+        assert_eq!(widest.get_index(), 0);
+        assert!(matches!(
+            announced(65),
+            Err(MerkleError::PathTooLong { levels: 65, .. })
+        ));
+        assert!(matches!(
+            InclusionProof::from_bytes(&[&bytes[..bytes.len() - 1], &[u8::MAX]].concat()),
+            Err(MerkleError::PathTooLong { levels: 255, .. })
+        ));
+    }
+
+    #[test]
+    fn canonical_bytes_key_a_cache() {
+        use std::collections::HashSet;
+
+        let leafs = users(6, 3, 0);
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        let mut seen = HashSet::new();
+        let mut keys = HashSet::new();
+        for _ in 0..3 {
+            for index in 0..6 {
+                let proof = tree.get_proof(index).unwrap().unwrap();
+                keys.insert(proof.canonical_bytes());
+                seen.insert(proof);
+            }
+        }
+        assert_eq!(seen.len(), 6);
+        assert_eq!(keys.len(), 6);
+
+        //Stamping the root or epoch makes another proof with another key
+        let stamped = tree.get_proof_with_root(0).unwrap();
+        let tagged = tree.get_proof_tagged(0).unwrap();
+        for proof in [stamped, tagged] {
+            assert!(seen.insert(proof.clone()));
+            assert!(keys.insert(proof.canonical_bytes()));
+        }
+
+        //The layout is fixed, so keys agree across processes and builds
+        let proof = InclusionProof {
+            leaf: Leaf {
+                id: "a".into(),
+                node: Node::new(Fr::ONE, -1),
+            },
+            index: 1,
+            path: vec![Neighbor::new(Position::Left, Node::new(Fr::from(2u64), 3))],
+            root: None,
+            epoch: Some(7),
+            exponent: default_exponent(),
+        };
+        let mut expected = vec![1, 0, 0, 0, 0, 0, 0, 0, b'a', 1];
+        expected.extend_from_slice(&[0; 31]);
+        expected.extend_from_slice(&[0xff; 4]);
+        expected.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[1, 2]);
+        expected.extend_from_slice(&[0; 31]);
+        expected.extend_from_slice(&[3, 0, 0, 0]);
+        expected.extend_from_slice(&[0, 1, 7, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[5, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(proof.canonical_bytes(), expected);
+    }
+}
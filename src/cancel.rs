@@ -0,0 +1,181 @@
+//Cooperative cancellation of long-running operations
+
+use crate::{MerkleError, MerkleSumTree, PROGRESS_INTERVAL};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+//Clones share the flag, cancel from any of them
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), MerkleError> {
+        match self.is_cancelled() {
+            true => Err(MerkleError::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    //check at the first unit of every chunk
+    pub(crate) fn check_at(&self, unit: usize) -> Result<(), MerkleError> {
+        match unit as u64 % PROGRESS_INTERVAL {
+            0 => self.check(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl MerkleSumTree {
+    //Stop later long operations once token is cancelled, None installs a fresh token
+    pub fn set_cancellation(&mut self, token: Option<CancellationToken>) {
+        self.cancel = token.unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::{Fr, Leaf, MutationLog, Node, Phase, Progress, ProgressCallback};
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+
+    fn leafs(count: usize) -> Vec<Leaf> {
+        (0..count)
+            .map(|i| Leaf::new(format!("user{}", i), (i % 50) as i32))
+            .collect()
+    }
+
+    //Pauses the operation at its first level 1 report until another thread has
+    //cancelled token, and records every report
+    fn cancel_midway(token: &CancellationToken) -> (ProgressCallback, Arc<Mutex<Vec<Progress>>>) {
+        let (paused, wait) = mpsc::channel();
+        let (done, resume) = mpsc::channel();
+        let remote = token.clone();
+        std::thread::spawn(move || {
+            if wait.recv().is_ok() {
+                remote.cancel();
+                done.send(()).unwrap();
+            }
+        });
+        let seen = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&seen);
+        let callback: ProgressCallback = Box::new(move |progress: Progress| {
+            sink.lock().unwrap().push(progress);
+            //Only the first one pauses, the canceller is gone after it
+            if matches!(progress.get_phase(), Phase::Level { level: 1, .. })
+                && paused.send(()).is_ok()
+            {
+                let _ = resume.recv();
+            }
+        });
+        (callback, seen)
+    }
+
+    #[test]
+    fn builds_stop_at_the_next_chunk() {
+        let token = CancellationToken::new();
+        let (callback, seen) = cancel_midway(&token);
+        let built = MerkleSumTree::builder()
+            .on_progress(callback)
+            .cancellation(token.clone())
+            .build(leafs(1 << 14));
+        assert!(matches!(built, Err(MerkleError::Cancelled)));
+        assert!(token.is_cancelled());
+        //The build stopped at the chunk boundary right after the pause
+        let reports = seen.lock().unwrap().clone();
+        assert_eq!(
+            reports
+                .last()
+                .map(|report| (report.get_phase(), report.get_completed())),
+            Some((
+                Phase::Level {
+                    level: 1,
+                    height: 15
+                },
+                PROGRESS_INTERVAL
+            ))
+        );
+        assert!(matches!(
+            MerkleSumTree::builder().cancellation(token).build(leafs(3)),
+            Err(MerkleError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn cancelled_rebuilds_leave_the_tree_untouched() {
+        let mut tree = MerkleSumTree::new(leafs(1 << 13)).unwrap();
+        let leaf_count = tree.get_leafs().len();
+        let at = tree.nodes.locate(leaf_count + 3).unwrap();
+        *tree.nodes.get_mut(at).unwrap() = Node::new(Fr::from(7u64), 1);
+        let corrupted = tree.clone();
+        let token = CancellationToken::new();
+        let (callback, _) = cancel_midway(&token);
+        tree.set_progress(Some(callback));
+        tree.set_cancellation(Some(token));
+        assert!(matches!(tree.rebuild(), Err(MerkleError::Cancelled)));
+        assert_eq!(tree.get_nodes(), corrupted.get_nodes());
+
+        //validate is not cancellable, it still sees the corruption
+        assert!(tree.validate().is_err());
+        tree.set_cancellation(None);
+        assert!(!tree.rebuild().unwrap().is_clean());
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn cancelled_bulk_writes_leave_the_tree_untouched() {
+        let token = CancellationToken::new();
+        let mut tree = MerkleSumTree::builder()
+            .cancellation(token.clone())
+            .build(leafs(8))
+            .unwrap();
+        let before = tree.clone();
+        token.cancel();
+        assert!(matches!(
+            tree.remove_many(&[1, 4, 6]),
+            Err(MerkleError::Cancelled)
+        ));
+        assert!(matches!(
+            tree.push(Leaf::new("late".to_string(), 1)),
+            Err(MerkleError::Cancelled)
+        ));
+        assert!(matches!(tree.defragment(), Ok(moves) if moves.is_empty()));
+        tree.set_mutation_log(MutationLog::new(std::io::sink()));
+        assert!(matches!(
+            tree.remove_many(&[1, 4]),
+            Err(MerkleError::Cancelled)
+        ));
+        assert_eq!(tree.get_leafs(), before.get_leafs());
+        assert_eq!(tree.get_nodes(), before.get_nodes());
+        assert_eq!(tree.get_epoch(), before.get_epoch());
+
+        tree.set_cancellation(None);
+        assert_eq!(tree.remove_many(&[1, 4, 6]).unwrap(), 3);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn cancelled_imports_return_no_tree() {
+        let tree = MerkleSumTree::new(leafs(300)).unwrap();
+        let archive = serde_json::to_vec(&tree).unwrap();
+        let token = CancellationToken::new();
+        token.cancel();
+        assert!(matches!(
+            MerkleSumTree::import_archive_parallel_cancellable(&archive, 4, token),
+            Err(MerkleError::Cancelled)
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+//Canonical-order trees, whose root depends only on the multiset of leafs
+
+use crate::{EmptyLeafSpec, Endianness, Leaf, MerkleError, MerkleSumTree};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+impl MerkleSumTree {
+    //Same multiset of leafs, same root, whatever order they come in
+    pub fn new_canonical(leafs: Vec<Leaf>) -> Result<MerkleSumTree, MerkleError> {
+        let empty = EmptyLeafSpec::default();
+        let mut leafs: Vec<Leaf> = leafs
+            .into_iter()
+            .filter(|leaf| !empty.matches(leaf))
+            .collect();
+        leafs.sort_by_cached_key(canonical_key);
+        let mut tree = Self::new(leafs)?;
+        tree.canonical = true;
+        Ok(tree)
+    }
+
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    //Insert at the sorted position and rebuild, return the index the leaf landed at
+    pub(crate) fn push_canonical(&mut self, leaf: Leaf) -> Result<usize, MerkleError> {
+        let started = self.metrics.start();
+        self.check_value(&leaf)?;
+        self.check_rules(&leaf)?;
+        let leaf = self.leaf_hash_mode.normalize(leaf);
+        let mut leafs: Vec<Leaf> = self
+            .non_empty_leafs(false)
+            .map(|(_, leaf)| leaf.clone())
+            .collect();
+        let key = canonical_key(&leaf);
+        let index = leafs.partition_point(|current| canonical_key(current) <= key);
+        //Every slot from index to the old end shifts up by one
+        let changes = (index..=leafs.len())
+            .map(|slot| {
+                let old = self.get_leaf(slot);
+                (slot, old.unwrap_or_else(|| self.empty.get_leaf()))
+            })
+            .collect();
+        leafs.insert(index, leaf.clone());
+        let new_tree = Self::create_tree(
+            leafs,
+            self.max_height,
+            None,
+            BTreeSet::new(),
+            self.empty.clone(),
+            self.leaf_hash_mode,
+            self.mimc_exponent,
+            &self.progress,
+            &self.cancel,
+        )?;
+        self.log_grown(index, &leaf, &new_tree)?;
+        self.update_tree(new_tree, started)?;
+        self.epoch += 1;
+        self.notify(changes);
+        Ok(index)
+    }
+
+    //Whether the layout is still canonical after a write to index
+    pub(crate) fn canonical_after_write(&self, index: usize) -> bool {
+        //Occupied slots are 0..count exactly when the first empty one is count
+        let count = self.leafs.len() - self.zero_index.len();
+        let packed = self.zero_index.first().is_none_or(|first| *first == count);
+        let capacity = count.max(1).checked_next_power_of_two() == Some(self.leafs.len());
+        let key = |slot: usize| {
+            self.leafs
+                .get(slot)
+                .filter(|_| slot < count)
+                .map(canonical_key)
+        };
+        let sorted = match key(index) {
+            Some(current) => {
+                index
+                    .checked_sub(1)
+                    .and_then(key)
+                    .is_none_or(|left| left <= current)
+                    && key(index + 1).is_none_or(|right| current <= right)
+            }
+            None => true,
+        };
+        packed && capacity && sorted && self.dummies.is_empty()
+    }
+
+    //Whether the whole layout is canonical, for trees loaded from elsewhere
+    pub(crate) fn in_canonical_order(&self) -> bool {
+        (0..self.leafs.len()).all(|index| self.canonical_after_write(index))
+    }
+}
+
+//(id hash as a big-endian integer, id, value)
+fn canonical_key(leaf: &Leaf) -> ([u8; 32], Arc<str>, i32) {
+    (id_key(leaf.id.clone()), leaf.id.clone(), leaf.node.value)
+}
+
+//The id hash Leaf::new gives, as a big-endian integer
+pub(crate) fn id_key(id: Arc<str>) -> [u8; 32] {
+    Leaf::with_shared_id(id, 0)
+        .node
+        .hash
+        .to_bytes(Endianness::Big)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    fn accounts() -> Vec<Leaf> {
+        let mut leafs = users(11, 7, 20);
+        leafs.push(Leaf::new("user3".to_string(), 5));
+        leafs
+    }
+
+    fn shuffled(mut leafs: Vec<Leaf>, seed: u32) -> Vec<Leaf> {
+        XorShiftRng::from_seed([seed, 2, 3, 4]).shuffle(&mut leafs);
+        leafs
+    }
+
+    #[test]
+    fn order_does_not_change_the_root() {
+        let first = MerkleSumTree::new_canonical(shuffled(accounts(), 1)).unwrap();
+        let second = MerkleSumTree::new_canonical(shuffled(accounts(), 9)).unwrap();
+        assert!(first.is_canonical());
+        assert_eq!(first.get_root(), second.get_root());
+        assert_eq!(first.get_leafs(), second.get_leafs());
+        assert_ne!(
+            MerkleSumTree::new(shuffled(accounts(), 1))
+                .unwrap()
+                .get_root(),
+            MerkleSumTree::new(shuffled(accounts(), 9))
+                .unwrap()
+                .get_root()
+        );
+        assert!(!MerkleSumTree::new(accounts()).unwrap().is_canonical());
+
+        //One balance apart
+        let mut other = accounts();
+        other[4] = Leaf::new("user4".to_string(), 9);
+        let other = MerkleSumTree::new_canonical(other).unwrap();
+        assert_ne!(other.get_root(), first.get_root());
+        assert!(other.audit_report().unwrap().is_canonical());
+    }
+
+    #[test]
+    fn push_inserts_in_order() {
+        let mut leafs = accounts();
+        let late = leafs.split_off(7);
+        let mut tree = MerkleSumTree::new_canonical(leafs.clone()).unwrap();
+        for leaf in late {
+            let index = tree.push(leaf.clone()).unwrap();
+            assert_eq!(tree.get_leaf(index), Some(leaf.clone()));
+            leafs.push(leaf);
+            let fresh = MerkleSumTree::new_canonical(leafs.clone()).unwrap();
+            assert_eq!(tree.get_leafs(), fresh.get_leafs());
+            assert_eq!(tree.get_nodes(), fresh.get_nodes());
+            assert!(tree.is_canonical());
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        assert_eq!(tree.get_indices_by_id("user3").len(), 2);
+        assert_eq!(tree.get_epoch(), 5);
+    }
+
+    #[test]
+    fn in_place_writes_keep_the_flag_only_while_canonical() {
+        let mut tree = MerkleSumTree::new_canonical(accounts()).unwrap();
+        let last = tree.get_leafs().len() - tree.get_zero_index().len() - 1;
+        let index = tree.get_index_by_id("user5").unwrap();
+        tree.add_to_leaf(index, 3).unwrap();
+        assert!(tree.is_canonical());
+        tree.remove(last).unwrap();
+        assert!(tree.is_canonical());
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let hole = rng.gen_range(0, last - 1);
+        tree.remove(hole).unwrap();
+        assert!(!tree.is_canonical());
+        let mut unsorted = MerkleSumTree::new_canonical(accounts()).unwrap();
+        unsorted.swap_leafs(0, 1).unwrap();
+        assert!(!unsorted.is_canonical());
+
+        //The flag survives serialization only when the layout backs it
+        unsorted.canonical = true;
+        let json = serde_json::to_string(&unsorted).unwrap();
+        assert!(!serde_json::from_str::<MerkleSumTree>(&json)
+            .unwrap()
+            .is_canonical());
+        let canonical = MerkleSumTree::new_canonical(accounts()).unwrap();
+        let json = serde_json::to_string(&canonical).unwrap();
+        assert!(serde_json::from_str::<MerkleSumTree>(&json)
+            .unwrap()
+            .is_canonical());
+    }
+}
@@ -0,0 +1,232 @@
+//Why a proof fails against a tree, for debugging mismatched circuit inputs
+
+use crate::{HashParams, InclusionProof, MerkleError, MerkleSumTree};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofCheck {
+    Valid,
+    //The leaf hash is not the one the tree's leaf hash mode derives
+    LeafHashMismatch,
+    ExponentMismatch { expected: u64, actual: u64 },
+    PathLength { expected: usize, actual: usize },
+    //Stamped with a root other than the current one, or tagged with an epoch whose
+    //root is not kept
+    StaleRoot,
+    //The sums overflow an i32 while folding
+    Overflow,
+    RootHashMismatch,
+    //The hash matches, the sum does not
+    RootSumMismatch { expected: i32, actual: i32 },
+}
+
+impl ProofCheck {
+    pub fn is_valid(&self) -> bool {
+        *self == ProofCheck::Valid
+    }
+
+    //What verify_proof returns for this outcome
+    pub(crate) fn into_verdict(self) -> Result<bool, MerkleError> {
+        match self {
+            ProofCheck::Valid => Ok(true),
+            ProofCheck::PathLength { expected, actual } => Err(MerkleError::InvalidProof(format!(
+                "path of {} levels for a tree of {}",
+                actual, expected
+            ))),
+            ProofCheck::Overflow => Err(MerkleError::Overflow),
+            _ => Ok(false),
+        }
+    }
+}
+
+impl fmt::Display for ProofCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProofCheck::Valid => write!(f, "the proof is valid"),
+            ProofCheck::LeafHashMismatch => {
+                write!(f, "the leaf hash does not match the leaf hash mode")
+            }
+            ProofCheck::ExponentMismatch { expected, actual } => {
+                write!(f, "the proof uses exponent {}, not {}", actual, expected)
+            }
+            ProofCheck::PathLength { expected, actual } => {
+                write!(f, "the path has {} levels, not {}", actual, expected)
+            }
+            ProofCheck::StaleRoot => write!(f, "the proof is stamped with a root not kept"),
+            ProofCheck::Overflow => write!(f, "the sums overflow while folding"),
+            ProofCheck::RootHashMismatch => write!(f, "the root hash does not match"),
+            ProofCheck::RootSumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "the root hash matches but the sum is {}, not {}",
+                    actual, expected
+                )
+            }
+        }
+    }
+}
+
+impl MerkleSumTree {
+    //The first check proof fails, or Valid
+    pub fn verify_proof_detailed(&self, proof: &InclusionProof) -> Result<ProofCheck, MerkleError> {
+        self.check_proof(proof, &HashParams::mimc(self.mimc_exponent)?)
+    }
+
+    //verify_proof_detailed folding under params, the tree's own
+    pub(crate) fn check_proof(
+        &self,
+        proof: &InclusionProof,
+        params: &HashParams,
+    ) -> Result<ProofCheck, MerkleError> {
+        let current = self.get_root().ok_or(MerkleError::EmptyTree)?;
+        if !self.leaf_hash_mode.accepts(&proof.leaf) {
+            return Ok(ProofCheck::LeafHashMismatch);
+        }
+        if proof.exponent != self.mimc_exponent {
+            return Ok(ProofCheck::ExponentMismatch {
+                expected: self.mimc_exponent,
+                actual: proof.exponent,
+            });
+        }
+        //A proof tagged with a past epoch and no root, as get_proof_tagged gives, checks
+        //against the root kept for it and the height of its tree; a stamped proof always
+        //against the current one
+        let (root, height) = match (proof.epoch, &proof.root) {
+            (Some(epoch), None) if epoch != self.epoch => match self.past_root(epoch) {
+                Some(past) => past,
+                None => return Ok(ProofCheck::StaleRoot),
+            },
+            _ => (current, self.height),
+        };
+        let levels = height.saturating_sub(1);
+        if proof.path.len() != levels {
+            return Ok(ProofCheck::PathLength {
+                expected: levels,
+                actual: proof.path.len(),
+            });
+        }
+        if proof.root.as_ref().is_some_and(|stamped| *stamped != root) {
+            return Ok(ProofCheck::StaleRoot);
+        }
+        self.metrics.hashes(proof.path.len());
+        let computed = match proof.compute_root_with(params) {
+            Ok(computed) => computed,
+            Err(MerkleError::Overflow) => return Ok(ProofCheck::Overflow),
+            Err(err) => return Err(err),
+        };
+        Ok(
+            match (computed.hash == root.hash, computed.value == root.value) {
+                (false, _) => ProofCheck::RootHashMismatch,
+                (true, false) => ProofCheck::RootSumMismatch {
+                    expected: root.value,
+                    actual: computed.value,
+                },
+                (true, true) => ProofCheck::Valid,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use crate::{Leaf, LeafHashMode, Node};
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, 20, 30])).unwrap()
+    }
+
+    #[test]
+    fn each_failure_is_named() {
+        let tree = tree();
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        assert_eq!(
+            tree.verify_proof_detailed(&proof).unwrap(),
+            ProofCheck::Valid
+        );
+
+        let mut value = proof.clone();
+        value.leaf = Leaf::new("bob".to_string(), 21);
+        assert_eq!(
+            tree.verify_proof_detailed(&value).unwrap(),
+            ProofCheck::RootHashMismatch
+        );
+
+        let mut short = proof.clone();
+        short.path.pop();
+        short.index = 1;
+        assert_eq!(
+            tree.verify_proof_detailed(&short).unwrap(),
+            ProofCheck::PathLength {
+                expected: 2,
+                actual: 1
+            }
+        );
+
+        let mut exponent = proof.clone();
+        exponent.exponent = 7;
+        assert_eq!(
+            tree.verify_proof_detailed(&exponent).unwrap(),
+            ProofCheck::ExponentMismatch {
+                expected: 5,
+                actual: 7
+            }
+        );
+
+        let mut moved = tree.clone();
+        moved.push(Leaf::new("dave".to_string(), 1)).unwrap();
+        let stamped = tree.get_proof_with_root(1).unwrap();
+        assert_eq!(
+            moved.verify_proof_detailed(&stamped).unwrap(),
+            ProofCheck::StaleRoot
+        );
+
+        let mut overflow = proof.clone();
+        overflow.path[1].node = Node::new(overflow.path[1].node.hash, i32::MAX);
+        assert_eq!(
+            tree.verify_proof_detailed(&overflow).unwrap(),
+            ProofCheck::Overflow
+        );
+        assert!(matches!(
+            tree.verify_proof(overflow),
+            Err(MerkleError::Overflow)
+        ));
+
+        let keyed = MerkleSumTree::builder()
+            .leaf_hash_mode(LeafHashMode::IdAndValue)
+            .build(vec![Leaf::new("alice".to_string(), 10)])
+            .unwrap();
+        let mut foreign = keyed.get_proof(0).unwrap().unwrap();
+        foreign.leaf = Leaf::new("alice".to_string(), 10);
+        assert_eq!(
+            keyed.verify_proof_detailed(&foreign).unwrap(),
+            ProofCheck::LeafHashMismatch
+        );
+        assert!(!keyed.verify_proof(foreign).unwrap());
+    }
+
+    #[test]
+    fn a_matching_hash_with_another_sum_is_told_apart() {
+        //A one-leaf tree is its root, so the sum can differ while the hash holds
+        let tree = MerkleSumTree::new(vec![Leaf::new("alice".to_string(), 10)]).unwrap();
+        let mut proof = tree.get_proof(0).unwrap().unwrap();
+        assert!(proof.get_path().is_empty());
+        proof.leaf.node = Node::new(proof.leaf.node.hash, 11);
+        let check = tree.verify_proof_detailed(&proof).unwrap();
+        assert_eq!(
+            check,
+            ProofCheck::RootSumMismatch {
+                expected: 10,
+                actual: 11
+            }
+        );
+        assert_eq!(
+            check.to_string(),
+            "the root hash matches but the sum is 11, not 10"
+        );
+        assert!(!check.is_valid());
+        assert!(!tree.verify_proof(proof).unwrap());
+    }
+}
@@ -0,0 +1,248 @@
+//Resumable streaming construction of a tree root
+
+use crate::container::{read_container, write_container, KIND_CHECKPOINT};
+use crate::progress::{PhaseTracker, ProgressSink};
+use crate::{empty_leaf, Leaf, MerkleError, MerkleSumTree, Node, Phase, ProgressCallback};
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+
+//Streaming construction state, the input offset and the (level, root) peaks
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    offset: u64,
+    peaks: Vec<(usize, Node)>,
+}
+
+impl Checkpoint {
+    //Number of leafs consumed, resume with the input past this offset
+    pub fn get_offset(&self) -> u64 {
+        self.offset
+    }
+
+    //Last complete checkpoint of a stream, a torn trailing write is ignored
+    pub fn read_last<R: Read>(mut reader: R) -> Result<Checkpoint, MerkleError> {
+        let mut last = None;
+        loop {
+            match read_container(&mut reader, KIND_CHECKPOINT) {
+                Ok(Some(payload)) => last = Some(serde_json::from_slice(&payload)?),
+                Ok(None) => break,
+                Err(MerkleError::Io(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+        }
+        last.ok_or_else(|| MerkleError::InvalidContainer("no checkpoint".to_string()))
+    }
+
+    fn write<W: Write>(&self, sink: &mut W) -> Result<(), MerkleError> {
+        write_container(sink, KIND_CHECKPOINT, &serde_json::to_vec(self)?)
+    }
+
+    fn push(&mut self, node: Node, level: usize) -> Result<(), MerkleError> {
+        let mut node = node;
+        let mut level = level;
+        while let Some((top, _)) = self.peaks.last() {
+            if *top != level {
+                break;
+            }
+            if let Some((_, left)) = self.peaks.pop() {
+                node = MerkleSumTree::build_parent(left, node)?;
+            }
+            level += 1;
+        }
+        self.peaks.push((level, node));
+        Ok(())
+    }
+
+    fn consume<I, W>(
+        &mut self,
+        leaves: I,
+        every_n: usize,
+        sink: &mut W,
+        progress: &PhaseTracker,
+    ) -> Result<(), MerkleError>
+    where
+        I: Iterator<Item = Leaf>,
+        W: Write,
+    {
+        for leaf in leaves {
+            self.push(leaf.get_node(), 0)?;
+            progress.advance(1);
+            self.offset += 1;
+            if every_n > 0 && self.offset % every_n as u64 == 0 {
+                self.write(sink)?;
+            }
+        }
+        Ok(())
+    }
+
+    //Pad with aligned empty subtrees until a single peak is left
+    fn finish(mut self) -> Result<Node, MerkleError> {
+        let mut empty = vec![empty_leaf().get_node()];
+        if self.peaks.is_empty() {
+            return Ok(empty_leaf().get_node());
+        }
+        while self.peaks.len() > 1 {
+            let level = self.peaks.last().map_or(0, |(level, _)| *level);
+            while empty.len() <= level {
+                let below = empty
+                    .last()
+                    .cloned()
+                    .unwrap_or_else(|| empty_leaf().get_node());
+                empty.push(MerkleSumTree::build_parent(below.clone(), below)?);
+            }
+            let padding = empty
+                .get(level)
+                .cloned()
+                .ok_or(MerkleError::MissingNode(level))?;
+            self.push(padding, level)?;
+        }
+        self.peaks
+            .pop()
+            .map(|(_, root)| root)
+            .ok_or(MerkleError::EmptyTree)
+    }
+}
+
+impl MerkleSumTree {
+    //Root of the tree over leaves, checkpointing every every_n leafs (0 never does)
+    pub fn build_with_checkpoints<I, W>(
+        leaves: I,
+        every_n: usize,
+        sink: W,
+    ) -> Result<Node, MerkleError>
+    where
+        I: Iterator<Item = Leaf>,
+        W: Write,
+    {
+        stream(leaves, every_n, sink, &ProgressSink::default())
+    }
+
+    //build_with_checkpoints reporting the leafs consumed to progress
+    pub fn build_with_checkpoints_and_progress<I, W>(
+        leaves: I,
+        every_n: usize,
+        sink: W,
+        progress: ProgressCallback,
+    ) -> Result<Node, MerkleError>
+    where
+        I: Iterator<Item = Leaf>,
+        W: Write,
+    {
+        stream(leaves, every_n, sink, &ProgressSink::new(Some(progress)))
+    }
+
+    //Continue from the last checkpoint in reader with the leafs past its offset
+    pub fn resume_from_checkpoint<R, I>(reader: R, remaining_leaves: I) -> Result<Node, MerkleError>
+    where
+        R: Read,
+        I: Iterator<Item = Leaf>,
+    {
+        let mut state = Checkpoint::read_last(reader)?;
+        let progress = ProgressSink::default();
+        let consumed = progress.phase(Phase::LeafHashing, None);
+        state.consume(remaining_leaves, 0, &mut std::io::sink(), &consumed)?;
+        state.finish()
+    }
+}
+
+//Fresh streaming construction, the input length is the total when it is exact
+fn stream<I, W>(
+    leaves: I,
+    every_n: usize,
+    mut sink: W,
+    progress: &ProgressSink,
+) -> Result<Node, MerkleError>
+where
+    I: Iterator<Item = Leaf>,
+    W: Write,
+{
+    let total = match leaves.size_hint() {
+        (lower, Some(upper)) if lower == upper => Some(lower as u64),
+        _ => None,
+    };
+    let consumed = progress.phase(Phase::LeafHashing, total);
+    let mut state = Checkpoint::default();
+    state.consume(leaves, every_n, &mut sink, &consumed)?;
+    consumed.finish();
+    state.finish()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::container::HEADER_BYTES;
+    use crate::test_support::users;
+
+    fn leafs(count: i32) -> Vec<Leaf> {
+        users(count, 3, 40)
+    }
+
+    #[test]
+    fn streaming_roots_match_built_trees() {
+        for count in [0, 1, 2, 3, 5, 8, 13, 33] {
+            let leafs = leafs(count);
+            let root = MerkleSumTree::build_with_checkpoints(
+                leafs.clone().into_iter(),
+                0,
+                std::io::sink(),
+            )
+            .unwrap();
+            assert_eq!(Some(root), MerkleSumTree::new(leafs).unwrap().get_root());
+        }
+    }
+
+    #[test]
+    fn interrupted_builds_resume_to_the_same_root() {
+        let leafs = leafs(45);
+        let expected = MerkleSumTree::new(leafs.clone())
+            .unwrap()
+            .get_root()
+            .unwrap();
+        for (interrupt_at, every_n) in [(30, 4), (7, 7), (44, 1), (45, 10)] {
+            let mut stream = vec![];
+            MerkleSumTree::build_with_checkpoints(
+                leafs.iter().take(interrupt_at).cloned(),
+                every_n,
+                &mut stream,
+            )
+            .unwrap();
+            let checkpoint = Checkpoint::read_last(stream.as_slice()).unwrap();
+            let offset = checkpoint.get_offset() as usize;
+            assert_eq!(offset, interrupt_at / every_n * every_n);
+
+            let root = MerkleSumTree::resume_from_checkpoint(
+                stream.as_slice(),
+                leafs.iter().skip(offset).cloned(),
+            )
+            .unwrap();
+            assert_eq!(root, expected);
+        }
+    }
+
+    #[test]
+    fn torn_and_missing_checkpoints() {
+        let leafs = leafs(12);
+        let mut stream = vec![];
+        MerkleSumTree::build_with_checkpoints(leafs.iter().cloned(), 5, &mut stream).unwrap();
+        //A crash in the middle of the second write leaves the first usable
+        let mut length = [0u8; 8];
+        length.copy_from_slice(&stream[7..15]);
+        let first_len = HEADER_BYTES + u64::from_le_bytes(length) as usize;
+        let torn = &stream[..stream.len() - 3];
+        assert_eq!(Checkpoint::read_last(torn).unwrap().get_offset(), 5);
+        assert_eq!(
+            Checkpoint::read_last(&stream[..first_len])
+                .unwrap()
+                .get_offset(),
+            5
+        );
+        assert!(matches!(
+            Checkpoint::read_last(&stream[..0]),
+            Err(MerkleError::InvalidContainer(_))
+        ));
+        let mut corrupt = stream.clone();
+        corrupt[0] = b'X';
+        assert!(Checkpoint::read_last(corrupt.as_slice()).is_err());
+    }
+}
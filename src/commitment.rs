@@ -0,0 +1,191 @@
+//The canonical (root hash, root sum, epoch) triple
+
+use crate::mimc_sponge::Fr;
+use crate::{Endianness, MerkleError, Node};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const COMMITMENT_BYTES: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootCommitment {
+    hash: Fr,
+    sum: i64,
+    epoch: u64,
+}
+
+impl RootCommitment {
+    pub fn new(hash: Fr, sum: i64, epoch: u64) -> RootCommitment {
+        RootCommitment { hash, sum, epoch }
+    }
+
+    pub fn from_node(node: &Node, epoch: u64) -> RootCommitment {
+        RootCommitment::new(node.get_hash(), i64::from(node.get_value()), epoch)
+    }
+
+    pub fn get_hash(&self) -> Fr {
+        self.hash
+    }
+
+    pub fn get_sum(&self) -> i64 {
+        self.sum
+    }
+
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    //Hash and sum agree with a recomputed root, the epoch is not part of a node
+    pub fn matches(&self, node: &Node) -> bool {
+        self.hash == node.get_hash() && self.sum == i64::from(node.get_value())
+    }
+
+    pub fn to_bytes(&self) -> [u8; COMMITMENT_BYTES] {
+        self.to_bytes_with(Endianness::Little)
+    }
+
+    pub fn to_bytes_with(&self, endianness: Endianness) -> [u8; COMMITMENT_BYTES] {
+        let mut bytes = [0u8; COMMITMENT_BYTES];
+        let (hash, rest) = bytes.split_at_mut(32);
+        let (sum, epoch) = rest.split_at_mut(8);
+        hash.copy_from_slice(&self.hash.to_bytes(endianness));
+        match endianness {
+            Endianness::Little => {
+                sum.copy_from_slice(&self.sum.to_le_bytes());
+                epoch.copy_from_slice(&self.epoch.to_le_bytes());
+            }
+            Endianness::Big => {
+                sum.copy_from_slice(&self.sum.to_be_bytes());
+                epoch.copy_from_slice(&self.epoch.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<RootCommitment, MerkleError> {
+        Self::from_bytes_with(bytes, Endianness::Little)
+    }
+
+    pub fn from_bytes_with(
+        bytes: &[u8],
+        endianness: Endianness,
+    ) -> Result<RootCommitment, MerkleError> {
+        let bytes: [u8; COMMITMENT_BYTES] =
+            bytes.try_into().map_err(|_| MerkleError::InvalidLength {
+                expected: COMMITMENT_BYTES,
+                actual: bytes.len(),
+            })?;
+        let (hash_bytes, rest) = bytes.split_at(32);
+        let (sum_bytes, epoch_bytes) = rest.split_at(8);
+        let hash = Fr::from_bytes(hash_bytes, endianness)?;
+        let mut sum = [0u8; 8];
+        sum.copy_from_slice(sum_bytes);
+        let mut epoch = [0u8; 8];
+        epoch.copy_from_slice(epoch_bytes);
+        let (sum, epoch) = match endianness {
+            Endianness::Little => (i64::from_le_bytes(sum), u64::from_le_bytes(epoch)),
+            Endianness::Big => (i64::from_be_bytes(sum), u64::from_be_bytes(epoch)),
+        };
+        Ok(RootCommitment::new(hash, sum, epoch))
+    }
+}
+
+//A bare node carries no epoch, it converts at epoch 0
+impl From<Node> for RootCommitment {
+    fn from(node: Node) -> Self {
+        RootCommitment::from_node(&node, 0)
+    }
+}
+
+impl fmt::Display for RootCommitment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x} sum {} epoch {}", self.hash, self.sum, self.epoch)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_layout_round_trips() {
+        let commitment = RootCommitment::new(Fr::from(0x0102u64), -5, 9);
+        let bytes = commitment.to_bytes();
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(bytes[0..2], [0x02, 0x01]);
+        assert_eq!(bytes[32..40], (-5i64).to_le_bytes());
+        assert_eq!(bytes[40..48], 9u64.to_le_bytes());
+        assert_eq!(RootCommitment::from_bytes(&bytes).unwrap(), commitment);
+
+        assert!(matches!(
+            RootCommitment::from_bytes(&bytes[1..]),
+            Err(MerkleError::InvalidLength {
+                expected: 48,
+                actual: 47
+            })
+        ));
+        let mut overflowing = bytes;
+        overflowing[0..32].copy_from_slice(&[0xff; 32]);
+        assert!(matches!(
+            RootCommitment::from_bytes(&overflowing),
+            Err(MerkleError::InvalidFieldElement(_))
+        ));
+    }
+
+    #[test]
+    fn fixtures_under_each_endianness() {
+        let tree = crate::MerkleSumTree::new(vec![
+            crate::Leaf::new("alice".to_string(), 10),
+            crate::Leaf::new("bob".to_string(), 20),
+            crate::Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        let commitment = RootCommitment::from_node(&tree.get_root().unwrap(), 3);
+        let hex = |bytes: [u8; COMMITMENT_BYTES]| -> String {
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        };
+        let little = commitment.to_bytes_with(Endianness::Little);
+        let big = commitment.to_bytes_with(Endianness::Big);
+        assert_eq!(
+            hex(little),
+            "b1c7f02961bb697903a69876367aa24735886f936d833c36efdcfa5b305a0a33\
+             3c00000000000000\
+             0300000000000000"
+        );
+        assert_eq!(
+            hex(big),
+            "330a5a305bfadcef363c836d936f883547a27a367698a6037969bb6129f0c7b1\
+             000000000000003c\
+             0000000000000003"
+        );
+        assert_eq!(little, commitment.to_bytes());
+        for (bytes, endianness) in [(little, Endianness::Little), (big, Endianness::Big)] {
+            assert_eq!(
+                RootCommitment::from_bytes_with(&bytes, endianness).unwrap(),
+                commitment
+            );
+        }
+        //The root hash is above the modulus when read in the wrong order
+        assert!(RootCommitment::from_bytes_with(&big, Endianness::Little).is_err());
+        assert!(RootCommitment::from_bytes_with(&little, Endianness::Big).is_err());
+    }
+
+    #[test]
+    fn display_serde_and_node_conversion() {
+        let commitment = RootCommitment::new(Fr::from(255u64), 60, 3);
+        assert_eq!(commitment.to_string(), "0xff sum 60 epoch 3");
+        let json = serde_json::to_string(&commitment).unwrap();
+        assert_eq!(json, "{\"hash\":\"255\",\"sum\":60,\"epoch\":3}");
+        assert_eq!(
+            serde_json::from_str::<RootCommitment>(&json).unwrap(),
+            commitment
+        );
+
+        let node = Node::new(Fr::from(255u64), 60);
+        assert!(commitment.matches(&node));
+        assert_eq!(RootCommitment::from(node.clone()).get_epoch(), 0);
+        assert_eq!(RootCommitment::from_node(&node, 3), commitment);
+        assert!(!commitment.matches(&Node::new(Fr::from(255u64), 61)));
+    }
+}
@@ -0,0 +1,291 @@
+//Inclusion proofs without the siblings an empty subtree fills
+
+use crate::mimc_sponge::DEFAULT_EXPONENT;
+use crate::{
+    default_exponent, empty_leaf, is_default_exponent, Fr, HashParams, InclusionProof, Leaf,
+    MerkleError, Neighbor, Node, Position, MAX_HEIGHT,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressedProof {
+    leaf: Leaf,
+    index: usize,
+    levels: usize,
+    //Bit i set when the sibling at level i is left out
+    empty: u64,
+    siblings: Vec<Node>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root: Option<Node>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    epoch: Option<u64>,
+    #[serde(
+        default = "default_exponent",
+        skip_serializing_if = "is_default_exponent"
+    )]
+    exponent: u64,
+}
+
+//Extend nodes with empty subtree roots up to count levels
+fn hash_empty_subtrees(
+    mut nodes: Vec<Node>,
+    count: usize,
+    params: &HashParams,
+) -> Result<Vec<Node>, MerkleError> {
+    while nodes.len() < count {
+        let node = match nodes.last() {
+            Some(below) => Node::combine(below, below, params)?,
+            None => empty_leaf().get_node(),
+        };
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+//Every level a tree can have, under the default exponent
+fn default_empty_subtrees() -> &'static [Node] {
+    static TABLE: OnceLock<Vec<Node>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        hash_empty_subtrees(vec![], MAX_HEIGHT, &HashParams::default()).unwrap_or_default()
+    })
+}
+
+//Roots of the empty subtrees of levels 0..count under exponent, the leaf first
+pub(crate) fn empty_subtree_roots(exponent: u64, count: usize) -> Result<Vec<Node>, MerkleError> {
+    let params = HashParams::mimc(exponent)?;
+    let cached = match exponent {
+        DEFAULT_EXPONENT => default_empty_subtrees(),
+        _ => &[],
+    };
+    hash_empty_subtrees(cached.iter().take(count).cloned().collect(), count, &params)
+}
+
+impl InclusionProof {
+    //Leave out the siblings that are empty subtree roots, refusing a path that does
+    //not agree with the index
+    pub fn compress(&self) -> Result<CompressedProof, MerkleError> {
+        self.check_index()?;
+        if self.path.len() >= MAX_HEIGHT {
+            return Err(MerkleError::InvalidLength {
+                expected: MAX_HEIGHT - 1,
+                actual: self.path.len(),
+            });
+        }
+        let table = empty_subtree_roots(self.exponent, self.path.len())?;
+        let mut empty = 0;
+        let mut siblings = vec![];
+        for (level, (neighbor, omitted)) in self.path.iter().zip(&table).enumerate() {
+            match neighbor.node == *omitted {
+                true => empty |= 1 << level,
+                false => siblings.push(neighbor.node.clone()),
+            }
+        }
+        Ok(CompressedProof {
+            leaf: self.leaf.clone(),
+            index: self.index,
+            levels: self.path.len(),
+            empty,
+            siblings,
+            root: self.root.clone(),
+            epoch: self.epoch,
+            exponent: self.exponent,
+        })
+    }
+}
+
+impl CompressedProof {
+    pub fn get_leaf(&self) -> Leaf {
+        self.leaf.clone()
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_levels(&self) -> usize {
+        self.levels
+    }
+
+    //Levels whose sibling is left out, ascending
+    pub fn get_empty_levels(&self) -> Vec<usize> {
+        (0..self.levels)
+            .filter(|level| (self.empty >> level) & 1 == 1)
+            .collect()
+    }
+
+    //The siblings kept, from the leaf up
+    pub fn get_siblings(&self) -> Vec<Node> {
+        self.siblings.clone()
+    }
+
+    pub fn get_exponent(&self) -> u64 {
+        self.exponent
+    }
+
+    //The full proof, omitted siblings filled in. A bitmap, index or sibling count
+    //that does not fit the levels is an invalid proof
+    pub fn decompress(&self) -> Result<InclusionProof, MerkleError> {
+        if self.levels >= MAX_HEIGHT {
+            return Err(MerkleError::InvalidProof(format!(
+                "path of {} levels is above the height cap",
+                self.levels
+            )));
+        }
+        if self.empty >> self.levels != 0 {
+            return Err(MerkleError::InvalidProof(
+                "empty bits above the path length".to_string(),
+            ));
+        }
+        if self.index >> self.levels != 0 {
+            return Err(MerkleError::InvalidProof(format!(
+                "index {} does not fit in {} levels",
+                self.index, self.levels
+            )));
+        }
+        let kept = self.levels - self.empty.count_ones() as usize;
+        if self.siblings.len() != kept {
+            return Err(MerkleError::InvalidProof(format!(
+                "{} siblings for {} kept levels",
+                self.siblings.len(),
+                kept
+            )));
+        }
+        let table = empty_subtree_roots(self.exponent, self.levels)?;
+        let mut siblings = self.siblings.iter();
+        let mut path = Vec::with_capacity(self.levels);
+        for (level, omitted) in table.iter().enumerate() {
+            let node = match (self.empty >> level) & 1 {
+                1 => omitted,
+                _ => siblings.next().ok_or_else(|| {
+                    MerkleError::InvalidProof(format!("siblings run out at level {}", level))
+                })?,
+            };
+            let position = match (self.index >> level) & 1 {
+                1 => Position::Left,
+                _ => Position::Right,
+            };
+            path.push(Neighbor::new(position, node.clone()));
+        }
+        Ok(InclusionProof {
+            leaf: self.leaf.clone(),
+            index: self.index,
+            path,
+            root: self.root.clone(),
+            epoch: self.epoch,
+            exponent: self.exponent,
+        })
+    }
+
+    //InclusionProof::verify on the decompressed proof
+    pub fn verify(&self, root_hash: Fr, root_sum: i32) -> Result<bool, MerkleError> {
+        self.decompress()?.verify(root_hash, root_sum)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::MerkleSumTree;
+
+    //4 accounts padded to 1024 slots
+    fn sparse() -> MerkleSumTree {
+        let mut leafs = users(4, 10, -5);
+        leafs.resize(513, empty_leaf());
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.get_leafs().len(), 1024);
+        tree
+    }
+
+    #[test]
+    fn empty_siblings_are_left_out() {
+        let tree = sparse();
+        let root = tree.get_root().unwrap();
+        for index in 0..4 {
+            let proof = tree.get_proof_with_root(index).unwrap();
+            let compressed = proof.compress().unwrap();
+            //The siblings at levels 0 and 1 hold accounts, the other 8 are empty
+            assert_eq!(
+                compressed.get_empty_levels(),
+                (2..10).collect::<Vec<usize>>()
+            );
+            assert_eq!(compressed.get_siblings().len(), 2);
+            assert_eq!(compressed.decompress().unwrap(), proof);
+            assert!(compressed
+                .verify(root.get_hash(), root.get_value())
+                .unwrap());
+
+            let full = serde_json::to_vec(&proof).unwrap().len();
+            let small = serde_json::to_vec(&compressed).unwrap().len();
+            assert!(small * 2 < full, "{} bytes against {}", small, full);
+        }
+
+        //The table agrees with the tree's own empty subtrees
+        let table = empty_subtree_roots(DEFAULT_EXPONENT, 10).unwrap();
+        assert_eq!(
+            table[9],
+            tree.get_proof(0).unwrap().unwrap().get_path()[9].get_node()
+        );
+    }
+
+    #[test]
+    fn full_trees_and_other_exponents_round_trip() {
+        let leafs = users(6, 1, 0);
+        for exponent in [5, 7] {
+            let tree = MerkleSumTree::builder()
+                .mimc_exponent(exponent)
+                .build(leafs.clone())
+                .unwrap();
+            let root = tree.get_root().unwrap();
+            for index in 0..6 {
+                let proof = tree.get_proof(index).unwrap().unwrap();
+                let compressed = proof.compress().unwrap();
+                assert_eq!(compressed.decompress().unwrap(), proof);
+                assert!(tree.verify_proof(compressed.decompress().unwrap()).unwrap());
+                assert!(compressed
+                    .verify(root.get_hash(), root.get_value())
+                    .unwrap());
+            }
+            //Slots 6 and 7 are empty, the sibling of 4 and 5 one level up
+            let compressed = tree.get_proof(5).unwrap().unwrap().compress().unwrap();
+            assert_eq!(compressed.get_empty_levels(), vec![1]);
+        }
+    }
+
+    #[test]
+    fn malformed_compressed_proofs_are_invalid() {
+        let proof = sparse().get_proof(2).unwrap().unwrap();
+        let compressed = proof.compress().unwrap();
+        let invalid = |forged: CompressedProof| {
+            matches!(forged.decompress(), Err(MerkleError::InvalidProof(_)))
+        };
+        assert!(invalid(CompressedProof {
+            empty: compressed.empty | 1 << 10,
+            ..compressed.clone()
+        }));
+        assert!(invalid(CompressedProof {
+            index: 1024,
+            ..compressed.clone()
+        }));
+        assert!(invalid(CompressedProof {
+            empty: compressed.empty | 1,
+            ..compressed.clone()
+        }));
+        assert!(invalid(CompressedProof {
+            levels: MAX_HEIGHT,
+            ..compressed.clone()
+        }));
+        let mut extra = compressed.clone();
+        extra.siblings.push(empty_leaf().get_node());
+        assert!(invalid(extra));
+
+        let mut moved = proof;
+        moved.index = 3;
+        assert!(matches!(
+            moved.compress(),
+            Err(MerkleError::InvalidProof(_))
+        ));
+    }
+}
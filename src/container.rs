@@ -0,0 +1,126 @@
+//Versioned container for persisted blobs
+
+use crate::MerkleError;
+use std::io::{self, Read, Write};
+
+pub(crate) const MAGIC: &[u8; 4] = b"MSTC";
+pub(crate) const VERSION: u16 = 1;
+pub(crate) const HEADER_BYTES: usize = 15;
+
+//Payload kinds
+pub(crate) const KIND_CHECKPOINT: u8 = 1;
+pub(crate) const KIND_LOG_ENTRY: u8 = 2;
+
+pub(crate) fn write_container<W: Write>(
+    sink: &mut W,
+    kind: u8,
+    payload: &[u8],
+) -> Result<(), MerkleError> {
+    sink.write_all(MAGIC)?;
+    sink.write_all(&VERSION.to_le_bytes())?;
+    sink.write_all(&[kind])?;
+    sink.write_all(&(payload.len() as u64).to_le_bytes())?;
+    sink.write_all(payload)?;
+    sink.flush()?;
+    Ok(())
+}
+
+//Next container of kind, None at a clean end of stream
+pub(crate) fn read_container<R: Read>(
+    reader: &mut R,
+    kind: u8,
+) -> Result<Option<Vec<u8>>, MerkleError> {
+    let mut header = [0u8; HEADER_BYTES];
+    let mut filled = 0;
+    while filled < HEADER_BYTES {
+        let read = reader.read(header.get_mut(filled..).unwrap_or_default())?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    match filled {
+        0 => return Ok(None),
+        HEADER_BYTES => {}
+        _ => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    }
+    let (magic, rest) = header.split_at(4);
+    let (version, rest) = rest.split_at(2);
+    let (found_kind, length) = rest.split_at(1);
+    if magic != MAGIC.as_slice() {
+        return Err(MerkleError::InvalidContainer("bad magic".to_string()));
+    }
+    let mut version_bytes = [0u8; 2];
+    version_bytes.copy_from_slice(version);
+    let version = u16::from_le_bytes(version_bytes);
+    if version != VERSION {
+        return Err(MerkleError::InvalidContainer(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+    if found_kind != [kind].as_slice() {
+        return Err(MerkleError::InvalidContainer(format!(
+            "expected kind {}, found {:?}",
+            kind, found_kind
+        )));
+    }
+    let mut length_bytes = [0u8; 8];
+    length_bytes.copy_from_slice(length);
+    let length = usize::try_from(u64::from_le_bytes(length_bytes))
+        .map_err(|_| MerkleError::InvalidContainer("payload too large".to_string()))?;
+    let mut payload = vec![];
+    reader
+        .by_ref()
+        .take(length as u64)
+        .read_to_end(&mut payload)?;
+    if payload.len() != length {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn containers_round_trip_back_to_back() {
+        let mut bytes = vec![];
+        write_container(&mut bytes, KIND_CHECKPOINT, b"first").unwrap();
+        write_container(&mut bytes, KIND_CHECKPOINT, b"").unwrap();
+        assert_eq!(&bytes[0..4], b"MSTC");
+        assert_eq!(bytes.len(), 2 * HEADER_BYTES + 5);
+
+        let mut reader = bytes.as_slice();
+        assert_eq!(
+            read_container(&mut reader, KIND_CHECKPOINT).unwrap(),
+            Some(b"first".to_vec())
+        );
+        assert_eq!(
+            read_container(&mut reader, KIND_CHECKPOINT).unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(read_container(&mut reader, KIND_CHECKPOINT).unwrap(), None);
+
+        assert!(matches!(
+            read_container(&mut &bytes[..HEADER_BYTES + 2], KIND_CHECKPOINT),
+            Err(MerkleError::Io(_))
+        ));
+        assert!(matches!(
+            read_container(&mut &bytes[..3], KIND_CHECKPOINT),
+            Err(MerkleError::Io(_))
+        ));
+        assert!(matches!(
+            read_container(&mut bytes.as_slice(), 2),
+            Err(MerkleError::InvalidContainer(_))
+        ));
+        let mut future = bytes.clone();
+        future[4] = 2;
+        assert!(matches!(
+            read_container(&mut future.as_slice(), KIND_CHECKPOINT),
+            Err(MerkleError::InvalidContainer(_))
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//Readable forms of inclusion proofs, for logs and debugging
+
+use crate::{Fr, InclusionProof, Neighbor, Position};
+use std::fmt;
+
+//0x, the first and last 4 hex digits; short hashes in full
+fn abbreviate(hash: &Fr) -> String {
+    let hex = format!("{:x}", hash);
+    match (hex.get(..4), hex.len() > 8) {
+        (Some(head), true) => format!("0x{}…{}", head, hex.get(hex.len() - 4..).unwrap_or("")),
+        _ => format!("0x{}", hex),
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Position::Left => write!(f, "Left"),
+            Position::Right => write!(f, "Right"),
+        }
+    }
+}
+
+impl fmt::Display for Neighbor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sibling={} hash={} value={}",
+            self.position,
+            abbreviate(&self.node.hash),
+            self.node.value
+        )
+    }
+}
+
+impl fmt::Display for InclusionProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "leaf {} index={} hash={} value={}",
+            self.leaf.get_id_str(),
+            self.index,
+            abbreviate(&self.leaf.node.hash),
+            self.leaf.node.value
+        )?;
+        if let Ok(root) = self.compute_root() {
+            writeln!(
+                f,
+                "root hash={} value={}",
+                abbreviate(&root.hash),
+                root.value
+            )?;
+        }
+        for (level, neighbor) in self.path.iter().enumerate() {
+            writeln!(f, "level {}: {}", level, neighbor)?;
+        }
+        Ok(())
+    }
+}
+
+impl InclusionProof {
+    //Every hash in full, one row per level under a header row
+    pub fn to_debug_table(&self) -> String {
+        let mut table = String::from("level\tposition\thash\tvalue\n");
+        table.push_str(&format!(
+            "leaf\t{}\t{:#x}\t{}\n",
+            self.leaf.get_id_str(),
+            self.leaf.node.hash,
+            self.leaf.node.value
+        ));
+        for (level, neighbor) in self.path.iter().enumerate() {
+            table.push_str(&format!(
+                "{}\t{}\t{:#x}\t{}\n",
+                level, neighbor.position, neighbor.node.hash, neighbor.node.value
+            ));
+        }
+        if let Ok(root) = self.compute_root() {
+            table.push_str(&format!("root\t\t{:#x}\t{}\n", root.hash, root.value));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::{Leaf, MerkleSumTree, Node};
+
+    fn proof() -> InclusionProof {
+        MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap()
+        .get_proof(1)
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn one_line_per_level() {
+        let proof = proof();
+        let text = proof.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        let leaf_hash = format!("{:x}", proof.get_leaf().get_node().get_hash());
+        assert_eq!(
+            lines[0],
+            format!(
+                "leaf bob index=1 hash=0x{}…{} value=20",
+                &leaf_hash[..4],
+                &leaf_hash[leaf_hash.len() - 4..]
+            )
+        );
+        assert!(lines[1].starts_with("root hash=0x") && lines[1].ends_with(" value=60"));
+        assert!(lines[2].starts_with("level 0: sibling=Left hash=0x"));
+        assert!(lines[2].ends_with(" value=10"));
+        assert!(lines[3].starts_with("level 1: sibling=Right hash=0x"));
+        assert_eq!(Position::Right.to_string(), "Right");
+
+        let short = Neighbor::new(Position::Left, Node::new(Fr::from(0xabcu64), -3));
+        assert_eq!(short.to_string(), "sibling=Left hash=0xabc value=-3");
+    }
+
+    #[test]
+    fn the_table_keeps_full_hashes() {
+        let proof = proof();
+        let table = proof.to_debug_table();
+        let rows: Vec<Vec<&str>> = table.lines().map(|row| row.split('\t').collect()).collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], vec!["level", "position", "hash", "value"]);
+        assert_eq!(rows[1][1], "bob");
+        for (row, neighbor) in rows[2..4].iter().zip(proof.get_path()) {
+            assert_eq!(
+                row[2].parse::<Fr>().unwrap(),
+                neighbor.get_node().get_hash()
+            );
+        }
+        let root = proof.compute_root().unwrap();
+        assert_eq!(rows[4][2], format!("{:#x}", root.get_hash()));
+        assert_eq!(rows[4][3], "60");
+
+        //A path that does not fold shows no root
+        let mut broken = proof;
+        broken.index = 2;
+        assert_eq!(broken.to_string().lines().count(), 3);
+        assert!(!broken.to_debug_table().contains("root"));
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{LeafRule, RootCommitment};
+use std::fmt;
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MerkleError {
+    #[error("Index {0} out of bounds")]
+    IndexOutOfBounds(usize),
+    #[error("Slot {0} is retired and cannot be reused")]
+    SlotRetired(usize),
+    #[error("Slot {index} is already occupied by {id}")]
+    SlotOccupied { index: usize, id: String },
+    #[error("Tree too large: {requested_leaves} leafs requested, at most {max_leaves} supported")]
+    TreeTooLarge {
+        requested_leaves: usize,
+        max_leaves: usize,
+    },
+    #[error("Slot {0} is empty")]
+    EmptySlot(usize),
+    #[error("Invalid field element: {0}")]
+    InvalidFieldElement(String),
+    #[error("Sum overflow")]
+    Overflow,
+    #[error("Tree is empty")]
+    EmptyTree,
+    #[error("Node {0} missing from the tree")]
+    MissingNode(usize),
+    #[error("Id {0} appears more than once")]
+    DuplicateId(String),
+    #[error("Id {0} not found")]
+    UnknownId(String),
+    #[error("Cannot split into {0} shares")]
+    InvalidShareCount(usize),
+    #[error("Expected {expected} bytes, found {actual}")]
+    InvalidLength { expected: usize, actual: usize },
+    #[error("Path of {levels} levels is above the maximum of {max}")]
+    PathTooLong { levels: usize, max: usize },
+    #[error("Value {value} of {id} is above the cap {cap}")]
+    ValueAboveCap { id: String, value: i64, cap: i64 },
+    #[error("Value {value} of {id} is negative")]
+    NegativeValue { id: String, value: i64 },
+    #[error("No node at level {level} offset {offset}")]
+    InvalidCoordinates { level: usize, offset: usize },
+    #[error("Level of {0} nodes cannot be paired")]
+    OddLevel(usize),
+    #[error("Invalid tree: {} integrity violations", .0.len())]
+    InvalidTree(Vec<IntegrityViolation>),
+    #[error("Invalid container: {0}")]
+    InvalidContainer(String),
+    #[error("Log entry {0} does not chain onto the previous one")]
+    LogChainBroken(usize),
+    #[error("Replay diverged at entry {at_entry}: expected {expected}, found {actual:?}")]
+    ReplayDiverged {
+        at_entry: usize,
+        expected: RootCommitment,
+        actual: Option<RootCommitment>,
+    },
+    #[error("Chunk {chunk} (leafs {first_leaf}..{end}) diverged from the archive", end = first_leaf + leaf_count)]
+    ChunkDiverged {
+        chunk: usize,
+        first_leaf: usize,
+        leaf_count: usize,
+    },
+    #[error("Statement does not hold for these inputs")]
+    StatementNotSatisfied,
+    #[error("MiMC exponent {0} is not a permutation of this field")]
+    UnsupportedExponent(u64),
+    #[error("Invalid indices: {out_of_range:?} out of range, {duplicates:?} repeated")]
+    InvalidIndices {
+        out_of_range: Vec<usize>,
+        duplicates: Vec<usize>,
+    },
+    #[error("Node at level {level} offset {offset} is not the parent of its children")]
+    InconsistentNode { level: usize, offset: usize },
+    #[error("Invalid leaf {id}: {rule}")]
+    InvalidLeaf { id: String, rule: LeafRule },
+    #[error("Invalid proof: {0}")]
+    InvalidProof(String),
+    #[error("Tree is not in canonical order")]
+    NotCanonical,
+    #[error("Id {0} is in the tree")]
+    IdPresent(String),
+    #[error("Operation cancelled")]
+    Cancelled,
+    #[error("I/O error")]
+    Io(#[from] io::Error),
+    #[error("Serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl MerkleError {
+    //Stable machine-readable codes, part of the public contract
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            MerkleError::IndexOutOfBounds(_) => "E_INDEX_OUT_OF_BOUNDS",
+            MerkleError::SlotRetired(_) => "E_SLOT_RETIRED",
+            MerkleError::SlotOccupied { .. } => "E_SLOT_OCCUPIED",
+            MerkleError::TreeTooLarge { .. } => "E_TREE_TOO_LARGE",
+            MerkleError::EmptySlot(_) => "E_EMPTY_SLOT",
+            MerkleError::InvalidFieldElement(_) => "E_INVALID_FIELD_ELEMENT",
+            MerkleError::Overflow => "E_OVERFLOW",
+            MerkleError::EmptyTree => "E_EMPTY_TREE",
+            MerkleError::MissingNode(_) => "E_MISSING_NODE",
+            MerkleError::DuplicateId(_) => "E_DUPLICATE_ID",
+            MerkleError::UnknownId(_) => "E_UNKNOWN_ID",
+            MerkleError::InvalidShareCount(_) => "E_INVALID_SHARE_COUNT",
+            MerkleError::InvalidLength { .. } => "E_INVALID_LENGTH",
+            MerkleError::PathTooLong { .. } => "E_PATH_TOO_LONG",
+            MerkleError::ValueAboveCap { .. } => "E_VALUE_ABOVE_CAP",
+            MerkleError::NegativeValue { .. } => "E_NEGATIVE_VALUE",
+            MerkleError::InvalidCoordinates { .. } => "E_INVALID_COORDINATES",
+            MerkleError::OddLevel(_) => "E_ODD_LEVEL",
+            MerkleError::InvalidTree(_) => "E_INVALID_TREE",
+            MerkleError::InvalidContainer(_) => "E_INVALID_CONTAINER",
+            MerkleError::LogChainBroken(_) => "E_LOG_CHAIN_BROKEN",
+            MerkleError::ReplayDiverged { .. } => "E_REPLAY_DIVERGED",
+            MerkleError::ChunkDiverged { .. } => "E_CHUNK_DIVERGED",
+            MerkleError::StatementNotSatisfied => "E_STATEMENT_NOT_SATISFIED",
+            MerkleError::UnsupportedExponent(_) => "E_UNSUPPORTED_EXPONENT",
+            MerkleError::InvalidIndices { .. } => "E_INVALID_INDICES",
+            MerkleError::InconsistentNode { .. } => "E_INCONSISTENT_NODE",
+            MerkleError::InvalidLeaf { .. } => "E_INVALID_LEAF",
+            MerkleError::InvalidProof(_) => "E_INVALID_PROOF",
+            MerkleError::NotCanonical => "E_NOT_CANONICAL",
+            MerkleError::IdPresent(_) => "E_ID_PRESENT",
+            MerkleError::Cancelled => "E_CANCELLED",
+            MerkleError::Io(_) => "E_IO",
+            MerkleError::Serialization(_) => "E_SERIALIZATION",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityViolation {
+    HeightMismatch { height: usize, leaf_count: usize },
+    NodeCountMismatch { expected: usize, actual: usize },
+    NodeMismatch(usize),
+    ZeroIndexNotSorted(usize),
+    ZeroIndexOccupied(usize),
+    ZeroIndexMissing(usize),
+    RetiredOccupied(usize),
+    DummyMismatch(usize),
+    NextSlotOutOfRange(usize),
+    LeafHashMismatch(usize),
+    ValueAboveCap { index: usize, value: i64, cap: i64 },
+    NegativeValue { index: usize, value: i64 },
+    //The id index lacks the slot of a leaf, or lists a slot not holding the id
+    IdIndexMissing { index: usize, id: String },
+    IdIndexStale { index: usize, id: String },
+    RootSumMismatch { expected: i64, actual: Option<i32> },
+}
+
+impl fmt::Display for IntegrityViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntegrityViolation::HeightMismatch { height, leaf_count } => {
+                write!(f, "Height {} does not match {} leafs", height, leaf_count)
+            }
+            IntegrityViolation::NodeCountMismatch { expected, actual } => {
+                write!(f, "Expected {} nodes, found {}", expected, actual)
+            }
+            IntegrityViolation::NodeMismatch(index) => {
+                write!(f, "Node {} does not match its recomputation", index)
+            }
+            IntegrityViolation::ZeroIndexNotSorted(position) => {
+                write!(f, "Zero index is not sorted at position {}", position)
+            }
+            IntegrityViolation::ZeroIndexOccupied(index) => {
+                write!(f, "Zero index lists non-empty leaf {}", index)
+            }
+            IntegrityViolation::ZeroIndexMissing(index) => {
+                write!(f, "Zero index is missing empty leaf {}", index)
+            }
+            IntegrityViolation::RetiredOccupied(index) => {
+                write!(f, "Retired slot {} holds a leaf", index)
+            }
+            IntegrityViolation::DummyMismatch(index) => {
+                write!(f, "Dummy slot {} does not hold its derived padding", index)
+            }
+            IntegrityViolation::NextSlotOutOfRange(next_slot) => {
+                write!(f, "Next slot {} is past the leaf layer", next_slot)
+            }
+            IntegrityViolation::LeafHashMismatch(index) => {
+                write!(
+                    f,
+                    "Leaf {} hash does not match the tree's leaf hash mode",
+                    index
+                )
+            }
+            IntegrityViolation::ValueAboveCap { index, value, cap } => {
+                write!(f, "Leaf {} holds {}, above the cap {}", index, value, cap)
+            }
+            IntegrityViolation::NegativeValue { index, value } => {
+                write!(f, "Leaf {} holds the negative value {}", index, value)
+            }
+            IntegrityViolation::IdIndexMissing { index, id } => {
+                write!(f, "Id index is missing {} at leaf {}", id, index)
+            }
+            IntegrityViolation::IdIndexStale { index, id } => {
+                write!(
+                    f,
+                    "Id index lists {} at leaf {}, which holds another",
+                    id, index
+                )
+            }
+            IntegrityViolation::RootSumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "Root sum {:?} does not match leaf total {}",
+                    actual, expected
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn error_codes_and_sources() {
+        let err = MerkleError::TreeTooLarge {
+            requested_leaves: 5,
+            max_leaves: 4,
+        };
+        assert_eq!(err.error_code(), "E_TREE_TOO_LARGE");
+        assert_eq!(
+            err.to_string(),
+            "Tree too large: 5 leafs requested, at most 4 supported"
+        );
+        assert!(err.source().is_none());
+
+        let err = MerkleError::from(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated"));
+        assert_eq!(err.error_code(), "E_IO");
+        assert_eq!(err.source().unwrap().to_string(), "truncated");
+
+        let err = MerkleError::from(serde_json::from_str::<u32>("nope").unwrap_err());
+        assert_eq!(err.error_code(), "E_SERIALIZATION");
+        assert!(err.source().unwrap().is::<serde_json::Error>());
+
+        assert_eq!(
+            MerkleError::SlotOccupied {
+                index: 2,
+                id: "alice".to_string()
+            }
+            .to_string(),
+            "Slot 2 is already occupied by alice"
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//Construction for inputs larger than memory
+
+use crate::layout::path;
+use crate::{
+    empty_leaf, Endianness, Fr, Leaf, MerkleError, MerkleSumTree, Neighbor, Node, Phase, Progress,
+    ProgressCallback, MAX_HEIGHT,
+};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const NODE_BYTES: u64 = 36;
+
+pub struct ExternalOptions {
+    dir: PathBuf,
+    chunk_size: usize,
+    endianness: Endianness,
+    progress: Option<ProgressCallback>,
+}
+
+//A tree whose levels live in files under dir
+#[derive(Debug, Clone)]
+pub struct ExternalTree {
+    dir: PathBuf,
+    leaf_count: u64,
+    height: usize,
+    endianness: Endianness,
+    root: Node,
+}
+
+impl ExternalOptions {
+    pub fn new(dir: PathBuf) -> ExternalOptions {
+        ExternalOptions {
+            dir,
+            chunk_size: 1 << 16,
+            endianness: Endianness::default(),
+            progress: None,
+        }
+    }
+
+    //Nodes held in memory at once, the resident budget, rounded down to pairs
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(2) & !1;
+        self
+    }
+
+    //Byte order of the level files
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    //Report the nodes written so far after every chunk, leafs without a total
+    pub fn on_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn report(&mut self, phase: Phase, written: u64, total: Option<u64>) {
+        if let Some(progress) = self.progress.as_mut() {
+            progress(Progress::new(phase, written, total));
+        }
+    }
+}
+
+impl MerkleSumTree {
+    pub fn build_external<R: Read>(
+        reader: R,
+        mut opts: ExternalOptions,
+    ) -> Result<ExternalTree, MerkleError> {
+        fs::create_dir_all(&opts.dir)?;
+        let mut sink = BufWriter::new(File::create(level_path(&opts.dir, 0))?);
+        let mut chunk: Vec<Node> = Vec::with_capacity(opts.chunk_size);
+        let mut written = 0u64;
+        for (number, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            chunk.push(parse_record(&line, number)?.get_node());
+            if chunk.len() == opts.chunk_size {
+                written += write_nodes(&mut sink, &chunk, opts.endianness)?;
+                chunk.clear();
+                opts.report(Phase::LeafHashing, written, None);
+            }
+        }
+        let filled = written + chunk.len() as u64;
+        let leaf_count = match filled.max(1).checked_next_power_of_two() {
+            Some(power) if power.trailing_zeros() < MAX_HEIGHT as u32 => power,
+            _ => {
+                return Err(MerkleError::TreeTooLarge {
+                    requested_leaves: usize::try_from(filled).unwrap_or(usize::MAX),
+                    max_leaves: 1 << (MAX_HEIGHT - 1),
+                })
+            }
+        };
+        let empty = empty_leaf().get_node();
+        for _ in filled..leaf_count {
+            chunk.push(empty.clone());
+            if chunk.len() == opts.chunk_size {
+                written += write_nodes(&mut sink, &chunk, opts.endianness)?;
+                chunk.clear();
+                opts.report(Phase::LeafHashing, written, None);
+            }
+        }
+        written += write_nodes(&mut sink, &chunk, opts.endianness)?;
+        sink.flush()?;
+        opts.report(Phase::LeafHashing, written, None);
+
+        let height = leaf_count.trailing_zeros() as usize + 1;
+        for level in 1..height {
+            merge_level(&mut opts, level, height, leaf_count)?;
+        }
+        let root = read_node(&opts.dir, height - 1, 0, opts.endianness)?;
+        Ok(ExternalTree {
+            dir: opts.dir,
+            leaf_count,
+            height,
+            endianness: opts.endianness,
+            root,
+        })
+    }
+}
+
+impl ExternalTree {
+    pub fn get_root(&self) -> Node {
+        self.root.clone()
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    //Size of the leaf layer, padding included
+    pub fn get_leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
+
+    pub fn get_dir(&self) -> PathBuf {
+        self.dir.clone()
+    }
+
+    pub fn get_endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    pub fn get_node(&self, level: usize, offset: u64) -> Result<Node, MerkleError> {
+        if level >= self.height || offset >= self.leaf_count >> level {
+            return Err(MerkleError::InvalidCoordinates {
+                level,
+                offset: usize::try_from(offset).unwrap_or(usize::MAX),
+            });
+        }
+        read_node(&self.dir, level, offset, self.endianness)
+    }
+
+    //Neighbors of the leaf at index, leaf to root, one seek per level
+    pub fn get_path(&self, index: u64) -> Result<Vec<Neighbor>, MerkleError> {
+        let offset = usize::try_from(index).unwrap_or(usize::MAX);
+        if index >= self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds(offset));
+        }
+        path(self.leaf_count as usize, offset)
+            .iter()
+            .enumerate()
+            .map(|(level, step)| {
+                let sibling = ((offset >> level) ^ 1) as u64;
+                Ok(Neighbor::new(step.position, self.get_node(level, sibling)?))
+            })
+            .collect()
+    }
+}
+
+fn level_path(dir: &Path, level: usize) -> PathBuf {
+    dir.join(format!("level_{}.bin", level))
+}
+
+fn parse_record(line: &str, number: usize) -> Result<Leaf, MerkleError> {
+    let (id, value) = line.rsplit_once(',').ok_or_else(|| {
+        MerkleError::InvalidContainer(format!("record {} is not id,value", number))
+    })?;
+    let value = value.trim().parse().map_err(|_| {
+        MerkleError::InvalidContainer(format!("record {} has no i32 value", number))
+    })?;
+    Ok(Leaf::new(id.to_string(), value))
+}
+
+fn write_nodes<W: Write>(
+    sink: &mut W,
+    nodes: &[Node],
+    endianness: Endianness,
+) -> Result<u64, MerkleError> {
+    for node in nodes {
+        sink.write_all(&node.hash.to_bytes(endianness))?;
+        match endianness {
+            Endianness::Little => sink.write_all(&node.value.to_le_bytes())?,
+            Endianness::Big => sink.write_all(&node.value.to_be_bytes())?,
+        }
+    }
+    Ok(nodes.len() as u64)
+}
+
+fn decode_node(
+    bytes: &[u8; NODE_BYTES as usize],
+    endianness: Endianness,
+) -> Result<Node, MerkleError> {
+    let (hash_bytes, value_bytes) = bytes.split_at(32);
+    let hash = Fr::from_bytes(hash_bytes, endianness)
+        .map_err(|_| MerkleError::InvalidContainer("node hash above the modulus".to_string()))?;
+    let mut value = [0u8; 4];
+    value.copy_from_slice(value_bytes);
+    let value = match endianness {
+        Endianness::Little => i32::from_le_bytes(value),
+        Endianness::Big => i32::from_be_bytes(value),
+    };
+    Ok(Node::new(hash, value))
+}
+
+fn read_node(
+    dir: &Path,
+    level: usize,
+    offset: u64,
+    endianness: Endianness,
+) -> Result<Node, MerkleError> {
+    let mut file = File::open(level_path(dir, level))?;
+    file.seek(SeekFrom::Start(offset * NODE_BYTES))?;
+    let mut bytes = [0u8; NODE_BYTES as usize];
+    file.read_exact(&mut bytes)?;
+    decode_node(&bytes, endianness)
+}
+
+//Hash the level below pairwise into level, chunk_size nodes at a time
+fn merge_level(
+    opts: &mut ExternalOptions,
+    level: usize,
+    height: usize,
+    leaf_count: u64,
+) -> Result<(), MerkleError> {
+    let phase = Phase::Level { level, height };
+    let mut source = BufReader::new(File::open(level_path(&opts.dir, level - 1))?);
+    let mut sink = BufWriter::new(File::create(level_path(&opts.dir, level))?);
+    let mut chunk: Vec<Node> = Vec::with_capacity(opts.chunk_size);
+    let mut bytes = [0u8; NODE_BYTES as usize];
+    let mut written = 0u64;
+    loop {
+        let done = match source.read_exact(&mut bytes) {
+            Ok(()) => {
+                chunk.push(decode_node(&bytes, opts.endianness)?);
+                false
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => true,
+            Err(err) => return Err(err.into()),
+        };
+        if chunk.len() == opts.chunk_size || (done && !chunk.is_empty()) {
+            let mut parents = Vec::with_capacity(chunk.len() / 2);
+            for pair in chunk.chunks(2) {
+                match pair {
+                    [left, right] => {
+                        parents.push(MerkleSumTree::build_parent(left.clone(), right.clone())?)
+                    }
+                    _ => return Err(MerkleError::OddLevel(chunk.len())),
+                }
+            }
+            chunk.clear();
+            written += write_nodes(&mut sink, &parents, opts.endianness)?;
+            opts.report(phase, written, Some(leaf_count >> level));
+        }
+        if done {
+            break;
+        }
+    }
+    sink.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mst-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn records(count: i32) -> (String, Vec<Leaf>) {
+        let leafs: Vec<Leaf> = (0..count)
+            .map(|i| Leaf::new(format!("user,{}", i), i * 7 - 300))
+            .collect();
+        let text = leafs
+            .iter()
+            .map(|leaf| format!("{},{}\n", leaf.get_id(), leaf.get_node().get_value()))
+            .collect();
+        (text, leafs)
+    }
+
+    #[test]
+    fn external_builds_match_in_memory_builds() {
+        for count in [0, 1, 2, 5, 100] {
+            let dir = scratch(&format!("match-{}", count));
+            let (text, leafs) = records(count);
+            let tree = MerkleSumTree::new(leafs).unwrap();
+            let external = MerkleSumTree::build_external(
+                text.as_bytes(),
+                ExternalOptions::new(dir.clone()).chunk_size(8),
+            )
+            .unwrap();
+            assert_eq!(Some(external.get_root()), tree.get_root());
+            assert_eq!(external.get_height(), tree.get_height());
+            assert_eq!(external.get_leaf_count(), tree.get_leafs().len() as u64);
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn external_paths_prove_membership() {
+        let dir = scratch("paths");
+        let (text, leafs) = records(100);
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        let progress = Arc::new(Mutex::new(vec![]));
+        let seen = Arc::clone(&progress);
+        let external = MerkleSumTree::build_external(
+            text.as_bytes(),
+            ExternalOptions::new(dir.clone())
+                .chunk_size(16)
+                .on_progress(Box::new(move |progress| {
+                    seen.lock().unwrap().push(progress)
+                })),
+        )
+        .unwrap();
+        let progress = progress.lock().unwrap().clone();
+        assert_eq!(
+            progress.first(),
+            Some(&Progress::new(Phase::LeafHashing, 16, None))
+        );
+        assert!(progress.contains(&Progress::new(Phase::LeafHashing, 128, None)));
+        assert_eq!(
+            progress.last(),
+            Some(&Progress::new(
+                Phase::Level {
+                    level: 7,
+                    height: 8
+                },
+                1,
+                Some(1)
+            ))
+        );
+
+        let root = tree.get_commitment().unwrap();
+        for index in [0u64, 37, 99] {
+            let path = external.get_path(index).unwrap();
+            assert_eq!(
+                path,
+                tree.get_proof(index as usize).unwrap().unwrap().get_path()
+            );
+            let leaf = external.get_node(0, index).unwrap();
+            assert!(MerkleSumTree::verify_membership_by_hash(
+                &root,
+                leaf.get_hash(),
+                i64::from(leaf.get_value()),
+                &path
+            )
+            .unwrap());
+        }
+        assert!(external.get_path(128).is_err());
+        assert!(external.get_node(7, 1).is_err());
+
+        //Big-endian level files hold the same tree
+        let big_dir = scratch("paths-big");
+        let big = MerkleSumTree::build_external(
+            text.as_bytes(),
+            ExternalOptions::new(big_dir.clone())
+                .chunk_size(16)
+                .endianness(Endianness::Big),
+        )
+        .unwrap();
+        assert_eq!(big.get_root(), external.get_root());
+        assert_eq!(big.get_path(37).unwrap(), external.get_path(37).unwrap());
+        let little_file = fs::read(level_path(&dir, 0)).unwrap();
+        let big_file = fs::read(level_path(&big_dir, 0)).unwrap();
+        let leaf = &tree.get_leafs()[1];
+        let mut hash = leaf.get_node().get_hash().to_bytes(Endianness::Big);
+        assert_eq!(big_file[36..68], hash);
+        assert_eq!(big_file[68..72], leaf.get_node().get_value().to_be_bytes());
+        hash.reverse();
+        assert_eq!(little_file[36..68], hash);
+        fs::remove_dir_all(big_dir).unwrap();
+
+        let bad = MerkleSumTree::build_external(
+            "alice,10\nbob\n".as_bytes(),
+            ExternalOptions::new(dir.clone()),
+        );
+        assert!(matches!(bad, Err(MerkleError::InvalidContainer(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+//Canonical text and byte forms of field elements
+
+use crate::error::MerkleError;
+use crate::mimc_sponge::{Fr, FrRepr};
+use ff::PrimeField;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+//Largest power of ten below 2^64, decimal digits are peeled 19 at a time
+const DECIMAL_CHUNK: u128 = 10_000_000_000_000_000_000;
+
+fn to_limbs(value: &Fr) -> [u64; 4] {
+    let repr = value.to_repr();
+    let mut limbs = [0u64; 4];
+    for (limb, chunk) in limbs.iter_mut().zip(repr.as_ref().chunks_exact(8)) {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(chunk);
+        *limb = u64::from_le_bytes(bytes);
+    }
+    limbs
+}
+
+//None when the integer is not below the modulus
+fn from_limbs(limbs: [u64; 4]) -> Option<Fr> {
+    let mut repr = FrRepr::default();
+    for (chunk, limb) in repr.as_mut().chunks_exact_mut(8).zip(limbs.iter()) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+    Option::from(Fr::from_repr(repr))
+}
+
+//Accumulate digits into 256 bits, None on a bad digit or overflow
+fn parse_radix(digits: &str, radix: u32) -> Option<[u64; 4]> {
+    if digits.is_empty() {
+        return None;
+    }
+    let mut limbs = [0u64; 4];
+    for c in digits.chars() {
+        let mut carry = u128::from(c.to_digit(radix)?);
+        for limb in limbs.iter_mut() {
+            let current = u128::from(*limb) * u128::from(radix) + carry;
+            *limb = current as u64;
+            carry = current >> 64;
+        }
+        if carry != 0 {
+            return None;
+        }
+    }
+    Some(limbs)
+}
+
+fn hex_digits(value: &Fr, upper: bool) -> String {
+    let repr = value.to_repr();
+    let digits: String = repr
+        .as_ref()
+        .iter()
+        .rev()
+        .map(|byte| match upper {
+            true => format!("{:02X}", byte),
+            false => format!("{:02x}", byte),
+        })
+        .collect();
+    match digits.trim_start_matches('0') {
+        "" => "0".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+impl Fr {
+    pub fn to_bytes(&self, endianness: Endianness) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(self.to_repr().as_ref());
+        if endianness == Endianness::Big {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8], endianness: Endianness) -> Result<Fr, MerkleError> {
+        let mut repr = FrRepr::default();
+        if bytes.len() != repr.as_ref().len() {
+            return Err(MerkleError::InvalidLength {
+                expected: repr.as_ref().len(),
+                actual: bytes.len(),
+            });
+        }
+        repr.as_mut().copy_from_slice(bytes);
+        if endianness == Endianness::Big {
+            repr.as_mut().reverse();
+        }
+        Option::from(Fr::from_repr(repr)).ok_or_else(|| {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            MerkleError::InvalidFieldElement(format!("{:?} bytes {}", endianness, hex))
+        })
+    }
+}
+
+impl fmt::Display for Fr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut limbs = to_limbs(self);
+        let mut chunks = vec![];
+        while limbs.iter().any(|limb| *limb != 0) {
+            let mut remainder: u128 = 0;
+            for limb in limbs.iter_mut().rev() {
+                let current = (remainder << 64) | u128::from(*limb);
+                *limb = (current / DECIMAL_CHUNK) as u64;
+                remainder = current % DECIMAL_CHUNK;
+            }
+            chunks.push(remainder as u64);
+        }
+        let mut digits = match chunks.pop() {
+            Some(chunk) => chunk.to_string(),
+            None => "0".to_string(),
+        };
+        for chunk in chunks.iter().rev() {
+            digits.push_str(&format!("{:019}", chunk));
+        }
+        f.pad_integral(true, "", &digits)
+    }
+}
+
+impl fmt::LowerHex for Fr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad_integral(true, "0x", &hex_digits(self, false))
+    }
+}
+
+impl fmt::UpperHex for Fr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad_integral(true, "0x", &hex_digits(self, true))
+    }
+}
+
+impl FromStr for Fr {
+    type Err = MerkleError;
+
+    //Decimal, or hex with a 0x prefix, values must be below the modulus
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let limbs = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(digits) => parse_radix(digits, 16),
+            None => parse_radix(s, 10),
+        };
+        limbs
+            .and_then(from_limbs)
+            .ok_or_else(|| MerkleError::InvalidFieldElement(s.to_string()))
+    }
+}
+
+impl Serialize for Fr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Fr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use ff::Field;
+
+    const P_MINUS_ONE: &str =
+        "28948022309329048855892746252171976963363056481941647379679742748393362948096";
+    const P_MINUS_ONE_HEX: &str =
+        "40000000000000000000000000000000224698fc0994a8dd8c46eb2100000000";
+
+    #[test]
+    fn canonical_formats() {
+        let max = -Fr::ONE;
+        assert_eq!(Fr::ZERO.to_string(), "0");
+        assert_eq!(Fr::ONE.to_string(), "1");
+        assert_eq!(max.to_string(), P_MINUS_ONE);
+        assert_eq!(
+            Fr::from(10_000_000_000_000_000_000u64).to_string(),
+            "10000000000000000000"
+        );
+
+        assert_eq!(format!("{:x}", Fr::ZERO), "0");
+        assert_eq!(format!("{:#x}", Fr::ONE), "0x1");
+        assert_eq!(format!("{:x}", max), P_MINUS_ONE_HEX);
+        assert_eq!(format!("{:X}", max), P_MINUS_ONE_HEX.to_uppercase());
+    }
+
+    #[test]
+    fn parse_decimal_and_hex() {
+        let max = -Fr::ONE;
+        assert_eq!("0".parse::<Fr>().unwrap(), Fr::ZERO);
+        assert_eq!("1".parse::<Fr>().unwrap(), Fr::ONE);
+        assert_eq!(P_MINUS_ONE.parse::<Fr>().unwrap(), max);
+        assert_eq!(format!("0x{}", P_MINUS_ONE_HEX).parse::<Fr>().unwrap(), max);
+        assert_eq!("0x0001".parse::<Fr>().unwrap(), Fr::ONE);
+
+        let modulus =
+            "28948022309329048855892746252171976963363056481941647379679742748393362948097";
+        let too_long = "9".repeat(90);
+        for bad in [
+            "",
+            "0x",
+            "-1",
+            "+1",
+            "1.5",
+            "0xg",
+            modulus,
+            too_long.as_str(),
+        ] {
+            assert!(matches!(
+                bad.parse::<Fr>(),
+                Err(MerkleError::InvalidFieldElement(_))
+            ));
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn byte_forms_under_each_endianness() {
+        //Root of alice 10, bob 20, carol 30, as each order exports it
+        let root: Fr =
+            "23086246209329638428905147697824522265071079370733918444515595950348334843825"
+                .parse()
+                .unwrap();
+        let little = root.to_bytes(Endianness::Little);
+        let big = root.to_bytes(Endianness::Big);
+        assert_eq!(
+            hex(&little),
+            "b1c7f02961bb697903a69876367aa24735886f936d833c36efdcfa5b305a0a33"
+        );
+        assert_eq!(
+            hex(&big),
+            "330a5a305bfadcef363c836d936f883547a27a367698a6037969bb6129f0c7b1"
+        );
+        assert_eq!(Endianness::default(), Endianness::Little);
+        assert_eq!(little.as_slice(), root.to_repr().as_ref());
+        assert_eq!(format!("{:x}", root), hex(&big));
+        for endianness in [Endianness::Little, Endianness::Big] {
+            for value in [Fr::ZERO, Fr::ONE, -Fr::ONE, root] {
+                let bytes = value.to_bytes(endianness);
+                assert_eq!(Fr::from_bytes(&bytes, endianness).unwrap(), value);
+            }
+        }
+
+        //Bytes read under the other order are either another element or refused
+        assert!(matches!(
+            Fr::from_bytes(&big, Endianness::Little),
+            Err(MerkleError::InvalidFieldElement(_))
+        ));
+        let one = Fr::from_bytes(&Fr::ONE.to_bytes(Endianness::Big), Endianness::Little);
+        assert_ne!(one.unwrap(), Fr::ONE);
+        let mut modulus = (-Fr::ONE).to_bytes(Endianness::Big);
+        modulus[31] += 1;
+        assert!(Fr::from_bytes(&modulus, Endianness::Big).is_err());
+        modulus.reverse();
+        assert!(Fr::from_bytes(&modulus, Endianness::Little).is_err());
+        assert!(matches!(
+            Fr::from_bytes(&big[1..], Endianness::Big),
+            Err(MerkleError::InvalidLength {
+                expected: 32,
+                actual: 31
+            })
+        ));
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let value = Fr::from(123456789u64);
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, "\"123456789\"");
+        assert_eq!(serde_json::from_str::<Fr>(&json).unwrap(), value);
+        assert_eq!(serde_json::from_str::<Fr>("\"0x75bcd15\"").unwrap(), value);
+    }
+}
@@ -0,0 +1,142 @@
+//Arbitrary values for fuzzing and property tests, with the arbitrary feature
+
+use crate::{Fr, InclusionProof, Leaf, MerkleSumTree, Node, Position};
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+const ID_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+//Bounds of generated trees, MAX_LEAFS and MAX_ID_LEN by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArbitrarySize {
+    pub max_leafs: usize,
+    pub max_id_len: usize,
+}
+
+impl ArbitrarySize {
+    pub const MAX_LEAFS: usize = 16;
+    pub const MAX_ID_LEN: usize = 8;
+}
+
+impl Default for ArbitrarySize {
+    fn default() -> ArbitrarySize {
+        ArbitrarySize {
+            max_leafs: ArbitrarySize::MAX_LEAFS,
+            max_id_len: ArbitrarySize::MAX_ID_LEN,
+        }
+    }
+}
+
+impl<'a> Arbitrary<'a> for Position {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.arbitrary::<bool>()? {
+            true => Position::Left,
+            false => Position::Right,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Node {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Node::new(Fr::from(u.arbitrary::<u64>()?), u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Leaf {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        arbitrary_leaf(u, ArbitrarySize::default())
+    }
+}
+
+impl<'a> Arbitrary<'a> for MerkleSumTree {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        MerkleSumTree::arbitrary_with(u, ArbitrarySize::default())
+    }
+}
+
+//A proof of an occupied slot of an arbitrary tree, so its path folds to that root
+impl<'a> Arbitrary<'a> for InclusionProof {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let tree = MerkleSumTree::arbitrary_with(u, ArbitrarySize::default())?;
+        let occupied: Vec<usize> = tree.non_empty_leafs(true).map(|(index, _)| index).collect();
+        let index = match occupied.as_slice() {
+            [] => 0,
+            slots => *u.choose(slots)?,
+        };
+        tree.get_proof_allow_empty(index)
+            .ok()
+            .flatten()
+            .ok_or(arbitrary::Error::IncorrectFormat)
+    }
+}
+
+impl MerkleSumTree {
+    //A tree of 1 to size.max_leafs leafs, some of them removed again
+    pub fn arbitrary_with(u: &mut Unstructured, size: ArbitrarySize) -> Result<MerkleSumTree> {
+        let count = u.int_in_range(1..=size.max_leafs.max(1))?;
+        let leafs = (0..count)
+            .map(|_| arbitrary_leaf(u, size))
+            .collect::<Result<Vec<Leaf>>>()?;
+        let mut tree = MerkleSumTree::new(leafs).map_err(|_| arbitrary::Error::IncorrectFormat)?;
+        for index in 0..count {
+            if u.ratio(1, 4)? {
+                tree.remove(index)
+                    .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+            }
+        }
+        Ok(tree)
+    }
+}
+
+//Values within 2^20 either way, so up to 2^10 leafs cannot overflow a sum
+fn arbitrary_leaf(u: &mut Unstructured, size: ArbitrarySize) -> Result<Leaf> {
+    let len = u.int_in_range(1..=size.max_id_len.max(1))?;
+    let id = (0..len)
+        .map(|_| u.choose(ID_CHARS).map(|byte| char::from(*byte)))
+        .collect::<Result<String>>()?;
+    Ok(Leaf::new(id, u.int_in_range(-(1 << 20)..=1 << 20)?))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    #[test]
+    fn arbitrary_trees_validate() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut sizes = vec![];
+        for _ in 0..300 {
+            let bytes: Vec<u8> = (0..rng.gen_range(0, 512)).map(|_| rng.gen()).collect();
+            let mut u = Unstructured::new(&bytes);
+            let tree = MerkleSumTree::arbitrary(&mut u).unwrap();
+            assert_eq!(tree.validate(), Ok(()));
+            sizes.push(tree.get_leafs().len());
+
+            let mut u = Unstructured::new(&bytes);
+            let proof = InclusionProof::arbitrary(&mut u).unwrap();
+            assert_eq!(proof.compute_root().unwrap(), tree.get_root().unwrap());
+        }
+        assert!(sizes.iter().any(|size| *size == 1));
+        assert!(sizes.iter().any(|size| *size == ArbitrarySize::MAX_LEAFS));
+    }
+
+    #[test]
+    fn sizes_bound_the_output() {
+        let bytes = [0xffu8; 4096];
+        let size = ArbitrarySize {
+            max_leafs: 5,
+            max_id_len: 3,
+        };
+        let tree = MerkleSumTree::arbitrary_with(&mut Unstructured::new(&bytes), size).unwrap();
+        assert!(tree.get_leafs().len() <= 8);
+        for leaf in tree.get_leafs() {
+            assert!(leaf.get_id().len() <= 3);
+        }
+        //Exhausted input shrinks to the smallest tree
+        let tree = MerkleSumTree::arbitrary(&mut Unstructured::new(&[])).unwrap();
+        assert_eq!(tree.get_leafs().len(), 1);
+        assert!(Position::arbitrary(&mut Unstructured::new(&[])).is_ok());
+        assert!(Node::arbitrary(&mut Unstructured::new(&[1; 12])).is_ok());
+    }
+}
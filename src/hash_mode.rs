@@ -0,0 +1,158 @@
+//How a leaf's hash is derived from its id and value
+
+use crate::{value_to_fr, Fr, Leaf, MimcSponge, Node};
+use ff::Field;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LeafHashMode {
+    #[default]
+    IdOnly,
+    IdAndValue,
+}
+
+impl LeafHashMode {
+    pub fn leaf(&self, id: String, value: i32) -> Leaf {
+        let id_only = Leaf::new(id, value);
+        match self {
+            LeafHashMode::IdOnly => id_only,
+            LeafHashMode::IdAndValue => {
+                let arr = [id_only.node.hash, value_to_fr(value)];
+                let hash = MimcSponge::default().single_hash(&arr, Fr::ZERO);
+                Leaf {
+                    id: id_only.id,
+                    node: Node::new(hash, value),
+                }
+            }
+        }
+    }
+
+    //Whether the leaf hash is the one this mode derives from its id and value
+    pub fn matches(&self, leaf: &Leaf) -> bool {
+        self.leaf(leaf.get_id(), leaf.node.value) == *leaf
+    }
+
+    //Whether a tree in this mode accepts the stored leaf; IdOnly takes any hash
+    pub(crate) fn accepts(&self, leaf: &Leaf) -> bool {
+        match self {
+            LeafHashMode::IdOnly => true,
+            LeafHashMode::IdAndValue => self.matches(leaf),
+        }
+    }
+
+    //The leaf as a tree in this mode stores it; IdOnly keeps hashes as given
+    pub(crate) fn normalize(&self, leaf: Leaf) -> Leaf {
+        match self {
+            LeafHashMode::IdOnly => leaf,
+            LeafHashMode::IdAndValue => self.leaf(leaf.get_id(), leaf.node.value),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use crate::{IntegrityViolation, MerkleSumTree};
+
+    fn leafs() -> Vec<Leaf> {
+        accounts(&[10, 20, 30])
+    }
+
+    #[test]
+    fn leaf_hash_vectors() {
+        let id_only = LeafHashMode::IdOnly.leaf("alice".to_string(), 10);
+        assert_eq!(id_only, Leaf::new("alice".to_string(), 10));
+        assert_eq!(
+            id_only.get_node().get_hash().to_string(),
+            "200341070530370144"
+        );
+        let bound = LeafHashMode::IdAndValue.leaf("alice".to_string(), 10);
+        assert_eq!(
+            bound.get_node().get_hash().to_string(),
+            "20060795592170112291775406267337601855757157707088853558970254837893712732439"
+        );
+        assert_ne!(
+            LeafHashMode::IdAndValue.leaf("alice".to_string(), 11),
+            LeafHashMode::IdAndValue.leaf("alice".to_string(), -11)
+        );
+
+        let tree = MerkleSumTree::builder()
+            .leaf_hash_mode(LeafHashMode::IdAndValue)
+            .build(leafs())
+            .unwrap();
+        assert_eq!(
+            tree.get_root_hash().unwrap().to_string(),
+            "274242491697016996096308307793841117884195874614575252876659869248045167811"
+        );
+        assert_eq!(
+            format!(
+                "{:#x}",
+                MerkleSumTree::new(leafs())
+                    .unwrap()
+                    .get_root_hash()
+                    .unwrap()
+            ),
+            "0x330a5a305bfadcef363c836d936f883547a27a367698a6037969bb6129f0c7b1"
+        );
+    }
+
+    #[test]
+    fn trees_rehash_and_check_leafs_under_their_mode() {
+        let mut tree = MerkleSumTree::builder()
+            .leaf_hash_mode(LeafHashMode::IdAndValue)
+            .build(leafs())
+            .unwrap();
+        assert_eq!(tree.get_leaf_hash_mode(), LeafHashMode::IdAndValue);
+        assert!(tree
+            .get_leafs()
+            .iter()
+            .all(|leaf| LeafHashMode::IdAndValue.matches(leaf)));
+
+        //Leafs built with Leaf::new are rehashed on the way in
+        tree.set_leaf(Leaf::new("bob".to_string(), 25), 1).unwrap();
+        tree.add_to_leaf(0, 5).unwrap();
+        tree.push(Leaf::new("dave".to_string(), 1)).unwrap();
+        tree.push(Leaf::new("erin".to_string(), 2)).unwrap();
+        tree.remove(2).unwrap();
+        assert!(tree
+            .get_leafs()
+            .iter()
+            .all(|leaf| LeafHashMode::IdAndValue.matches(leaf)));
+        assert_eq!(tree.validate(), Ok(()));
+
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        assert!(tree.verify_proof(proof.clone()).unwrap());
+        assert!(proof.verify_leaf_hash(LeafHashMode::IdAndValue));
+        assert!(!proof.verify_leaf_hash(LeafHashMode::IdOnly));
+
+        //An internal node passed off as a leaf folds to the root but fails the rehash
+        let mut forged = proof;
+        forged.leaf = Leaf {
+            id: "forged".into(),
+            node: tree.get_node(8).unwrap(),
+        };
+        forged.index = 0;
+        forged.path.remove(0);
+        assert!(tree
+            .get_root()
+            .unwrap()
+            .is_equal(forged.compute_root().unwrap()));
+        assert!(!tree.verify_proof(forged).unwrap());
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let restored: MerkleSumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_leaf_hash_mode(), LeafHashMode::IdAndValue);
+
+        //Imports check every leaf hash against the recorded mode
+        let mut tampered = tree.clone();
+        tampered.leafs[1] = Leaf::new("bob".to_string(), 25);
+        assert!(tampered
+            .validate()
+            .unwrap_err()
+            .contains(&IntegrityViolation::LeafHashMismatch(1)));
+        let json = serde_json::to_string(&tampered).unwrap();
+        assert!(serde_json::from_str::<MerkleSumTree>(&json).is_err());
+    }
+}
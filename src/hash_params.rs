@@ -0,0 +1,268 @@
+//Parent derivation, the one rule every internal node of every tree follows
+
+use crate::mimc_sponge::DEFAULT_EXPONENT;
+use crate::{value_to_fr, Fr, MerkleError, MerkleSumTree, MimcSponge, Node};
+use ff::Field;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HashFunction {
+    //circomlib MiMCSponge, 220 rounds, round function x^exponent
+    MimcSponge { exponent: u64 },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ValueEncoding {
+    //i32 into the field, negatives as their additive inverse
+    #[default]
+    FieldNegation,
+}
+
+//Everything a verifier needs to rederive a parent; the sponge is built once
+#[derive(Clone)]
+pub struct HashParams {
+    hasher: HashFunction,
+    key: Fr,
+    encoding: ValueEncoding,
+    sponge: MimcSponge,
+}
+
+impl Default for HashFunction {
+    fn default() -> Self {
+        HashFunction::MimcSponge {
+            exponent: DEFAULT_EXPONENT,
+        }
+    }
+}
+
+impl ValueEncoding {
+    pub fn encode(&self, value: i32) -> Fr {
+        match self {
+            ValueEncoding::FieldNegation => value_to_fr(value),
+        }
+    }
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams {
+            hasher: HashFunction::default(),
+            key: Fr::ZERO,
+            encoding: ValueEncoding::default(),
+            sponge: MimcSponge::default(),
+        }
+    }
+}
+
+impl fmt::Debug for HashParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HashParams")
+            .field("hasher", &self.hasher)
+            .field("key", &self.key)
+            .field("encoding", &self.encoding)
+            .finish()
+    }
+}
+
+impl PartialEq for HashParams {
+    fn eq(&self, other: &Self) -> bool {
+        self.hasher == other.hasher && self.key == other.key && self.encoding == other.encoding
+    }
+}
+
+impl Eq for HashParams {}
+
+impl HashParams {
+    pub fn new(
+        hasher: HashFunction,
+        key: Fr,
+        encoding: ValueEncoding,
+    ) -> Result<HashParams, MerkleError> {
+        let sponge = match hasher {
+            HashFunction::MimcSponge { exponent } => MimcSponge::with_exponent(exponent)?,
+        };
+        Ok(HashParams {
+            hasher,
+            key,
+            encoding,
+            sponge,
+        })
+    }
+
+    //The parameters of a tree with this MiMC exponent
+    pub fn mimc(exponent: u64) -> Result<HashParams, MerkleError> {
+        HashParams::new(
+            HashFunction::MimcSponge { exponent },
+            Fr::ZERO,
+            ValueEncoding::default(),
+        )
+    }
+
+    pub fn get_hasher(&self) -> HashFunction {
+        self.hasher
+    }
+
+    pub fn get_key(&self) -> Fr {
+        self.key
+    }
+
+    pub fn get_encoding(&self) -> ValueEncoding {
+        self.encoding
+    }
+}
+
+impl Node {
+    //The parent of left and right, see the module comment for the absorption order
+    pub fn combine(left: &Node, right: &Node, params: &HashParams) -> Result<Node, MerkleError> {
+        let sum = left
+            .value
+            .checked_add(right.value)
+            .ok_or(MerkleError::Overflow)?;
+        let arr = [
+            left.hash,
+            params.encoding.encode(left.value),
+            right.hash,
+            params.encoding.encode(right.value),
+        ];
+        let hash = params.sponge.single_hash(&arr, params.key);
+        Ok(Node::new(hash, sum))
+    }
+}
+
+impl MerkleSumTree {
+    //What this tree's parents are combined under
+    pub fn get_hash_params(&self) -> HashParams {
+        HashParams::mimc(self.mimc_exponent).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::Leaf;
+    use ff::PrimeField;
+
+    fn node(hash: &str, value: i32) -> Node {
+        Node::new(Fr::from_str_vartime(hash).unwrap(), value)
+    }
+
+    //(left, right, key, exponent, parent), hashes in decimal
+    const VECTORS: [(&str, i32, &str, i32, &str, u64, &str, i32); 6] = [
+        (
+            "1",
+            10,
+            "2",
+            20,
+            "0",
+            5,
+            "8722745087349979609394098420089127708221333919706720542449085679492452304413",
+            30,
+        ),
+        (
+            "2",
+            20,
+            "1",
+            10,
+            "0",
+            5,
+            "25765612498314843112679419553469986150544933988436471537747502921299519915718",
+            30,
+        ),
+        (
+            "0",
+            0,
+            "0",
+            0,
+            "0",
+            5,
+            "10823843188602664068088898338081332751484025067283255271924608438470098915840",
+            0,
+        ),
+        (
+            "11672136",
+            -5,
+            "10566265",
+            7,
+            "0",
+            5,
+            "7739737390085695979192018833435091227393468464296236767086655996908288870871",
+            2,
+        ),
+        (
+            "1",
+            10,
+            "2",
+            20,
+            "3",
+            5,
+            "4182262555305829526983328825715722328976621320604017967956092409810639530304",
+            30,
+        ),
+        (
+            "1",
+            10,
+            "2",
+            20,
+            "0",
+            7,
+            "27384522005169193037033131946797158565366533146165175416921346924465312942268",
+            30,
+        ),
+    ];
+
+    #[test]
+    fn combine_matches_the_published_vectors() {
+        for (left, left_value, right, right_value, key, exponent, parent, sum) in VECTORS {
+            let params = HashParams::new(
+                HashFunction::MimcSponge { exponent },
+                Fr::from_str_vartime(key).unwrap(),
+                ValueEncoding::FieldNegation,
+            )
+            .unwrap();
+            assert_eq!(
+                Node::combine(&node(left, left_value), &node(right, right_value), &params).unwrap(),
+                node(parent, sum)
+            );
+        }
+    }
+
+    #[test]
+    fn trees_combine_under_their_own_params() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), -4),
+        ];
+        for exponent in [5, 7] {
+            let tree = MerkleSumTree::builder()
+                .mimc_exponent(exponent)
+                .build(leafs.clone())
+                .unwrap();
+            let params = tree.get_hash_params();
+            assert_eq!(params, HashParams::mimc(exponent).unwrap());
+            let root = Node::combine(&tree.leafs[0].node, &tree.leafs[1].node, &params);
+            assert_eq!(root.ok(), tree.get_root());
+        }
+        assert_eq!(
+            MerkleSumTree::new(leafs).unwrap().get_hash_params(),
+            HashParams::default()
+        );
+    }
+
+    #[test]
+    fn combine_refuses_overflow_and_bad_exponents() {
+        let params = HashParams::default();
+        let big = Node::new(Fr::ONE, i32::MAX);
+        assert!(matches!(
+            Node::combine(&big, &Node::new(Fr::ONE, 1), &params),
+            Err(MerkleError::Overflow)
+        ));
+        let mixed = Node::combine(&big, &Node::new(Fr::ONE, i32::MIN), &params).unwrap();
+        assert_eq!(mixed.get_value(), -1);
+        assert!(matches!(
+            HashParams::mimc(4),
+            Err(MerkleError::UnsupportedExponent(4))
+        ));
+    }
+}
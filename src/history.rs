@@ -0,0 +1,271 @@
+//Recent roots, for proofs issued against a root that has since rotated
+
+use crate::{InclusionProof, MerkleError, MerkleSumTree, Node, RootCommitment};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RootHistory {
+    capacity: usize,
+    roots: VecDeque<(RootCommitment, usize)>,
+}
+
+impl RootHistory {
+    pub(crate) fn new(capacity: usize) -> RootHistory {
+        RootHistory {
+            capacity,
+            roots: VecDeque::new(),
+        }
+    }
+
+    //A later root of the same epoch replaces the earlier one
+    pub(crate) fn record(&mut self, root: Option<RootCommitment>, height: usize) {
+        let root = match root {
+            Some(root) if self.capacity > 0 => root,
+            _ => return,
+        };
+        if self.roots.back().map(|(last, _)| last.get_epoch()) == Some(root.get_epoch()) {
+            self.roots.pop_back();
+        }
+        self.roots.push_back((root, height));
+        while self.roots.len() > self.capacity {
+            self.roots.pop_front();
+        }
+    }
+}
+
+impl MerkleSumTree {
+    //Keep the roots of the last capacity epochs from now on, 0 turns the history off
+    pub fn set_root_history(&mut self, capacity: usize) {
+        let mut history = RootHistory::new(capacity);
+        for (root, height) in std::mem::take(&mut self.history.roots) {
+            history.record(Some(root), height);
+        }
+        history.record(self.get_commitment(), self.height);
+        self.history = history;
+    }
+
+    //Recorded roots, oldest first
+    pub fn roots(&self) -> Vec<RootCommitment> {
+        self.history.roots.iter().map(|(root, _)| *root).collect()
+    }
+
+    //Root of epoch: the current one, or a recorded one while it is kept
+    pub fn get_root_at(&self, epoch: u64) -> Option<RootCommitment> {
+        self.root_and_height_at(epoch).map(|(root, _)| root)
+    }
+
+    fn root_and_height_at(&self, epoch: u64) -> Option<(RootCommitment, usize)> {
+        match epoch == self.epoch {
+            true => self.get_commitment().map(|root| (root, self.height)),
+            false => self
+                .history
+                .roots
+                .iter()
+                .find(|(root, _)| root.get_epoch() == epoch)
+                .copied(),
+        }
+    }
+
+    //get_root_at as a node with the height of its tree, recorded sums always fit an i32
+    pub(crate) fn past_root(&self, epoch: u64) -> Option<(Node, usize)> {
+        let (root, height) = self.root_and_height_at(epoch)?;
+        let sum = i32::try_from(root.get_sum()).ok()?;
+        Some((Node::new(root.get_hash(), sum), height))
+    }
+
+    //get_proof tagged with the current epoch, verify_proof then checks it against
+    //the root of that epoch while the history keeps it
+    pub fn get_proof_tagged(&self, index: usize) -> Result<InclusionProof, MerkleError> {
+        let mut proof = self
+            .get_proof(index)?
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        proof.epoch = Some(self.epoch);
+        Ok(proof)
+    }
+
+    //Index of the first root the proof folds to, None when it folds to none of them
+    pub fn verify_against_any(
+        roots: &[RootCommitment],
+        proof: &InclusionProof,
+    ) -> Result<Option<usize>, MerkleError> {
+        let computed = proof.compute_root()?;
+        Ok(roots.iter().position(|root| folds_to(root, &computed)))
+    }
+
+    //The recorded root of the last n epochs the proof folds to, if any
+    pub fn verify_within_window(
+        &self,
+        proof: &InclusionProof,
+        n: u64,
+    ) -> Result<Option<RootCommitment>, MerkleError> {
+        if !self.leaf_hash_mode.accepts(&proof.leaf) || proof.exponent != self.mimc_exponent {
+            return Ok(None);
+        }
+        let computed = proof.compute_root()?;
+        self.metrics.hashes(proof.path.len());
+        let current = self.get_commitment().map(|root| (root, self.height));
+        //Only the roots of a tree as high as the proof is long
+        let window: Vec<RootCommitment> = self
+            .history
+            .roots
+            .iter()
+            .copied()
+            .chain(current)
+            .filter(|(root, height)| {
+                root.get_epoch().saturating_add(n) > self.epoch
+                    && height.saturating_sub(1) == proof.path.len()
+            })
+            .map(|(root, _)| root)
+            .collect();
+        //Newest first, so a proof valid under several roots reports the latest
+        Ok(window
+            .into_iter()
+            .rev()
+            .find(|root| folds_to(root, &computed)))
+    }
+}
+
+//The sum first, it is the cheaper comparison
+fn folds_to(root: &RootCommitment, computed: &Node) -> bool {
+    root.get_sum() == i64::from(computed.get_value()) && root.get_hash() == computed.get_hash()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use crate::Leaf;
+
+    fn tree(capacity: usize) -> MerkleSumTree {
+        MerkleSumTree::builder()
+            .root_history(capacity)
+            .build(accounts(&[10, 20, 30]))
+            .unwrap()
+    }
+
+    #[test]
+    fn tagged_proofs_verify_against_their_epoch() {
+        let mut tree = tree(3);
+        let tagged = tree.get_proof_tagged(0).unwrap();
+        let stamped = tree.get_proof_with_root(0).unwrap();
+        assert_eq!(tagged.get_epoch(), Some(0));
+        assert!(tagged.get_root().is_none());
+        let first = tree.get_commitment();
+        tree.set_leaf(Leaf::new("alice".to_string(), 11), 0)
+            .unwrap();
+        assert_eq!(tree.get_root_at(0), first);
+        assert_eq!(tree.get_root_at(1), tree.get_commitment());
+        assert_eq!(tree.get_root_at(2), None);
+        assert!(tree.verify_proof(tagged.clone()).unwrap());
+        //A stamped proof is checked against the current root, and the tree has moved
+        assert!(!tree.verify_proof(stamped.clone()).unwrap());
+        //Untagged, the same path is checked against the current root
+        let mut untagged = tagged.clone();
+        untagged.epoch = None;
+        assert!(!tree.verify_proof(untagged).unwrap());
+        //A stamp of the current root on a path of the old one
+        let mut forged = stamped.clone();
+        forged.root = tree.get_root();
+        assert!(!tree.verify_proof(forged).unwrap());
+
+        //Once evicted the epoch's root is gone
+        for value in 12..15 {
+            tree.set_leaf(Leaf::new("alice".to_string(), value), 0)
+                .unwrap();
+        }
+        assert_eq!(tree.get_root_at(0), None);
+        assert!(!tree.verify_proof(tagged).unwrap());
+        assert!(!tree.verify_proof(stamped).unwrap());
+        assert!(matches!(
+            tree.get_proof_tagged(3),
+            Err(MerkleError::EmptySlot(3))
+        ));
+    }
+
+    #[test]
+    fn proofs_match_any_candidate_root() {
+        let mut tree = tree(0);
+        let old = tree.get_commitment().unwrap();
+        let proof = tree.get_proof(0).unwrap().unwrap();
+        tree.set_leaf(Leaf::new("bob".to_string(), 21), 1).unwrap();
+        let new = tree.get_commitment().unwrap();
+        assert_eq!(
+            MerkleSumTree::verify_against_any(&[new, old], &proof).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            MerkleSumTree::verify_against_any(&[new], &proof).unwrap(),
+            None
+        );
+        //Same sum, other hash
+        let forged = RootCommitment::new(new.get_hash(), old.get_sum(), 0);
+        assert_eq!(
+            MerkleSumTree::verify_against_any(&[forged], &proof).unwrap(),
+            None
+        );
+        assert!(tree.roots().is_empty());
+    }
+
+    #[test]
+    fn window_covers_the_last_epochs() {
+        let mut tree = tree(3);
+        let first = tree.get_proof(0).unwrap().unwrap();
+        for value in [21, 22, 23] {
+            tree.set_leaf(Leaf::new("bob".to_string(), value), 1)
+                .unwrap();
+        }
+        assert_eq!(
+            tree.roots()
+                .iter()
+                .map(RootCommitment::get_epoch)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(tree.verify_within_window(&first, 10).unwrap(), None);
+
+        let mut tree = self::tree(8);
+        let first = tree.get_proof(0).unwrap().unwrap();
+        let root = tree.get_commitment().unwrap();
+        tree.set_leaf(Leaf::new("bob".to_string(), 21), 1).unwrap();
+        tree.remove_many(&[1, 2]).unwrap();
+        assert_eq!(tree.get_epoch(), 3);
+        assert_eq!(tree.verify_within_window(&first, 4).unwrap(), Some(root));
+        assert_eq!(tree.verify_within_window(&first, 3).unwrap(), None);
+        let current = tree.get_proof(0).unwrap().unwrap();
+        assert_eq!(
+            tree.verify_within_window(&current, 1).unwrap(),
+            tree.get_commitment()
+        );
+        assert!(!tree.verify_proof(first).unwrap());
+    }
+
+    #[test]
+    fn tagged_proofs_outlive_a_growth() {
+        let mut tree = tree(4);
+        let tagged = tree.get_proof_tagged(0).unwrap();
+        let untagged = tree.get_proof(0).unwrap().unwrap();
+        //Three leafs fill four slots, the second push doubles the tree
+        tree.push(Leaf::new("dave".to_string(), 40)).unwrap();
+        tree.push(Leaf::new("erin".to_string(), 50)).unwrap();
+        assert_eq!(tree.get_proof(0).unwrap().unwrap().path.len(), 3);
+        assert_eq!(tagged.path.len(), 2);
+        assert!(tree.verify_proof(tagged).unwrap());
+        assert!(tree.verify_within_window(&untagged, 3).unwrap().is_some());
+        assert!(tree.verify_proof(untagged).is_err());
+    }
+
+    #[test]
+    fn history_can_be_enabled_later() {
+        let mut tree = tree(0);
+        let proof = tree.get_proof(2).unwrap().unwrap();
+        tree.set_root_history(2);
+        tree.push(Leaf::new("dave".to_string(), 1)).unwrap();
+        assert_eq!(tree.roots().len(), 2);
+        assert!(tree.verify_within_window(&proof, 2).unwrap().is_some());
+        tree.set_root_history(1);
+        assert_eq!(tree.roots(), vec![tree.get_commitment().unwrap()]);
+        tree.set_root_history(0);
+        assert!(tree.roots().is_empty());
+    }
+}
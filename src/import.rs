@@ -0,0 +1,161 @@
+//Trees wrapped around raw node dumps, without rehashing the leafs
+
+use crate::layout::{flat_index, level_count, level_size};
+use crate::{EmptyLeafSpec, Leaf, MerkleError, MerkleSumTree, Node, MAX_HEIGHT};
+use std::collections::BTreeSet;
+
+impl MerkleSumTree {
+    pub fn try_from_nodes(
+        nodes: Vec<Node>,
+        leaf_ids: Option<Vec<String>>,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        let leaf_count = dump_leaf_count(nodes.len())?;
+        for level in 1..level_count(leaf_count) {
+            for offset in 0..level_size(leaf_count, level) {
+                let node = |level, offset| {
+                    flat_index(leaf_count, level, offset)
+                        .and_then(|index| nodes.get(index))
+                        .cloned()
+                        .ok_or(MerkleError::InvalidCoordinates { level, offset })
+                };
+                let expected = Self::build_parent(
+                    node(level - 1, 2 * offset)?,
+                    node(level - 1, 2 * offset + 1)?,
+                );
+                if expected.ok() != Some(node(level, offset)?) {
+                    return Err(MerkleError::InconsistentNode { level, offset });
+                }
+            }
+        }
+        Self::try_from_nodes_trusted(nodes, leaf_ids)
+    }
+
+    //try_from_nodes without checking the internal nodes, for dumps already verified
+    pub fn try_from_nodes_trusted(
+        nodes: Vec<Node>,
+        leaf_ids: Option<Vec<String>>,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        let leaf_count = dump_leaf_count(nodes.len())?;
+        if let Some(ids) = &leaf_ids {
+            if ids.len() != leaf_count {
+                return Err(MerkleError::InvalidLength {
+                    expected: leaf_count,
+                    actual: ids.len(),
+                });
+            }
+        }
+        let empty = EmptyLeafSpec::default();
+        let empty_node = empty.get_leaf().get_node();
+        let mut ids = leaf_ids.map(Vec::into_iter);
+        let leafs = nodes
+            .iter()
+            .take(leaf_count)
+            .enumerate()
+            .map(|(index, node)| {
+                let id = ids.as_mut().and_then(Iterator::next);
+                match id {
+                    Some(id) => Leaf {
+                        id: id.into(),
+                        node: node.clone(),
+                    },
+                    None if *node == empty_node => empty.get_leaf(),
+                    None => Leaf {
+                        id: format!("#{}", index).into(),
+                        node: node.clone(),
+                    },
+                }
+            })
+            .collect();
+        Ok(Self::assemble(
+            leafs,
+            nodes,
+            level_count(leaf_count),
+            MAX_HEIGHT,
+            None,
+            BTreeSet::new(),
+            empty,
+        ))
+    }
+}
+
+//Leaf count of a 2^h - 1 node dump
+fn dump_leaf_count(len: usize) -> Result<usize, MerkleError> {
+    let leaf_count = len / 2 + 1;
+    if len == 0 || !leaf_count.is_power_of_two() || 2 * leaf_count - 1 != len {
+        return Err(MerkleError::InvalidLength {
+            expected: len.saturating_add(1).next_power_of_two().saturating_sub(1),
+            actual: len,
+        });
+    }
+    Ok(leaf_count)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use crate::Fr;
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, 20, 30])).unwrap()
+    }
+
+    #[test]
+    fn dumps_wrap_into_the_same_tree() {
+        let tree = tree();
+        let ids = vec![
+            "alice".to_string(),
+            "bob".to_string(),
+            "carol".to_string(),
+            "0".to_string(),
+        ];
+        let wrapped = MerkleSumTree::try_from_nodes(tree.get_nodes(), Some(ids)).unwrap();
+        assert_eq!(wrapped.get_leafs(), tree.get_leafs());
+        assert_eq!(wrapped.iter_free_slots().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(wrapped.get_index_by_id("carol"), Some(2));
+        assert_eq!(wrapped.validate(), Ok(()));
+
+        let anonymous = MerkleSumTree::try_from_nodes(tree.get_nodes(), None).unwrap();
+        assert_eq!(anonymous.get_root(), tree.get_root());
+        assert_eq!(anonymous.get_leaf(1).unwrap().get_id(), "#1");
+        assert!(anonymous.get_leaf(3).unwrap().is_none());
+        let proof = anonymous.get_proof(1).unwrap().unwrap();
+        assert!(tree.verify_proof(proof).unwrap());
+    }
+
+    #[test]
+    fn inconsistent_dumps_are_refused() {
+        let mut nodes = tree().get_nodes();
+        //The parent of carol and the empty slot
+        nodes[5] = Node::new(Fr::from(7u64), nodes[5].get_value());
+        assert!(matches!(
+            MerkleSumTree::try_from_nodes(nodes.clone(), None),
+            Err(MerkleError::InconsistentNode {
+                level: 1,
+                offset: 1
+            })
+        ));
+        //The trusted path takes it as given, validate still sees it
+        let trusted = MerkleSumTree::try_from_nodes_trusted(nodes, None).unwrap();
+        assert!(trusted.validate().is_err());
+
+        let nodes = tree().get_nodes();
+        for len in [0, 2, 4, 6] {
+            assert!(matches!(
+                MerkleSumTree::try_from_nodes(nodes[..len].to_vec(), None),
+                Err(MerkleError::InvalidLength { .. })
+            ));
+        }
+        assert!(matches!(
+            MerkleSumTree::try_from_nodes(nodes, Some(vec!["alice".to_string()])),
+            Err(MerkleError::InvalidLength {
+                expected: 4,
+                actual: 1
+            })
+        ));
+        let single =
+            MerkleSumTree::try_from_nodes(vec![Node::new(Fr::from(3u64), 9)], None).unwrap();
+        assert_eq!(single.get_root_sum(), Some(9));
+    }
+}
@@ -0,0 +1,194 @@
+//Insertion at an arbitrary slot, shifting the later leafs up by one
+
+use crate::metrics::MutationKind;
+use crate::wal::{LogEntry, Logger};
+use crate::{Leaf, MerkleError, MerkleSumTree};
+use std::collections::BTreeSet;
+
+impl MerkleSumTree {
+    //One past the last occupied slot, dummy padding excluded
+    pub fn logical_len(&self) -> usize {
+        self.non_empty_leafs(false)
+            .last()
+            .map_or(0, |(index, _)| index + 1)
+    }
+
+    //Put leaf at index and move every slot at or above it up by one
+    pub fn insert(&mut self, index: usize, leaf: Leaf) -> Result<(), MerkleError> {
+        let end = self.logical_len();
+        if index > end {
+            return Err(MerkleError::IndexOutOfBounds(index));
+        }
+        //An append-only slot never changes hands
+        if self.append_only {
+            if let Some(current) = self.non_empty_leafs(false).find(|(slot, _)| *slot >= index) {
+                return Err(MerkleError::SlotOccupied {
+                    index: current.0,
+                    id: current.1.get_id(),
+                });
+            }
+            if self.retired.contains(&index) {
+                return Err(MerkleError::SlotRetired(index));
+            }
+        }
+        let leaf = self.leaf_hash_mode.normalize(leaf);
+        self.check_value(&leaf)?;
+        self.check_rules(&leaf)?;
+        if matches!(self.log, Logger::Off) {
+            return self.shift_in(index, end, leaf);
+        }
+        let mut staged = self.staged();
+        staged.log = Logger::Off;
+        staged.shift_in(index, end, leaf.clone())?;
+        let pre_root = self.get_commitment().ok_or(MerkleError::EmptyTree)?;
+        let post_root = staged.get_commitment().ok_or(MerkleError::EmptyTree)?;
+        self.log.append(LogEntry::Insert {
+            index,
+            leaf,
+            pre_root,
+            post_root,
+        })?;
+        let indices: Vec<usize> = (index..=end).collect();
+        self.commit_staged(staged, &indices)
+    }
+
+    fn shift_in(&mut self, index: usize, end: usize, leaf: Leaf) -> Result<(), MerkleError> {
+        let mut writes = vec![(index, leaf)];
+        for slot in index..end {
+            let current = self
+                .get_leaf(slot)
+                .ok_or(MerkleError::IndexOutOfBounds(slot))?;
+            let moved = match self.is_empty_leaf(&current) || self.is_dummy(slot) {
+                true => self.empty.get_leaf(),
+                false => current,
+            };
+            writes.push((slot + 1, moved));
+        }
+        if end < self.leafs.len() {
+            return self.write_leafs(writes, MutationKind::SetLeaf);
+        }
+
+        //The last slot is occupied: rebuild one size up over the shifted leafs
+        let started = self.metrics.start();
+        let changes = writes
+            .iter()
+            .map(|(slot, _)| {
+                let old = self.get_leaf(*slot);
+                (*slot, old.unwrap_or_else(|| self.empty.get_leaf()))
+            })
+            .collect();
+        let count = writes.len();
+        let mut leafs: Vec<Leaf> = self.leafs.iter().take(index).cloned().collect();
+        leafs.extend(writes.into_iter().map(|(_, leaf)| leaf));
+        let dummies: BTreeSet<usize> = self.dummies.range(..index).copied().collect();
+        let grown = Self::create_tree(
+            leafs,
+            self.max_height,
+            self.dummy_seed,
+            dummies,
+            self.empty.clone(),
+            self.leaf_hash_mode,
+            self.mimc_exponent,
+            &self.progress,
+            &self.cancel,
+        )?;
+        self.update_tree(grown, started)?;
+        self.next_slot = self.next_slot.max(end + 1);
+        self.canonical = self.canonical && self.in_canonical_order();
+        self.epoch += count as u64;
+        self.notify(changes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::CountingMetrics;
+    use std::sync::Arc;
+
+    fn ranked(count: usize) -> Vec<Leaf> {
+        (0..count)
+            .map(|i| Leaf::new(format!("rank{}", i), 100 - i as i32))
+            .collect()
+    }
+
+    #[test]
+    fn insertion_equals_a_fresh_build_over_the_shifted_leafs() {
+        for (count, index) in [(5, 0), (5, 2), (5, 5), (8, 3), (8, 8), (1, 0), (6, 6)] {
+            let last = if index < count { count } else { count - 1 };
+            let mut leafs = ranked(count);
+            let mut tree = MerkleSumTree::new(leafs.clone()).unwrap();
+            let leaf = Leaf::new("newcomer".to_string(), 42);
+            tree.insert(index, leaf.clone()).unwrap();
+            leafs.insert(index, leaf);
+            let fresh = MerkleSumTree::new(leafs).unwrap();
+            assert_eq!(
+                tree.get_leafs(),
+                fresh.get_leafs(),
+                "{} at {}",
+                count,
+                index
+            );
+            assert_eq!(tree.get_nodes(), fresh.get_nodes());
+            assert_eq!(tree.validate(), Ok(()));
+            assert_eq!(tree.get_index_by_id("newcomer"), Some(index));
+            assert_eq!(tree.logical_len(), count + 1);
+            assert_eq!(
+                tree.get_index_by_id(&format!("rank{}", count - 1)),
+                Some(last)
+            );
+        }
+    }
+
+    #[test]
+    fn holes_shift_with_the_leafs() {
+        let mut tree = MerkleSumTree::new(ranked(6)).unwrap();
+        tree.remove(3).unwrap();
+        tree.insert(1, Leaf::new("newcomer".to_string(), 1))
+            .unwrap();
+        assert_eq!(tree.get_zero_index(), vec![4, 7]);
+        assert_eq!(tree.get_index_by_id("rank5"), Some(6));
+        assert_eq!(tree.validate(), Ok(()));
+        assert!(matches!(
+            tree.insert(8, Leaf::new("late".to_string(), 1)),
+            Err(MerkleError::IndexOutOfBounds(8))
+        ));
+        tree.insert(7, Leaf::new("late".to_string(), 1)).unwrap();
+        assert_eq!(tree.get_leafs().len(), 8);
+        assert_eq!(tree.get_root_sum(), Some(100 + 99 + 98 + 96 + 95 + 1 + 1));
+    }
+
+    #[test]
+    fn insertion_rehashes_each_ancestor_once() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let mut tree = MerkleSumTree::builder()
+            .metrics(metrics.clone())
+            .build(ranked(12))
+            .unwrap();
+        let before = metrics.get_hashes();
+        tree.insert(9, Leaf::new("newcomer".to_string(), 1))
+            .unwrap();
+        //Slots 9..=12 sit under three parents, two level-2 nodes, one level-3 node and the root
+        assert_eq!(metrics.get_hashes() - before, 3 + 2 + 1 + 1);
+    }
+
+    #[test]
+    fn append_only_trees_only_insert_past_the_end() {
+        let mut tree = MerkleSumTree::builder()
+            .append_only(true)
+            .build(ranked(3))
+            .unwrap();
+        assert!(matches!(
+            tree.insert(1, Leaf::new("a".to_string(), 1)),
+            Err(MerkleError::SlotOccupied { index: 1, .. })
+        ));
+        tree.insert(3, Leaf::new("a".to_string(), 1)).unwrap();
+        tree.remove(3).unwrap();
+        assert!(matches!(
+            tree.insert(3, Leaf::new("b".to_string(), 1)),
+            Err(MerkleError::SlotRetired(3))
+        ));
+    }
+}
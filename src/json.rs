@@ -0,0 +1,257 @@
+//Inclusion proofs as JSON for verifiers outside Rust, such as a browser
+
+use crate::binary::check_levels;
+use crate::{
+    default_exponent, is_default_exponent, path_index, Fr, InclusionProof, Leaf, MerkleError,
+    Neighbor, Node, Position,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct JsonProof {
+    leaf: JsonLeaf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    index: Option<usize>,
+    path: Vec<JsonNeighbor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root: Option<JsonNode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    epoch: Option<u64>,
+    #[serde(
+        default = "default_exponent",
+        skip_serializing_if = "is_default_exponent"
+    )]
+    exponent: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonLeaf {
+    id: String,
+    hash: String,
+    value: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonNode {
+    hash: String,
+    value: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonNeighbor {
+    position: JsonPosition,
+    hash: String,
+    value: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum JsonPosition {
+    Left,
+    Right,
+}
+
+fn to_hex(hash: &Fr) -> String {
+    format!("{:#x}", hash)
+}
+
+//Hex only, the decimal form FromStr also takes is refused
+fn from_hex(hash: &str) -> Result<Fr, MerkleError> {
+    match hash.starts_with("0x") {
+        true => hash.parse(),
+        false => Err(MerkleError::InvalidFieldElement(hash.to_string())),
+    }
+}
+
+fn to_json_node(node: &Node) -> JsonNode {
+    JsonNode {
+        hash: to_hex(&node.hash),
+        value: node.value,
+    }
+}
+
+fn from_json_node(node: &JsonNode) -> Result<Node, MerkleError> {
+    Ok(Node::new(from_hex(&node.hash)?, node.value))
+}
+
+impl InclusionProof {
+    pub fn to_json(&self) -> String {
+        let json = JsonProof {
+            leaf: JsonLeaf {
+                id: self.leaf.get_id(),
+                hash: to_hex(&self.leaf.node.hash),
+                value: self.leaf.node.value,
+            },
+            index: Some(self.index),
+            path: self
+                .path
+                .iter()
+                .map(|neighbor| JsonNeighbor {
+                    position: match neighbor.position {
+                        Position::Left => JsonPosition::Left,
+                        Position::Right => JsonPosition::Right,
+                    },
+                    hash: to_hex(&neighbor.node.hash),
+                    value: neighbor.node.value,
+                })
+                .collect(),
+            root: self.root.as_ref().map(to_json_node),
+            epoch: self.epoch,
+            exponent: self.exponent,
+        };
+        //Strings and integers only, writing them cannot fail
+        serde_json::to_string(&json).unwrap_or_default()
+    }
+
+    //Inverse of to_json; a proof without an index takes the one its path spells
+    pub fn from_json(json: &str) -> Result<InclusionProof, MerkleError> {
+        let json: JsonProof = serde_json::from_str(json)?;
+        check_levels(json.path.len())?;
+        let path = json
+            .path
+            .iter()
+            .map(|neighbor| {
+                let position = match neighbor.position {
+                    JsonPosition::Left => Position::Left,
+                    JsonPosition::Right => Position::Right,
+                };
+                Ok(Neighbor::new(
+                    position,
+                    Node::new(from_hex(&neighbor.hash)?, neighbor.value),
+                ))
+            })
+            .collect::<Result<Vec<Neighbor>, MerkleError>>()?;
+        let proof = InclusionProof {
+            leaf: Leaf {
+                id: json.leaf.id.into(),
+                node: Node::new(from_hex(&json.leaf.hash)?, json.leaf.value),
+            },
+            index: json.index.unwrap_or_else(|| path_index(&path)),
+            path,
+            root: json.root.as_ref().map(from_json_node).transpose()?,
+            epoch: json.epoch,
+            exponent: json.exponent,
+        };
+        proof.check_index()?;
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use crate::MerkleSumTree;
+    use serde_json::Value;
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, -20, 30])).unwrap()
+    }
+
+    #[test]
+    fn proofs_round_trip_with_hex_hashes() {
+        let tree = tree();
+        let root = tree.get_root().unwrap();
+        for index in 0..3 {
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            let json = proof.to_json();
+            let value: Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(value["leaf"]["id"], tree.get_leaf(index).unwrap().get_id());
+            assert_eq!(
+                value["leaf"]["hash"].as_str().unwrap(),
+                format!("{:#x}", proof.get_leaf().get_node().get_hash())
+            );
+            assert_eq!(
+                value["leaf"]["value"],
+                proof.get_leaf().get_node().get_value()
+            );
+            assert!(value["path"][0]["hash"].as_str().unwrap().starts_with("0x"));
+            let restored = InclusionProof::from_json(&json).unwrap();
+            assert_eq!(restored, proof);
+            assert!(restored.verify(root.get_hash(), root.get_value()).unwrap());
+        }
+
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        let value: Value = serde_json::from_str(&proof.to_json()).unwrap();
+        assert_eq!(value["path"][0]["position"], "left");
+        assert_eq!(value["path"][1]["position"], "right");
+        assert_eq!(value["leaf"]["value"], -20);
+        assert!(value.get("exponent").is_none());
+    }
+
+    #[test]
+    fn bad_field_elements_and_garbage_are_errors() {
+        let json = tree().get_proof(0).unwrap().unwrap().to_json();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        let hash = value["leaf"]["hash"].as_str().unwrap().to_string();
+
+        let modulus = "0x40000000000000000000000000000000224698fc0994a8dd8c46eb2100000001";
+        let decimal = hash.parse::<Fr>().unwrap().to_string();
+        for bad in [modulus, "0xzz", "0x", decimal.as_str()] {
+            let replaced = json.replace(&hash, bad);
+            assert!(matches!(
+                InclusionProof::from_json(&replaced),
+                Err(MerkleError::InvalidFieldElement(_))
+            ));
+        }
+        let unknown_position = json.replace("\"right\"", "\"up\"");
+        for garbage in [
+            "",
+            "{",
+            "[1, 2]",
+            "{\"leaf\": 3}",
+            unknown_position.as_str(),
+        ] {
+            assert!(matches!(
+                InclusionProof::from_json(garbage),
+                Err(MerkleError::Serialization(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn lengths_indices_and_values_are_checked() {
+        let proof = tree().get_proof(2).unwrap().unwrap();
+        let json = proof.to_json();
+        let mut value: Value = serde_json::from_str(&json).unwrap();
+
+        //0 levels is a one-leaf tree, 64 the most any platform takes, 65 is above it
+        let single = MerkleSumTree::new(vec![Leaf::new("alice".to_string(), 1)]).unwrap();
+        let single = single.get_proof(0).unwrap().unwrap();
+        assert_eq!(
+            InclusionProof::from_json(&single.to_json()).unwrap(),
+            single
+        );
+        assert_eq!(value["path"][0]["position"], "right");
+        let mut long = value.clone();
+        long.as_object_mut().unwrap().remove("index");
+        long["path"] = Value::Array(vec![value["path"][0].clone(); 64]);
+        let widest = InclusionProof::from_json(&long.to_string()).unwrap();
+        assert_eq!(widest.get_path().len(), 64);
+        long["path"] = Value::Array(vec![value["path"][0].clone(); 65]);
+        assert!(matches!(
+            InclusionProof::from_json(&long.to_string()),
+            Err(MerkleError::PathTooLong { levels: 65, .. })
+        ));
+
+        value["index"] = 1.into();
+        assert!(matches!(
+            InclusionProof::from_json(&value.to_string()),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        value["index"] = 2.into();
+        for large in [i64::from(i32::MAX) + 1, i64::from(i32::MIN) - 1] {
+            let mut large_value = value.clone();
+            large_value["path"][0]["value"] = large.into();
+            assert!(matches!(
+                InclusionProof::from_json(&large_value.to_string()),
+                Err(MerkleError::Serialization(_))
+            ));
+        }
+        assert_eq!(
+            InclusionProof::from_json(&value.to_string()).unwrap(),
+            proof
+        );
+    }
+}
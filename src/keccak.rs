@@ -0,0 +1,243 @@
+//Dual hashing: a keccak-256 digest for every node next to its MiMC hash
+
+use crate::plain::refresh_shadow;
+use crate::{
+    value_to_fr, Endianness, Fr, InclusionProof, MerkleError, MerkleSumTree, Node, Position,
+};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+//An inclusion proof with the keccak digest of every sibling, for both verifiers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DualProof {
+    proof: InclusionProof,
+    keccak_path: Vec<[u8; 32]>,
+}
+
+impl MerkleSumTree {
+    //Maintain the keccak digests from now on, or drop them
+    pub fn set_keccak_dual(&mut self, enabled: bool) {
+        self.keccak = match enabled {
+            true => Some(vec![]),
+            false => None,
+        };
+        self.refresh_keccak(std::iter::empty());
+    }
+
+    pub fn get_keccak_root(&self) -> Option<[u8; 32]> {
+        self.keccak
+            .as_ref()
+            .and_then(|keccak| keccak.last().copied())
+    }
+
+    //(MiMC root hash, keccak root digest, root sum), None unless dual hashing is on
+    pub fn get_root_dual(&self) -> Option<(Fr, [u8; 32], i32)> {
+        let root = self.get_root()?;
+        Some((root.get_hash(), self.get_keccak_root()?, root.get_value()))
+    }
+
+    //get_proof with the keccak sibling digests, None unless dual hashing is on
+    pub fn get_dual_proof(&self, index: usize) -> Result<Option<DualProof>, MerkleError> {
+        let proof = match self.get_proof(index)? {
+            Some(proof) => proof,
+            None => return Err(MerkleError::IndexOutOfBounds(index)),
+        };
+        let keccak = match &self.keccak {
+            Some(keccak) => keccak,
+            None => return Ok(None),
+        };
+        let keccak_path = crate::layout::path(self.leafs.len(), index)
+            .into_iter()
+            .map(|step| {
+                let index = self.nodes.flat(step.sibling);
+                keccak
+                    .get(index)
+                    .copied()
+                    .ok_or(MerkleError::MissingNode(index))
+            })
+            .collect::<Result<_, MerkleError>>()?;
+        Ok(Some(DualProof { proof, keccak_path }))
+    }
+
+    //Rehash the keccak layout above the written leafs, a no-op when it is not maintained
+    pub(crate) fn refresh_keccak<I: IntoIterator<Item = usize>>(&mut self, written: I) {
+        let mut keccak = match self.keccak.take() {
+            Some(keccak) => keccak,
+            None => return,
+        };
+        let value = |index: usize| self.nodes.get_flat(index).map_or(0, Node::get_value);
+        refresh_shadow(
+            &mut keccak,
+            self.leafs.len(),
+            self.nodes.len(),
+            written,
+            |index| self.leafs.get(index).map(|leaf| leaf_digest(&leaf.node)),
+            |(left, left_digest), (right, right_digest)| {
+                parent_digest(&left_digest, value(left), &right_digest, value(right))
+            },
+        );
+        self.keccak = Some(keccak);
+    }
+}
+
+impl DualProof {
+    pub fn get_proof(&self) -> InclusionProof {
+        self.proof.clone()
+    }
+
+    //Keccak digests of the siblings, leaf to root
+    pub fn get_keccak_path(&self) -> Vec<[u8; 32]> {
+        self.keccak_path.clone()
+    }
+
+    //Keccak root digest and root sum the proof folds to
+    pub fn compute_keccak_root(&self) -> Result<([u8; 32], i32), MerkleError> {
+        if self.keccak_path.len() != self.proof.path.len() {
+            return Err(MerkleError::InvalidLength {
+                expected: self.proof.path.len(),
+                actual: self.keccak_path.len(),
+            });
+        }
+        let leaf = self.proof.leaf.get_node();
+        let mut digest = leaf_digest(&leaf);
+        let mut sum = leaf.get_value();
+        for (neighbor, sibling) in self.proof.path.iter().zip(&self.keccak_path) {
+            let value = neighbor.node.get_value();
+            digest = match neighbor.position {
+                Position::Right => parent_digest(&digest, sum, sibling, value),
+                Position::Left => parent_digest(sibling, value, &digest, sum),
+            };
+            sum = sum.checked_add(value).ok_or(MerkleError::Overflow)?;
+        }
+        Ok((digest, sum))
+    }
+
+    //Whether the proof folds to root, a (MiMC hash, keccak digest, sum) triple
+    pub fn verify(&self, root: (Fr, [u8; 32], i32)) -> Result<bool, MerkleError> {
+        let (hash, digest, sum) = root;
+        let mimc = self.proof.compute_root()?;
+        Ok(mimc.get_hash() == hash
+            && mimc.get_value() == sum
+            && self.compute_keccak_root()? == (digest, sum))
+    }
+}
+
+fn leaf_digest(node: &Node) -> [u8; 32] {
+    node.get_hash().to_bytes(Endianness::Big)
+}
+
+fn parent_digest(left: &[u8; 32], left_value: i32, right: &[u8; 32], right_value: i32) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(left);
+    hasher.update(value_to_fr(left_value).to_bytes(Endianness::Big));
+    hasher.update(right);
+    hasher.update(value_to_fr(right_value).to_bytes(Endianness::Big));
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use crate::Leaf;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    fn leafs() -> Vec<Leaf> {
+        accounts(&[10, -20, 30])
+    }
+
+    #[test]
+    fn keccak_vectors() {
+        //keccak256 of the empty input
+        assert_eq!(
+            Keccak256::digest(b"").as_slice(),
+            &[
+                0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7,
+                0x03, 0xc0, 0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04,
+                0x5d, 0x85, 0xa4, 0x70
+            ]
+        );
+        //Values are packed as field elements, -1 is p - 1
+        let left = [1u8; 32];
+        let right = [2u8; 32];
+        let mut packed = vec![];
+        packed.extend_from_slice(&left);
+        packed.extend_from_slice(&(-Fr::from(1u64)).to_bytes(Endianness::Big));
+        packed.extend_from_slice(&right);
+        packed.extend_from_slice(&Fr::from(7u64).to_bytes(Endianness::Big));
+        let expected: [u8; 32] = Keccak256::digest(&packed).into();
+        assert_eq!(parent_digest(&left, -1, &right, 7), expected);
+    }
+
+    #[test]
+    fn both_roots_share_structure_and_sum() {
+        let mut tree = MerkleSumTree::builder()
+            .keccak_dual(true)
+            .build(leafs())
+            .unwrap();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for step in 0..100 {
+            let len = tree.get_leafs().len();
+            match rng.gen_range(0, 4) {
+                0 | 1 => {
+                    tree.push(Leaf::new(format!("user{}", step), rng.gen_range(-50, 100)))
+                        .unwrap();
+                }
+                2 => tree.remove(rng.gen_range(0, len)).unwrap(),
+                _ => {
+                    let index = rng.gen_range(0, len);
+                    tree.set_leaf(Leaf::new(format!("set{}", step), 3), index)
+                        .unwrap();
+                }
+            }
+            //Maintained in lockstep, the same digests as a fresh computation
+            let mut fresh = tree.clone();
+            fresh.set_keccak_dual(true);
+            assert_eq!(tree.keccak, fresh.keccak, "step {}", step);
+            let (hash, digest, sum) = tree.get_root_dual().unwrap();
+            assert_eq!(hash, tree.get_root().unwrap().get_hash());
+            assert_eq!(sum, tree.get_root_sum().unwrap());
+            assert_ne!(digest, hash.to_bytes(Endianness::Big));
+            assert_eq!(tree.keccak.as_ref().unwrap().len(), tree.get_nodes().len());
+        }
+
+        let root = tree.get_root_dual().unwrap();
+        for (index, leaf) in tree.get_leafs().iter().enumerate() {
+            if leaf.is_none() {
+                continue;
+            }
+            let proof = tree.get_dual_proof(index).unwrap().unwrap();
+            assert!(proof.verify(root).unwrap());
+            assert_eq!(proof.compute_keccak_root().unwrap(), (root.1, root.2));
+            assert!(tree.verify_proof(proof.get_proof()).unwrap());
+        }
+    }
+
+    #[test]
+    fn dual_hashing_is_opt_in() {
+        let mut tree = MerkleSumTree::new(leafs()).unwrap();
+        assert_eq!(tree.get_root_dual(), None);
+        assert_eq!(tree.get_dual_proof(0).unwrap(), None);
+        tree.set_keccak_dual(true);
+        let root = tree.get_root_dual().unwrap();
+        let proof = tree.get_dual_proof(2).unwrap().unwrap();
+        assert!(proof.verify(root).unwrap());
+
+        //A wrong keccak sibling fails even though the MiMC path still holds
+        let mut forged = proof.clone();
+        forged.keccak_path[0] = [0; 32];
+        assert!(!forged.verify(root).unwrap());
+        assert!(tree.verify_proof(forged.get_proof()).unwrap());
+        forged.keccak_path.pop();
+        assert!(matches!(
+            forged.compute_keccak_root(),
+            Err(MerkleError::InvalidLength { .. })
+        ));
+
+        let json = serde_json::to_string(&proof).unwrap();
+        assert_eq!(serde_json::from_str::<DualProof>(&json).unwrap(), proof);
+        tree.set_keccak_dual(false);
+        assert_eq!(tree.get_keccak_root(), None);
+    }
+}
@@ -0,0 +1,294 @@
+//Index math for the node layout
+
+use crate::{Fr, MerkleSumTree, Node, Position};
+
+//Number of levels of a tree over leaf_count slots, leaf_count a power of two
+pub(crate) fn level_count(leaf_count: usize) -> usize {
+    match leaf_count {
+        0 => 0,
+        _ => leaf_count.trailing_zeros() as usize + 1,
+    }
+}
+
+pub(crate) fn level_size(leaf_count: usize, level: usize) -> usize {
+    leaf_count.checked_shr(level as u32).unwrap_or(0)
+}
+
+//Flat index of the leftmost node of level
+pub(crate) fn level_start(leaf_count: usize, level: usize) -> usize {
+    (0..level).map(|below| level_size(leaf_count, below)).sum()
+}
+
+pub(crate) fn flat_index(leaf_count: usize, level: usize, offset: usize) -> Option<usize> {
+    if level >= level_count(leaf_count) || offset >= level_size(leaf_count, level) {
+        return None;
+    }
+    Some(level_start(leaf_count, level) + offset)
+}
+
+//Inverse of flat_index
+pub(crate) fn coordinates(leaf_count: usize, index: usize) -> Option<(usize, usize)> {
+    let mut start = 0;
+    for level in 0..level_count(leaf_count) {
+        let size = level_size(leaf_count, level);
+        if index < start + size {
+            return Some((level, index - start));
+        }
+        start += size;
+    }
+    None
+}
+
+//A node by level and offset within the level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct NodeIndex {
+    level: u16,
+    offset: u64,
+}
+
+impl NodeIndex {
+    pub(crate) fn new(level: usize, offset: usize) -> NodeIndex {
+        NodeIndex {
+            level: level as u16,
+            offset: offset as u64,
+        }
+    }
+
+    pub(crate) fn level(&self) -> usize {
+        usize::from(self.level)
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        self.offset as usize
+    }
+
+    pub(crate) fn sibling(&self) -> NodeIndex {
+        NodeIndex {
+            level: self.level,
+            offset: self.offset ^ 1,
+        }
+    }
+
+    pub(crate) fn parent(&self) -> NodeIndex {
+        NodeIndex {
+            level: self.level + 1,
+            offset: self.offset / 2,
+        }
+    }
+
+    //Where the sibling sits, Right of a left child
+    pub(crate) fn sibling_position(&self) -> Position {
+        match self.offset % 2 {
+            0 => Position::Right,
+            _ => Position::Left,
+        }
+    }
+}
+
+//One level of the walk from a leaf to the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PathStep {
+    pub(crate) sibling: NodeIndex,
+    pub(crate) position: Position,
+    pub(crate) parent: NodeIndex,
+}
+
+//Steps from the leaf at offset up to the root, bottom-up
+pub(crate) fn path(leaf_count: usize, offset: usize) -> Vec<PathStep> {
+    path_from(leaf_count, 0, offset)
+}
+
+//Steps from the node at (level, offset) up to the root, bottom-up
+pub(crate) fn path_from(leaf_count: usize, level: usize, offset: usize) -> Vec<PathStep> {
+    let mut steps = vec![];
+    let mut node = NodeIndex::new(level, offset);
+    for _ in level + 1..level_count(leaf_count) {
+        steps.push(PathStep {
+            sibling: node.sibling(),
+            position: node.sibling_position(),
+            parent: node.parent(),
+        });
+        node = node.parent();
+    }
+    steps
+}
+
+//A node with its coordinates, as yielded by traverse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    level: usize,
+    offset: usize,
+    index: usize,
+    hash: Fr,
+    value: i32,
+    empty_subtree: bool,
+}
+
+impl NodeInfo {
+    pub fn get_level(&self) -> usize {
+        self.level
+    }
+
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_hash(&self) -> Fr {
+        self.hash
+    }
+
+    pub fn get_value(&self) -> i32 {
+        self.value
+    }
+
+    pub fn get_node(&self) -> Node {
+        Node::new(self.hash, self.value)
+    }
+
+    //Every slot beneath is unoccupied, dummy padding counts as occupied
+    pub fn is_empty_subtree(&self) -> bool {
+        self.empty_subtree
+    }
+}
+
+impl MerkleSumTree {
+    //Breadth-first from the root down, each level left to right
+    pub fn traverse(&self) -> impl Iterator<Item = NodeInfo> + '_ {
+        let leaf_count = self.leafs.len();
+        let empty = self.empty_subtrees();
+        (0..level_count(leaf_count)).rev().flat_map(move |level| {
+            let start = level_start(leaf_count, level);
+            let empty = empty.clone();
+            let nodes = self.nodes.level(level).unwrap_or_default();
+            nodes.iter().enumerate().map(move |(offset, node)| {
+                let index = start + offset;
+                NodeInfo {
+                    level,
+                    offset,
+                    index,
+                    hash: node.hash,
+                    value: node.value,
+                    empty_subtree: empty.get(index).copied().unwrap_or(false),
+                }
+            })
+        })
+    }
+
+    //Per flat index, whether every slot beneath is unoccupied
+    pub(crate) fn empty_subtrees(&self) -> Vec<bool> {
+        let leaf_count = self.leafs.len();
+        let mut empty: Vec<bool> = self
+            .leafs
+            .iter()
+            .map(|leaf| self.is_empty_leaf(leaf))
+            .collect();
+        for level in 1..level_count(leaf_count) {
+            let below = level_start(leaf_count, level - 1);
+            for offset in 0..level_size(leaf_count, level) {
+                let left = empty.get(below + 2 * offset).copied().unwrap_or(false);
+                let right = empty.get(below + 2 * offset + 1).copied().unwrap_or(false);
+                empty.push(left && right);
+            }
+        }
+        empty
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::Leaf;
+    use std::collections::HashMap;
+
+    #[test]
+    fn coordinates_round_trip() {
+        assert_eq!(level_count(8), 4);
+        assert_eq!(level_start(8, 3), 14);
+        for index in 0..15 {
+            let (level, offset) = coordinates(8, index).unwrap();
+            assert_eq!(flat_index(8, level, offset), Some(index));
+        }
+        assert_eq!(coordinates(8, 15), None);
+        assert_eq!(flat_index(8, 1, 4), None);
+        assert_eq!(flat_index(8, 4, 0), None);
+
+        let steps = path(8, 5);
+        assert_eq!(
+            steps
+                .iter()
+                .map(|step| flat_index(8, step.sibling.level(), step.sibling.offset()).unwrap())
+                .collect::<Vec<_>>(),
+            vec![4, 11, 12]
+        );
+        assert_eq!(
+            steps
+                .iter()
+                .map(|step| flat_index(8, step.parent.level(), step.parent.offset()).unwrap())
+                .collect::<Vec<_>>(),
+            vec![10, 13, 14]
+        );
+        assert_eq!(steps[2].parent, NodeIndex::new(3, 0));
+        assert_eq!(steps[0].position, Position::Left);
+        assert_eq!(steps[1].position, Position::Right);
+        assert_eq!(path_from(8, 1, 2), steps[1..].to_vec());
+        assert!(path_from(8, 3, 0).is_empty());
+    }
+
+    #[test]
+    fn traversal_rehashes_to_the_root() {
+        let mut tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), -4),
+            Leaf::new("carol".to_string(), 30),
+            Leaf::new("dave".to_string(), 7),
+            Leaf::new("erin".to_string(), 1),
+        ])
+        .unwrap();
+        tree.remove(2).unwrap();
+        let infos: Vec<NodeInfo> = tree.traverse().collect();
+        assert_eq!(infos.len(), tree.get_nodes().len());
+        assert_eq!(infos[0].get_level(), 3);
+        assert_eq!(Some(infos[0].get_node()), tree.get_root());
+
+        let by_coordinates: HashMap<(usize, usize), NodeInfo> = infos
+            .iter()
+            .map(|info| ((info.get_level(), info.get_offset()), info.clone()))
+            .collect();
+        for info in &infos {
+            assert_eq!(tree.get_node(info.get_index()), Some(info.get_node()));
+            if info.get_level() == 0 {
+                continue;
+            }
+            let child = |offset| &by_coordinates[&(info.get_level() - 1, offset)];
+            let (left, right) = (
+                child(2 * info.get_offset()),
+                child(2 * info.get_offset() + 1),
+            );
+            let parent = MerkleSumTree::build_parent(left.get_node(), right.get_node()).unwrap();
+            assert_eq!(parent, info.get_node());
+            assert_eq!(
+                info.is_empty_subtree(),
+                left.is_empty_subtree() && right.is_empty_subtree()
+            );
+        }
+
+        let empty: Vec<(usize, usize)> = infos
+            .iter()
+            .filter(|info| info.is_empty_subtree())
+            .map(|info| (info.get_level(), info.get_offset()))
+            .collect();
+        assert_eq!(empty, vec![(1, 3), (0, 2), (0, 5), (0, 6), (0, 7)]);
+
+        //The proof path visits the same coordinates
+        let proof = tree.get_proof(4).unwrap().unwrap();
+        for (step, neighbor) in path(8, 4).iter().zip(proof.get_path()) {
+            assert_eq!(tree.nodes.get(step.sibling), Some(&neighbor.get_node()));
+            assert_eq!(step.position, neighbor.get_position());
+        }
+    }
+}
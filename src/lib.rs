@@ -1,48 +1,339 @@
+#![warn(clippy::indexing_slicing, clippy::unwrap_used)]
+
+#[cfg(feature = "ethereum")]
+mod abi;
+mod allocation;
+#[cfg(feature = "parallel")]
+mod archive;
+#[cfg(feature = "signing")]
+mod attestation;
+pub mod audit;
+mod batch;
+mod binary;
+mod cancel;
+mod canonical;
+mod check;
+mod checkpoint;
+mod commitment;
+mod compress;
 mod constants;
+mod container;
+mod display;
+mod error;
+mod external;
+mod field;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+mod hash_mode;
+mod hash_params;
+mod history;
+mod import;
+mod insert;
+mod json;
+mod keccak;
+mod layout;
+mod merge;
+mod metrics;
+mod migrate;
 mod mimc_sponge;
+mod mmr;
+mod multiproof;
+mod noalloc;
+mod nonmembership;
+#[cfg(feature = "rayon")]
+mod par_verify;
+mod plain;
+pub mod por;
+mod progress;
+mod range;
+mod rebuild;
+mod redact;
+mod rotation;
+mod rules;
+mod salt;
+mod sentinel;
+pub mod sharded;
+mod size;
+pub mod solvency;
+mod split;
+mod storage;
+mod subtree;
+#[cfg(test)]
+pub(crate) mod test_support;
+mod threshold;
+mod trusted;
+mod update;
+mod visit;
+mod wal;
+mod watch;
+mod witness;
 
-use crate::mimc_sponge::{Fr, MimcSponge};
-use anyhow::Result;
+pub use crate::allocation::AllocationStrategy;
+#[cfg(feature = "signing")]
+pub use crate::attestation::RootAttestation;
+pub use crate::audit::AuditReport;
+pub use crate::binary::{MAX_PROOF_LEVELS, PROOF_VERSION};
+pub use crate::cancel::CancellationToken;
+pub use crate::check::ProofCheck;
+pub use crate::checkpoint::Checkpoint;
+pub use crate::commitment::RootCommitment;
+pub use crate::compress::CompressedProof;
+pub use crate::error::{IntegrityViolation, MerkleError};
+pub use crate::external::{ExternalOptions, ExternalTree};
+pub use crate::field::Endianness;
+#[cfg(feature = "arbitrary")]
+pub use crate::fuzz::ArbitrarySize;
+pub use crate::hash_mode::LeafHashMode;
+pub use crate::hash_params::{HashFunction, HashParams, ValueEncoding};
+use crate::history::RootHistory;
+pub use crate::keccak::DualProof;
+use crate::layout::NodeIndex;
+pub use crate::layout::NodeInfo;
+pub use crate::merge::MergePolicy;
+use crate::metrics::MetricsSink;
+pub use crate::metrics::{CountingMetrics, Metrics, MutationKind};
+pub use crate::migrate::{MigrationReport, TreeParams};
+use crate::mimc_sponge::{small_value, DEFAULT_EXPONENT};
+pub use crate::mimc_sponge::{Fr, MimcSponge};
+pub use crate::mmr::{MmrProof, MmrSumTree};
+pub use crate::multiproof::MultiProof;
+pub use crate::nonmembership::NonMembershipProof;
+pub use crate::plain::PlainProof;
+use crate::progress::{finish_all, PhaseTracker, ProgressSink};
+pub use crate::progress::{Phase, Progress, ProgressCallback, PROGRESS_INTERVAL};
+pub use crate::range::RangeProof;
+pub use crate::rebuild::RebuildReport;
+pub use crate::redact::RedactedProof;
+pub use crate::rotation::{RotationOpening, RotationProof};
+pub use crate::rules::{CharPredicate, LeafRule, LeafRules};
+pub use crate::salt::{DummyId, Salt, SaltDeriver};
+pub use crate::sentinel::EmptyLeafSpec;
+pub use crate::size::{MemoryReport, ProofFormat};
+use crate::storage::NodeStore;
+pub use crate::subtree::SubtreeProof;
+pub use crate::threshold::ThresholdInputs;
+pub use crate::trusted::TrustedBuildOpts;
+pub use crate::update::LeafUpdateProof;
+pub use crate::visit::{TreeVisitor, VisitControl};
+pub use crate::wal::{LogEntry, MutationLog, MutationLogReader};
+use crate::wal::{LogKind, Logger};
+use crate::watch::Watches;
+pub use crate::watch::{LeafChange, WatchCallback, WatchId, WatchTarget};
 use ff::{self, *};
-use std::cmp::Ordering;
+use rand::{ChaChaRng, Rng, SeedableRng};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::sync::Arc;
+use std::time::Instant;
 
+/// Largest supported height: a tree of height `h` holds `2^(h-1)` leafs and
+/// `2^h - 1` nodes, and both must be addressable with a `usize`.
+pub const MAX_HEIGHT: usize = usize::BITS as usize;
 
-#[derive(Debug, Clone)]
+//Deserialization goes through validate, see the Deserialize impl below
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct MerkleSumTree {
     leafs: Vec<Leaf>,
-    nodes: Vec<Node>,
+    nodes: NodeStore,
     height: usize,
     zero_index: Vec<usize>,
+    append_only: bool,
+    next_slot: usize,
+    retired: BTreeSet<usize>,
+    #[serde(default = "default_max_height")]
+    max_height: usize,
+    #[serde(default)]
+    epoch: u64,
+    #[serde(default)]
+    shuffle_seed: Option<[u8; 32]>,
+    #[serde(default)]
+    dummy_seed: Option<[u8; 32]>,
+    #[serde(default)]
+    dummies: BTreeSet<usize>,
+    #[serde(default)]
+    empty: EmptyLeafSpec,
+    #[serde(default)]
+    max_leaf_value: Option<i64>,
+    #[serde(default)]
+    non_negative: bool,
+    #[serde(default)]
+    leaf_hash_mode: LeafHashMode,
+    #[serde(
+        default = "default_exponent",
+        skip_serializing_if = "is_default_exponent"
+    )]
+    mimc_exponent: u64,
+    #[serde(default)]
+    canonical: bool,
+    //Runtime policy, a Random seed must not end up in serialized trees
+    #[serde(skip)]
+    allocation: AllocationStrategy,
+    #[serde(skip)]
+    rules: Option<LeafRules>,
+    #[serde(skip)]
+    history: RootHistory,
+    #[serde(skip)]
+    plain: Option<Vec<Fr>>,
+    #[serde(skip)]
+    keccak: Option<Vec<[u8; 32]>>,
+    //Derived from leafs, rebuilt on deserialization; keys share the leafs' id strings
+    #[serde(skip)]
+    ids: HashMap<Arc<str>, BTreeSet<usize>>,
+    #[serde(skip)]
+    watches: Watches,
+    #[serde(skip)]
+    log: Logger,
+    #[serde(skip)]
+    metrics: MetricsSink,
+    #[serde(skip)]
+    progress: ProgressSink,
+    #[serde(skip)]
+    cancel: CancellationToken,
+}
+
+#[derive(Debug, Clone)]
+pub struct MerkleSumTreeBuilder {
+    append_only: bool,
+    max_height: usize,
+    shuffle_seed: Option<[u8; 32]>,
+    record_seed: bool,
+    dummy_seed: Option<[u8; 32]>,
+    empty: EmptyLeafSpec,
+    max_leaf_value: Option<i64>,
+    non_negative: bool,
+    leaf_hash_mode: LeafHashMode,
+    mimc_exponent: u64,
+    allocation: AllocationStrategy,
+    leaf_rules: Option<LeafRules>,
+    history: usize,
+    plain_root: bool,
+    keccak_dual: bool,
+    metrics: MetricsSink,
+    progress: ProgressSink,
+    cancel: CancellationToken,
+}
+
+fn default_max_height() -> usize {
+    MAX_HEIGHT
+}
+
+fn default_exponent() -> u64 {
+    DEFAULT_EXPONENT
+}
+
+fn is_default_exponent(exponent: &u64) -> bool {
+    *exponent == DEFAULT_EXPONENT
+}
+
+//Marks a proof deserialized without an index
+const UNSTATED_INDEX: usize = usize::MAX;
+
+fn unstated_index() -> usize {
+    UNSTATED_INDEX
+}
+
+//The index a path walks up from: bit i is set when the neighbor at level i is on the
+//left. Levels past usize::BITS fall off the top
+pub(crate) fn path_index(path: &[Neighbor]) -> usize {
+    path.iter()
+        .rev()
+        .fold(0, |index, neighbor| match neighbor.position {
+            Position::Left => (index << 1) | 1,
+            Position::Right => index << 1,
+        })
 }
 
-#[derive(Debug, Clone, PartialEq)]
+//Sharing a tree across threads relies on these, fail the build if one regresses
+fn assert_send_sync<T: Send + Sync>() {}
+
+const _: fn() = || {
+    assert_send_sync::<MerkleSumTree>();
+    assert_send_sync::<MerkleSumTreeBuilder>();
+    assert_send_sync::<Leaf>();
+    assert_send_sync::<Node>();
+    assert_send_sync::<InclusionProof>();
+    assert_send_sync::<LeafUpdateProof>();
+    assert_send_sync::<AuditReport>();
+    assert_send_sync::<RootCommitment>();
+    assert_send_sync::<Neighbor>();
+    assert_send_sync::<Position>();
+    assert_send_sync::<MmrSumTree>();
+    assert_send_sync::<MmrProof>();
+    assert_send_sync::<LeafChange>();
+    assert_send_sync::<NodeInfo>();
+    assert_send_sync::<SubtreeProof>();
+    assert_send_sync::<Checkpoint>();
+    assert_send_sync::<RebuildReport>();
+    assert_send_sync::<EmptyLeafSpec>();
+    assert_send_sync::<LeafHashMode>();
+    assert_send_sync::<RedactedProof>();
+    assert_send_sync::<ThresholdInputs>();
+    assert_send_sync::<ExternalTree>();
+    assert_send_sync::<Endianness>();
+    assert_send_sync::<MemoryReport>();
+    assert_send_sync::<CountingMetrics>();
+    assert_send_sync::<Progress>();
+    assert_send_sync::<CancellationToken>();
+    assert_send_sync::<Salt>();
+    assert_send_sync::<SaltDeriver>();
+    assert_send_sync::<RotationProof>();
+    assert_send_sync::<RotationOpening>();
+    assert_send_sync::<LogEntry>();
+    assert_send_sync::<MutationLog>();
+    assert_send_sync::<sharded::TopTree>();
+    assert_send_sync::<sharded::CompositeProof>();
+    assert_send_sync::<MimcSponge>();
+    assert_send_sync::<HashParams>();
+    assert_send_sync::<Fr>();
+    assert_send_sync::<MerkleError>();
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Leaf {
-    id: String,
+    //Shared with the id index and every proof of the leaf, serialized as a plain string
+    id: Arc<str>,
     node: Node,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Node {
     hash: Fr,
     value: i32,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+//Proofs serialized without an index take the one their path spells, and paths above
+//MAX_PROOF_LEVELS or disagreeing with the index are refused, see the Deserialize impl
+//below
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct InclusionProof {
     leaf: Leaf,
+    //Slot the proof was generated for, bit i agrees with the neighbor at level i
+    #[serde(default = "unstated_index")]
+    index: usize,
     path: Vec<Neighbor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root: Option<Node>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    epoch: Option<u64>,
+    #[serde(
+        default = "default_exponent",
+        skip_serializing_if = "is_default_exponent"
+    )]
+    exponent: u64,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Neighbor {
     position: Position,
     node: Node,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Position {
     Left,
     Right,
@@ -64,8 +355,21 @@ impl Node {
     }
 }
 
+//Hash the canonical repr so Hash agrees with PartialEq
+impl Hash for Node {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash.to_repr().as_ref().hash(state);
+        self.value.hash(state);
+    }
+}
+
 impl Leaf {
     pub fn new(id: String, value: i32) -> Leaf {
+        Leaf::with_shared_id(Arc::from(id), value)
+    }
+
+    //Leaf::new without copying the id, the hash of a str is the hash of its String
+    pub(crate) fn with_shared_id(id: Arc<str>, value: i32) -> Leaf {
         let mut hr = DefaultHasher::new();
         id.hash(&mut hr);
         let hash = Fr::from_u128(hr.finish() as u128);
@@ -74,14 +378,19 @@ impl Leaf {
     }
 
     pub fn get_id(&self) -> String {
-        self.id.clone()
+        self.id.to_string()
+    }
+
+    //The id without a copy
+    pub fn get_id_str(&self) -> &str {
+        &self.id
     }
     pub fn get_node(&self) -> Node {
         self.node.clone()
     }
 
     pub fn is_none(&self) -> bool {
-        self.get_id() == "0".to_string() && self.get_node().get_value() == 0
+        &*self.id == "0" && self.node.value == 0
     }
 }
 
@@ -98,283 +407,1456 @@ impl Neighbor {
     }
 }
 
+impl Default for MerkleSumTreeBuilder {
+    fn default() -> Self {
+        MerkleSumTreeBuilder {
+            append_only: false,
+            max_height: MAX_HEIGHT,
+            shuffle_seed: None,
+            record_seed: true,
+            dummy_seed: None,
+            empty: EmptyLeafSpec::default(),
+            max_leaf_value: None,
+            non_negative: false,
+            leaf_hash_mode: LeafHashMode::default(),
+            mimc_exponent: DEFAULT_EXPONENT,
+            allocation: AllocationStrategy::default(),
+            leaf_rules: None,
+            history: 0,
+            plain_root: false,
+            keccak_dual: false,
+            metrics: MetricsSink::default(),
+            progress: ProgressSink::default(),
+            cancel: CancellationToken::default(),
+        }
+    }
+}
+
+impl MerkleSumTreeBuilder {
+    //Never hand out a slot again once it has held a leaf
+    pub fn append_only(mut self, append_only: bool) -> Self {
+        self.append_only = append_only;
+        self
+    }
+
+    //Lower the height cap, values above MAX_HEIGHT are clamped to it
+    pub fn max_height(mut self, max_height: usize) -> Self {
+        self.max_height = max_height.min(MAX_HEIGHT);
+        self
+    }
+
+    //Permute the leafs with a generator seeded from seed before building
+    pub fn shuffle_seed(mut self, seed: [u8; 32]) -> Self {
+        self.shuffle_seed = Some(seed);
+        self
+    }
+
+    //Keep the shuffle seed on the tree so the operator can rebuild it, true by default
+    pub fn record_seed(mut self, record_seed: bool) -> Self {
+        self.record_seed = record_seed;
+        self
+    }
+
+    //Pad with zero-value leafs whose ids are derived from seed instead of the empty leaf
+    pub fn dummy_padding(mut self, seed: [u8; 32]) -> Self {
+        self.dummy_seed = Some(seed);
+        self
+    }
+
+    //Leaf held by unoccupied slots, id "0" with value 0 by default
+    pub fn empty_leaf(mut self, empty: EmptyLeafSpec) -> Self {
+        self.empty = empty;
+        self
+    }
+
+    //Refuse any leaf worth more than cap, now and on every later mutation
+    pub fn max_leaf_value(mut self, cap: Option<i64>) -> Self {
+        self.max_leaf_value = cap;
+        self
+    }
+
+    //Refuse any leaf with a negative value, now and on every later mutation, so no
+    //balance can deflate the root sum
+    pub fn non_negative(mut self, non_negative: bool) -> Self {
+        self.non_negative = non_negative;
+        self
+    }
+
+    //Derive leaf hashes from the id alone (the default) or from the id and value
+    pub fn leaf_hash_mode(mut self, mode: LeafHashMode) -> Self {
+        self.leaf_hash_mode = mode;
+        self
+    }
+
+    //Round exponent of the MiMC permutation, 5 by default, checked against the field at build
+    pub fn mimc_exponent(mut self, exponent: u64) -> Self {
+        self.mimc_exponent = exponent;
+        self
+    }
+
+    //Order push fills free slots in, FirstFree by default
+    pub fn allocation_strategy(mut self, strategy: AllocationStrategy) -> Self {
+        self.allocation = strategy;
+        self
+    }
+
+    //Refuse leafs breaking rules, at build and on every later insertion
+    pub fn leaf_rules(mut self, rules: LeafRules) -> Self {
+        self.leaf_rules = Some(rules);
+        self
+    }
+
+    //Keep the roots of the last capacity epochs, off (0) by default
+    pub fn root_history(mut self, capacity: usize) -> Self {
+        self.history = capacity;
+        self
+    }
+
+    //Also maintain a plain Merkle root over the leaf hashes, off by default
+    pub fn plain_root(mut self, enabled: bool) -> Self {
+        self.plain_root = enabled;
+        self
+    }
+
+    //Also keep a keccak-256 digest of every node, off by default
+    pub fn keccak_dual(mut self, enabled: bool) -> Self {
+        self.keccak_dual = enabled;
+        self
+    }
+
+    //Report construction and every later hash and mutation to metrics
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = MetricsSink::new(Some(metrics));
+        self
+    }
+
+    //Report the build, and every later rebuild and validation, to progress
+    pub fn on_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = ProgressSink::new(Some(progress));
+        self
+    }
+
+    //Abort the build, and every later long operation, once token is cancelled
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    pub fn build(self, mut leafs: Vec<Leaf>) -> Result<MerkleSumTree, MerkleError> {
+        if let Some(rules) = &self.leaf_rules {
+            for leaf in leafs.iter().filter(|leaf| !self.empty.matches(leaf)) {
+                rules.check_leaf(leaf)?;
+            }
+        }
+        if self.non_negative {
+            if let Some(leaf) = leafs.iter().find(|leaf| leaf.node.value < 0) {
+                return Err(MerkleError::NegativeValue {
+                    id: leaf.get_id(),
+                    value: i64::from(leaf.node.value),
+                });
+            }
+        }
+        if let Some(seed) = self.shuffle_seed {
+            shuffle_leafs(&mut leafs, seed);
+        }
+        let mut tree = MerkleSumTree::create_tree(
+            leafs,
+            self.max_height,
+            self.dummy_seed,
+            BTreeSet::new(),
+            self.empty,
+            self.leaf_hash_mode,
+            self.mimc_exponent,
+            &self.progress,
+            &self.cancel,
+        )?;
+        tree.append_only = self.append_only;
+        if self.record_seed {
+            tree.shuffle_seed = self.shuffle_seed;
+        }
+        tree.max_leaf_value = self.max_leaf_value;
+        tree.non_negative = self.non_negative;
+        tree.allocation = self.allocation;
+        tree.rules = self.leaf_rules;
+        tree.set_root_history(self.history);
+        let violations = tree.value_violations();
+        if !violations.is_empty() {
+            return Err(MerkleError::InvalidTree(violations));
+        }
+        tree.metrics = self.metrics;
+        tree.progress = self.progress;
+        tree.cancel = self.cancel;
+        tree.metrics.hashes(tree.nodes.len() - tree.leafs.len());
+        tree.set_plain_root(self.plain_root);
+        tree.set_keccak_dual(self.keccak_dual);
+        Ok(tree)
+    }
+}
+
 impl MerkleSumTree {
-    pub fn new(leafs: Vec<Leaf>) -> Result<MerkleSumTree> {
-        Self::create_tree(leafs)
+    pub fn new(leafs: Vec<Leaf>) -> Result<MerkleSumTree, MerkleError> {
+        Self::create_tree(
+            leafs,
+            MAX_HEIGHT,
+            None,
+            BTreeSet::new(),
+            EmptyLeafSpec::default(),
+            LeafHashMode::default(),
+            DEFAULT_EXPONENT,
+            &ProgressSink::default(),
+            &CancellationToken::default(),
+        )
+    }
+
+    pub fn builder() -> MerkleSumTreeBuilder {
+        MerkleSumTreeBuilder::default()
+    }
+
+    //Same seed, same leafs, same tree; positions do not correlate across seeds
+    pub fn new_shuffled(leafs: Vec<Leaf>, seed: [u8; 32]) -> Result<MerkleSumTree, MerkleError> {
+        Self::builder().shuffle_seed(seed).build(leafs)
     }
 
     pub fn get_root_hash(&self) -> Option<Fr> {
-        match self.nodes.len() {
-            0 => None,
-            n => Some(self.nodes[n - 1].get_hash()),
-        }
+        self.nodes.root().map(Node::get_hash)
     }
 
     pub fn get_root_sum(&self) -> Option<i32> {
-        match self.nodes.len() {
-            0 => None,
-            n => Some(self.nodes[n - 1].get_value()),
-        }
+        self.nodes.root().map(Node::get_value)
     }
 
     pub fn get_root(&self) -> Option<Node> {
-        match self.nodes.len() {
-            0 => None,
-            n => self.get_node(n - 1),
-        }
+        self.nodes.root().cloned()
+    }
+
+    //Root and epoch as one value, the form every verifier takes
+    pub fn get_commitment(&self) -> Option<RootCommitment> {
+        self.nodes
+            .root()
+            .map(|root| RootCommitment::from_node(root, self.epoch))
     }
 
+    //Flat layout, levels bottom-up
     pub fn get_nodes(&self) -> Vec<Node> {
-        self.nodes.clone()
+        self.nodes.to_flat()
     }
 
     pub fn get_leafs(&self) -> Vec<Leaf> {
         self.leafs.clone()
     }
 
+    #[deprecated(note = "use iter_free_slots or free_slot_count")]
     pub fn get_zero_index(&self) -> Vec<usize> {
         self.zero_index.clone()
     }
 
+    //By flat index
     pub fn get_node(&self, index: usize) -> Option<Node> {
-        match index.cmp(&self.get_nodes().len()) {
-            Ordering::Less => Some(self.nodes[index].clone()),
-            _ => None,
-        }
+        self.nodes.get_flat(index).cloned()
     }
 
     pub fn get_leaf(&self, index: usize) -> Option<Leaf> {
-        match index.cmp(&self.get_leafs().len()) {
-            Ordering::Less => Some(self.leafs[index].clone()),
-            _ => None,
-        }
+        self.leafs.get(index).cloned()
     }
 
     pub fn get_height(&self) -> usize {
         self.height.clone()
     }
 
-    fn update_path(&mut self, leaf: Leaf, index: usize) -> Result<()> {
-        let height = self.height;
-        let mut level_size = 1 << (height - 1);
-        let mut level_index = index;
-        let mut current_index = index;
-        let mut level_start = 0;
-        let mut current_node = leaf.get_node();
-        for _ in 1..height {
-            if current_index % 2 == 0 {
-                let neighbor = self.get_node(current_index + 1).unwrap();
-                current_node = Self::build_parent(current_node, neighbor)?;
-            } else {
-                let neighbor = self.get_node(current_index - 1).unwrap();
-                current_node = Self::build_parent(neighbor, current_node)?;
-            }
-            level_start += level_size;
-            level_index = level_index / 2;
-            current_index = level_start + level_index;
-            level_size = level_size / 2;
-            self.nodes[current_index] = current_node.clone();
+    pub fn is_append_only(&self) -> bool {
+        self.append_only
+    }
+
+    pub fn get_retired(&self) -> Vec<usize> {
+        self.retired.iter().copied().collect()
+    }
+
+    pub fn get_max_height(&self) -> usize {
+        self.max_height
+    }
+
+    //Number of mutations applied since construction
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn get_shuffle_seed(&self) -> Option<[u8; 32]> {
+        self.shuffle_seed
+    }
+
+    pub fn get_empty_leaf(&self) -> EmptyLeafSpec {
+        self.empty.clone()
+    }
+
+    pub fn get_max_leaf_value(&self) -> Option<i64> {
+        self.max_leaf_value
+    }
+
+    pub fn is_non_negative(&self) -> bool {
+        self.non_negative
+    }
+
+    pub fn get_leaf_hash_mode(&self) -> LeafHashMode {
+        self.leaf_hash_mode
+    }
+
+    pub fn get_mimc_exponent(&self) -> u64 {
+        self.mimc_exponent
+    }
+
+    //Sponge of the tree's exponent, validated when the tree was built or loaded
+    fn sponge(&self) -> MimcSponge {
+        MimcSponge::with_exponent(self.mimc_exponent).unwrap_or_default()
+    }
+
+    //For proofs whose verifiers carry no exponent and fold with the default one
+    fn require_default_exponent(&self) -> Result<(), MerkleError> {
+        match self.mimc_exponent {
+            DEFAULT_EXPONENT => Ok(()),
+            exponent => Err(MerkleError::UnsupportedExponent(exponent)),
         }
-        Ok(())
     }
 
-    pub fn get_proof(&self, index: usize) -> Result<Option<InclusionProof>> {
-        if self.get_leafs().len() <= index {
-            return Ok(None);
+    //Leafs above the cap or, in a non-negative tree, below zero, padding included
+    fn value_violations(&self) -> Vec<IntegrityViolation> {
+        let values = self
+            .leafs
+            .iter()
+            .enumerate()
+            .map(|(index, leaf)| (index, i64::from(leaf.node.value)));
+        let mut violations = vec![];
+        for (index, value) in values {
+            match self.max_leaf_value {
+                Some(cap) if value > cap => {
+                    violations.push(IntegrityViolation::ValueAboveCap { index, value, cap })
+                }
+                _ => {}
+            }
+            if self.non_negative && value < 0 {
+                violations.push(IntegrityViolation::NegativeValue { index, value });
+            }
+        }
+        violations
+    }
+
+    fn check_value(&self, leaf: &Leaf) -> Result<(), MerkleError> {
+        let value = i64::from(leaf.node.value);
+        match self.max_leaf_value {
+            Some(cap) if value > cap => Err(MerkleError::ValueAboveCap {
+                id: leaf.get_id(),
+                value,
+                cap,
+            }),
+            _ if self.non_negative && value < 0 => Err(MerkleError::NegativeValue {
+                id: leaf.get_id(),
+                value,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    //Whether leaf is this tree's placeholder for an unoccupied slot
+    fn is_empty_leaf(&self, leaf: &Leaf) -> bool {
+        self.empty.matches(leaf)
+    }
+
+    //Dummy padding looks like any other leaf from outside the tree
+    pub fn is_dummy(&self, index: usize) -> bool {
+        self.dummies.contains(&index)
+    }
+
+    //Occupied slots in index order, dummy padding only when include_dummies
+    pub fn non_empty_leafs(
+        &self,
+        include_dummies: bool,
+    ) -> impl Iterator<Item = (usize, &Leaf)> + '_ {
+        self.leafs.iter().enumerate().filter(move |(index, leaf)| {
+            !self.is_empty_leaf(leaf) && (include_dummies || !self.dummies.contains(index))
+        })
+    }
+
+    //First slot holding id, empty and dummy slots are never indexed
+    pub fn get_index_by_id(&self, id: &str) -> Option<usize> {
+        self.ids
+            .get(id)
+            .and_then(|indices| indices.first().copied())
+    }
+
+    //Every slot holding id, in index order
+    pub fn get_indices_by_id(&self, id: &str) -> Vec<usize> {
+        self.ids
+            .get(id)
+            .map_or(vec![], |indices| indices.iter().copied().collect())
+    }
+
+    //Proof of the first slot holding id
+    pub fn get_proof_by_id(&self, id: &str) -> Result<InclusionProof, MerkleError> {
+        let index = self
+            .get_index_by_id(id)
+            .ok_or_else(|| MerkleError::UnknownId(id.to_string()))?;
+        self.get_proof(index)?
+            .ok_or(MerkleError::IndexOutOfBounds(index))
+    }
+
+    fn node_at(&self, at: NodeIndex) -> Result<Node, MerkleError> {
+        self.nodes
+            .get(at)
+            .cloned()
+            .ok_or_else(|| MerkleError::MissingNode(self.nodes.flat(at)))
+    }
+
+    //Recompute the ancestors of a leaf without touching the tree
+    fn path_updates(
+        &self,
+        node: Node,
+        index: usize,
+    ) -> Result<Vec<(NodeIndex, Node)>, MerkleError> {
+        let mut updates = vec![];
+        let mut current_node = node;
+        let params = self.get_hash_params();
+        for step in layout::path(self.leafs.len(), index) {
+            let neighbor = self.node_at(step.sibling)?;
+            current_node = match step.position {
+                Position::Right => Self::build_parent_with(&params, current_node, neighbor)?,
+                Position::Left => Self::build_parent_with(&params, neighbor, current_node)?,
+            };
+            updates.push((step.parent, current_node.clone()));
+        }
+        Ok(updates)
+    }
+
+    //Empty slots are refused, use get_proof_allow_empty to prove a slot is unoccupied
+    pub fn get_proof(&self, index: usize) -> Result<Option<InclusionProof>, MerkleError> {
+        match self.get_leaf(index) {
+            Some(leaf) if self.is_empty_leaf(&leaf) => Err(MerkleError::EmptySlot(index)),
+            _ => self.get_proof_allow_empty(index),
         }
-        let leaf = self.get_leaf(index).unwrap();
+    }
+
+    pub fn get_proof_allow_empty(
+        &self,
+        index: usize,
+    ) -> Result<Option<InclusionProof>, MerkleError> {
+        let leaf = match self.get_leaf(index) {
+            Some(leaf) => leaf,
+            None => return Ok(None),
+        };
         let mut path = vec![];
-        let height = self.get_height();
-        let mut level_size = 1 << (height - 1);
-        let mut level_index = index;
-        let mut current_index = index;
-        let mut level_start = 0;
-        for _ in 1..height {
-            if current_index % 2 == 0 {
-                let node = self.get_node(current_index + 1).unwrap();
-                let neighbor = Neighbor {
-                    position: Position::Right,
-                    node,
-                };
-                path.push(neighbor);
-            } else {
-                let node = self.get_node(current_index - 1).unwrap();
-                let neighbor = Neighbor {
-                    position: Position::Left,
-                    node,
-                };
-                path.push(neighbor);
-            }
-            level_start += level_size;
-            level_index = level_index / 2;
-            current_index = level_start + level_index;
-            level_size = level_size / 2;
+        for step in layout::path(self.leafs.len(), index) {
+            path.push(Neighbor {
+                position: step.position,
+                node: self.node_at(step.sibling)?,
+            });
         }
-        Ok(Some(InclusionProof { leaf, path }))
+        Ok(Some(InclusionProof {
+            leaf,
+            index,
+            path,
+            root: None,
+            epoch: None,
+            exponent: self.mimc_exponent,
+        }))
     }
 
-    pub fn verify_proof(&self, proof: InclusionProof) -> Result<bool> {
-        let mut node = proof.leaf.get_node();
-        let path = proof.path;
+    //Proof that index holds the tree's empty leaf; an account or dummy padding there
+    //is refused, so the proof never doubles as a membership proof
+    pub fn get_emptiness_proof(&self, index: usize) -> Result<InclusionProof, MerkleError> {
+        let leaf = self
+            .leafs
+            .get(index)
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        if !self.is_empty_leaf(leaf) {
+            return Err(MerkleError::SlotOccupied {
+                index,
+                id: leaf.get_id(),
+            });
+        }
+        self.get_proof_allow_empty(index)?
+            .ok_or(MerkleError::IndexOutOfBounds(index))
+    }
 
-        for neighbor in path {
-            match neighbor.position {
-                Position::Right => {
-                    node = Self::build_parent(node, neighbor.node)?;
-                }
-                Position::Left => {
-                    node = Self::build_parent(neighbor.node, node)?;
-                }
+    //verify_proof, and the proven leaf must be the tree's empty leaf
+    pub fn verify_emptiness_proof(&self, proof: InclusionProof) -> Result<bool, MerkleError> {
+        if !self.is_empty_leaf(&proof.leaf) {
+            return Ok(false);
+        }
+        self.verify_proof(proof)
+    }
+
+    //verify_proof, and the proven leaf must be the one stored at the proof's index
+    pub fn verify_proof_strict(&self, proof: InclusionProof) -> Result<bool, MerkleError> {
+        if self.leafs.get(proof.index) != Some(&proof.leaf) {
+            return Ok(false);
+        }
+        self.verify_proof(proof)
+    }
+
+    //get_proof stamped with the current root and epoch, for proofs checked later
+    pub fn get_proof_with_root(&self, index: usize) -> Result<InclusionProof, MerkleError> {
+        let proof = self
+            .get_proof(index)?
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        Ok(self.stamp(proof))
+    }
+
+    //get_proof of every index, in order, reading each level once for all of them.
+    //Every index is checked before any path is built
+    pub fn get_proofs(&self, indices: &[usize]) -> Result<Vec<InclusionProof>, MerkleError> {
+        if let Some(index) = indices.iter().find(|index| **index >= self.leafs.len()) {
+            return Err(MerkleError::IndexOutOfBounds(*index));
+        }
+        let mut leafs = Vec::with_capacity(indices.len());
+        for &index in indices {
+            match self.leafs.get(index) {
+                Some(leaf) if !self.is_empty_leaf(leaf) => leafs.push(leaf.clone()),
+                _ => return Err(MerkleError::EmptySlot(index)),
+            }
+        }
+        let levels = layout::level_count(self.leafs.len()).saturating_sub(1);
+        let mut paths: Vec<Vec<Neighbor>> =
+            indices.iter().map(|_| Vec::with_capacity(levels)).collect();
+        for level in 0..levels {
+            let nodes = self.nodes.level(level).unwrap_or_default();
+            for (path, index) in paths.iter_mut().zip(indices) {
+                let node = NodeIndex::new(level, index >> level);
+                let sibling = node.sibling();
+                let sibling_node = nodes
+                    .get(sibling.offset())
+                    .ok_or_else(|| MerkleError::MissingNode(self.nodes.flat(sibling)))?;
+                path.push(Neighbor {
+                    position: node.sibling_position(),
+                    node: sibling_node.clone(),
+                });
             }
         }
-        Ok(node.is_equal(self.get_root().unwrap()))
+        Ok(leafs
+            .into_iter()
+            .zip(indices)
+            .zip(paths)
+            .map(|((leaf, &index), path)| InclusionProof {
+                leaf,
+                index,
+                path,
+                root: None,
+                epoch: None,
+                exponent: self.mimc_exponent,
+            })
+            .collect())
+    }
+
+    //A proof stamped with a root other than the current one is stale and fails. A
+    //path of the wrong length for this tree is refused rather than folded. See
+    //verify_proof_detailed for why a proof fails
+    pub fn verify_proof(&self, proof: InclusionProof) -> Result<bool, MerkleError> {
+        self.verify_proof_detailed(&proof)?.into_verdict()
+    }
+
+    //dummies lists the dummy slots already among leafs, padding adds its own
+    #[allow(clippy::too_many_arguments)]
+    fn create_tree(
+        leafs: Vec<Leaf>,
+        max_height: usize,
+        dummy_seed: Option<[u8; 32]>,
+        mut dummies: BTreeSet<usize>,
+        empty: EmptyLeafSpec,
+        mode: LeafHashMode,
+        exponent: u64,
+        progress: &ProgressSink,
+        cancel: &CancellationToken,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        let params = HashParams::mimc(exponent)?;
+        let filled = leafs.len();
+        let (leafs, height) = Self::fill_leafs(leafs, max_height, dummy_seed, &empty)?;
+        if dummy_seed.is_some() {
+            dummies.extend(filled..leafs.len());
+        }
+        let hashing = progress.phase(Phase::LeafHashing, Some(leafs.len() as u64));
+        let leafs = leafs
+            .into_iter()
+            .enumerate()
+            .map(|(index, leaf)| {
+                cancel.check_at(index)?;
+                hashing.advance(1);
+                Ok(mode.normalize(leaf))
+            })
+            .collect::<Result<Vec<Leaf>, MerkleError>>()?;
+        hashing.finish();
+        let levels = progress.levels(leafs.len());
+        let nodes = Self::build_nodes(&leafs, &params, &levels, cancel)?;
+        finish_all(&levels);
+        let mut tree = Self::assemble(leafs, nodes, height, max_height, dummy_seed, dummies, empty);
+        tree.leaf_hash_mode = mode;
+        tree.mimc_exponent = exponent;
+        Ok(tree)
     }
 
-    fn create_tree(mut leafs: Vec<Leaf>) -> Result<MerkleSumTree> {
-        let height;
+    //Derive the bookkeeping of a fresh tree from its padded leafs and nodes
+    fn assemble(
+        mut leafs: Vec<Leaf>,
+        nodes: Vec<Node>,
+        height: usize,
+        max_height: usize,
+        dummy_seed: Option<[u8; 32]>,
+        dummies: BTreeSet<usize>,
+        empty: EmptyLeafSpec,
+    ) -> MerkleSumTree {
         let mut zero_index = vec![];
-        (leafs, height, zero_index) = Self::fill_leafs(leafs)?;
-        let mut nodes: Vec<Node> = vec![];
-        let mut nodes_to_hash: Vec<Node> = vec![];
-        let mut temp_hash_nodes: Vec<Node> = vec![];
-        let mut i = 0;
-        for leaf in leafs.iter() {
-            if leaf.is_none() {
+        for (i, leaf) in leafs.iter().enumerate() {
+            if empty.matches(leaf) {
                 zero_index.push(i)
             }
-            let node = leaf.get_node();
-            nodes.push(node.clone());
-            nodes_to_hash.push(node);
-            i += 1
         }
-        while nodes_to_hash.len() > 1 {
-            let mut j = 0;
-            while j < nodes_to_hash.len() {
-                let new_node =
-                    Self::build_parent(nodes_to_hash[j].clone(), nodes_to_hash[j + 1].clone())?;
-                nodes.push(new_node.clone());
-                temp_hash_nodes.push(new_node);
-                j += 2;
-            }
-            nodes_to_hash = temp_hash_nodes.clone();
-            temp_hash_nodes = vec![];
-        }
-        Ok(MerkleSumTree {
+        let ids = Self::index_ids(&mut leafs, &dummies, &empty);
+        let nodes = NodeStore::from_flat(nodes);
+        let next_slot = leafs
+            .iter()
+            .enumerate()
+            .rposition(|(index, leaf)| !empty.matches(leaf) && !dummies.contains(&index))
+            .map_or(0, |index| index + 1);
+        MerkleSumTree {
             leafs,
             nodes,
             height,
             zero_index,
-        })
+            append_only: false,
+            next_slot,
+            retired: BTreeSet::new(),
+            max_height,
+            epoch: 0,
+            shuffle_seed: None,
+            dummy_seed,
+            dummies,
+            empty,
+            max_leaf_value: None,
+            non_negative: false,
+            leaf_hash_mode: LeafHashMode::default(),
+            mimc_exponent: DEFAULT_EXPONENT,
+            canonical: false,
+            allocation: AllocationStrategy::default(),
+            rules: None,
+            history: RootHistory::default(),
+            plain: None,
+            keccak: None,
+            ids,
+            watches: Watches::default(),
+            log: Logger::default(),
+            metrics: MetricsSink::default(),
+            progress: ProgressSink::default(),
+            cancel: CancellationToken::default(),
+        }
     }
 
-    fn fill_leafs(mut leafs: Vec<Leaf>) -> Result<(Vec<Leaf>, usize, Vec<usize>)> {
-        let mut power = 1;
-        let mut height = 1;
-        let mut zero_index = vec![];
-        while power < leafs.len() {
-            power = power << 1;
-            height += 1
-        }
-        let mut index = leafs.len();
-        let empty_leaf = Leaf::new("0".to_string(), 0);
-        for _ in 0..power - leafs.len() {
-            zero_index.push(index);
-            leafs.push(empty_leaf.clone());
-            index += 1;
-        }
-        Ok((leafs, height, zero_index))
-    }
-
-    fn build_parent(child_1: Node, child_2: Node) -> Result<Node> {
-        let arr = vec![
-            child_1.get_hash(),
-            Fr::from_str_vartime(&child_1.get_value().to_string()).unwrap(),
-            child_2.get_hash(),
-            Fr::from_str_vartime(&child_2.get_value().to_string()).unwrap(),
-        ];
-        let k = Fr::from_str_vartime("0").unwrap();
-        let ms = MimcSponge::default();
-        let hash = ms.multi_hash(&arr, k, 1);
-        let sum = child_1.get_value() + child_2.get_value();
-        Ok(Node::new(hash[0], sum))
+    //Index the occupied slots by id; equal ids, and all empty slots, end up sharing one string
+    fn index_ids(
+        leafs: &mut [Leaf],
+        dummies: &BTreeSet<usize>,
+        empty: &EmptyLeafSpec,
+    ) -> HashMap<Arc<str>, BTreeSet<usize>> {
+        let mut ids: HashMap<Arc<str>, BTreeSet<usize>> = HashMap::new();
+        let mut placeholder: Option<Arc<str>> = None;
+        for (index, leaf) in leafs.iter_mut().enumerate() {
+            if empty.matches(leaf) {
+                leaf.id = Arc::clone(placeholder.get_or_insert_with(|| Arc::clone(&leaf.id)));
+            } else if !dummies.contains(&index) {
+                leaf.id = intern(&ids, &leaf.id);
+                ids.entry(Arc::clone(&leaf.id)).or_default().insert(index);
+            }
+        }
+        ids
     }
 
-    //Push new leaf, return index
-    pub fn push(&mut self, leaf: Leaf) -> Result<usize> {
-        match self.zero_index.len() {
-            0 => {
-                let index_value = self.leafs.len();
-                self.leafs.push(leaf);
-                let new_tree = Self::create_tree(self.leafs.clone())?;
-                _ = self.update_tree(new_tree);
-                Ok(index_value)
+    //Hash every level bottom-up from a power-of-two leaf layer, advancing levels[k - 1]
+    //per parent hashed at level k and checking cancel at every chunk of parents
+    fn build_nodes(
+        leafs: &[Leaf],
+        params: &HashParams,
+        levels: &[PhaseTracker],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Node>, MerkleError> {
+        let bottom = leafs.iter().map(Leaf::get_node).collect();
+        Self::build_levels(bottom, params, levels, cancel)
+    }
+
+    //Same as build_nodes, from the bottom level as nodes. Each level is hashed out of
+    //the range of nodes the previous one was appended to, so nothing else is allocated
+    fn build_levels(
+        bottom: Vec<Node>,
+        params: &HashParams,
+        levels: &[PhaseTracker],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Node>, MerkleError> {
+        let mut nodes = bottom;
+        nodes.reserve_exact(nodes.len().saturating_sub(1));
+        let (mut start, mut end) = (0, nodes.len());
+        let mut levels = levels.iter();
+        while end - start > 1 {
+            let level = levels.next();
+            let width = end - start;
+            for offset in 0..width.div_ceil(2) {
+                cancel.check_at(offset)?;
+                let left = start + 2 * offset;
+                let new_node = match nodes.get(left..(left + 2).min(end)) {
+                    Some([left, right]) => {
+                        Self::build_parent_with(params, left.clone(), right.clone())?
+                    }
+                    _ => return Err(MerkleError::OddLevel(width)),
+                };
+                if let Some(level) = level {
+                    level.advance(1);
+                }
+                nodes.push(new_node);
             }
+            (start, end) = (end, nodes.len());
+        }
+        Ok(nodes)
+    }
+
+    fn fill_leafs(
+        mut leafs: Vec<Leaf>,
+        max_height: usize,
+        dummy_seed: Option<[u8; 32]>,
+        empty: &EmptyLeafSpec,
+    ) -> Result<(Vec<Leaf>, usize), MerkleError> {
+        let max_leaves = Self::max_leaves(max_height);
+        let power = match leafs.len().checked_next_power_of_two() {
+            Some(power) if power <= max_leaves => power,
             _ => {
-                let index_value = self.zero_index[0];
-                _ = self.set_leaf(leaf, index_value);
-                Ok(index_value)
+                return Err(MerkleError::TreeTooLarge {
+                    requested_leaves: leafs.len(),
+                    max_leaves,
+                })
+            }
+        };
+        let height = power.trailing_zeros() as usize + 1;
+        for index in leafs.len()..power {
+            match dummy_seed {
+                Some(seed) => leafs.push(dummy_leaf(seed, index)),
+                None => leafs.push(empty.get_leaf()),
             }
         }
+        Ok((leafs, height))
     }
 
-    //Modify a current leaf
-    pub fn set_leaf(&mut self, leaf: Leaf, index: usize) -> Result<()> {
-        if leaf.is_none() && !self.get_leaf(index).unwrap().is_none() {
-            let pos = self.zero_index.binary_search(&index).unwrap_or_else(|e| e);
-            self.zero_index.insert(pos, index);
-        } else if !leaf.is_none() && self.get_leaf(index).unwrap().is_none() {
-            let pos = self.zero_index.binary_search(&index).unwrap_or_else(|e| e);
-            self.zero_index.remove(pos);
+    fn max_leaves(max_height: usize) -> usize {
+        match max_height {
+            0 => 0,
+            height => 1 << (height.min(MAX_HEIGHT) - 1),
         }
-        self.leafs[index] = leaf.clone();
-        self.nodes[index] = leaf.get_node();
-        _ = self.update_path(leaf, index);
-        Ok(())
     }
 
-    pub fn remove(&mut self, index: usize) -> Result<()> {
-        let leaf = Leaf::new("0".to_string(), 0);
-        _ = self.set_leaf(leaf, index);
-        Ok(())
+    //Check the stored state against a recomputation from the leaves
+    pub fn validate(&self) -> Result<(), Vec<IntegrityViolation>> {
+        self.validate_reconciled(self.reconcile(&CancellationToken::default()))
     }
 
-    fn update_tree(&mut self, tree: MerkleSumTree) -> Result<()> {
-        self.leafs = tree.leafs;
-        self.nodes = tree.nodes;
-        self.height = tree.height;
-        self.zero_index = tree.zero_index;
-        Ok(())
+    //validate against an already computed reconcile pass
+    fn validate_reconciled(
+        &self,
+        reconciled: Result<(Vec<Node>, Vec<usize>), MerkleError>,
+    ) -> Result<(), Vec<IntegrityViolation>> {
+        let mut violations = vec![];
+        let leaf_count = self.leafs.len();
+        let capacity = self
+            .height
+            .checked_sub(1)
+            .and_then(|shift| u32::try_from(shift).ok())
+            .and_then(|shift| 1usize.checked_shl(shift));
+        if capacity != Some(leaf_count) {
+            violations.push(IntegrityViolation::HeightMismatch {
+                height: self.height,
+                leaf_count,
+            });
+        }
+
+        if leaf_count.is_power_of_two() {
+            if let Ok((expected, mismatched)) = reconciled {
+                if expected.len() != self.nodes.len() {
+                    violations.push(IntegrityViolation::NodeCountMismatch {
+                        expected: expected.len(),
+                        actual: self.nodes.len(),
+                    });
+                }
+                for index in mismatched {
+                    if index < self.nodes.len() {
+                        violations.push(IntegrityViolation::NodeMismatch(index));
+                    }
+                }
+            }
+        }
+
+        for (position, pair) in self.zero_index.windows(2).enumerate() {
+            if let [previous, next] = pair {
+                if previous >= next {
+                    violations.push(IntegrityViolation::ZeroIndexNotSorted(position + 1));
+                }
+            }
+        }
+        let zero_set: BTreeSet<usize> = self.zero_index.iter().copied().collect();
+        for &index in &zero_set {
+            match self.leafs.get(index) {
+                Some(leaf) if self.is_empty_leaf(leaf) => {}
+                _ => violations.push(IntegrityViolation::ZeroIndexOccupied(index)),
+            }
+        }
+        for (index, leaf) in self.leafs.iter().enumerate() {
+            if self.is_empty_leaf(leaf) && !zero_set.contains(&index) {
+                violations.push(IntegrityViolation::ZeroIndexMissing(index));
+            }
+        }
+
+        for &index in &self.retired {
+            match self.leafs.get(index) {
+                Some(leaf) if self.is_empty_leaf(leaf) => {}
+                _ => violations.push(IntegrityViolation::RetiredOccupied(index)),
+            }
+        }
+        for &index in &self.dummies {
+            match (self.dummy_seed, self.leafs.get(index)) {
+                (Some(seed), Some(leaf))
+                    if *leaf == self.leaf_hash_mode.normalize(dummy_leaf(seed, index)) => {}
+                _ => violations.push(IntegrityViolation::DummyMismatch(index)),
+            }
+        }
+        if self.next_slot > leaf_count {
+            violations.push(IntegrityViolation::NextSlotOutOfRange(self.next_slot));
+        }
+        for (index, leaf) in self.leafs.iter().enumerate() {
+            if !self.leaf_hash_mode.accepts(leaf) {
+                violations.push(IntegrityViolation::LeafHashMismatch(index));
+            }
+        }
+        violations.extend(self.value_violations());
+        violations.extend(self.id_index_violations());
+
+        let expected: i64 = self
+            .leafs
+            .iter()
+            .map(|leaf| i64::from(leaf.get_node().get_value()))
+            .sum();
+        let actual = self.get_root_sum();
+        if actual.map(i64::from) != Some(expected) {
+            violations.push(IntegrityViolation::RootSumMismatch { expected, actual });
+        }
+
+        match violations.len() {
+            0 => Ok(()),
+            _ => Err(violations),
+        }
     }
-}
 
-impl InclusionProof {
-    pub fn get_path(&self) -> Vec<Neighbor> {
-        self.path.clone()
+    //Entries the id index lacks or keeps against the one index_ids derives, by slot
+    fn id_index_violations(&self) -> Vec<IntegrityViolation> {
+        let pairs = |ids: &HashMap<Arc<str>, BTreeSet<usize>>| -> BTreeSet<(usize, String)> {
+            ids.iter()
+                .flat_map(|(id, indices)| indices.iter().map(move |index| (*index, id.to_string())))
+                .collect()
+        };
+        let mut leafs = self.leafs.clone();
+        let expected = pairs(&Self::index_ids(&mut leafs, &self.dummies, &self.empty));
+        let actual = pairs(&self.ids);
+        let missing =
+            expected
+                .difference(&actual)
+                .map(|(index, id)| IntegrityViolation::IdIndexMissing {
+                    index: *index,
+                    id: id.clone(),
+                });
+        let stale =
+            actual
+                .difference(&expected)
+                .map(|(index, id)| IntegrityViolation::IdIndexStale {
+                    index: *index,
+                    id: id.clone(),
+                });
+        missing.chain(stale).collect()
     }
-    pub fn get_leaf(&self) -> Leaf {
-        self.leaf.clone()
+
+    //Parent under the default exponent, what every tree-less verifier folds with
+    fn build_parent(child_1: Node, child_2: Node) -> Result<Node, MerkleError> {
+        Self::build_parent_with(&HashParams::default(), child_1, child_2)
     }
-}
 
-#[cfg(test)]
-mod tests {
+    fn build_parent_with(
+        params: &HashParams,
+        child_1: Node,
+        child_2: Node,
+    ) -> Result<Node, MerkleError> {
+        Node::combine(&child_1, &child_2, params)
+    }
 
-    use super::*;
-    #[test]
-    fn it_works() {
-        let leaf_1 = Leaf::new("11672136".to_string(), 10);
-        let leaf_2 = Leaf::new("10566265".to_string(), 11);
-        let leaf_3 = Leaf::new("10566215".to_string(), 12);
-        let leaf_4 = Leaf::new("10566215".to_string(), 13);
-        let leaf_0 = Leaf::new("0".to_string(), 0);
-        let mut leafs = vec![
-            leaf_0.clone(),
-            leaf_0.clone(),
-            leaf_0.clone(),
-            leaf_0.clone(),
-        ];
+    //Push new leaf, return index
+    pub fn push(&mut self, leaf: Leaf) -> Result<usize, MerkleError> {
+        let started = self.metrics.start();
+        if self.append_only {
+            return self.push_append_only(leaf);
+        }
+        if self.canonical {
+            return self.push_canonical(leaf);
+        }
+        match self.allocate() {
+            None => {
+                let index_value = self.leafs.len();
+                let new_tree = self.grown(&leaf)?;
+                self.log_grown(index_value, &leaf, &new_tree)?;
+                self.update_tree(new_tree, started)?;
+                self.epoch += 1;
+                self.notify(vec![(index_value, self.empty.get_leaf())]);
+                Ok(index_value)
+            }
+            Some(index_value) => {
+                self.write_leaf(leaf, index_value, LogKind::Push)?;
+                Ok(index_value)
+            }
+        }
+    }
 
-        let mut merkle_sum_tree = MerkleSumTree::new(leafs).unwrap();
+    //Append at the next never-used slot, growing the tree when full
+    fn push_append_only(&mut self, leaf: Leaf) -> Result<usize, MerkleError> {
+        let started = self.metrics.start();
+        let index_value = self.next_slot;
+        if index_value < self.leafs.len() {
+            self.write_leaf(leaf, index_value, LogKind::Push)?;
+        } else {
+            let new_tree = self.grown(&leaf)?;
+            self.log_grown(index_value, &leaf, &new_tree)?;
+            self.update_tree(new_tree, started)?;
+            self.next_slot = index_value + 1;
+            self.epoch += 1;
+            self.notify(vec![(index_value, self.empty.get_leaf())]);
+        }
+        Ok(index_value)
+    }
+
+    //Rebuild with leaf appended, keeping the padding configuration
+    fn grown(&self, leaf: &Leaf) -> Result<MerkleSumTree, MerkleError> {
+        self.check_value(leaf)?;
+        self.check_rules(leaf)?;
+        let mut leafs = self.leafs.clone();
+        leafs.push(leaf.clone());
+        Self::create_tree(
+            leafs,
+            self.max_height,
+            self.dummy_seed,
+            self.dummies.clone(),
+            self.empty.clone(),
+            self.leaf_hash_mode,
+            self.mimc_exponent,
+            &self.progress,
+            &self.cancel,
+        )
+    }
+
+    fn log_grown(
+        &mut self,
+        index: usize,
+        leaf: &Leaf,
+        grown: &MerkleSumTree,
+    ) -> Result<(), MerkleError> {
+        let root = grown.get_root().ok_or(MerkleError::EmptyTree)?;
+        self.log_leaf(LogKind::Push, index, leaf, &root)
+    }
+
+    //Modify a current leaf, a dummy slot counts as free
+    pub fn set_leaf(&mut self, leaf: Leaf, index: usize) -> Result<(), MerkleError> {
+        self.write_leaf(leaf, index, LogKind::SetLeaf)
+    }
+
+    //set_leaf, logged as kind
+    fn write_leaf(&mut self, leaf: Leaf, index: usize, kind: LogKind) -> Result<(), MerkleError> {
+        let started = self.metrics.start();
+        let mut leaf = self.leaf_hash_mode.normalize(leaf);
+        leaf.id = intern(&self.ids, &leaf.id);
+        let current = self
+            .get_leaf(index)
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        self.check_value(&leaf)?;
+        self.check_rules(&leaf)?;
+        let was_dummy = self.dummies.contains(&index);
+        if self.append_only && !was_dummy {
+            self.check_slot_reuse(&current, &leaf, index)?;
+        }
+        let updates = self.path_updates(leaf.get_node(), index)?;
+        let root = updates
+            .last()
+            .map_or_else(|| leaf.get_node(), |(_, node)| node.clone());
+        self.log_leaf(kind, index, &leaf, &root)?;
+        let (emptied, was_empty) = (self.is_empty_leaf(&leaf), self.is_empty_leaf(&current));
+        if emptied && !was_empty {
+            if let Err(pos) = self.zero_index.binary_search(&index) {
+                self.zero_index.insert(pos, index);
+            }
+            if self.append_only && !was_dummy {
+                self.retired.insert(index);
+            }
+        } else if !emptied && was_empty {
+            if let Ok(pos) = self.zero_index.binary_search(&index) {
+                self.zero_index.remove(pos);
+            }
+        }
+        if !emptied {
+            self.next_slot = self.next_slot.max(index + 1);
+        }
+        if !was_empty {
+            if let Some(indices) = self.ids.get_mut(&current.id) {
+                indices.remove(&index);
+                if indices.is_empty() {
+                    self.ids.remove(&current.id);
+                }
+            }
+        }
+        if !emptied {
+            self.ids
+                .entry(Arc::clone(&leaf.id))
+                .or_default()
+                .insert(index);
+        }
+        self.dummies.remove(&index);
+        self.metrics.hashes(updates.len());
+        self.metrics
+            .mutation(kind.into(), updates.len() + 1, started);
+        let node = leaf.get_node();
+        if let Some(slot) = self.leafs.get_mut(index) {
+            *slot = leaf;
+        }
+        for (at, node) in std::iter::once((NodeIndex::new(0, index), node)).chain(updates) {
+            if let Some(slot) = self.nodes.get_mut(at) {
+                *slot = node;
+            }
+        }
+        self.refresh_plain([index]);
+        self.refresh_keccak([index]);
+        self.canonical = self.canonical && self.canonical_after_write(index);
+        self.epoch += 1;
+        self.notify(vec![(index, current)]);
+        Ok(())
+    }
+
+    //Credit or debit an occupied slot, return the new balance
+    pub fn add_to_leaf(&mut self, index: usize, delta: i64) -> Result<i64, MerkleError> {
+        let current = self
+            .get_leaf(index)
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        if self.is_empty_leaf(&current) || self.is_dummy(index) {
+            return Err(MerkleError::EmptySlot(index));
+        }
+        let value = i64::from(current.node.value)
+            .checked_add(delta)
+            .and_then(|value| i32::try_from(value).ok())
+            .ok_or(MerkleError::Overflow)?;
+        self.set_leaf(Leaf::with_shared_id(current.id, value), index)?;
+        Ok(i64::from(value))
+    }
+
+    //Apply a delta to the first slot holding id, with upsert a missing id is pushed
+    pub fn increment_by_id(
+        &mut self,
+        id: &str,
+        delta: i64,
+        upsert: bool,
+    ) -> Result<i64, MerkleError> {
+        match self.get_index_by_id(id) {
+            Some(index) => self.add_to_leaf(index, delta),
+            None if upsert => {
+                let value = i32::try_from(delta).map_err(|_| MerkleError::Overflow)?;
+                self.push(Leaf::new(id.to_string(), value))?;
+                Ok(delta)
+            }
+            None => Err(MerkleError::UnknownId(id.to_string())),
+        }
+    }
+
+    //Push and prove against the post-insertion root in one call
+    pub fn push_with_proof(&mut self, leaf: Leaf) -> Result<(usize, InclusionProof), MerkleError> {
+        let index = self.push(leaf)?;
+        Ok((index, self.get_bound_proof(index)?))
+    }
+
+    //Set a leaf and prove it against the post-update root in one call
+    pub fn set_leaf_with_proof(
+        &mut self,
+        leaf: Leaf,
+        index: usize,
+    ) -> Result<InclusionProof, MerkleError> {
+        self.set_leaf(leaf, index)?;
+        self.get_bound_proof(index)
+    }
+
+    //Proof stamped with the current root and epoch
+    fn get_bound_proof(&self, index: usize) -> Result<InclusionProof, MerkleError> {
+        let proof = self
+            .get_proof_allow_empty(index)?
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        Ok(self.stamp(proof))
+    }
+
+    fn stamp(&self, mut proof: InclusionProof) -> InclusionProof {
+        proof.root = self.get_root();
+        proof.epoch = Some(self.epoch);
+        proof
+    }
+
+    //In append-only mode a slot only accepts updates from its original id
+    fn check_slot_reuse(
+        &self,
+        current: &Leaf,
+        leaf: &Leaf,
+        index: usize,
+    ) -> Result<(), MerkleError> {
+        if self.is_empty_leaf(leaf) {
+            return Ok(());
+        }
+        if self.retired.contains(&index) {
+            return Err(MerkleError::SlotRetired(index));
+        }
+        if !self.is_empty_leaf(current) && current.get_id() != leaf.get_id() {
+            return Err(MerkleError::SlotOccupied {
+                index,
+                id: current.get_id(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<(), MerkleError> {
+        self.write_leaf(self.empty.get_leaf(), index, LogKind::Remove)
+    }
+
+    //Swap in a rebuilt tree, every node of which was hashed anew
+    fn update_tree(
+        &mut self,
+        tree: MerkleSumTree,
+        started: Option<Instant>,
+    ) -> Result<(), MerkleError> {
+        self.metrics.hashes(tree.nodes.len() - tree.leafs.len());
+        self.metrics
+            .mutation(MutationKind::Push, tree.nodes.len(), started);
+        self.leafs = tree.leafs;
+        self.nodes = tree.nodes;
+        self.height = tree.height;
+        self.zero_index = tree.zero_index;
+        self.dummies = tree.dummies;
+        self.ids = tree.ids;
+        self.refresh_plain(std::iter::empty());
+        self.refresh_keccak(std::iter::empty());
+        self.trim_buffers();
+        Ok(())
+    }
+}
+
+impl Serialize for MerkleSumTree {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        MerkleSumTree::serialize(self, serializer)
+    }
+}
+
+impl Serialize for InclusionProof {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        InclusionProof::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for InclusionProof {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut proof = InclusionProof::deserialize(deserializer)?;
+        binary::check_levels(proof.path.len()).map_err(serde::de::Error::custom)?;
+        if proof.index == UNSTATED_INDEX {
+            proof.index = path_index(&proof.path);
+        }
+        proof.check_index().map_err(serde::de::Error::custom)?;
+        Ok(proof)
+    }
+}
+
+//Imported trees are untrusted, reject any that fail validate
+impl<'de> Deserialize<'de> for MerkleSumTree {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut tree = MerkleSumTree::deserialize(deserializer)?;
+        MimcSponge::with_exponent(tree.mimc_exponent).map_err(serde::de::Error::custom)?;
+        //The id index is not serialized, rebuilt before validate compares it
+        tree.ids = MerkleSumTree::index_ids(&mut tree.leafs, &tree.dummies, &tree.empty);
+        tree.validate()
+            .map_err(|violations| serde::de::Error::custom(MerkleError::InvalidTree(violations)))?;
+        tree.canonical = tree.canonical && tree.in_canonical_order();
+        Ok(tree)
+    }
+}
+
+//Fisher-Yates driven by ChaCha
+fn shuffle_leafs(leafs: &mut [Leaf], seed: [u8; 32]) {
+    seeded_rng(seed).shuffle(leafs);
+}
+
+//The indexed copy of id if there is one, so equal ids share a single string
+fn intern(ids: &HashMap<Arc<str>, BTreeSet<usize>>, id: &Arc<str>) -> Arc<str> {
+    ids.get_key_value(&**id)
+        .map_or_else(|| Arc::clone(id), |(shared, _)| Arc::clone(shared))
+}
+
+//Zero-value padding with a 32 hex digit id, drawn from the ChaCha block at index
+fn dummy_leaf(seed: [u8; 32], index: usize) -> Leaf {
+    let mut rng = seeded_rng(seed);
+    rng.set_counter(index as u64, 0);
+    let id: String = (0..4).map(|_| format!("{:08x}", rng.next_u32())).collect();
+    Leaf::new(id, 0)
+}
+
+//ChaCha keyed by the 32 seed bytes, read as 8 little-endian words
+fn seeded_rng(seed: [u8; 32]) -> ChaChaRng {
+    let mut words = [0u32; 8];
+    for (word, chunk) in words.iter_mut().zip(seed.chunks_exact(4)) {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(chunk);
+        *word = u32::from_le_bytes(bytes);
+    }
+    ChaChaRng::from_seed(words.as_slice())
+}
+
+//The default placeholder held by unoccupied slots
+fn empty_leaf() -> Leaf {
+    EmptyLeafSpec::default().get_leaf()
+}
+
+//Negative values map to their additive inverse in the field
+fn value_to_fr(value: i32) -> Fr {
+    sum_to_fr(i64::from(value))
+}
+
+//Same map for the wider sums and bounds of statements
+fn sum_to_fr(value: i64) -> Fr {
+    let magnitude = value.unsigned_abs();
+    let magnitude = small_value(magnitude).unwrap_or_else(|| Fr::from(magnitude));
+    if value < 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+//Inverse of value_to_fr, None for elements no i32 maps to
+fn fr_to_value(fr: Fr) -> Option<i32> {
+    fr_to_sum(fr).and_then(|value| i32::try_from(value).ok())
+}
+
+//Inverse of sum_to_fr, None for elements no i64 maps to
+fn fr_to_sum(fr: Fr) -> Option<i64> {
+    let small = |fr: Fr| {
+        let repr = fr.to_repr();
+        let (low, high) = repr.as_ref().split_at(8);
+        if high.iter().any(|byte| *byte != 0) {
+            return None;
+        }
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(low);
+        Some(u64::from_le_bytes(bytes))
+    };
+    match small(fr) {
+        Some(value) => i64::try_from(value).ok(),
+        None => small(-fr).and_then(|magnitude| 0i64.checked_sub_unsigned(magnitude)),
+    }
+}
+
+impl InclusionProof {
+    pub fn get_path(&self) -> Vec<Neighbor> {
+        self.path.clone()
+    }
+    pub fn get_leaf(&self) -> Leaf {
+        self.leaf.clone()
+    }
+
+    //Root the proof was generated against, when it was stamped with one
+    pub fn get_root(&self) -> Option<Node> {
+        self.root.clone()
+    }
+
+    pub fn get_epoch(&self) -> Option<u64> {
+        self.epoch
+    }
+
+    //MiMC exponent of the tree the proof came from
+    pub fn get_exponent(&self) -> u64 {
+        self.exponent
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    //Refuse a path whose positions do not walk up from the proof's index
+    fn check_index(&self) -> Result<(), MerkleError> {
+        let mut rest = self.index;
+        for (level, neighbor) in self.path.iter().enumerate() {
+            let right_child = rest % 2 == 1;
+            if right_child != (neighbor.position == Position::Left) {
+                return Err(MerkleError::InvalidProof(format!(
+                    "neighbor at level {} is on the wrong side for index {}",
+                    level, self.index
+                )));
+            }
+            rest /= 2;
+        }
+        match rest {
+            0 => Ok(()),
+            _ => Err(MerkleError::InvalidProof(format!(
+                "index {} does not fit in a path of {} levels",
+                self.index,
+                self.path.len()
+            ))),
+        }
+    }
+
+    //Fold the path from the leaf up, a valid proof yields the tree root. A path
+    //disagreeing with the index is refused before any hashing
+    pub fn compute_root(&self) -> Result<Node, MerkleError> {
+        self.compute_root_with(&HashParams::mimc(self.exponent)?)
+    }
+
+    //compute_root under params already built for the proof's exponent, so a batch
+    //of proofs shares one sponge
+    pub(crate) fn compute_root_with(&self, params: &HashParams) -> Result<Node, MerkleError> {
+        self.check_index()?;
+        let mut node = self.leaf.get_node();
+        for neighbor in &self.path {
+            match neighbor.position {
+                Position::Right => {
+                    node = MerkleSumTree::build_parent_with(params, node, neighbor.node.clone())?;
+                }
+                Position::Left => {
+                    node = MerkleSumTree::build_parent_with(params, neighbor.node.clone(), node)?;
+                }
+            }
+        }
+        Ok(node)
+    }
+
+    //Check against a published root alone, no tree needed; a path that cannot be
+    //folded, such as one whose sums overflow, is an error rather than false
+    pub fn verify(&self, root_hash: Fr, root_sum: i32) -> Result<bool, MerkleError> {
+        let computed = self.compute_root()?;
+        Ok(computed.hash == root_hash && computed.value == root_sum)
+    }
+
+    //Check against the root the proof was stamped with, no tree needed
+    pub fn verify_bound(&self) -> Result<bool, MerkleError> {
+        let root = self
+            .root
+            .as_ref()
+            .ok_or_else(|| MerkleError::InvalidProof("no embedded root".to_string()))?;
+        self.verify(root.hash, root.value)
+    }
+
+    //True when the proof shows an unoccupied slot rather than an account, under the
+    //default empty leaf; see EmptyLeafSpec::matches for trees built with another
+    pub fn is_empty_slot(&self) -> bool {
+        self.leaf.is_none()
+    }
+
+    //Whether the leaf hash is the one mode derives from the leaf's id and value
+    pub fn verify_leaf_hash(&self, mode: LeafHashMode) -> bool {
+        mode.matches(&self.leaf)
+    }
+
+    //Per level, leaf to root: 1 when the sibling is on the left (the node is a right
+    //child), the bits of get_index and the order compute_root folds in
+    pub fn path_indices(&self) -> Vec<u8> {
+        self.path
+            .iter()
+            .map(|neighbor| match neighbor.position {
+                Position::Left => 1,
+                Position::Right => 0,
+            })
+            .collect()
+    }
+
+    pub fn sibling_hashes(&self) -> Vec<Fr> {
+        self.path
+            .iter()
+            .map(|neighbor| neighbor.node.hash)
+            .collect()
+    }
+
+    pub fn sibling_values(&self) -> Vec<i32> {
+        self.path
+            .iter()
+            .map(|neighbor| neighbor.node.value)
+            .collect()
+    }
+
+    //Directions packed little-endian: bit i is set when the node at level i is a right
+    //child (its neighbor is on the left), so the packed value is the leaf index. Any
+    //path of up to 254 levels fits below the modulus, MAX_HEIGHT trees use at most 63
+    pub fn path_bits_as_fr(&self) -> Fr {
+        self.path.iter().rev().fold(Fr::ZERO, |packed, neighbor| {
+            let packed = packed.double();
+            match neighbor.position {
+                Position::Left => packed + Fr::ONE,
+                Position::Right => packed,
+            }
+        })
+    }
+
+    //Neighbor positions, leaf to root, of a height tall tree, the inverse of path_bits_as_fr
+    pub fn path_bits_from_fr(bits: Fr, height: usize) -> Result<Vec<Position>, MerkleError> {
+        let levels = height.saturating_sub(1);
+        let repr = bits.to_repr();
+        let bytes = repr.as_ref();
+        let bit = |i: usize| {
+            bytes
+                .get(i / 8)
+                .is_some_and(|byte| (byte >> (i % 8)) & 1 == 1)
+        };
+        if (levels..bytes.len() * 8).any(bit) {
+            return Err(MerkleError::InvalidFieldElement(format!(
+                "{} does not fit in {} path bits",
+                bits, levels
+            )));
+        }
+        Ok((0..levels)
+            .map(|level| {
+                if bit(level) {
+                    Position::Left
+                } else {
+                    Position::Right
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing, deprecated)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::users;
+    use rand::XorShiftRng;
+    use std::collections::HashSet;
+    #[test]
+    fn it_works() {
+        let leaf_1 = Leaf::new("11672136".to_string(), 10);
+        let leaf_2 = Leaf::new("10566265".to_string(), 11);
+        let leaf_3 = Leaf::new("10566215".to_string(), 12);
+        let leaf_4 = Leaf::new("10566215".to_string(), 13);
+        let leaf_0 = Leaf::new("0".to_string(), 0);
+        let mut leafs = vec![
+            leaf_0.clone(),
+            leaf_0.clone(),
+            leaf_0.clone(),
+            leaf_0.clone(),
+        ];
+
+        let mut merkle_sum_tree = MerkleSumTree::new(leafs).unwrap();
 
         println!("{:?}", merkle_sum_tree.get_root());
         println!("{:?}", merkle_sum_tree.get_nodes());
@@ -382,4 +1864,1127 @@ mod tests {
         merkle_sum_tree.push(leaf_1);
         println!("{:?}", merkle_sum_tree.get_nodes());
     }
+
+    #[test]
+    fn append_only_never_reuses_slots() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+        ];
+        let mut tree = MerkleSumTree::builder()
+            .append_only(true)
+            .build(leafs)
+            .unwrap();
+
+        tree.remove(0).unwrap();
+        let index = tree.push(Leaf::new("carol".to_string(), 30)).unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(tree.get_retired(), vec![0]);
+        assert_eq!(tree.get_root_sum(), Some(50));
+
+        assert!(tree.set_leaf(Leaf::new("dave".to_string(), 5), 0).is_err());
+        assert!(tree.set_leaf(Leaf::new("dave".to_string(), 5), 1).is_err());
+        assert!(tree.set_leaf(Leaf::new("bob".to_string(), 25), 1).is_ok());
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let mut restored: MerkleSumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_root(), tree.get_root());
+        assert_eq!(restored.get_retired(), vec![0]);
+        assert!(restored.is_append_only());
+
+        restored.remove(2).unwrap();
+        let index = restored.push(Leaf::new("erin".to_string(), 1)).unwrap();
+        assert_eq!(index, 3);
+        assert!(restored
+            .set_leaf(Leaf::new("erin".to_string(), 2), 2)
+            .is_err());
+    }
+
+    #[test]
+    fn validate_accepts_consistent_trees() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.get_zero_index(), vec![3]);
+
+        tree.push(Leaf::new("dave".to_string(), 40)).unwrap();
+        tree.push(Leaf::new("erin".to_string(), 50)).unwrap();
+        tree.remove(1).unwrap();
+        tree.set_leaf(Leaf::new("frank".to_string(), 60), 6)
+            .unwrap();
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_collects_every_violation() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        tree.leafs[1] = Leaf::new("mallory".to_string(), 99);
+        tree.zero_index = vec![3, 3, 0];
+
+        let violations = tree.validate().unwrap_err();
+        for violation in [
+            IntegrityViolation::NodeMismatch(1),
+            IntegrityViolation::NodeMismatch(4),
+            IntegrityViolation::NodeMismatch(6),
+            IntegrityViolation::ZeroIndexNotSorted(1),
+            IntegrityViolation::ZeroIndexNotSorted(2),
+            IntegrityViolation::ZeroIndexOccupied(0),
+            IntegrityViolation::RootSumMismatch {
+                expected: 139,
+                actual: Some(60),
+            },
+        ] {
+            assert!(violations.contains(&violation), "missing {:?}", violation);
+        }
+        assert!(!violations
+            .iter()
+            .any(|violation| matches!(violation, IntegrityViolation::HeightMismatch { .. })));
+    }
+
+    #[test]
+    fn validate_checks_the_id_index() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        assert!(tree.validate().is_ok());
+
+        tree.ids.remove("bob");
+        tree.ids.entry(Arc::from("alice")).or_default().insert(2);
+        tree.ids.entry(Arc::from("mallory")).or_default().insert(3);
+        let violations = tree.validate().unwrap_err();
+        assert_eq!(
+            violations,
+            vec![
+                IntegrityViolation::IdIndexMissing {
+                    index: 1,
+                    id: "bob".to_string()
+                },
+                IntegrityViolation::IdIndexStale {
+                    index: 2,
+                    id: "alice".to_string()
+                },
+                IntegrityViolation::IdIndexStale {
+                    index: 3,
+                    id: "mallory".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            violations[1].to_string(),
+            "Id index lists alice at leaf 2, which holds another"
+        );
+    }
+
+    fn hash_of<T: Hash>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn equal_values_hash_equally() {
+        let leafs: Vec<Leaf> = (0..16)
+            .map(|i| Leaf::new(format!("user{}", i % 5), i % 3))
+            .collect();
+        let tree = MerkleSumTree::new(leafs.clone()).unwrap();
+        let twin = MerkleSumTree::new(leafs.clone()).unwrap();
+
+        for a in &leafs {
+            for b in &leafs {
+                assert_eq!(a == b, hash_of(a) == hash_of(b));
+            }
+        }
+        let unique_leafs: HashSet<Leaf> = leafs.iter().cloned().collect();
+        assert_eq!(unique_leafs.len(), 15);
+
+        for (a, b) in tree.get_nodes().iter().zip(twin.get_nodes().iter()) {
+            assert_eq!(a, b);
+            assert_eq!(hash_of(a), hash_of(b));
+        }
+        let unique_nodes: HashSet<Node> = tree.get_nodes().into_iter().collect();
+        assert!(unique_nodes.contains(&tree.get_root().unwrap()));
+
+        let mut proofs = HashSet::new();
+        for i in 0..16 {
+            let proof = tree.get_proof(i).unwrap().unwrap();
+            let twin_proof = twin.get_proof(i).unwrap().unwrap();
+            assert_eq!(hash_of(&proof), hash_of(&twin_proof));
+            proofs.insert(proof);
+            proofs.insert(twin_proof);
+        }
+        assert_eq!(proofs.len(), 16);
+
+        let mut positions = vec![Position::Right, Position::Left, Position::Right];
+        positions.sort();
+        assert_eq!(
+            positions,
+            vec![Position::Left, Position::Right, Position::Right]
+        );
+    }
+
+    #[test]
+    fn shared_tree_across_threads() {
+        let leafs = users(8, 1, 0);
+        let tree = std::sync::Arc::new(MerkleSumTree::new(leafs).unwrap());
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let tree = tree.clone();
+                std::thread::spawn(move || {
+                    let proof = tree.get_proof(i).unwrap().unwrap();
+                    tree.verify_proof(proof).unwrap()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn negative_values_are_supported() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), -25),
+            Leaf::new("carol".to_string(), 5),
+        ];
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.get_root_sum(), Some(-10));
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        assert!(tree.verify_proof(proof).unwrap());
+    }
+
+    #[test]
+    fn overflow_is_an_error_and_leaves_tree_untouched() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), i32::MAX),
+            Leaf::new("bob".to_string(), 1),
+        ];
+        let err = MerkleSumTree::new(leafs).unwrap_err();
+        assert_eq!(err.error_code(), "E_OVERFLOW");
+
+        let leafs = vec![
+            Leaf::new("alice".to_string(), i32::MAX),
+            Leaf::new("bob".to_string(), 0),
+        ];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        let before = tree.clone();
+        let err = tree
+            .set_leaf(Leaf::new("bob".to_string(), 1), 1)
+            .unwrap_err();
+        assert!(matches!(err, MerkleError::Overflow));
+        assert!(tree.push(Leaf::new("carol".to_string(), 1)).is_err());
+        assert_eq!(tree.get_leafs(), before.get_leafs());
+        assert_eq!(tree.get_nodes(), before.get_nodes());
+        assert_eq!(tree.get_zero_index(), before.get_zero_index());
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn corrupted_imports_are_rejected() {
+        let leafs = users(5, 1, 0);
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        let value = serde_json::to_value(&tree).unwrap();
+        assert!(serde_json::from_value::<MerkleSumTree>(value.clone()).is_ok());
+
+        let mut odd_nodes = value.clone();
+        odd_nodes["nodes"].as_array_mut().unwrap().pop();
+        assert!(serde_json::from_value::<MerkleSumTree>(odd_nodes).is_err());
+
+        let mut odd_leafs = value.clone();
+        odd_leafs["leafs"].as_array_mut().unwrap().pop();
+        assert!(serde_json::from_value::<MerkleSumTree>(odd_leafs).is_err());
+
+        let mut huge_height = value.clone();
+        huge_height["height"] = serde_json::json!(usize::MAX);
+        assert!(serde_json::from_value::<MerkleSumTree>(huge_height).is_err());
+
+        let mut bad_slot = value;
+        bad_slot["next_slot"] = serde_json::json!(1000);
+        assert!(serde_json::from_value::<MerkleSumTree>(bad_slot).is_err());
+    }
+
+    #[test]
+    fn out_of_range_indices_are_errors() {
+        let leafs = vec![Leaf::new("alice".to_string(), 10)];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        assert!(tree.get_proof(1).unwrap().is_none());
+        assert!(matches!(
+            tree.set_leaf(Leaf::new("bob".to_string(), 1), 5),
+            Err(MerkleError::IndexOutOfBounds(5))
+        ));
+        assert!(matches!(
+            tree.remove(usize::MAX),
+            Err(MerkleError::IndexOutOfBounds(_))
+        ));
+    }
+
+    #[test]
+    fn empty_slots_are_distinguished_from_zero_balances() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 0),
+            Leaf::new("carol".to_string(), 30),
+        ];
+        let tree = MerkleSumTree::new(leafs).unwrap();
+
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        assert!(!proof.is_empty_slot());
+        assert_eq!(proof.get_leaf().get_node().get_value(), 0);
+        assert!(tree.verify_proof(proof).unwrap());
+
+        assert!(matches!(tree.get_proof(3), Err(MerkleError::EmptySlot(3))));
+        let proof = tree.get_proof_allow_empty(3).unwrap().unwrap();
+        assert!(proof.is_empty_slot());
+        assert!(tree.verify_proof(proof).unwrap());
+
+        assert!(tree.get_proof_allow_empty(4).unwrap().is_none());
+    }
+
+    #[test]
+    fn mutations_return_proofs_against_the_new_root() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+        ];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.get_epoch(), 0);
+
+        let (index, proof) = tree
+            .push_with_proof(Leaf::new("carol".to_string(), 30))
+            .unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(proof.get_root(), tree.get_root());
+        assert_eq!(proof.get_epoch(), Some(tree.get_epoch()));
+        assert_eq!(proof.get_leaf().get_id(), "carol");
+        assert!(tree.verify_proof(proof).unwrap());
+
+        let proof = tree
+            .set_leaf_with_proof(Leaf::new("alice".to_string(), 15), 0)
+            .unwrap();
+        assert_eq!(proof.get_root(), tree.get_root());
+        assert_eq!(proof.get_epoch(), Some(2));
+        assert_eq!(tree.get_root_sum(), Some(65));
+        assert!(tree.verify_proof(proof).unwrap());
+
+        assert!(tree.get_proof(1).unwrap().unwrap().get_root().is_none());
+    }
+
+    #[test]
+    fn stamped_proofs_go_stale_when_the_tree_moves() {
+        let mut tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        let bound = tree.get_proof_with_root(1).unwrap();
+        assert_eq!(bound.get_root(), tree.get_root());
+        assert_eq!(bound.get_epoch(), Some(0));
+        assert!(bound.verify_bound().unwrap());
+        assert!(tree.verify_proof(bound.clone()).unwrap());
+
+        tree.set_leaf(Leaf::new("alice".to_string(), 11), 0)
+            .unwrap();
+        assert!(bound.verify_bound().unwrap());
+        assert!(!tree.verify_proof(bound.clone()).unwrap());
+        //The same path unstamped folds to the old root, so fails too
+        let mut unstamped = bound.clone();
+        unstamped.root = None;
+        assert!(!tree.verify_proof(unstamped).unwrap());
+
+        let fresh = tree.get_proof_with_root(1).unwrap();
+        assert!(tree.verify_proof(fresh.clone()).unwrap());
+        let mut forged = fresh;
+        forged.root = bound.get_root();
+        assert!(!forged.verify_bound().unwrap());
+        assert!(!tree.verify_proof(forged).unwrap());
+
+        assert!(matches!(
+            tree.get_proof(1).unwrap().unwrap().verify_bound(),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        assert!(matches!(
+            tree.get_proof_with_root(3),
+            Err(MerkleError::EmptySlot(3))
+        ));
+        assert!(matches!(
+            tree.get_proof_with_root(4),
+            Err(MerkleError::IndexOutOfBounds(4))
+        ));
+    }
+
+    #[test]
+    fn proofs_of_another_height_are_refused() {
+        let tree = |count: i32| {
+            let leafs = users(count, 1, -1);
+            MerkleSumTree::new(leafs).unwrap()
+        };
+        let (four, eight) = (tree(4), tree(8));
+        let short = four.get_proof(2).unwrap().unwrap();
+        let long = eight.get_proof(2).unwrap().unwrap();
+        assert!(four.verify_proof(short.clone()).unwrap());
+        assert!(eight.verify_proof(long.clone()).unwrap());
+        assert!(matches!(
+            eight.verify_proof(short.clone()),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        assert!(matches!(
+            four.verify_proof(long),
+            Err(MerkleError::InvalidProof(_))
+        ));
+
+        //Cut to land on an internal node, the path is refused before folding
+        let mut truncated = short.clone();
+        truncated.path.pop();
+        truncated.index = 0;
+        assert!(matches!(
+            four.verify_proof(truncated),
+            Err(MerkleError::InvalidProof(_))
+        ));
+
+        //Strict, the leaf must be the one stored at its index
+        assert!(four.verify_proof_strict(short.clone()).unwrap());
+        let mut elsewhere = short;
+        elsewhere.leaf = four.get_leaf(3).unwrap();
+        assert!(!four.verify_proof_strict(elsewhere).unwrap());
+    }
+
+    #[test]
+    fn max_height_boundary() {
+        assert_eq!(MAX_HEIGHT, usize::BITS as usize);
+        assert_eq!(
+            MerkleSumTree::max_leaves(MAX_HEIGHT),
+            1 << (usize::BITS - 1)
+        );
+
+        let leafs = users(4, 1, 0);
+        let mut tree = MerkleSumTree::builder()
+            .max_height(3)
+            .build(leafs.clone())
+            .unwrap();
+        assert_eq!(tree.get_height(), 3);
+
+        let err = tree.push(Leaf::new("user4".to_string(), 4)).unwrap_err();
+        assert!(matches!(
+            err,
+            MerkleError::TreeTooLarge {
+                requested_leaves: 5,
+                max_leaves: 4
+            }
+        ));
+        assert_eq!(tree.get_leafs(), leafs);
+        assert_eq!(tree.validate(), Ok(()));
+
+        let mut too_many = leafs;
+        too_many.push(Leaf::new("user4".to_string(), 4));
+        let err = MerkleSumTree::builder()
+            .max_height(3)
+            .build(too_many)
+            .unwrap_err();
+        assert_eq!(err.error_code(), "E_TREE_TOO_LARGE");
+    }
+
+    #[test]
+    fn shuffled_trees_are_reproducible_from_the_seed() {
+        let leafs = users(16, 1, 0);
+        let tree = MerkleSumTree::new_shuffled(leafs.clone(), [1; 32]).unwrap();
+        let again = MerkleSumTree::new_shuffled(leafs.clone(), [1; 32]).unwrap();
+        let other = MerkleSumTree::new_shuffled(leafs.clone(), [2; 32]).unwrap();
+
+        assert_eq!(tree.get_root(), again.get_root());
+        assert_eq!(tree.get_root_sum(), other.get_root_sum());
+        assert_ne!(tree.get_leafs(), other.get_leafs());
+        assert_ne!(tree.get_leafs(), leafs);
+        assert_eq!(tree.get_shuffle_seed(), Some([1; 32]));
+
+        let unrecorded = MerkleSumTree::builder()
+            .shuffle_seed([1; 32])
+            .record_seed(false)
+            .build(leafs)
+            .unwrap();
+        assert_eq!(unrecorded.get_root(), tree.get_root());
+        assert_eq!(unrecorded.get_shuffle_seed(), None);
+
+        let index = tree.get_index_by_id("user5").unwrap();
+        let proof = tree.get_proof(index).unwrap().unwrap();
+        assert_eq!(proof.get_leaf().get_id(), "user5");
+        assert!(tree.verify_proof(proof).unwrap());
+    }
+
+    #[test]
+    fn id_index_tracks_mutations() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("alice".to_string(), 30),
+        ];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.get_indices_by_id("alice"), vec![0, 2]);
+        assert_eq!(tree.get_index_by_id("0"), None);
+
+        tree.remove(0).unwrap();
+        assert_eq!(tree.get_index_by_id("alice"), Some(2));
+        tree.set_leaf(Leaf::new("carol".to_string(), 5), 1).unwrap();
+        assert_eq!(tree.get_index_by_id("bob"), None);
+        assert_eq!(tree.push(Leaf::new("dave".to_string(), 1)).unwrap(), 0);
+        tree.push(Leaf::new("erin".to_string(), 1)).unwrap();
+        tree.push(Leaf::new("frank".to_string(), 1)).unwrap();
+        assert_eq!(tree.get_index_by_id("frank"), Some(4));
+        assert_eq!(tree.get_index_by_id("carol"), Some(1));
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let restored: MerkleSumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_index_by_id("erin"), Some(3));
+
+        //Proofs by id follow the index, the first slot of a repeated id
+        let proof = tree.get_proof_by_id("frank").unwrap();
+        assert_eq!(proof.get_index(), 4);
+        assert!(tree.verify_proof(proof).unwrap());
+        tree.push(Leaf::new("frank".to_string(), 2)).unwrap();
+        assert_eq!(tree.get_proof_by_id("frank").unwrap().get_index(), 4);
+        tree.remove(4).unwrap();
+        let moved = tree.get_proof_by_id("frank").unwrap();
+        assert_eq!(moved.get_leaf().get_node().get_value(), 2);
+        assert!(tree.verify_proof(moved.clone()).unwrap());
+        tree.remove(moved.get_index()).unwrap();
+        assert!(matches!(
+            tree.get_proof_by_id("frank"),
+            Err(MerkleError::UnknownId(id)) if id == "frank"
+        ));
+        assert!(matches!(
+            tree.get_proof_by_id("bob"),
+            Err(MerkleError::UnknownId(_))
+        ));
+        assert_eq!(restored.get_indices_by_id("alice"), vec![2]);
+    }
+
+    #[test]
+    fn dummy_padding_hides_the_account_count() {
+        let leafs = users(5, 1, 0);
+        let build = |seed| {
+            MerkleSumTree::builder()
+                .dummy_padding(seed)
+                .build(leafs.clone())
+                .unwrap()
+        };
+        let tree = build([7; 32]);
+        let plain = MerkleSumTree::new(leafs.clone()).unwrap();
+        assert_eq!(tree.get_root_sum(), plain.get_root_sum());
+        assert!(tree.get_zero_index().is_empty());
+        assert_eq!(tree.get_root(), build([7; 32]).get_root());
+        assert_ne!(tree.get_root(), build([8; 32]).get_root());
+
+        let dummy_ids: HashSet<String> = (5..8)
+            .map(|index| {
+                assert!(tree.is_dummy(index));
+                let leaf = tree.get_leaf(index).unwrap();
+                assert_eq!(leaf.get_node().get_value(), 0);
+                assert_eq!(leaf.get_id().len(), 32);
+                leaf.get_id()
+            })
+            .collect();
+        assert_eq!(dummy_ids.len(), 3);
+        assert!(!tree.is_dummy(4));
+        assert_eq!(tree.non_empty_leafs(false).count(), 5);
+        assert_eq!(tree.non_empty_leafs(true).count(), 8);
+        assert!(dummy_ids
+            .iter()
+            .all(|id| tree.get_index_by_id(id).is_none()));
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let mut restored: MerkleSumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_root(), tree.get_root());
+        assert!((5..8).all(|index| restored.is_dummy(index)));
+
+        assert_eq!(restored.push(Leaf::new("user5".to_string(), 5)).unwrap(), 5);
+        assert!(!restored.is_dummy(5));
+        for i in 6..9 {
+            restored.push(Leaf::new(format!("user{}", i), i)).unwrap();
+        }
+        assert_eq!(restored.get_leafs().len(), 16);
+        assert_eq!(restored.non_empty_leafs(false).count(), 9);
+        assert!((9..16).all(|index| restored.is_dummy(index)));
+        assert_eq!(restored.get_root_sum(), Some(36));
+        assert_eq!(restored.validate(), Ok(()));
+
+        let mut forged = serde_json::to_value(&tree).unwrap();
+        forged["dummies"] = serde_json::json!([4, 5, 6, 7]);
+        assert!(serde_json::from_value::<MerkleSumTree>(forged).is_err());
+    }
+
+    #[test]
+    fn deltas_update_balances_in_place() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), i32::MAX - 20),
+        ];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.add_to_leaf(0, 5).unwrap(), 15);
+        assert_eq!(tree.add_to_leaf(0, -25).unwrap(), -10);
+        assert_eq!(
+            tree.get_leaf(0).unwrap(),
+            Leaf::new("alice".to_string(), -10)
+        );
+        assert_eq!(tree.get_root_sum(), Some(i32::MAX - 30));
+
+        let before = tree.clone();
+        assert!(matches!(
+            tree.add_to_leaf(0, 31),
+            Err(MerkleError::Overflow)
+        ));
+        assert!(matches!(
+            tree.add_to_leaf(1, i64::MAX),
+            Err(MerkleError::Overflow)
+        ));
+        assert_eq!(tree.get_nodes(), before.get_nodes());
+        assert_eq!(tree.get_epoch(), before.get_epoch());
+
+        tree.remove(1).unwrap();
+        assert!(matches!(
+            tree.add_to_leaf(1, 1),
+            Err(MerkleError::EmptySlot(1))
+        ));
+        assert!(matches!(
+            tree.add_to_leaf(2, 1),
+            Err(MerkleError::IndexOutOfBounds(2))
+        ));
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn increment_by_id_with_upsert() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+        ];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.increment_by_id("bob", -5, false).unwrap(), 15);
+        assert!(matches!(
+            tree.increment_by_id("carol", 5, false),
+            Err(MerkleError::UnknownId(_))
+        ));
+        assert_eq!(tree.get_leafs().len(), 2);
+
+        //The tree is full, so the upsert grows it
+        assert_eq!(tree.increment_by_id("carol", 5, true).unwrap(), 5);
+        assert_eq!(tree.get_leafs().len(), 4);
+        assert_eq!(tree.get_index_by_id("carol"), Some(2));
+        assert_eq!(tree.increment_by_id("carol", 5, true).unwrap(), 10);
+        assert_eq!(tree.get_root_sum(), Some(35));
+
+        let before = tree.clone();
+        assert!(matches!(
+            tree.increment_by_id("dave", i64::from(i32::MAX), true),
+            Err(MerkleError::Overflow)
+        ));
+        assert!(matches!(
+            tree.increment_by_id("erin", i64::MAX, true),
+            Err(MerkleError::Overflow)
+        ));
+        assert!(matches!(
+            tree.increment_by_id("alice", i64::from(i32::MAX), false),
+            Err(MerkleError::Overflow)
+        ));
+        assert_eq!(tree.get_leafs(), before.get_leafs());
+        assert_eq!(tree.get_nodes(), before.get_nodes());
+        assert_eq!(tree.get_index_by_id("dave"), None);
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn max_leaf_value_caps_every_mutation() {
+        let capped = || MerkleSumTree::builder().max_leaf_value(Some(100));
+        let mut tree = capped()
+            .build(vec![
+                Leaf::new("alice".to_string(), 100),
+                Leaf::new("bob".to_string(), -500),
+                Leaf::new("carol".to_string(), 20),
+            ])
+            .unwrap();
+        assert_eq!(tree.get_max_leaf_value(), Some(100));
+        match capped().build(vec![
+            Leaf::new("alice".to_string(), 101),
+            Leaf::new("bob".to_string(), 5),
+            Leaf::new("carol".to_string(), 200),
+        ]) {
+            Err(MerkleError::InvalidTree(violations)) => assert_eq!(
+                violations,
+                vec![
+                    IntegrityViolation::ValueAboveCap {
+                        index: 0,
+                        value: 101,
+                        cap: 100
+                    },
+                    IntegrityViolation::ValueAboveCap {
+                        index: 2,
+                        value: 200,
+                        cap: 100
+                    },
+                ]
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let before = tree.clone();
+        assert!(matches!(
+            tree.set_leaf(Leaf::new("carol".to_string(), 101), 2),
+            Err(MerkleError::ValueAboveCap {
+                value: 101,
+                cap: 100,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tree.add_to_leaf(0, 1),
+            Err(MerkleError::ValueAboveCap { .. })
+        ));
+        assert!(matches!(
+            tree.push(Leaf::new("dave".to_string(), 101)),
+            Err(MerkleError::ValueAboveCap { .. })
+        ));
+        tree.push(Leaf::new("dave".to_string(), 100)).unwrap();
+        //Full again, so this push would grow the tree
+        assert!(matches!(
+            tree.push(Leaf::new("erin".to_string(), 1000)),
+            Err(MerkleError::ValueAboveCap { .. })
+        ));
+        assert_eq!(tree.get_leafs().len(), 4);
+        tree.remove(3).unwrap();
+        assert_eq!(tree.get_leafs(), before.get_leafs());
+        assert_eq!(tree.get_nodes(), before.get_nodes());
+
+        //A batch with one share above the cap applies nothing
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        assert!(matches!(
+            tree.split_insert("frank", 250, 2, &mut rng),
+            Err(MerkleError::ValueAboveCap { .. })
+        ));
+        assert_eq!(tree.get_leafs(), before.get_leafs());
+        assert_eq!(tree.get_nodes(), before.get_nodes());
+
+        //Imports re-check the cap
+        let mut json: serde_json::Value = serde_json::to_value(&tree).unwrap();
+        json["max_leaf_value"] = serde_json::json!(50);
+        assert!(serde_json::from_value::<MerkleSumTree>(json).is_err());
+        let json = serde_json::to_string(&tree).unwrap();
+        let restored: MerkleSumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_max_leaf_value(), Some(100));
+    }
+
+    #[test]
+    fn non_negative_trees_refuse_negative_values() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 0),
+            Leaf::new("carol".to_string(), 30),
+        ];
+        let strict = || MerkleSumTree::builder().non_negative(true);
+        let mut tree = strict().build(leafs.clone()).unwrap();
+        assert!(tree.is_non_negative());
+
+        let mut negative = leafs.clone();
+        negative.push(Leaf::new("mallory".to_string(), -5));
+        let err = strict().build(negative.clone()).unwrap_err();
+        assert_eq!(err.error_code(), "E_NEGATIVE_VALUE");
+        assert!(matches!(
+            err,
+            MerkleError::NegativeValue { id, value: -5 } if id == "mallory"
+        ));
+        assert!(MerkleSumTree::new(negative).is_ok());
+
+        let before = tree.clone();
+        assert!(matches!(
+            tree.push(Leaf::new("dave".to_string(), -1)),
+            Err(MerkleError::NegativeValue { value: -1, .. })
+        ));
+        assert!(matches!(
+            tree.set_leaf(Leaf::new("alice".to_string(), -10), 0),
+            Err(MerkleError::NegativeValue { .. })
+        ));
+        assert!(matches!(
+            tree.add_to_leaf(2, -31),
+            Err(MerkleError::NegativeValue { .. })
+        ));
+        assert_eq!(tree.get_nodes(), before.get_nodes());
+        assert_eq!(tree.add_to_leaf(2, -30).unwrap(), 0);
+        tree.remove(0).unwrap();
+
+        //The flag is serialized and validate checks the leafs against it
+        let plain = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), -20),
+        ])
+        .unwrap();
+        let mut json: serde_json::Value = serde_json::to_value(&plain).unwrap();
+        json["non_negative"] = serde_json::json!(true);
+        assert!(serde_json::from_value::<MerkleSumTree>(json).is_err());
+        let restored: MerkleSumTree =
+            serde_json::from_str(&serde_json::to_string(&tree).unwrap()).unwrap();
+        assert!(restored.is_non_negative());
+        let mut forged = restored;
+        forged.leafs[1] = Leaf::new("bob".to_string(), -1);
+        let violations = forged.validate().unwrap_err();
+        assert!(violations.contains(&IntegrityViolation::NegativeValue {
+            index: 1,
+            value: -1
+        }));
+    }
+
+    #[test]
+    fn emptiness_proofs_only_prove_empty_slots() {
+        let mut tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        tree.remove(1).unwrap();
+        for index in [1, 3] {
+            let proof = tree.get_emptiness_proof(index).unwrap();
+            assert!(proof.is_empty_slot());
+            assert!(tree.verify_emptiness_proof(proof).unwrap());
+        }
+        assert!(matches!(
+            tree.get_emptiness_proof(0),
+            Err(MerkleError::SlotOccupied { index: 0, id }) if id == "alice"
+        ));
+        assert!(matches!(
+            tree.get_emptiness_proof(4),
+            Err(MerkleError::IndexOutOfBounds(4))
+        ));
+        //A membership proof does not pass as one of emptiness
+        let member = tree.get_proof(2).unwrap().unwrap();
+        assert!(!tree.verify_emptiness_proof(member).unwrap());
+        //Nor does one taken before the slot was filled again
+        let stale = tree.get_emptiness_proof(1).unwrap();
+        tree.push(Leaf::new("dave".to_string(), 40)).unwrap();
+        assert!(!tree.verify_emptiness_proof(stale).unwrap());
+
+        let dummies = MerkleSumTree::builder()
+            .dummy_padding([7; 32])
+            .build(vec![
+                Leaf::new("alice".to_string(), 10),
+                Leaf::new("bob".to_string(), 20),
+                Leaf::new("carol".to_string(), 30),
+            ])
+            .unwrap();
+        assert!(matches!(
+            dummies.get_emptiness_proof(3),
+            Err(MerkleError::SlotOccupied { index: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn path_bits_round_trip_through_a_field_element() {
+        let leafs = users(512, 1, 0);
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.get_height(), 10);
+        for index in 0..512 {
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            let bits = proof.path_bits_as_fr();
+            assert_eq!(bits, Fr::from(index as u64));
+            let positions: Vec<Position> = proof
+                .get_path()
+                .iter()
+                .map(Neighbor::get_position)
+                .collect();
+            assert_eq!(
+                InclusionProof::path_bits_from_fr(bits, 10).unwrap(),
+                positions
+            );
+        }
+        assert!(matches!(
+            InclusionProof::path_bits_from_fr(Fr::from(512u64), 10),
+            Err(MerkleError::InvalidFieldElement(_))
+        ));
+        assert!(matches!(
+            InclusionProof::path_bits_from_fr(-Fr::ONE, 10),
+            Err(MerkleError::InvalidFieldElement(_))
+        ));
+        assert_eq!(
+            InclusionProof::path_bits_from_fr(Fr::ZERO, 1).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn mimc_exponent_is_part_of_the_tree() {
+        let leafs = || {
+            vec![
+                Leaf::new("alice".to_string(), 10),
+                Leaf::new("bob".to_string(), 20),
+                Leaf::new("carol".to_string(), 30),
+            ]
+        };
+        let default = MerkleSumTree::new(leafs()).unwrap();
+        assert_eq!(default.get_mimc_exponent(), 5);
+        assert_eq!(
+            MerkleSumTree::builder()
+                .mimc_exponent(5)
+                .build(leafs())
+                .unwrap()
+                .get_root(),
+            default.get_root()
+        );
+        assert!(matches!(
+            MerkleSumTree::builder().mimc_exponent(3).build(leafs()),
+            Err(MerkleError::UnsupportedExponent(3))
+        ));
+
+        let mut tree = MerkleSumTree::builder()
+            .mimc_exponent(7)
+            .build(leafs())
+            .unwrap();
+        assert_eq!(
+            tree.get_root_hash().unwrap().to_string(),
+            "11206553831132728041773250250929818157303126177269970465726360649794734688713"
+        );
+        tree.set_leaf(Leaf::new("dave".to_string(), 5), 3).unwrap();
+        tree.push(Leaf::new("erin".to_string(), 1)).unwrap();
+        assert_eq!(tree.validate(), Ok(()));
+
+        //Proofs carry the exponent and never verify against the other one
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        assert_eq!(proof.get_exponent(), 7);
+        assert!(tree.verify_proof(proof.clone()).unwrap());
+        assert!(!default.verify_proof(proof.clone()).unwrap());
+        assert!(!tree
+            .verify_proof(default.get_proof(1).unwrap().unwrap())
+            .unwrap());
+        let json = serde_json::to_string(&proof).unwrap();
+        assert!(json.contains("\"exponent\":7"));
+        let relabeled: InclusionProof =
+            serde_json::from_str(&json.replace("\"exponent\":7", "\"exponent\":5")).unwrap();
+        assert_ne!(
+            relabeled.compute_root().unwrap(),
+            proof.compute_root().unwrap()
+        );
+        let default_json = serde_json::to_string(&default.get_proof(0).unwrap().unwrap()).unwrap();
+        assert!(!default_json.contains("exponent"));
+
+        let restored: MerkleSumTree =
+            serde_json::from_str(&serde_json::to_string(&tree).unwrap()).unwrap();
+        assert_eq!(restored.get_mimc_exponent(), 7);
+        let tree_json = serde_json::to_string(&tree)
+            .unwrap()
+            .replace("\"mimc_exponent\":7", "\"mimc_exponent\":3");
+        assert!(serde_json::from_str::<MerkleSumTree>(&tree_json).is_err());
+        assert!(matches!(
+            tree.prove_subtree(1, 0),
+            Err(MerkleError::UnsupportedExponent(7))
+        ));
+    }
+
+    #[test]
+    fn small_values_convert_like_large_ones() {
+        for value in [0, 1, -1, 7, 1023, -1023, 1024, -1024, i32::MAX, i32::MIN] {
+            let magnitude = Fr::from(u64::from(value.unsigned_abs()));
+            let expected = if value < 0 { -magnitude } else { magnitude };
+            assert_eq!(value_to_fr(value), expected, "{}", value);
+            assert_eq!(fr_to_value(value_to_fr(value)), Some(value));
+        }
+        assert_eq!(sum_to_fr(-5000), -Fr::from(5000u64));
+    }
+
+    #[test]
+    fn proofs_verify_against_the_published_root_alone() {
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), -4),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        let (root_hash, root_sum) = (tree.get_root_hash().unwrap(), tree.get_root_sum().unwrap());
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        drop(tree);
+        assert!(proof.verify(root_hash, root_sum).unwrap());
+        assert!(!proof.verify(root_hash, root_sum + 1).unwrap());
+        assert!(!proof.verify(root_hash + Fr::ONE, root_sum).unwrap());
+
+        //A path that cannot be folded is an error, not a mismatch
+        let mut forged = proof.clone();
+        forged.path[0] = Neighbor::new(Position::Left, Node::new(Fr::ONE, i32::MAX));
+        assert!(matches!(
+            forged.verify(root_hash, root_sum),
+            Err(MerkleError::Overflow)
+        ));
+        let mut forged = proof;
+        forged.exponent = 4;
+        assert!(matches!(
+            forged.verify(root_hash, root_sum),
+            Err(MerkleError::UnsupportedExponent(4))
+        ));
+    }
+
+    #[test]
+    fn flattened_paths_fold_to_the_root() {
+        let leafs = users(11, 9, 40);
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        for index in 0..11 {
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            let (indices, hashes, values) = (
+                proof.path_indices(),
+                proof.sibling_hashes(),
+                proof.sibling_values(),
+            );
+            assert_eq!(indices.len(), hashes.len());
+            assert_eq!(indices.len(), values.len());
+            let mut node = proof.get_leaf().get_node();
+            for ((direction, hash), value) in indices.iter().zip(hashes).zip(values) {
+                let sibling = Node::new(hash, value);
+                node = match direction {
+                    0 => MerkleSumTree::build_parent(node, sibling).unwrap(),
+                    _ => MerkleSumTree::build_parent(sibling, node).unwrap(),
+                };
+            }
+            assert_eq!(Some(node), tree.get_root());
+            let bits = indices
+                .iter()
+                .rev()
+                .fold(0, |index, bit| (index << 1) | usize::from(*bit));
+            assert_eq!(bits, proof.get_index());
+        }
+    }
+
+    #[test]
+    fn proofs_carry_the_index_their_path_walks() {
+        let leafs = users(6, 1, 0);
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        for index in 0..6 {
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            assert_eq!(proof.get_index(), index);
+            assert_eq!(path_index(&proof.path), index);
+            assert!(tree.verify_proof(proof).unwrap());
+        }
+
+        //A valid proof for 3 presented as one for 5
+        let mut relabeled = tree.get_proof(3).unwrap().unwrap();
+        relabeled.index = 5;
+        assert!(matches!(
+            relabeled.compute_root(),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        assert!(matches!(
+            tree.verify_proof(relabeled.clone()),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        //An index with bits above the path
+        relabeled.index = 3 + 8;
+        let err = relabeled.compute_root().unwrap_err();
+        assert_eq!(err.error_code(), "E_INVALID_PROOF");
+        assert_eq!(
+            err.to_string(),
+            "Invalid proof: index 11 does not fit in a path of 3 levels"
+        );
+
+        //Proofs serialized without an index take the one their path spells
+        let proof = tree.get_proof(5).unwrap().unwrap();
+        let mut json = serde_json::to_value(&proof).unwrap();
+        assert_eq!(json["index"], 5);
+        json.as_object_mut().unwrap().remove("index");
+        let restored: InclusionProof = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(restored, proof);
+
+        //The serde form is held to the bounds of from_json and from_bytes
+        let mut moved = json.clone();
+        moved["index"] = 4.into();
+        assert!(serde_json::from_value::<InclusionProof>(moved).is_err());
+        let mut long = json.clone();
+        long["path"] = serde_json::Value::Array(vec![json["path"][0].clone(); 65]);
+        let err = serde_json::from_value::<InclusionProof>(long).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Path of 65 levels is above the maximum of 64"
+        );
+    }
+
+    #[test]
+    fn batch_proofs_match_single_proofs() {
+        for count in [1, 2, 5, 8, 37] {
+            let leafs = users(count, 3, 7);
+            let tree = MerkleSumTree::new(leafs).unwrap();
+            let mut indices: Vec<usize> = (0..count as usize).rev().collect();
+            indices.push(0);
+            let proofs = tree.get_proofs(&indices).unwrap();
+            assert_eq!(proofs.len(), indices.len());
+            for (proof, index) in proofs.into_iter().zip(&indices) {
+                assert_eq!(proof, tree.get_proof(*index).unwrap().unwrap());
+            }
+        }
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        assert!(tree.get_proofs(&[]).unwrap().is_empty());
+        //Out of range is reported before the empty slot earlier in the list
+        assert!(matches!(
+            tree.get_proofs(&[0, 3, 7, 4]),
+            Err(MerkleError::IndexOutOfBounds(7))
+        ));
+        assert!(matches!(
+            tree.get_proofs(&[0, 3, 1]),
+            Err(MerkleError::EmptySlot(3))
+        ));
+    }
+
+    //The level loop as it was, with a copy of each level to hash from
+    fn scratch_build_levels(bottom: Vec<Node>, params: &HashParams) -> Vec<Node> {
+        let mut nodes = bottom.clone();
+        let mut nodes_to_hash = bottom;
+        while nodes_to_hash.len() > 1 {
+            let mut temp_hash_nodes = vec![];
+            for pair in nodes_to_hash.chunks(2) {
+                let parent =
+                    MerkleSumTree::build_parent_with(params, pair[0].clone(), pair[1].clone());
+                temp_hash_nodes.push(parent.unwrap());
+            }
+            nodes.extend_from_slice(&temp_hash_nodes);
+            nodes_to_hash = temp_hash_nodes;
+        }
+        nodes
+    }
+
+    #[test]
+    fn levels_hashed_in_place_keep_the_flat_order() {
+        let params = HashParams::default();
+        for leaf_count in [0usize, 1, 2, 4, 8, 32, 256] {
+            let bottom: Vec<Node> = (0..leaf_count)
+                .map(|i| Leaf::new(format!("user{}", i), i as i32 * 3 - 40).get_node())
+                .collect();
+            let nodes = MerkleSumTree::build_levels(
+                bottom.clone(),
+                &params,
+                &[],
+                &CancellationToken::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                nodes,
+                scratch_build_levels(bottom, &params),
+                "{}",
+                leaf_count
+            );
+            assert_eq!(nodes.len(), (2 * leaf_count).saturating_sub(1));
+            assert_eq!(nodes.capacity(), nodes.len());
+        }
+        let odd = vec![Node::new(Fr::ONE, 1); 6];
+        assert!(matches!(
+            MerkleSumTree::build_levels(odd, &params, &[], &CancellationToken::default()),
+            Err(MerkleError::OddLevel(3))
+        ));
+    }
 }
@@ -0,0 +1,150 @@
+//Consolidation of two trees by account id
+
+use crate::{Leaf, MerkleError, MerkleSumTree};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MergePolicy {
+    //Checked sum of both balances
+    #[default]
+    Sum,
+    PreferLeft,
+    PreferRight,
+    //MerkleError::DuplicateId on the first shared id
+    ErrorOnConflict,
+}
+
+impl MerkleSumTree {
+    pub fn merge_by_id(
+        a: &MerkleSumTree,
+        b: &MerkleSumTree,
+        policy: MergePolicy,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        let (mut order, mut merged) = a.balances()?;
+        let (right_order, right) = b.balances()?;
+        for id in right_order {
+            let value = right.get(&id).copied().unwrap_or_default();
+            match merged.get_mut(&id) {
+                None => {
+                    merged.insert(id.clone(), value);
+                    order.push(id);
+                }
+                Some(left) => match policy {
+                    MergePolicy::Sum => {
+                        *left = left.checked_add(value).ok_or(MerkleError::Overflow)?
+                    }
+                    MergePolicy::PreferLeft => {}
+                    MergePolicy::PreferRight => *left = value,
+                    MergePolicy::ErrorOnConflict => return Err(MerkleError::DuplicateId(id)),
+                },
+            }
+        }
+        let leafs = order
+            .into_iter()
+            .map(|id| {
+                let value = merged.get(&id).copied().unwrap_or_default();
+                Leaf::new(id, value)
+            })
+            .collect();
+        Self::create_tree(
+            leafs,
+            a.max_height,
+            None,
+            BTreeSet::new(),
+            a.empty.clone(),
+            a.leaf_hash_mode,
+            a.mimc_exponent,
+            &a.progress,
+            &a.cancel,
+        )
+    }
+
+    //Total per id over the occupied slots, ids in order of first slot
+    fn balances(&self) -> Result<(Vec<String>, HashMap<String, i32>), MerkleError> {
+        let mut order = vec![];
+        let mut totals: HashMap<String, i32> = HashMap::new();
+        for (_, leaf) in self.non_empty_leafs(false) {
+            let value = leaf.node.value;
+            match totals.get_mut(&*leaf.id) {
+                Some(total) => *total = total.checked_add(value).ok_or(MerkleError::Overflow)?,
+                None => {
+                    totals.insert(leaf.get_id(), value);
+                    order.push(leaf.get_id());
+                }
+            }
+        }
+        Ok((order, totals))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    fn tree(pairs: &[(&str, i32)]) -> MerkleSumTree {
+        MerkleSumTree::new(
+            pairs
+                .iter()
+                .map(|(id, value)| Leaf::new(id.to_string(), *value))
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn shared_ids_follow_the_policy() {
+        let a = tree(&[("alice", 10), ("bob", 20), ("carol", 30)]);
+        let b = tree(&[("dave", 5), ("bob", -4), ("alice", 1)]);
+        let summed = MerkleSumTree::merge_by_id(&a, &b, MergePolicy::default()).unwrap();
+        assert_eq!(
+            summed.get_root(),
+            tree(&[("alice", 11), ("bob", 16), ("carol", 30), ("dave", 5)]).get_root()
+        );
+        assert_eq!(summed.get_root_sum(), Some(62));
+        assert_eq!(summed.validate(), Ok(()));
+
+        let left = MerkleSumTree::merge_by_id(&a, &b, MergePolicy::PreferLeft).unwrap();
+        assert_eq!(left.get_root_sum(), Some(65));
+        let right = MerkleSumTree::merge_by_id(&a, &b, MergePolicy::PreferRight).unwrap();
+        assert_eq!(right.get_root_sum(), Some(32));
+        assert!(matches!(
+            MerkleSumTree::merge_by_id(&a, &b, MergePolicy::ErrorOnConflict),
+            Err(MerkleError::DuplicateId(id)) if id == "bob"
+        ));
+    }
+
+    #[test]
+    fn disjoint_ids_are_concatenated() {
+        let mut a = tree(&[("alice", 10), ("bob", 20), ("carol", 30)]);
+        a.remove(1).unwrap();
+        let b = MerkleSumTree::builder()
+            .dummy_padding([5; 32])
+            .build(vec![
+                Leaf::new("dave".to_string(), 5),
+                Leaf::new("erin".to_string(), 6),
+                Leaf::new("dave".to_string(), 1),
+            ])
+            .unwrap();
+        let merged = MerkleSumTree::merge_by_id(&a, &b, MergePolicy::ErrorOnConflict).unwrap();
+        assert_eq!(
+            merged.get_root(),
+            tree(&[("alice", 10), ("carol", 30), ("dave", 6), ("erin", 6)]).get_root()
+        );
+        assert_eq!(merged.get_leafs().len(), 4);
+    }
+
+    #[test]
+    fn merge_overflow_is_reported() {
+        let a = tree(&[("alice", i32::MAX - 1), ("bob", 1)]);
+        let b = tree(&[("alice", 2)]);
+        assert!(matches!(
+            MerkleSumTree::merge_by_id(&a, &b, MergePolicy::Sum),
+            Err(MerkleError::Overflow)
+        ));
+        let left = MerkleSumTree::merge_by_id(&a, &b, MergePolicy::PreferLeft).unwrap();
+        assert_eq!(left.get_root_sum(), Some(i32::MAX));
+        let right = MerkleSumTree::merge_by_id(&a, &b, MergePolicy::PreferRight).unwrap();
+        assert_eq!(right.get_root_sum(), Some(3));
+    }
+}
@@ -0,0 +1,234 @@
+//Instrumentation of hashing and mutations
+
+use crate::wal::LogKind;
+use crate::MerkleSumTree;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub trait Metrics: Send + Sync {
+    fn record_hash(&self);
+
+    //Batched form, sinks with a cheaper bulk update override it
+    fn record_hashes(&self, count: u64) {
+        for _ in 0..count {
+            self.record_hash();
+        }
+    }
+
+    fn record_mutation(&self, kind: MutationKind, nodes_touched: usize, duration: Duration);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MutationKind {
+    Push,
+    SetLeaf,
+    Remove,
+}
+
+//Running totals, safe to share between trees and threads
+#[derive(Debug, Default)]
+pub struct CountingMetrics {
+    hashes: AtomicU64,
+    mutations: AtomicU64,
+    nodes_touched: AtomicU64,
+    nanos: AtomicU64,
+}
+
+//The sink a tree reports to, clones share it
+#[derive(Clone, Default)]
+pub(crate) struct MetricsSink(Option<Arc<dyn Metrics>>);
+
+impl From<LogKind> for MutationKind {
+    fn from(kind: LogKind) -> Self {
+        match kind {
+            LogKind::Push => MutationKind::Push,
+            LogKind::SetLeaf => MutationKind::SetLeaf,
+            LogKind::Remove => MutationKind::Remove,
+        }
+    }
+}
+
+impl CountingMetrics {
+    pub fn new() -> CountingMetrics {
+        CountingMetrics::default()
+    }
+
+    pub fn get_hashes(&self) -> u64 {
+        self.hashes.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mutations(&self) -> u64 {
+        self.mutations.load(Ordering::Relaxed)
+    }
+
+    pub fn get_nodes_touched(&self) -> u64 {
+        self.nodes_touched.load(Ordering::Relaxed)
+    }
+
+    //Total time spent in recorded mutations
+    pub fn get_duration(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.hashes,
+            &self.mutations,
+            &self.nodes_touched,
+            &self.nanos,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Metrics for CountingMetrics {
+    fn record_hash(&self) {
+        self.hashes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_hashes(&self, count: u64) {
+        self.hashes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn record_mutation(&self, _kind: MutationKind, nodes_touched: usize, duration: Duration) {
+        self.mutations.fetch_add(1, Ordering::Relaxed);
+        self.nodes_touched
+            .fetch_add(nodes_touched as u64, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for MetricsSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            None => f.write_str("None"),
+            Some(_) => f.write_str("Sink"),
+        }
+    }
+}
+
+impl MetricsSink {
+    pub(crate) fn new(metrics: Option<Arc<dyn Metrics>>) -> MetricsSink {
+        MetricsSink(metrics)
+    }
+
+    //Start of a timed mutation, the clock is only read with a sink
+    pub(crate) fn start(&self) -> Option<Instant> {
+        self.0.as_ref().map(|_| Instant::now())
+    }
+
+    pub(crate) fn hashes(&self, count: usize) {
+        if let Some(metrics) = &self.0 {
+            metrics.record_hashes(count as u64);
+        }
+    }
+
+    pub(crate) fn mutation(
+        &self,
+        kind: MutationKind,
+        nodes_touched: usize,
+        started: Option<Instant>,
+    ) {
+        if let (Some(metrics), Some(started)) = (&self.0, started) {
+            metrics.record_mutation(kind, nodes_touched, started.elapsed());
+        }
+    }
+}
+
+impl MerkleSumTree {
+    //Report to metrics from now on, None detaches the current sink
+    pub fn set_metrics(&mut self, metrics: Option<Arc<dyn Metrics>>) {
+        self.metrics = MetricsSink::new(metrics);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::Leaf;
+    use std::sync::Mutex;
+
+    fn leafs(count: i32) -> Vec<Leaf> {
+        users(count, 1, 0)
+    }
+
+    #[test]
+    fn hash_counts_follow_the_closed_form() {
+        for (count, padded) in [(1, 1), (2, 2), (5, 8), (16, 16), (17, 32)] {
+            let metrics = Arc::new(CountingMetrics::new());
+            let tree = MerkleSumTree::builder()
+                .metrics(metrics.clone())
+                .build(leafs(count))
+                .unwrap();
+            //A binary tree over n padded leafs has n - 1 parents
+            assert_eq!(metrics.get_hashes(), padded - 1);
+
+            metrics.reset();
+            let proof = tree.get_proof(0).unwrap().unwrap();
+            assert_eq!(metrics.get_hashes(), 0);
+            assert!(tree.verify_proof(proof).unwrap());
+            assert_eq!(metrics.get_hashes(), tree.get_height() as u64 - 1);
+        }
+    }
+
+    #[test]
+    fn mutations_report_touched_nodes() {
+        let metrics = Arc::new(CountingMetrics::new());
+        let mut tree = MerkleSumTree::new(leafs(5)).unwrap();
+        tree.set_metrics(Some(metrics.clone()));
+        tree.set_leaf(Leaf::new("user1".to_string(), 50), 1)
+            .unwrap();
+        assert_eq!(metrics.get_hashes(), 3);
+        assert_eq!(metrics.get_nodes_touched(), 4);
+        tree.add_to_leaf(1, 5).unwrap();
+        tree.remove(2).unwrap();
+        tree.push(Leaf::new("user9".to_string(), 9)).unwrap();
+        assert_eq!(metrics.get_mutations(), 4);
+        assert_eq!(metrics.get_hashes(), 12);
+
+        //Growth rebuilds every node
+        metrics.reset();
+        for i in 0..4 {
+            tree.push(Leaf::new(format!("new{}", i), i)).unwrap();
+        }
+        assert_eq!(tree.get_leafs().len(), 16);
+        assert_eq!(metrics.get_hashes(), 3 * 3 + 15);
+        assert_eq!(metrics.get_nodes_touched(), 3 * 4 + 31);
+
+        tree.set_metrics(None);
+        tree.remove(0).unwrap();
+        assert_eq!(metrics.get_mutations(), 4);
+    }
+
+    #[test]
+    fn custom_sinks_see_each_kind() {
+        #[derive(Default)]
+        struct Kinds(Mutex<Vec<MutationKind>>);
+        impl Metrics for Kinds {
+            fn record_hash(&self) {}
+            fn record_mutation(&self, kind: MutationKind, _: usize, _: Duration) {
+                self.0.lock().unwrap().push(kind);
+            }
+        }
+        let kinds = Arc::new(Kinds::default());
+        let mut tree = MerkleSumTree::new(leafs(3)).unwrap();
+        tree.set_metrics(Some(kinds.clone()));
+        tree.push(Leaf::new("dave".to_string(), 1)).unwrap();
+        tree.set_leaf(Leaf::new("erin".to_string(), 2), 0).unwrap();
+        tree.remove(1).unwrap();
+        assert_eq!(
+            *kinds.0.lock().unwrap(),
+            vec![
+                MutationKind::Push,
+                MutationKind::SetLeaf,
+                MutationKind::Remove
+            ]
+        );
+    }
+}
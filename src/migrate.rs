@@ -0,0 +1,237 @@
+//Migration of a tree to another hashing parameterization
+
+use crate::progress::finish_all;
+use crate::{
+    HashParams, Leaf, LeafHashMode, MerkleError, MerkleSumTree, RootCommitment, DEFAULT_EXPONENT,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TreeParams {
+    leaf_hash_mode: LeafHashMode,
+    mimc_exponent: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationReport {
+    old_params: TreeParams,
+    new_params: TreeParams,
+    old_root: RootCommitment,
+    new_root: RootCommitment,
+    leaf_count: usize,
+    account_count: usize,
+    rehashed_count: usize,
+}
+
+impl TreeParams {
+    pub fn new(leaf_hash_mode: LeafHashMode, mimc_exponent: u64) -> TreeParams {
+        TreeParams {
+            leaf_hash_mode,
+            mimc_exponent,
+        }
+    }
+
+    pub fn get_leaf_hash_mode(&self) -> LeafHashMode {
+        self.leaf_hash_mode
+    }
+
+    pub fn get_mimc_exponent(&self) -> u64 {
+        self.mimc_exponent
+    }
+}
+
+//The params of MerkleSumTree::new
+impl Default for TreeParams {
+    fn default() -> TreeParams {
+        TreeParams::new(LeafHashMode::default(), DEFAULT_EXPONENT)
+    }
+}
+
+impl MigrationReport {
+    pub fn get_old_params(&self) -> TreeParams {
+        self.old_params
+    }
+
+    pub fn get_new_params(&self) -> TreeParams {
+        self.new_params
+    }
+
+    pub fn get_old_root(&self) -> RootCommitment {
+        self.old_root
+    }
+
+    pub fn get_new_root(&self) -> RootCommitment {
+        self.new_root
+    }
+
+    //Slots, padding included
+    pub fn get_leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    //Occupied slots, without empty and dummy ones
+    pub fn get_account_count(&self) -> usize {
+        self.account_count
+    }
+
+    //Slots whose leaf hash changed
+    pub fn get_rehashed_count(&self) -> usize {
+        self.rehashed_count
+    }
+}
+
+impl MerkleSumTree {
+    pub fn get_params(&self) -> TreeParams {
+        TreeParams::new(self.leaf_hash_mode, self.mimc_exponent)
+    }
+
+    //Rebuild under target, leaving self untouched
+    pub fn migrate(
+        &self,
+        target: TreeParams,
+    ) -> Result<(MerkleSumTree, MigrationReport), MerkleError> {
+        let params = HashParams::mimc(target.mimc_exponent)?;
+        let old_root = self.get_commitment().ok_or(MerkleError::EmptyTree)?;
+        let leafs: Vec<Leaf> = self
+            .leafs
+            .iter()
+            .map(|leaf| target.leaf_hash_mode.normalize(self.id_only(leaf)))
+            .collect();
+        let levels = self.progress.levels(leafs.len());
+        let nodes = Self::build_nodes(&leafs, &params, &levels, &self.cancel)?;
+        finish_all(&levels);
+        self.metrics.hashes(nodes.len().saturating_sub(leafs.len()));
+        let rehashed_count = leafs
+            .iter()
+            .zip(&self.leafs)
+            .filter(|(new, old)| new.node.hash != old.node.hash)
+            .count();
+        let mut tree = Self::assemble(
+            leafs,
+            nodes,
+            self.height,
+            self.max_height,
+            self.dummy_seed,
+            self.dummies.clone(),
+            self.empty.clone(),
+        );
+        tree.zero_index = self.zero_index.clone();
+        tree.append_only = self.append_only;
+        tree.next_slot = self.next_slot;
+        tree.retired = self.retired.clone();
+        tree.epoch = self.epoch;
+        tree.shuffle_seed = self.shuffle_seed;
+        tree.max_leaf_value = self.max_leaf_value;
+        tree.non_negative = self.non_negative;
+        tree.allocation = self.allocation;
+        tree.canonical = self.canonical;
+        tree.leaf_hash_mode = target.leaf_hash_mode;
+        tree.mimc_exponent = target.mimc_exponent;
+        tree.set_plain_root(self.plain.is_some());
+        tree.set_keccak_dual(self.keccak.is_some());
+        let report = MigrationReport {
+            old_params: self.get_params(),
+            new_params: target,
+            old_root,
+            new_root: tree.get_commitment().ok_or(MerkleError::EmptyTree)?,
+            leaf_count: tree.leafs.len(),
+            account_count: tree.non_empty_leafs(false).count(),
+            rehashed_count,
+        };
+        Ok((tree, report))
+    }
+
+    //The leaf under IdOnly, whose hash every mode starts from
+    fn id_only(&self, leaf: &Leaf) -> Leaf {
+        match self.leaf_hash_mode {
+            LeafHashMode::IdOnly => leaf.clone(),
+            LeafHashMode::IdAndValue => Leaf::with_shared_id(leaf.id.clone(), leaf.node.value),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+
+    fn leafs() -> Vec<Leaf> {
+        accounts(&[10, -20, 30])
+    }
+
+    #[test]
+    fn migration_keeps_slots_ids_and_sums() {
+        let mut tree = MerkleSumTree::new(leafs()).unwrap();
+        tree.push(Leaf::new("dave".to_string(), 4)).unwrap();
+        tree.remove(1).unwrap();
+        let bound = TreeParams::new(LeafHashMode::IdAndValue, 7);
+        let (migrated, report) = tree.migrate(bound).unwrap();
+
+        assert_eq!(migrated.get_params(), bound);
+        assert_eq!(migrated.validate(), Ok(()));
+        assert_eq!(migrated.get_epoch(), tree.get_epoch());
+        assert_eq!(migrated.get_root_sum(), tree.get_root_sum());
+        assert_ne!(migrated.get_root(), tree.get_root());
+        for (old, new) in tree.get_leafs().iter().zip(migrated.get_leafs()) {
+            assert_eq!(old.get_id(), new.get_id());
+            assert_eq!(old.get_node().get_value(), new.get_node().get_value());
+        }
+        assert_eq!(migrated.get_index_by_id("dave"), Some(3));
+        assert_eq!(
+            migrated.iter_free_slots().collect::<Vec<_>>(),
+            tree.iter_free_slots().collect::<Vec<_>>()
+        );
+        let mut fresh = MerkleSumTree::builder()
+            .leaf_hash_mode(LeafHashMode::IdAndValue)
+            .mimc_exponent(7)
+            .build(leafs())
+            .unwrap();
+        fresh.push(Leaf::new("dave".to_string(), 4)).unwrap();
+        fresh.remove(1).unwrap();
+        assert_eq!(migrated.get_leafs(), fresh.get_leafs());
+        assert_eq!(migrated.get_nodes(), fresh.get_nodes());
+
+        assert_eq!(report.get_old_params(), TreeParams::default());
+        assert_eq!(report.get_old_root(), tree.get_commitment().unwrap());
+        assert_eq!(report.get_new_root(), migrated.get_commitment().unwrap());
+        assert_eq!(report.get_leaf_count(), 4);
+        assert_eq!(report.get_account_count(), 3);
+        assert_eq!(report.get_rehashed_count(), 4);
+
+        //And back, the source is left as it was
+        let (restored, back) = migrated.migrate(TreeParams::default()).unwrap();
+        assert_eq!(restored.get_root(), tree.get_root());
+        assert_eq!(back.get_new_root(), report.get_old_root());
+        assert_eq!(migrated.get_params(), bound);
+    }
+
+    #[test]
+    fn migration_keeps_padding_and_refuses_bad_params() {
+        let tree = MerkleSumTree::builder()
+            .dummy_padding([4; 32])
+            .append_only(true)
+            .build(leafs())
+            .unwrap();
+        let target = TreeParams::new(LeafHashMode::IdOnly, 3);
+        assert!(matches!(
+            tree.migrate(target),
+            Err(MerkleError::UnsupportedExponent(3))
+        ));
+        let (migrated, report) = tree
+            .migrate(TreeParams::new(LeafHashMode::IdOnly, 7))
+            .unwrap();
+        assert_eq!(migrated.validate(), Ok(()));
+        assert_eq!(migrated.get_leafs(), tree.get_leafs());
+        assert_eq!(report.get_rehashed_count(), 0);
+        assert_eq!(report.get_account_count(), 3);
+        assert_ne!(
+            report.get_new_root().get_hash(),
+            report.get_old_root().get_hash()
+        );
+
+        let json = serde_json::to_string(&report).unwrap();
+        let parsed: MigrationReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, report);
+    }
+}
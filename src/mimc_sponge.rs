@@ -1,9 +1,8 @@
 #![allow(clippy::derive_hash_xor_eq)]
 #![allow(clippy::too_many_arguments)]
 use crate::constants::C_STR;
+use crate::MerkleError;
 use ff::{self, *};
-use once_cell::sync::Lazy;
-use std::fmt;
 use std::ops::AddAssign;
 
 #[derive(PrimeField)]
@@ -14,45 +13,71 @@ use std::ops::AddAssign;
 #[PrimeFieldGenerator = "7"]
 #[PrimeFieldReprEndianness = "little"]
 pub struct Fr([u64; 4]);
-impl fmt::Display for Fr {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut format = format!("{:?}", self);
-        format = format.replace("Fr(", "");
-        format = format.replace(")", "");
-        write!(f, "{}", format)
-    }
-}
+
+//Round exponent of every vector the crate has published
+pub(crate) const DEFAULT_EXPONENT: u64 = 5;
 
 const DEFAULT_CONSTS_LEN: usize = C_STR.len();
-static DEFAULT_CONSTS: Lazy<[Fr; DEFAULT_CONSTS_LEN]> = Lazy::new(|| {
-    C_STR
-        .iter()
-        .map(|s| Fr::from_str_vartime(s).unwrap())
-        .collect::<Vec<_>>()
-        .try_into()
-        .unwrap()
-});
+//C_STR in Montgomery form, converted by build.rs
+static DEFAULT_CONSTS: [Fr; DEFAULT_CONSTS_LEN] =
+    include!(concat!(env!("OUT_DIR"), "/mimc_constants.rs"));
 
+//Must match SMALL_VALUES in build.rs
+#[cfg(feature = "value-table")]
+const SMALL_VALUES_LEN: usize = 1024;
+//0..SMALL_VALUES_LEN in Montgomery form, so small balances skip the conversion
+#[cfg(feature = "value-table")]
+static SMALL_VALUES: [Fr; SMALL_VALUES_LEN] =
+    include!(concat!(env!("OUT_DIR"), "/small_values.rs"));
+
+#[derive(Clone)]
 pub struct MimcSponge {
     constants: [Fr; DEFAULT_CONSTS_LEN],
+    exponent: u64,
 }
 
 impl Default for MimcSponge {
     fn default() -> Self {
         Self {
-            constants: *DEFAULT_CONSTS,
+            constants: DEFAULT_CONSTS,
+            exponent: DEFAULT_EXPONENT,
         }
     }
 }
 
 impl MimcSponge {
+    //x^exponent is only a permutation when gcd(exponent, p - 1) = 1
+    pub fn with_exponent(exponent: u64) -> Result<MimcSponge, MerkleError> {
+        if !matches!(exponent, 3 | 5 | 7) || modulus_minus_one_rem(exponent) == 0 {
+            return Err(MerkleError::UnsupportedExponent(exponent));
+        }
+        Ok(Self {
+            exponent,
+            ..Self::default()
+        })
+    }
+
+    pub fn get_exponent(&self) -> u64 {
+        self.exponent
+    }
+
+    //Addition chains: 2 multiplications for 3, 3 for 5 and 4 for 7
+    fn round_power(&self, t: Fr) -> Fr {
+        let t2 = t.square();
+        match self.exponent {
+            3 => t2 * t,
+            7 => t2.square() * t2 * t,
+            _ => t2.square() * t,
+        }
+    }
+
     fn hash(&self, mut xl: Fr, mut xr: Fr, k: Fr) -> (Fr, Fr) {
         let mut t;
         let mut xr_tmp;
         let last_index = self.constants.len() - 1;
 
         for (i, c) in self.constants.iter().enumerate() {
-            t = Fr::from_str_vartime("0").unwrap();
+            t = Fr::ZERO;
 
             t.add_assign(&xl);
             t.add_assign(&k);
@@ -61,7 +86,7 @@ impl MimcSponge {
                 t.add_assign(c);
             }
 
-            t = t.pow([5u64]);
+            t = self.round_power(t);
 
             xr_tmp = xr;
             xr_tmp.add_assign(&t);
@@ -77,10 +102,9 @@ impl MimcSponge {
         (xl, xr)
     }
 
-    /// Takes &slice of Fr elements, key and num_outputs
-    pub fn multi_hash(&self, arr: &[Fr], key: Fr, num_outputs: usize) -> Vec<Fr> {
-        let mut r = Fr::from_str_vartime("0").unwrap();
-        let mut c = Fr::from_str_vartime("0").unwrap();
+    fn absorb(&self, arr: &[Fr], key: Fr) -> (Fr, Fr) {
+        let mut r = Fr::ZERO;
+        let mut c = Fr::ZERO;
 
         for elem in arr {
             r.add_assign(elem);
@@ -88,6 +112,22 @@ impl MimcSponge {
             (r, c) = s;
         }
 
+        (r, c)
+    }
+
+    /// Takes &slice of Fr elements and key, returns the first output
+    pub fn single_hash(&self, arr: &[Fr], key: Fr) -> Fr {
+        self.absorb(arr, key).0
+    }
+
+    /// Takes &slice of Fr elements, key and num_outputs
+    ///
+    /// Squeezes like circomlib's `MiMCSponge(nInputs, nRounds, nOutputs)`: the first
+    /// output is the left half after absorbing, and each further output is the left
+    /// half after one more permutation of the whole state under the same key.
+    pub fn multi_hash(&self, arr: &[Fr], key: Fr, num_outputs: usize) -> Vec<Fr> {
+        let (mut r, mut c) = self.absorb(arr, key);
+
         let mut out = Vec::with_capacity(num_outputs);
         out.push(r);
 
@@ -101,7 +141,32 @@ impl MimcSponge {
     }
 }
 
+//magnitude from the small value table, None past its end
+#[cfg(feature = "value-table")]
+pub(crate) fn small_value(magnitude: u64) -> Option<Fr> {
+    usize::try_from(magnitude)
+        .ok()
+        .and_then(|index| SMALL_VALUES.get(index))
+        .copied()
+}
+
+#[cfg(not(feature = "value-table"))]
+pub(crate) fn small_value(_magnitude: u64) -> Option<Fr> {
+    None
+}
+
+//(p - 1) mod divisor, folded from the most significant byte of the repr
+fn modulus_minus_one_rem(divisor: u64) -> u64 {
+    (-Fr::ONE)
+        .to_repr()
+        .as_ref()
+        .iter()
+        .rev()
+        .fold(0, |rem, byte| (rem * 256 + u64::from(*byte)) % divisor)
+}
+
 #[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
 mod tests {
     use super::*;
     #[test]
@@ -118,4 +183,114 @@ mod tests {
         let res = ms.multi_hash(&arr, k, 1);
         println!("res: {}", res[0].to_string());
     }
+
+    #[test]
+    fn exponents_are_checked_against_the_field() {
+        //p - 1 is divisible by 3 but not by 5 or 7
+        assert_eq!(modulus_minus_one_rem(3), 0);
+        assert!(matches!(
+            MimcSponge::with_exponent(3),
+            Err(MerkleError::UnsupportedExponent(3))
+        ));
+        assert!(MimcSponge::with_exponent(4).is_err());
+        assert!(MimcSponge::with_exponent(11).is_err());
+        assert_eq!(MimcSponge::default().get_exponent(), 5);
+
+        let arr = [Fr::from(1u64), Fr::from(2u64)];
+        let five = MimcSponge::with_exponent(5).unwrap();
+        assert_eq!(
+            five.single_hash(&arr, Fr::ZERO),
+            MimcSponge::default().single_hash(&arr, Fr::ZERO)
+        );
+        assert_eq!(
+            five.single_hash(&arr, Fr::ZERO).to_string(),
+            "14853461800317369808065348307811036576757638768282215228606608913339258852805"
+        );
+        assert_eq!(
+            MimcSponge::with_exponent(7)
+                .unwrap()
+                .single_hash(&arr, Fr::ZERO)
+                .to_string(),
+            "9509806175384555886599140154399941232299001937158169667112402947020697702460"
+        );
+        let t = Fr::from(123456789u64);
+        for exponent in [5u64, 7] {
+            let sponge = MimcSponge::with_exponent(exponent).unwrap();
+            assert_eq!(sponge.round_power(t), t.pow([exponent]));
+        }
+        assert_ne!(
+            MimcSponge::with_exponent(7)
+                .unwrap()
+                .single_hash(&arr, Fr::ZERO),
+            five.single_hash(&arr, Fr::ZERO)
+        );
+    }
+
+    fn decimal(outputs: Vec<Fr>) -> Vec<String> {
+        outputs.iter().map(Fr::to_string).collect()
+    }
+
+    //Generated by a port of circomlibjs' MiMCSponge.multiHash over this crate's modulus
+    #[test]
+    fn squeeze_matches_the_circomlib_reference() {
+        let sponge = MimcSponge::default();
+        let arr = [Fr::from(1u64), Fr::from(2u64)];
+        assert_eq!(
+            decimal(sponge.multi_hash(&arr, Fr::ZERO, 3)),
+            vec![
+                "14853461800317369808065348307811036576757638768282215228606608913339258852805",
+                "1858802484344578968930191239468054993665651027826359588915641581448192104058",
+                "14870206268177894836512494094588277263211847382051936629524084586074038536617",
+            ]
+        );
+        assert_eq!(
+            decimal(sponge.multi_hash(&arr, Fr::from(7u64), 3)),
+            vec![
+                "11047116860320295718855842260154755629188264000401010488994700932746494200587",
+                "24700457452088226399691171337333715528966490209742854055839433109762197156754",
+                "2819631093575838590750557924149996052589433288688856497221273428093640450969",
+            ]
+        );
+        let arr = [11672136u64, 10, 10566265, 11].map(Fr::from);
+        assert_eq!(
+            decimal(sponge.multi_hash(&arr, Fr::ZERO, 2)),
+            vec![
+                "16553565579072543485186950579965049473545293550789632887160175454161067304169",
+                "10046704850361034061136322934388166414041870078487812194933422241257505049759",
+            ]
+        );
+
+        //Each squeeze permutes the full state with the key, as absorbing a zero does
+        for key in [Fr::ZERO, Fr::from(7u64)] {
+            let outputs = sponge.multi_hash(&arr, key, 3);
+            assert_eq!(outputs[0], sponge.single_hash(&arr, key));
+            let mut padded = arr.to_vec();
+            for output in &outputs[1..] {
+                padded.push(Fr::ZERO);
+                assert_eq!(*output, sponge.single_hash(&padded, key));
+            }
+        }
+        assert_eq!(sponge.multi_hash(&arr, Fr::ZERO, 1).len(), 1);
+    }
+
+    #[cfg(feature = "value-table")]
+    #[test]
+    fn small_values_match_the_general_conversion() {
+        for value in 0..SMALL_VALUES_LEN as u64 {
+            assert_eq!(small_value(value), Some(Fr::from(value)));
+        }
+        assert_eq!(small_value(SMALL_VALUES_LEN as u64), None);
+        assert_eq!(small_value(u64::MAX), None);
+        assert_eq!(small_value(0), Some(Fr::ZERO));
+    }
+
+    #[test]
+    fn build_time_constants_match_the_decimal_strings() {
+        let parsed: Vec<Fr> = C_STR
+            .iter()
+            .map(|s| Fr::from_str_vartime(s).unwrap())
+            .collect();
+        assert_eq!(DEFAULT_CONSTS.to_vec(), parsed);
+        assert_eq!(DEFAULT_CONSTS[1].to_string(), C_STR[1]);
+    }
 }
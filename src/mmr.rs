@@ -0,0 +1,326 @@
+//Merkle Mountain Range over the same nodes as MerkleSumTree, for append-only logs
+
+use crate::{Leaf, MerkleError, MerkleSumTree, Neighbor, Node, Position, RootCommitment};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default)]
+pub struct MmrSumTree {
+    leafs: Vec<Leaf>,
+    //levels[k][i] is the node over leafs i*2^k .. (i+1)*2^k
+    levels: Vec<Vec<Node>>,
+    root: Option<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MmrProof {
+    leaf: Leaf,
+    position: u64,
+    leaf_count: u64,
+    path: Vec<Neighbor>,
+    peaks: Vec<Node>,
+}
+
+impl MmrSumTree {
+    pub fn new() -> MmrSumTree {
+        Self::default()
+    }
+
+    pub fn get_root(&self) -> Option<Node> {
+        self.root.clone()
+    }
+
+    //Every append is one mutation, so the epoch is the leaf count
+    pub fn get_commitment(&self) -> Option<RootCommitment> {
+        self.root
+            .as_ref()
+            .map(|root| RootCommitment::from_node(root, self.get_leaf_count()))
+    }
+
+    pub fn get_leaf_count(&self) -> u64 {
+        self.leafs.len() as u64
+    }
+
+    pub fn get_leaf(&self, position: u64) -> Option<Leaf> {
+        let index = usize::try_from(position).ok()?;
+        self.leafs.get(index).cloned()
+    }
+
+    //Peaks in bagging order, largest mountain first
+    pub fn get_peaks(&self) -> Vec<Node> {
+        self.levels
+            .iter()
+            .rev()
+            .filter(|level| level.len() % 2 == 1)
+            .filter_map(|level| level.last().cloned())
+            .collect()
+    }
+
+    //Append a leaf, return its position
+    pub fn append(&mut self, leaf: Leaf) -> Result<u64, MerkleError> {
+        let position = self.get_leaf_count();
+        //merged[k] lands on levels[k], every odd level pairs its last node with it
+        let mut current = leaf.get_node();
+        let mut merged = vec![current.clone()];
+        for level in &self.levels {
+            match level.last() {
+                Some(left) if level.len() % 2 == 1 => {
+                    current = MerkleSumTree::build_parent(left.clone(), current)?;
+                    merged.push(current.clone());
+                }
+                _ => break,
+            }
+        }
+
+        //Bag the new peaks before mutating so an overflow leaves the range untouched
+        let depth = self.levels.len().max(merged.len());
+        let mut peaks = vec![];
+        for k in (0..depth).rev() {
+            let level = self.levels.get(k);
+            let level_len = level.map_or(0, Vec::len) + usize::from(k < merged.len());
+            if level_len % 2 == 1 {
+                let peak = merged
+                    .get(k)
+                    .or_else(|| level.and_then(|level| level.last()));
+                peaks.extend(peak.cloned());
+            }
+        }
+        let root = Self::bag_peaks(&peaks)?;
+
+        for (k, node) in merged.into_iter().enumerate() {
+            match self.levels.get_mut(k) {
+                Some(level) => level.push(node),
+                None => self.levels.push(vec![node]),
+            }
+        }
+        self.leafs.push(leaf);
+        self.root = root;
+        Ok(position)
+    }
+
+    pub fn get_proof(&self, position: u64) -> Result<Option<MmrProof>, MerkleError> {
+        let leaf = match self.get_leaf(position) {
+            Some(leaf) => leaf,
+            None => return Ok(None),
+        };
+        //get_leaf succeeded, so the position fits a usize
+        let mut level_index = position as usize;
+        let leaf_count = self.get_leaf_count();
+        let (_, height) =
+            mountain_of(position, leaf_count).ok_or(MerkleError::IndexOutOfBounds(level_index))?;
+        let mut path = vec![];
+        for level in self.levels.iter().take(height) {
+            let (side, sibling) = match level_index % 2 {
+                0 => (Position::Right, level_index + 1),
+                _ => (Position::Left, level_index - 1),
+            };
+            let node = level
+                .get(sibling)
+                .cloned()
+                .ok_or(MerkleError::MissingNode(sibling))?;
+            path.push(Neighbor::new(side, node));
+            level_index /= 2;
+        }
+        Ok(Some(MmrProof {
+            leaf,
+            position,
+            leaf_count,
+            path,
+            peaks: self.get_peaks(),
+        }))
+    }
+
+    //Stateless check of a proof against a commitment, no range needed
+    pub fn verify_proof(proof: &MmrProof, root: &RootCommitment) -> Result<bool, MerkleError> {
+        if root.get_epoch() != proof.leaf_count {
+            return Ok(false);
+        }
+        let (peak_index, height) = match mountain_of(proof.position, proof.leaf_count) {
+            Some(mountain) => mountain,
+            None => return Ok(false),
+        };
+        if proof.peaks.len() != proof.leaf_count.count_ones() as usize || proof.path.len() != height
+        {
+            return Ok(false);
+        }
+        let mut node = proof.leaf.get_node();
+        for (bit, neighbor) in proof.path.iter().enumerate() {
+            //The path must follow the position, otherwise it proves another slot
+            let expected = match (proof.position >> bit) & 1 {
+                0 => Position::Right,
+                _ => Position::Left,
+            };
+            if neighbor.get_position() != expected {
+                return Ok(false);
+            }
+            node = match expected {
+                Position::Right => MerkleSumTree::build_parent(node, neighbor.get_node())?,
+                Position::Left => MerkleSumTree::build_parent(neighbor.get_node(), node)?,
+            };
+        }
+        if proof.peaks.get(peak_index) != Some(&node) {
+            return Ok(false);
+        }
+        Ok(Self::bag_peaks(&proof.peaks)?.is_some_and(|bagged| root.matches(&bagged)))
+    }
+
+    //Fold the peaks right to left, see the module comment
+    fn bag_peaks(peaks: &[Node]) -> Result<Option<Node>, MerkleError> {
+        let mut peaks = peaks.iter().rev().cloned();
+        let mut root = match peaks.next() {
+            Some(peak) => peak,
+            None => return Ok(None),
+        };
+        for peak in peaks {
+            root = MerkleSumTree::build_parent(peak, root)?;
+        }
+        Ok(Some(root))
+    }
+}
+
+//Peak index and height of the mountain holding a position, None past the end
+fn mountain_of(position: u64, leaf_count: u64) -> Option<(usize, usize)> {
+    let mut start = 0;
+    let mut peak_index = 0;
+    for height in (0..u64::BITS as usize).rev() {
+        let size = 1u64 << height;
+        if leaf_count & size == 0 {
+            continue;
+        }
+        if position < start + size {
+            return Some((peak_index, height));
+        }
+        start += size;
+        peak_index += 1;
+    }
+    None
+}
+
+impl MmrProof {
+    pub fn get_leaf(&self) -> Leaf {
+        self.leaf.clone()
+    }
+
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
+
+    pub fn get_path(&self) -> Vec<Neighbor> {
+        self.path.clone()
+    }
+
+    pub fn get_peaks(&self) -> Vec<Node> {
+        self.peaks.clone()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::Fr;
+
+    fn parent(left: &Node, right: &Node) -> Node {
+        MerkleSumTree::build_parent(left.clone(), right.clone()).unwrap()
+    }
+
+    #[test]
+    fn bagging_vectors() {
+        let p0 = Node::new(Fr::from(1u64), 1);
+        let p1 = Node::new(Fr::from(2u64), 2);
+        let p2 = Node::new(Fr::from(3u64), 3);
+
+        assert_eq!(MmrSumTree::bag_peaks(&[]).unwrap(), None);
+        assert_eq!(
+            MmrSumTree::bag_peaks(&[p0.clone()]).unwrap(),
+            Some(p0.clone())
+        );
+        let two = MmrSumTree::bag_peaks(&[p0.clone(), p1.clone()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            two.get_hash().to_string(),
+            "23048320307857291566075819439889768601612926615350494770467558997706967598422"
+        );
+        assert_eq!(two.get_value(), 3);
+
+        let three = MmrSumTree::bag_peaks(&[p0.clone(), p1.clone(), p2.clone()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(three, parent(&p0, &parent(&p1, &p2)));
+        assert_eq!(
+            three.get_hash().to_string(),
+            "15723801879311107543197036788871007203620274783640924940527080177805378447965"
+        );
+        assert_eq!(three.get_value(), 6);
+    }
+
+    #[test]
+    fn mountains_match_the_fixed_tree() {
+        let leafs = users(7, 1, 0);
+        let mut mmr = MmrSumTree::new();
+        assert_eq!(mmr.get_root(), None);
+        for (i, leaf) in leafs.iter().enumerate() {
+            assert_eq!(mmr.append(leaf.clone()).unwrap(), i as u64);
+        }
+
+        let four = MerkleSumTree::new(leafs[0..4].to_vec()).unwrap();
+        let two = MerkleSumTree::new(leafs[4..6].to_vec()).unwrap();
+        let one = leafs[6].get_node();
+        let peaks = vec![four.get_root().unwrap(), two.get_root().unwrap(), one];
+        assert_eq!(mmr.get_peaks(), peaks);
+        assert_eq!(
+            mmr.get_root().unwrap(),
+            parent(&peaks[0], &parent(&peaks[1], &peaks[2]))
+        );
+        assert_eq!(mmr.get_root().unwrap().get_value(), 21);
+    }
+
+    #[test]
+    fn proofs_verify_statelessly() {
+        let mut mmr = MmrSumTree::new();
+        for i in 0..11 {
+            mmr.append(Leaf::new(format!("user{}", i), i)).unwrap();
+        }
+        let root = mmr.get_commitment().unwrap();
+        for position in 0..11 {
+            let proof = mmr.get_proof(position).unwrap().unwrap();
+            assert_eq!(proof.get_position(), position);
+            assert!(MmrSumTree::verify_proof(&proof, &root).unwrap());
+        }
+        assert!(mmr.get_proof(11).unwrap().is_none());
+
+        let proof = mmr.get_proof(5).unwrap().unwrap();
+        let mut moved = proof.clone();
+        moved.position = 4;
+        assert!(!MmrSumTree::verify_proof(&moved, &root).unwrap());
+        let mut forged = proof.clone();
+        forged.leaf = Leaf::new("user5".to_string(), 50);
+        assert!(!MmrSumTree::verify_proof(&forged, &root).unwrap());
+
+        //An older root no longer matches once the range has grown
+        mmr.append(Leaf::new("user11".to_string(), 11)).unwrap();
+        assert!(!MmrSumTree::verify_proof(&proof, &mmr.get_commitment().unwrap()).unwrap());
+        let stale = RootCommitment::new(root.get_hash(), root.get_sum(), 12);
+        assert!(!MmrSumTree::verify_proof(&proof, &stale).unwrap());
+    }
+
+    #[test]
+    fn overflow_leaves_the_range_untouched() {
+        let mut mmr = MmrSumTree::new();
+        mmr.append(Leaf::new("alice".to_string(), i32::MAX))
+            .unwrap();
+        let root = mmr.get_root();
+        assert!(matches!(
+            mmr.append(Leaf::new("bob".to_string(), 1)),
+            Err(MerkleError::Overflow)
+        ));
+        assert_eq!(mmr.get_leaf_count(), 1);
+        assert_eq!(mmr.get_root(), root);
+        assert_eq!(mmr.append(Leaf::new("bob".to_string(), -1)).unwrap(), 1);
+    }
+}
@@ -0,0 +1,293 @@
+//Inclusion of several leafs against one root, each shared node sent once
+
+use crate::layout::{self, NodeIndex};
+use crate::{
+    default_exponent, is_default_exponent, HashParams, Leaf, MerkleError, MerkleSumTree, Node,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultiProof {
+    //Ascending by index, no index twice
+    leafs: Vec<(usize, Leaf)>,
+    levels: usize,
+    siblings: Vec<Node>,
+    #[serde(
+        default = "default_exponent",
+        skip_serializing_if = "is_default_exponent"
+    )]
+    exponent: u64,
+}
+
+impl MerkleSumTree {
+    //Proof of every leaf in indices at once; empty slots, repeats and indices out of
+    //range are refused
+    pub fn get_multiproof(&self, indices: &[usize]) -> Result<MultiProof, MerkleError> {
+        let slots = self.distinct_slots(indices)?;
+        let mut leafs = Vec::with_capacity(slots.len());
+        for &index in &slots {
+            match self.leafs.get(index) {
+                Some(leaf) if !self.is_empty_leaf(leaf) => leafs.push((index, leaf.clone())),
+                _ => return Err(MerkleError::EmptySlot(index)),
+            }
+        }
+        let levels = layout::level_count(self.leafs.len()).saturating_sub(1);
+        let seeds: Vec<NodeIndex> = slots.iter().map(|&slot| NodeIndex::new(0, slot)).collect();
+        let siblings = self.known_siblings(&seeds, levels)?;
+        Ok(MultiProof {
+            leafs,
+            levels,
+            siblings,
+            exponent: self.mimc_exponent,
+        })
+    }
+
+    //Siblings of the known nodes, level by level from the leafs up and left to right,
+    //one for each known node whose sibling is not known. seeds join the known set at
+    //their own level, the parents of known nodes are known
+    pub(crate) fn known_siblings(
+        &self,
+        seeds: &[NodeIndex],
+        levels: usize,
+    ) -> Result<Vec<Node>, MerkleError> {
+        let mut known = BTreeSet::new();
+        let mut siblings = vec![];
+        for level in 0..levels {
+            known.extend(
+                seeds
+                    .iter()
+                    .filter(|at| at.level() == level)
+                    .map(|at| at.offset()),
+            );
+            for &offset in &known {
+                let sibling = NodeIndex::new(level, offset).sibling();
+                if !known.contains(&sibling.offset()) {
+                    siblings.push(self.node_at(sibling)?);
+                }
+            }
+            known = known.iter().map(|offset| offset / 2).collect();
+        }
+        Ok(siblings)
+    }
+}
+
+//Fold the seeds up levels levels, taking the siblings in the order known_siblings
+//lists them; the root, None when nothing reaches it
+pub(crate) fn fold_known(
+    seeds: &[(NodeIndex, Node)],
+    siblings: &[Node],
+    levels: usize,
+    params: &HashParams,
+) -> Result<Option<Node>, MerkleError> {
+    let mut known: BTreeMap<usize, Node> = BTreeMap::new();
+    let mut remaining = siblings.iter();
+    for level in 0..=levels {
+        for (at, node) in seeds {
+            if at.level() == level {
+                known.insert(at.offset(), node.clone());
+            }
+        }
+        if level == levels {
+            break;
+        }
+        let mut parents = BTreeMap::new();
+        for (&offset, node) in &known {
+            //The left child of a known pair folds both
+            if offset % 2 == 1 && known.contains_key(&(offset - 1)) {
+                continue;
+            }
+            let sibling = match known.get(&(offset ^ 1)) {
+                Some(sibling) => sibling,
+                None => remaining.next().ok_or_else(|| {
+                    MerkleError::InvalidProof(format!("siblings run out at level {}", level))
+                })?,
+            };
+            let parent = match offset % 2 {
+                0 => Node::combine(node, sibling, params)?,
+                _ => Node::combine(sibling, node, params)?,
+            };
+            parents.insert(offset / 2, parent);
+        }
+        known = parents;
+    }
+    if remaining.next().is_some() {
+        return Err(MerkleError::InvalidProof(format!(
+            "{} siblings for {} levels",
+            siblings.len(),
+            levels
+        )));
+    }
+    Ok(known.remove(&0))
+}
+
+impl MultiProof {
+    pub fn get_leafs(&self) -> Vec<(usize, Leaf)> {
+        self.leafs.clone()
+    }
+
+    pub fn get_indices(&self) -> Vec<usize> {
+        self.leafs.iter().map(|(index, _)| *index).collect()
+    }
+
+    //Path length of each proven leaf, the tree height minus one
+    pub fn get_levels(&self) -> usize {
+        self.levels
+    }
+
+    //The siblings, in the order verification consumes them
+    pub fn get_siblings(&self) -> Vec<Node> {
+        self.siblings.clone()
+    }
+
+    pub fn get_exponent(&self) -> u64 {
+        self.exponent
+    }
+
+    //Fold every leaf up at once, merging paths where they meet. A proof whose
+    //indices or sibling count do not fit its levels is an error rather than false
+    pub fn compute_root(&self) -> Result<Node, MerkleError> {
+        let params = HashParams::mimc(self.exponent)?;
+        let mut seeds = Vec::with_capacity(self.leafs.len());
+        let mut previous = None;
+        for (index, leaf) in &self.leafs {
+            if previous.is_some_and(|previous| *index <= previous) {
+                return Err(MerkleError::InvalidProof(format!(
+                    "index {} is out of order",
+                    index
+                )));
+            }
+            if index.checked_shr(self.levels as u32).unwrap_or(0) != 0 {
+                return Err(MerkleError::InvalidProof(format!(
+                    "index {} does not fit in {} levels",
+                    index, self.levels
+                )));
+            }
+            seeds.push((NodeIndex::new(0, *index), leaf.get_node()));
+            previous = Some(*index);
+        }
+        fold_known(&seeds, &self.siblings, self.levels, &params)?
+            .ok_or_else(|| MerkleError::InvalidProof("no leafs to prove".to_string()))
+    }
+
+    //Whether every leaf is in the tree of root
+    pub fn verify(&self, root: Node) -> Result<bool, MerkleError> {
+        Ok(self.compute_root()?.is_equal(root))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::Fr;
+
+    fn tree(count: i32) -> MerkleSumTree {
+        MerkleSumTree::new(users(count, 5, 12)).unwrap()
+    }
+
+    fn path_len_total(tree: &MerkleSumTree, indices: &[usize]) -> usize {
+        tree.get_proofs(indices)
+            .unwrap()
+            .iter()
+            .map(|proof| proof.get_path().len())
+            .sum()
+    }
+
+    #[test]
+    fn overlapping_paths_share_their_siblings() {
+        let tree = tree(16);
+        let root = tree.get_root().unwrap();
+        let multi = tree.get_multiproof(&[9, 0, 5]).unwrap();
+        assert_eq!(multi.get_indices(), vec![0, 5, 9]);
+        assert!(multi.verify(root.clone()).unwrap());
+        //3 paths of 4 levels, the top level shared by all, the next by 0 and 5
+        assert_eq!(path_len_total(&tree, &[0, 5, 9]), 12);
+        assert_eq!(multi.get_siblings().len(), 8);
+
+        //Siblings of each other, no node at the leaf level for the pair
+        let adjacent = tree.get_multiproof(&[6, 7]).unwrap();
+        assert_eq!(adjacent.get_siblings().len(), 3);
+        assert_eq!(adjacent.get_siblings()[0], tree.get_node(16 + 2).unwrap());
+        assert!(adjacent.verify(root.clone()).unwrap());
+
+        let mixed = tree.get_multiproof(&[2, 3, 4, 11]).unwrap();
+        assert!(mixed.verify(root).unwrap());
+    }
+
+    #[test]
+    fn every_leaf_at_once_needs_no_sibling() {
+        for count in [1, 2, 5, 8] {
+            let tree = tree(count);
+            let occupied: Vec<usize> = (0..count as usize).collect();
+            let multi = tree.get_multiproof(&occupied).unwrap();
+            let full = occupied.len() == tree.get_leafs().len();
+            assert_eq!(multi.get_siblings().is_empty(), full);
+            assert!(multi.verify(tree.get_root().unwrap()).unwrap());
+        }
+    }
+
+    #[test]
+    fn a_single_index_is_a_plain_proof() {
+        let tree = tree(11);
+        for index in [0, 6, 10] {
+            let multi = tree.get_multiproof(&[index]).unwrap();
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            let path: Vec<Node> = proof.get_path().iter().map(|n| n.get_node()).collect();
+            assert_eq!(multi.get_siblings(), path);
+            assert_eq!(multi.get_levels(), path.len());
+            assert_eq!(multi.compute_root().unwrap(), proof.compute_root().unwrap());
+        }
+    }
+
+    #[test]
+    fn tampered_and_malformed_proofs_fail() {
+        let tree = tree(8);
+        let root = tree.get_root().unwrap();
+        let multi = tree.get_multiproof(&[1, 4]).unwrap();
+
+        let mut inflated = multi.clone();
+        inflated.leafs[1].1 = Leaf::new("user4".to_string(), 1000);
+        assert!(!inflated.verify(root.clone()).unwrap());
+
+        let mut moved = multi.clone();
+        moved.leafs[1].0 = 5;
+        assert!(!moved.verify(root.clone()).unwrap());
+
+        let mut short = multi.clone();
+        short.siblings.pop();
+        assert!(matches!(
+            short.verify(root.clone()),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        let mut long = multi.clone();
+        long.siblings.push(Node::new(Fr::ONE, 0));
+        assert!(matches!(
+            long.verify(root.clone()),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        let mut unordered = multi;
+        unordered.leafs.reverse();
+        assert!(matches!(
+            unordered.verify(root),
+            Err(MerkleError::InvalidProof(_))
+        ));
+
+        let sparse = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        assert!(matches!(
+            sparse.get_multiproof(&[0, 3]),
+            Err(MerkleError::EmptySlot(3))
+        ));
+        assert!(matches!(
+            sparse.get_multiproof(&[0, 0, 9]),
+            Err(MerkleError::InvalidIndices { out_of_range, duplicates })
+                if out_of_range == vec![9] && duplicates == vec![0]
+        ));
+    }
+}
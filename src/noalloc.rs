@@ -0,0 +1,146 @@
+//Proof verification without heap allocation, for embedded verifiers
+
+use crate::{
+    Fr, HashParams, MerkleError, MerkleSumTree, Neighbor, Node, Position, RootCommitment,
+    MAX_HEIGHT,
+};
+
+impl MerkleSumTree {
+    //verify_membership_by_hash over a borrowed path, allocation-free
+    pub fn verify_membership_noalloc(
+        root: &RootCommitment,
+        leaf_hash: Fr,
+        value: i64,
+        path: &[Neighbor],
+    ) -> Result<bool, MerkleError> {
+        if path.len() >= MAX_HEIGHT {
+            return Err(MerkleError::InvalidLength {
+                expected: MAX_HEIGHT - 1,
+                actual: path.len(),
+            });
+        }
+        let value = match i32::try_from(value) {
+            Ok(value) => value,
+            Err(_) => return Ok(false),
+        };
+        let params = HashParams::default();
+        let mut node = Node::new(leaf_hash, value);
+        for neighbor in path {
+            node = match neighbor.position {
+                Position::Right => Self::build_parent_with(&params, node, neighbor.node.clone())?,
+                Position::Left => Self::build_parent_with(&params, neighbor.node.clone(), node)?,
+            };
+        }
+        Ok(root.matches(&node))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::Leaf;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    //Counts the allocations of the current thread, tests run in parallel
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATIONS.with(Cell::get);
+        let result = f();
+        (result, ALLOCATIONS.with(Cell::get) - before)
+    }
+
+    #[test]
+    fn verification_does_not_allocate() {
+        let tree = MerkleSumTree::new(
+            (0..37)
+                .map(|i| Leaf::new(format!("user{}", i), i * 3 - 40))
+                .collect(),
+        )
+        .unwrap();
+        let root = tree.get_commitment().unwrap();
+        let proof = tree.get_proof(21).unwrap().unwrap();
+        let (leaf, path) = (proof.get_leaf().get_node(), proof.get_path());
+        let (verified, count) = allocations(|| {
+            MerkleSumTree::verify_membership_noalloc(
+                &root,
+                leaf.get_hash(),
+                i64::from(leaf.get_value()),
+                &path,
+            )
+        });
+        assert!(verified.unwrap());
+        assert_eq!(count, 0);
+        let (_, count) = allocations(|| {
+            MerkleSumTree::verify_membership_by_hash(
+                &root,
+                leaf.get_hash(),
+                i64::from(leaf.get_value()),
+                &path,
+            )
+        });
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn results_match_the_allocating_verifier() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for size in [1, 2, 3, 8, 13] {
+            let mut leafs: Vec<Leaf> = (0..size)
+                .map(|i| Leaf::new(format!("user{}", i), rng.gen_range(-100, 100)))
+                .collect();
+            if size == 13 {
+                leafs.push(Leaf::new("whale".to_string(), i32::MAX - 2000));
+            }
+            let tree = MerkleSumTree::new(leafs).unwrap();
+            let root = tree.get_commitment().unwrap();
+            for index in 0..tree.get_leafs().len() {
+                let proof = tree.get_proof_allow_empty(index).unwrap().unwrap();
+                let leaf = proof.get_leaf().get_node();
+                let mut path = proof.get_path();
+                let mut cases = vec![(leaf.get_hash(), i64::from(leaf.get_value()))];
+                cases.push((leaf.get_hash(), i64::from(leaf.get_value()) + 1));
+                cases.push((Fr::from(7u64), i64::from(leaf.get_value())));
+                cases.push((leaf.get_hash(), i64::from(i32::MAX) + 1));
+                cases.push((leaf.get_hash(), i64::from(i32::MAX)));
+                for _ in 0..2 {
+                    for (hash, value) in &cases {
+                        let expected =
+                            MerkleSumTree::verify_membership_by_hash(&root, *hash, *value, &path);
+                        let actual =
+                            MerkleSumTree::verify_membership_noalloc(&root, *hash, *value, &path);
+                        assert_eq!(format!("{:?}", actual), format!("{:?}", expected));
+                    }
+                    path.reverse();
+                }
+            }
+        }
+        let root = RootCommitment::new(Fr::ZERO, 0, 0);
+        let long = vec![Neighbor::new(Position::Left, Node::new(Fr::ZERO, 0)); MAX_HEIGHT];
+        assert!(matches!(
+            MerkleSumTree::verify_membership_noalloc(&root, Fr::ZERO, 0, &long),
+            Err(MerkleError::InvalidLength { .. })
+        ));
+    }
+}
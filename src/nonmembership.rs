@@ -0,0 +1,220 @@
+//Proofs that an id is absent from a canonical tree
+
+use crate::canonical::id_key;
+use crate::{InclusionProof, LeafHashMode, MerkleError, MerkleSumTree, RootCommitment};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NonMembershipProof {
+    //The last account ordered before the id, None when the id would come first
+    lower: Option<InclusionProof>,
+    //The slot after lower: the first account ordered after the id or an empty slot,
+    //None when lower is the last slot of the tree
+    upper: Option<InclusionProof>,
+}
+
+impl MerkleSumTree {
+    //Only canonical trees are ordered, an id in the tree has no such proof
+    pub fn get_non_membership_proof(&self, id: &str) -> Result<NonMembershipProof, MerkleError> {
+        if !self.canonical {
+            return Err(MerkleError::NotCanonical);
+        }
+        if self.get_index_by_id(id).is_some() {
+            return Err(MerkleError::IdPresent(id.to_string()));
+        }
+        let target = id_key(Arc::from(id));
+        let count = self.leafs.len() - self.zero_index.len();
+        let accounts = self.leafs.get(..count).unwrap_or_default();
+        let position = accounts.partition_point(|leaf| id_key(leaf.id.clone()) < target);
+        //Another id of the same hash leaves no gap to prove
+        if accounts
+            .get(position)
+            .is_some_and(|leaf| id_key(leaf.id.clone()) == target)
+        {
+            return Err(MerkleError::IdPresent(id.to_string()));
+        }
+        let lower = match position.checked_sub(1) {
+            Some(index) => self.get_proof(index)?,
+            None => None,
+        };
+        Ok(NonMembershipProof {
+            lower,
+            upper: self.get_proof_allow_empty(position)?,
+        })
+    }
+
+    //Whether proof shows id absent from the canonical tree of root. Malformed
+    //inclusion proofs are errors, proofs that do not bracket the id are false
+    pub fn verify_non_membership(
+        root: &RootCommitment,
+        id: &str,
+        proof: &NonMembershipProof,
+    ) -> Result<bool, MerkleError> {
+        let target = id_key(Arc::from(id));
+        let included = |proof: &InclusionProof| -> Result<bool, MerkleError> {
+            Ok(root.matches(&proof.compute_root()?))
+        };
+        //An account whose hash is the one its id gives, so its id orders it
+        let account = |proof: &InclusionProof| {
+            !proof.is_empty_slot() && LeafHashMode::IdOnly.matches(&proof.leaf)
+        };
+        if let Some(lower) = &proof.lower {
+            if !included(lower)? || !account(lower) || id_key(lower.leaf.id.clone()) >= target {
+                return Ok(false);
+            }
+        }
+        if let Some(upper) = &proof.upper {
+            let after =
+                upper.is_empty_slot() || (account(upper) && id_key(upper.leaf.id.clone()) > target);
+            if !included(upper)? || !after {
+                return Ok(false);
+            }
+        }
+        Ok(match (&proof.lower, &proof.upper) {
+            (Some(lower), Some(upper)) => {
+                lower.path.len() == upper.path.len()
+                    && lower.index.checked_add(1) == Some(upper.index)
+            }
+            (None, Some(upper)) => upper.index == 0,
+            (Some(lower), None) => {
+                1usize.checked_shl(lower.path.len() as u32) == lower.index.checked_add(1)
+            }
+            (None, None) => false,
+        })
+    }
+}
+
+impl NonMembershipProof {
+    pub fn get_lower(&self) -> Option<InclusionProof> {
+        self.lower.clone()
+    }
+
+    pub fn get_upper(&self) -> Option<InclusionProof> {
+        self.upper.clone()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::Leaf;
+
+    fn ids() -> Vec<String> {
+        (0..5).map(|i| format!("user{}", i * 2)).collect()
+    }
+
+    fn tree() -> MerkleSumTree {
+        let leafs = ids()
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| Leaf::new(id, i as i32 * 10))
+            .collect();
+        MerkleSumTree::new_canonical(leafs).unwrap()
+    }
+
+    fn key(id: &str) -> [u8; 32] {
+        id_key(Arc::from(id))
+    }
+
+    #[test]
+    fn absent_ids_are_bracketed() {
+        let tree = tree();
+        let root = tree.get_commitment().unwrap();
+        let mut lower_edge = false;
+        let mut upper_edge = false;
+        for i in 0..60 {
+            let id = format!("absent{}", i);
+            let proof = tree.get_non_membership_proof(&id).unwrap();
+            assert!(MerkleSumTree::verify_non_membership(&root, &id, &proof).unwrap());
+            lower_edge |= proof.get_lower().is_none();
+            upper_edge |= proof.get_upper().unwrap().is_empty_slot();
+            for present in ids() {
+                assert!(!MerkleSumTree::verify_non_membership(&root, &present, &proof).unwrap());
+            }
+        }
+        //Ids hashing below the first account and above the last one
+        assert!(lower_edge && upper_edge);
+
+        for id in ids() {
+            assert!(matches!(
+                tree.get_non_membership_proof(&id),
+                Err(MerkleError::IdPresent(present)) if present == id
+            ));
+        }
+        let plain = MerkleSumTree::new(vec![Leaf::new("alice".to_string(), 1)]).unwrap();
+        assert!(matches!(
+            plain.get_non_membership_proof("bob"),
+            Err(MerkleError::NotCanonical)
+        ));
+    }
+
+    #[test]
+    fn a_full_tree_ends_at_its_last_slot() {
+        let leafs = users(4, 1, 0);
+        let tree = MerkleSumTree::new_canonical(leafs).unwrap();
+        let root = tree.get_commitment().unwrap();
+        let last = tree.get_leaf(3).unwrap().get_id();
+        let above = (0..200)
+            .map(|i| format!("absent{}", i))
+            .find(|id| key(id) > key(&last))
+            .unwrap();
+        let proof = tree.get_non_membership_proof(&above).unwrap();
+        assert!(proof.get_upper().is_none());
+        assert_eq!(proof.get_lower().unwrap().get_index(), 3);
+        assert!(MerkleSumTree::verify_non_membership(&root, &above, &proof).unwrap());
+
+        //Dropping the upper proof of an inner gap does not pass as the end
+        let first = tree.get_leaf(0).unwrap().get_id();
+        let inner = (0..200)
+            .map(|i| format!("absent{}", i))
+            .find(|id| key(id) > key(&first) && key(id) < key(&tree.get_leaf(1).unwrap().get_id()))
+            .unwrap();
+        let mut cut = tree.get_non_membership_proof(&inner).unwrap();
+        cut.upper = None;
+        assert!(!MerkleSumTree::verify_non_membership(&root, &inner, &cut).unwrap());
+    }
+
+    #[test]
+    fn forged_brackets_fail() {
+        let tree = tree();
+        let root = tree.get_commitment().unwrap();
+        let id = (0..200)
+            .map(|i| format!("absent{}", i))
+            .find(|id| {
+                let proof = tree.get_non_membership_proof(id).unwrap();
+                proof.get_lower().is_some() && !proof.get_upper().unwrap().is_empty_slot()
+            })
+            .unwrap();
+        let proof = tree.get_non_membership_proof(&id).unwrap();
+        let lower = proof.get_lower().unwrap();
+
+        //Not adjacent: the bracket skips a slot
+        let mut gap = proof.clone();
+        gap.upper = tree.get_proof_allow_empty(lower.get_index() + 2).unwrap();
+        assert!(!MerkleSumTree::verify_non_membership(&root, &id, &gap).unwrap());
+
+        //Against another root
+        let mut moved = tree.clone();
+        moved.push(Leaf::new("late".to_string(), 1)).unwrap();
+        assert!(!MerkleSumTree::verify_non_membership(
+            &moved.get_commitment().unwrap(),
+            &id,
+            &proof
+        )
+        .unwrap());
+
+        //An empty lower slot is not an account
+        let mut empty_lower = proof.clone();
+        empty_lower.lower = tree.get_proof_allow_empty(7).unwrap();
+        assert!(!MerkleSumTree::verify_non_membership(&root, &id, &empty_lower).unwrap());
+
+        let none = NonMembershipProof {
+            lower: None,
+            upper: None,
+        };
+        assert!(!MerkleSumTree::verify_non_membership(&root, &id, &none).unwrap());
+    }
+}
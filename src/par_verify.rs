@@ -0,0 +1,69 @@
+//Verification of many proofs across the rayon thread pool, with the rayon feature
+
+use crate::{HashParams, InclusionProof, MerkleError, MerkleSumTree, ProofCheck};
+use rayon::prelude::*;
+
+impl MerkleSumTree {
+    //verify_proof of each proof, in input order
+    pub fn verify_proofs_parallel(
+        &self,
+        proofs: &[InclusionProof],
+    ) -> Vec<Result<bool, MerkleError>> {
+        let params = match HashParams::mimc(self.mimc_exponent) {
+            Ok(params) => params,
+            Err(_) => {
+                return proofs
+                    .iter()
+                    .map(|_| Err(MerkleError::UnsupportedExponent(self.mimc_exponent)))
+                    .collect()
+            }
+        };
+        proofs
+            .par_iter()
+            .map(|proof| {
+                self.check_proof(proof, &params)
+                    .and_then(ProofCheck::into_verdict)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::{Leaf, Node};
+
+    #[test]
+    fn results_match_verify_proof_in_order() {
+        let leafs = users(100, 1, 0);
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        let mut proofs: Vec<InclusionProof> = (0..100)
+            .map(|index| tree.get_proof(index).unwrap().unwrap())
+            .collect();
+        proofs[7].leaf = Leaf::new("user7".to_string(), 8);
+        proofs[40].path.pop();
+        proofs[40].index &= (1 << proofs[40].path.len()) - 1;
+        proofs[63].path[0].node = Node::new(proofs[63].path[0].node.hash, i32::MAX);
+
+        let results = tree.verify_proofs_parallel(&proofs);
+        assert_eq!(results.len(), proofs.len());
+        for (index, (result, proof)) in results.into_iter().zip(proofs).enumerate() {
+            match (result, tree.verify_proof(proof)) {
+                (Ok(parallel), Ok(sequential)) => {
+                    assert_eq!(parallel, sequential);
+                    assert_eq!(parallel, ![7, 40, 63].contains(&index));
+                }
+                (Err(MerkleError::InvalidProof(_)), Err(MerkleError::InvalidProof(_))) => {
+                    assert_eq!(index, 40)
+                }
+                (Err(MerkleError::Overflow), Err(MerkleError::Overflow)) => assert_eq!(index, 63),
+                (parallel, sequential) => {
+                    panic!("proof {}: {:?} against {:?}", index, parallel, sequential)
+                }
+            }
+        }
+        assert!(tree.verify_proofs_parallel(&[]).is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+//A classic binary Merkle root over the leaf hashes, next to the sum root
+
+use crate::layout::{flat_index, level_count, path};
+use crate::{
+    default_exponent, is_default_exponent, Fr, MerkleError, MerkleSumTree, MimcSponge, Position,
+};
+use ff::Field;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+//Plain-path siblings of one leaf, leaf to root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlainProof {
+    leaf_hash: Fr,
+    path: Vec<(Position, Fr)>,
+    #[serde(
+        default = "default_exponent",
+        skip_serializing_if = "is_default_exponent"
+    )]
+    exponent: u64,
+}
+
+impl MerkleSumTree {
+    //Maintain the plain root from now on, or drop it
+    pub fn set_plain_root(&mut self, enabled: bool) {
+        self.plain = match enabled {
+            true => Some(vec![]),
+            false => None,
+        };
+        self.refresh_plain(std::iter::empty());
+    }
+
+    pub fn get_plain_root(&self) -> Option<Fr> {
+        self.plain.as_ref().and_then(|plain| plain.last().copied())
+    }
+
+    //None when the plain root is not maintained
+    pub fn get_plain_proof(&self, index: usize) -> Result<Option<PlainProof>, MerkleError> {
+        let leaf = self
+            .leafs
+            .get(index)
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        let plain = match &self.plain {
+            Some(plain) => plain,
+            None => return Ok(None),
+        };
+        let path = path(self.leafs.len(), index)
+            .into_iter()
+            .map(|step| {
+                let index = self.nodes.flat(step.sibling);
+                let sibling = plain
+                    .get(index)
+                    .copied()
+                    .ok_or(MerkleError::MissingNode(index))?;
+                Ok((step.position, sibling))
+            })
+            .collect::<Result<_, MerkleError>>()?;
+        Ok(Some(PlainProof {
+            leaf_hash: leaf.node.hash,
+            path,
+            exponent: self.mimc_exponent,
+        }))
+    }
+
+    //Rehash the plain layout above the written leafs, a no-op when it is not maintained
+    pub(crate) fn refresh_plain<I: IntoIterator<Item = usize>>(&mut self, written: I) {
+        let mut plain = match self.plain.take() {
+            Some(plain) => plain,
+            None => return,
+        };
+        let sponge = self.sponge();
+        let hashes = refresh_shadow(
+            &mut plain,
+            self.leafs.len(),
+            self.nodes.len(),
+            written,
+            |index| self.leafs.get(index).map(|leaf| leaf.node.hash),
+            |(_, left), (_, right)| sponge.single_hash(&[left, right], Fr::ZERO),
+        );
+        self.metrics.hashes(hashes);
+        self.plain = Some(plain);
+    }
+}
+
+//Bring a layout shadowing the nodes up to date after writes to the leafs at written,
+//all of it when its size no longer matches. parent takes the (flat index, digest) of
+//both children. Returns the number of parents hashed
+pub(crate) fn refresh_shadow<T, I, L, P>(
+    shadow: &mut Vec<T>,
+    leaf_count: usize,
+    node_count: usize,
+    written: I,
+    leaf: L,
+    parent: P,
+) -> usize
+where
+    T: Copy + Default,
+    I: IntoIterator<Item = usize>,
+    L: Fn(usize) -> Option<T>,
+    P: Fn((usize, T), (usize, T)) -> T,
+{
+    let mut offsets: BTreeSet<usize> = if shadow.len() == node_count {
+        written.into_iter().collect()
+    } else {
+        *shadow = vec![T::default(); node_count];
+        (0..leaf_count).collect()
+    };
+    offsets.retain(|index| *index < leaf_count);
+    for &index in &offsets {
+        if let (Some(slot), Some(digest)) = (shadow.get_mut(index), leaf(index)) {
+            *slot = digest;
+        }
+    }
+    let mut hashes = 0;
+    for level in 1..level_count(leaf_count) {
+        offsets = offsets.iter().map(|offset| offset / 2).collect();
+        for &offset in &offsets {
+            let child = |offset| {
+                let index = flat_index(leaf_count, level - 1, offset)?;
+                shadow.get(index).map(|digest| (index, *digest))
+            };
+            let digest = match (child(2 * offset), child(2 * offset + 1)) {
+                (Some(left), Some(right)) => parent(left, right),
+                _ => continue,
+            };
+            if let Some(slot) =
+                flat_index(leaf_count, level, offset).and_then(|index| shadow.get_mut(index))
+            {
+                *slot = digest;
+            }
+            hashes += 1;
+        }
+    }
+    hashes
+}
+
+impl PlainProof {
+    pub fn get_leaf_hash(&self) -> Fr {
+        self.leaf_hash
+    }
+
+    pub fn get_path(&self) -> Vec<(Position, Fr)> {
+        self.path.clone()
+    }
+
+    pub fn get_exponent(&self) -> u64 {
+        self.exponent
+    }
+
+    pub fn compute_root(&self) -> Result<Fr, MerkleError> {
+        let sponge = MimcSponge::with_exponent(self.exponent)?;
+        Ok(self
+            .path
+            .iter()
+            .fold(self.leaf_hash, |node, (position, sibling)| {
+                let arr = match position {
+                    Position::Right => [node, *sibling],
+                    Position::Left => [*sibling, node],
+                };
+                sponge.single_hash(&arr, Fr::ZERO)
+            }))
+    }
+
+    pub fn verify(&self, root: Fr) -> Result<bool, MerkleError> {
+        Ok(self.compute_root()? == root)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::Leaf;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    //Plain root of the leaf hashes, level by level
+    fn expected(tree: &MerkleSumTree) -> Fr {
+        let sponge = MimcSponge::default();
+        let mut level: Vec<Fr> = tree
+            .get_leafs()
+            .iter()
+            .map(|leaf| leaf.get_node().get_hash())
+            .collect();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| sponge.single_hash(pair, Fr::ZERO))
+                .collect();
+        }
+        level[0]
+    }
+
+    #[test]
+    fn plain_root_follows_every_mutation() {
+        let mut tree = MerkleSumTree::builder()
+            .plain_root(true)
+            .build(vec![
+                Leaf::new("alice".to_string(), 10),
+                Leaf::new("bob".to_string(), 20),
+                Leaf::new("carol".to_string(), 30),
+            ])
+            .unwrap();
+        assert_eq!(tree.get_plain_root(), Some(expected(&tree)));
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for step in 0..200 {
+            let len = tree.get_leafs().len();
+            match rng.gen_range(0, 6) {
+                0 | 1 => {
+                    tree.push(Leaf::new(format!("user{}", step), rng.gen_range(0, 100)))
+                        .unwrap();
+                }
+                2 => {
+                    let index = rng.gen_range(0, len);
+                    tree.set_leaf(Leaf::new(format!("set{}", step), 1), index)
+                        .unwrap();
+                }
+                3 => tree.remove(rng.gen_range(0, len)).unwrap(),
+                4 => {
+                    let indices = vec![rng.gen_range(0, len)];
+                    tree.remove_many(&indices).unwrap();
+                }
+                _ => {
+                    tree.defragment().unwrap();
+                }
+            }
+            assert_eq!(
+                tree.get_plain_root(),
+                Some(expected(&tree)),
+                "step {}",
+                step
+            );
+        }
+        let index = tree.get_index_by_id("alice").unwrap_or(0);
+        let proof = tree.get_plain_proof(index).unwrap().unwrap();
+        assert!(proof.verify(tree.get_plain_root().unwrap()).unwrap());
+        assert!(!proof.verify(tree.get_root().unwrap().get_hash()).unwrap());
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn plain_root_is_opt_in() {
+        let mut tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+        ])
+        .unwrap();
+        assert_eq!(tree.get_plain_root(), None);
+        assert_eq!(tree.get_plain_proof(0).unwrap(), None);
+        assert!(tree.audit_report().unwrap().get_plain_root().is_none());
+
+        tree.set_plain_root(true);
+        let root = tree.get_plain_root().unwrap();
+        assert_eq!(root, expected(&tree));
+        assert_eq!(
+            tree.audit_report().unwrap().get_plain_root(),
+            Some(format!("{:#x}", root))
+        );
+        let proof = tree.get_plain_proof(1).unwrap().unwrap();
+        assert_eq!(proof.get_path().len(), 1);
+        assert!(proof.verify(root).unwrap());
+        assert!(tree.get_plain_proof(2).is_err());
+
+        //Not serialized, and the sum root does not depend on it
+        let json = serde_json::to_string(&tree).unwrap();
+        let loaded: MerkleSumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.get_plain_root(), None);
+        assert_eq!(loaded.get_root(), tree.get_root());
+        tree.set_plain_root(false);
+        assert_eq!(tree.get_plain_root(), None);
+    }
+}
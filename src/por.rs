@@ -0,0 +1,367 @@
+//Proof-of-reserves workflow over MerkleSumTree
+
+use crate::{Fr, InclusionProof, Leaf, MerkleError, MerkleSumTree, Position, RootCommitment};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotOptions {
+    epoch: u64,
+    timestamp: u64,
+}
+
+//What the exchange publishes, field order is part of the format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Publication {
+    root_hash: Fr,
+    root_sum: i32,
+    leaf_count: usize,
+    epoch: u64,
+    timestamp: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LiabilitySnapshot {
+    tree: MerkleSumTree,
+    publication: Publication,
+}
+
+//Everything a user needs to check their balance, field order is part of the format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserPackage {
+    publication: Publication,
+    index: usize,
+    proof: InclusionProof,
+}
+
+impl SnapshotOptions {
+    //Snapshot sequence number chosen by the exchange
+    pub fn epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    //Seconds since the unix epoch at which the balances were taken
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+}
+
+impl Publication {
+    pub fn get_root_hash(&self) -> Fr {
+        self.root_hash
+    }
+
+    pub fn get_root_sum(&self) -> i32 {
+        self.root_sum
+    }
+
+    //Size of the leaf layer, padding included
+    pub fn get_leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    //The published root, at the snapshot epoch
+    pub fn get_commitment(&self) -> RootCommitment {
+        RootCommitment::new(self.root_hash, i64::from(self.root_sum), self.epoch)
+    }
+}
+
+impl LiabilitySnapshot {
+    //Build the tree from (id, balance) pairs, ids must be unique and balances not negative
+    pub fn build(
+        pairs: Vec<(String, i32)>,
+        options: SnapshotOptions,
+    ) -> Result<LiabilitySnapshot, MerkleError> {
+        let mut seen = HashSet::new();
+        for (id, _) in &pairs {
+            if !seen.insert(id.as_str()) {
+                return Err(MerkleError::DuplicateId(id.clone()));
+            }
+        }
+        let leafs = pairs
+            .into_iter()
+            .map(|(id, value)| Leaf::new(id, value))
+            .collect();
+        let tree = MerkleSumTree::builder().non_negative(true).build(leafs)?;
+        let root = tree.get_root().ok_or(MerkleError::EmptyTree)?;
+        let publication = Publication {
+            root_hash: root.get_hash(),
+            root_sum: root.get_value(),
+            leaf_count: tree.get_leafs().len(),
+            epoch: options.epoch,
+            timestamp: options.timestamp,
+        };
+        Ok(LiabilitySnapshot { tree, publication })
+    }
+
+    pub fn get_tree(&self) -> MerkleSumTree {
+        self.tree.clone()
+    }
+
+    pub fn get_publication(&self) -> Publication {
+        self.publication.clone()
+    }
+
+    //Proof and publication for one user
+    pub fn user_package(&self, id: &str) -> Result<UserPackage, MerkleError> {
+        UserPackage::for_id(&self.tree, &self.publication, id)
+    }
+}
+
+impl UserPackage {
+    //Package of id's proof in tree, under the publication of that tree
+    pub(crate) fn for_id(
+        tree: &MerkleSumTree,
+        publication: &Publication,
+        id: &str,
+    ) -> Result<UserPackage, MerkleError> {
+        let index = tree
+            .get_index_by_id(id)
+            .ok_or_else(|| MerkleError::UnknownId(id.to_string()))?;
+        let proof = tree
+            .get_proof(index)?
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        Ok(UserPackage {
+            publication: publication.clone(),
+            index,
+            proof,
+        })
+    }
+
+    pub fn get_publication(&self) -> Publication {
+        self.publication.clone()
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_proof(&self) -> InclusionProof {
+        self.proof.clone()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<UserPackage, MerkleError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    //Whether the package proves expected_value for expected_id under its publication
+    pub fn verify(&self, expected_id: &str, expected_value: i32) -> Result<bool, MerkleError> {
+        let publication = &self.publication;
+        if self.proof.get_leaf() != Leaf::new(expected_id.to_string(), expected_value) {
+            return Ok(false);
+        }
+        //A negative balance, or one the total cannot cover, hides a negative leaf
+        if expected_value < 0 || expected_value > publication.root_sum {
+            return Ok(false);
+        }
+        //One neighbor per level, and the index must be the one the path walks
+        let path = self.proof.get_path();
+        if !publication.leaf_count.is_power_of_two()
+            || path.len() != publication.leaf_count.trailing_zeros() as usize
+            || self.index >= publication.leaf_count
+        {
+            return Ok(false);
+        }
+        for (level, neighbor) in path.iter().enumerate() {
+            let expected = match (self.index >> level) & 1 {
+                0 => Position::Right,
+                _ => Position::Left,
+            };
+            //Every subtree beside the path sums balances, so none can be negative
+            if neighbor.get_position() != expected || neighbor.get_node().get_value() < 0 {
+                return Ok(false);
+            }
+        }
+        let root = self.proof.compute_root()?;
+        Ok(publication.get_commitment().matches(&root))
+    }
+}
+
+//Client side check, the caller should also compare get_publication with the published one.
+//Malformed bytes are an error, a package that does not prove the balance is Ok(false).
+pub fn verify_user_package(
+    bytes: &[u8],
+    expected_id: &str,
+    expected_value: i32,
+) -> Result<bool, MerkleError> {
+    UserPackage::from_bytes(bytes)?.verify(expected_id, expected_value)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    fn balances() -> Vec<(String, i32)> {
+        vec![
+            ("alice".to_string(), 100),
+            ("bob".to_string(), 250),
+            ("carol".to_string(), 0),
+            ("dave".to_string(), 75),
+            ("erin".to_string(), 30),
+        ]
+    }
+
+    #[test]
+    fn exchange_and_users_round_trip() {
+        //Exchange side
+        let options = SnapshotOptions::default().epoch(7).timestamp(1_700_000_000);
+        let snapshot = LiabilitySnapshot::build(balances(), options).unwrap();
+        let publication = snapshot.get_publication();
+        assert_eq!(publication.get_root_sum(), 455);
+        assert_eq!(publication.get_leaf_count(), 8);
+        assert_eq!(publication.get_epoch(), 7);
+        assert_eq!(
+            Some(publication.get_root_hash()),
+            snapshot.get_tree().get_root_hash()
+        );
+        let published = serde_json::to_string(&publication).unwrap();
+        let packages: Vec<(String, i32, Vec<u8>)> = balances()
+            .into_iter()
+            .map(|(id, value)| {
+                let bytes = snapshot.user_package(&id).unwrap().to_bytes().unwrap();
+                (id, value, bytes)
+            })
+            .collect();
+
+        //User side, with only the published root and their own package
+        let published: Publication = serde_json::from_str(&published).unwrap();
+        for (id, value, bytes) in &packages {
+            assert!(verify_user_package(bytes, id, *value).unwrap());
+            let package = UserPackage::from_bytes(bytes).unwrap();
+            assert_eq!(package.get_publication(), published);
+        }
+
+        let (_, _, alice) = &packages[0];
+        assert!(!verify_user_package(alice, "alice", 101).unwrap());
+        assert!(!verify_user_package(alice, "bob", 250).unwrap());
+        assert!(verify_user_package(b"not a package", "alice", 100).is_err());
+
+        let mut moved = UserPackage::from_bytes(alice).unwrap();
+        moved.index = 1;
+        assert!(!verify_user_package(&moved.to_bytes().unwrap(), "alice", 100).unwrap());
+
+        //Auditor side, with the snapshot tree: consistent, no negative balance, and the
+        //published sum is the total of the balances
+        let tree = snapshot.get_tree();
+        assert!(tree.validate().is_ok());
+        assert!(tree.is_non_negative());
+        assert!(published
+            .get_commitment()
+            .matches(&tree.get_root().unwrap()));
+        let total: i32 = balances().iter().map(|(_, value)| value).sum();
+        assert_eq!(published.get_root_sum(), total);
+    }
+
+    #[test]
+    fn tampered_packages_are_rejected() {
+        let snapshot = LiabilitySnapshot::build(balances(), SnapshotOptions::default()).unwrap();
+        let package = snapshot.user_package("bob").unwrap();
+
+        let mut inflated = package.clone();
+        inflated.publication.root_sum += 1;
+        assert!(!verify_user_package(&inflated.to_bytes().unwrap(), "bob", 250).unwrap());
+
+        let mut truncated = package.clone();
+        truncated.proof.path.pop();
+        truncated.publication.leaf_count = 4;
+        assert!(!verify_user_package(&truncated.to_bytes().unwrap(), "bob", 250).unwrap());
+
+        let other =
+            LiabilitySnapshot::build(vec![("bob".to_string(), 250)], SnapshotOptions::default())
+                .unwrap();
+        let mut mixed = package;
+        mixed.publication = other.get_publication();
+        assert!(!verify_user_package(&mixed.to_bytes().unwrap(), "bob", 250).unwrap());
+    }
+
+    #[test]
+    fn negative_and_uncovered_balances_fail() {
+        let mut pairs = balances();
+        pairs.push(("mallory".to_string(), -300));
+        assert!(matches!(
+            LiabilitySnapshot::build(pairs, SnapshotOptions::default()),
+            Err(MerkleError::NegativeValue { id, value: -300 }) if id == "mallory"
+        ));
+
+        //An operator hiding a negative balance by hand: every path still folds
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 100),
+            Leaf::new("mallory".to_string(), -90),
+        ])
+        .unwrap();
+        let root = tree.get_root().unwrap();
+        let publication = Publication {
+            root_hash: root.get_hash(),
+            root_sum: root.get_value(),
+            leaf_count: 2,
+            epoch: 0,
+            timestamp: 0,
+        };
+        let package = |index: usize| UserPackage {
+            publication: publication.clone(),
+            index,
+            proof: tree.get_proof(index).unwrap().unwrap(),
+        };
+        assert!(publication
+            .get_commitment()
+            .matches(&package(0).get_proof().compute_root().unwrap()));
+        assert!(!package(0).verify("alice", 100).unwrap());
+        assert!(!package(1).verify("mallory", -90).unwrap());
+
+        //A balance the sum covers, beside a subtree deflated by a negative leaf
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 5),
+            Leaf::new("bob".to_string(), 10),
+            Leaf::new("mallory".to_string(), -1),
+            Leaf::new("carol".to_string(), 0),
+        ])
+        .unwrap();
+        let root = tree.get_root().unwrap();
+        let package = UserPackage {
+            publication: Publication {
+                root_hash: root.get_hash(),
+                root_sum: root.get_value(),
+                leaf_count: 4,
+                epoch: 0,
+                timestamp: 0,
+            },
+            index: 0,
+            proof: tree.get_proof(0).unwrap().unwrap(),
+        };
+        assert_eq!(package.get_publication().get_root_sum(), 14);
+        assert!(package
+            .get_publication()
+            .get_commitment()
+            .matches(&package.get_proof().compute_root().unwrap()));
+        assert!(!package.verify("alice", 5).unwrap());
+    }
+
+    #[test]
+    fn build_rejects_duplicates_and_unknown_ids() {
+        let mut pairs = balances();
+        pairs.push(("alice".to_string(), 1));
+        let err = LiabilitySnapshot::build(pairs, SnapshotOptions::default()).unwrap_err();
+        assert_eq!(err.error_code(), "E_DUPLICATE_ID");
+
+        let snapshot = LiabilitySnapshot::build(balances(), SnapshotOptions::default()).unwrap();
+        assert!(matches!(
+            snapshot.user_package("mallory"),
+            Err(MerkleError::UnknownId(_))
+        ));
+    }
+}
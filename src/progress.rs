@@ -0,0 +1,289 @@
+//Progress reporting for long-running builds, rebuilds and imports
+
+use crate::layout::{level_count, level_size};
+use crate::MerkleSumTree;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+//Units of work between two reports of a phase
+pub const PROGRESS_INTERVAL: u64 = 1 << 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Phase {
+    //Leafs hashed or consumed
+    LeafHashing,
+    //Parents hashed at level of a tree with height levels, leafs being level 0
+    Level { level: usize, height: usize },
+    //Stored nodes checked against a recomputation
+    Verification,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Progress {
+    phase: Phase,
+    completed: u64,
+    total: Option<u64>,
+}
+
+pub type ProgressCallback = Box<dyn FnMut(Progress) + Send>;
+
+//The callback a tree reports to, clones share it
+#[derive(Clone, Default)]
+pub(crate) struct ProgressSink(Option<Arc<Mutex<ProgressCallback>>>);
+
+//One phase in flight, shared by every thread working on it
+pub(crate) struct PhaseTracker<'a> {
+    sink: &'a ProgressSink,
+    phase: Phase,
+    total: Option<u64>,
+    completed: AtomicU64,
+    reported: AtomicU64,
+}
+
+impl Progress {
+    pub fn new(phase: Phase, completed: u64, total: Option<u64>) -> Progress {
+        Progress {
+            phase,
+            completed,
+            total,
+        }
+    }
+
+    pub fn get_phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn get_completed(&self) -> u64 {
+        self.completed
+    }
+
+    pub fn get_total(&self) -> Option<u64> {
+        self.total
+    }
+}
+
+impl fmt::Debug for ProgressSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            None => f.write_str("None"),
+            Some(_) => f.write_str("Sink"),
+        }
+    }
+}
+
+impl ProgressSink {
+    pub(crate) fn new(progress: Option<ProgressCallback>) -> ProgressSink {
+        ProgressSink(progress.map(|progress| Arc::new(Mutex::new(progress))))
+    }
+
+    pub(crate) fn is_some(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(crate) fn phase(&self, phase: Phase, total: Option<u64>) -> PhaseTracker<'_> {
+        PhaseTracker {
+            sink: self,
+            phase,
+            total,
+            completed: AtomicU64::new(0),
+            reported: AtomicU64::new(0),
+        }
+    }
+
+    //A tracker per parent level of a tree over leaf_count leafs, level k at k - 1
+    pub(crate) fn levels(&self, leaf_count: usize) -> Vec<PhaseTracker<'_>> {
+        let height = level_count(leaf_count);
+        (1..height)
+            .map(|level| {
+                let total = level_size(leaf_count, level) as u64;
+                self.phase(Phase::Level { level, height }, Some(total))
+            })
+            .collect()
+    }
+}
+
+impl PhaseTracker<'_> {
+    pub(crate) fn advance(&self, count: u64) {
+        if !self.sink.is_some() {
+            return;
+        }
+        let completed = self.completed.fetch_add(count, Ordering::Relaxed) + count;
+        if completed / PROGRESS_INTERVAL != (completed - count) / PROGRESS_INTERVAL {
+            self.report(completed);
+        }
+    }
+
+    //Report the final count, once all threads are done with the phase
+    pub(crate) fn finish(&self) {
+        if self.sink.is_some() {
+            self.report(self.completed.load(Ordering::Relaxed));
+        }
+    }
+
+    //Under the lock, so a report never goes out behind a later one
+    fn report(&self, completed: u64) {
+        let callback = match &self.sink.0 {
+            Some(callback) => callback,
+            None => return,
+        };
+        if let Ok(mut callback) = callback.lock() {
+            let reported = self.reported.load(Ordering::Relaxed);
+            if completed > reported {
+                self.reported.store(completed, Ordering::Relaxed);
+                callback(Progress::new(self.phase, completed, self.total));
+            }
+        }
+    }
+}
+
+//End every level phase, bottom first, once all threads are done with them
+pub(crate) fn finish_all(levels: &[PhaseTracker]) {
+    for level in levels {
+        level.finish();
+    }
+}
+
+impl MerkleSumTree {
+    //Report rebuilds, validation and growth from now on, None detaches the callback
+    pub fn set_progress(&mut self, progress: Option<ProgressCallback>) {
+        self.progress = ProgressSink::new(progress);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::Leaf;
+
+    //Records every report, shared with the callback
+    fn recorder() -> (ProgressCallback, Arc<Mutex<Vec<Progress>>>) {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&seen);
+        let callback: ProgressCallback =
+            Box::new(move |progress| sink.lock().unwrap().push(progress));
+        (callback, seen)
+    }
+
+    //Within each phase counts rise, the last report is complete, and phases don't interleave
+    fn check(reports: &[Progress]) {
+        let mut finished = vec![];
+        for pair in reports.windows(2) {
+            if pair[0].phase == pair[1].phase {
+                assert!(pair[0].completed < pair[1].completed, "{:?}", pair);
+            } else {
+                assert!(!finished.contains(&pair[0].phase));
+                finished.push(pair[0].phase);
+            }
+        }
+        for report in reports {
+            assert!(report.total.is_none_or(|total| report.completed <= total));
+        }
+        let last = reports.last().unwrap();
+        assert_eq!(Some(last.completed), last.total);
+    }
+
+    fn leafs(count: i32) -> Vec<Leaf> {
+        (0..count)
+            .map(|i| Leaf::new(format!("user{}", i), i % 100))
+            .collect()
+    }
+
+    #[test]
+    fn builds_report_monotone_complete_progress() {
+        let (callback, seen) = recorder();
+        let tree = MerkleSumTree::builder()
+            .on_progress(callback)
+            .build(leafs(5000))
+            .unwrap();
+        let reports = seen.lock().unwrap().clone();
+        check(&reports);
+        assert_eq!(
+            reports[0],
+            Progress::new(Phase::LeafHashing, 4096, Some(8192))
+        );
+        //Two reports for the leafs and one for each of the 13 levels above
+        assert_eq!(reports.len(), 2 + 13);
+        assert_eq!(
+            reports.last().unwrap().phase,
+            Phase::Level {
+                level: 13,
+                height: 14
+            }
+        );
+        assert_eq!(tree.get_height(), 14);
+        for level in 1..14 {
+            let total = 8192 >> level;
+            assert!(reports.contains(&Progress::new(
+                Phase::Level { level, height: 14 },
+                total,
+                Some(total)
+            )));
+        }
+    }
+
+    #[test]
+    fn rebuilds_report_levels_then_verification() {
+        let (callback, seen) = recorder();
+        let mut tree = MerkleSumTree::new(leafs(100)).unwrap();
+        tree.set_progress(Some(callback));
+        tree.rebuild().unwrap();
+        let reports = seen.lock().unwrap().clone();
+        check(&reports);
+        assert_eq!(reports.len(), 7 + 1);
+        assert_eq!(
+            reports.last(),
+            Some(&Progress::new(Phase::Verification, 255, Some(255)))
+        );
+
+        //A clone shares the callback, a detached tree reports nothing
+        seen.lock().unwrap().clear();
+        let clone = tree.clone();
+        clone.validate().unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 8);
+        tree.set_progress(None);
+        tree.rebuild().unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 8);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn imports_report_levels_then_verification() {
+        let tree = MerkleSumTree::new(leafs(300)).unwrap();
+        let archive = serde_json::to_vec(&tree).unwrap();
+        let (callback, seen) = recorder();
+        let imported =
+            MerkleSumTree::import_archive_parallel_with_progress(&archive, 4, callback).unwrap();
+        assert_eq!(imported.get_root(), tree.get_root());
+        let reports = seen.lock().unwrap().clone();
+        check(&reports);
+        assert_eq!(reports.len(), 9 + 1);
+    }
+
+    #[test]
+    fn streams_count_the_leafs_consumed() {
+        let (callback, seen) = recorder();
+        let root = MerkleSumTree::build_with_checkpoints_and_progress(
+            leafs(5000).into_iter(),
+            0,
+            std::io::sink(),
+            callback,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(root),
+            MerkleSumTree::new(leafs(5000)).unwrap().get_root()
+        );
+        let reports = seen.lock().unwrap().clone();
+        check(&reports);
+        assert_eq!(
+            reports,
+            vec![
+                Progress::new(Phase::LeafHashing, 4096, Some(5000)),
+                Progress::new(Phase::LeafHashing, 5000, Some(5000))
+            ]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//Proofs of what a contiguous run of slots contributes to the root sum
+
+use crate::layout::{self, NodeIndex};
+use crate::multiproof::fold_known;
+use crate::{default_exponent, is_default_exponent, HashParams, MerkleError, MerkleSumTree, Node};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeProof {
+    start: usize,
+    end: usize,
+    levels: usize,
+    //Roots of the covering subtrees, left to right
+    covers: Vec<Node>,
+    siblings: Vec<Node>,
+    #[serde(
+        default = "default_exponent",
+        skip_serializing_if = "is_default_exponent"
+    )]
+    exponent: u64,
+}
+
+//Aligned subtrees exactly covering [start, end), left to right
+fn cover(start: usize, end: usize) -> Vec<NodeIndex> {
+    let mut blocks = vec![];
+    let mut low = start;
+    while low < end {
+        let mut level = low.trailing_zeros();
+        while 1usize
+            .checked_shl(level)
+            .is_none_or(|size| size > end - low)
+        {
+            level -= 1;
+        }
+        blocks.push(NodeIndex::new(level as usize, low >> level));
+        low += 1 << level;
+    }
+    blocks
+}
+
+impl MerkleSumTree {
+    //Prove the slots in range together, empty slots count as zero
+    pub fn get_range_proof(&self, range: Range<usize>) -> Result<RangeProof, MerkleError> {
+        if range.end > self.leafs.len() {
+            return Err(MerkleError::IndexOutOfBounds(range.end));
+        }
+        if range.is_empty() {
+            return Err(MerkleError::IndexOutOfBounds(range.start));
+        }
+        let levels = layout::level_count(self.leafs.len()).saturating_sub(1);
+        let blocks = cover(range.start, range.end);
+        let covers = blocks
+            .iter()
+            .map(|at| self.node_at(*at))
+            .collect::<Result<Vec<Node>, MerkleError>>()?;
+        let siblings = self.known_siblings(&blocks, levels)?;
+        Ok(RangeProof {
+            start: range.start,
+            end: range.end,
+            levels,
+            covers,
+            siblings,
+            exponent: self.mimc_exponent,
+        })
+    }
+}
+
+impl RangeProof {
+    pub fn get_range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn get_levels(&self) -> usize {
+        self.levels
+    }
+
+    pub fn get_covers(&self) -> Vec<Node> {
+        self.covers.clone()
+    }
+
+    pub fn get_siblings(&self) -> Vec<Node> {
+        self.siblings.clone()
+    }
+
+    pub fn get_exponent(&self) -> u64 {
+        self.exponent
+    }
+
+    //Whether the range folds to root, and the sum it attests. A proof whose range,
+    //covers or siblings do not fit its levels is an error rather than false
+    pub fn verify(&self, root: Node) -> Result<(bool, i32), MerkleError> {
+        let params = HashParams::mimc(self.exponent)?;
+        let width = 1usize.checked_shl(self.levels as u32);
+        if self.start >= self.end || width.is_none_or(|width| self.end > width) {
+            return Err(MerkleError::InvalidProof(format!(
+                "range {}..{} in {} levels",
+                self.start, self.end, self.levels
+            )));
+        }
+        let blocks = cover(self.start, self.end);
+        if blocks.len() != self.covers.len() {
+            return Err(MerkleError::InvalidProof(format!(
+                "{} covering nodes for {} subtrees",
+                self.covers.len(),
+                blocks.len()
+            )));
+        }
+        let sum = self
+            .covers
+            .iter()
+            .try_fold(0i32, |sum, node| sum.checked_add(node.value))
+            .ok_or(MerkleError::Overflow)?;
+        let seeds: Vec<(NodeIndex, Node)> = blocks
+            .into_iter()
+            .zip(self.covers.iter().cloned())
+            .collect();
+        let computed = fold_known(&seeds, &self.siblings, self.levels, &params)?
+            .ok_or_else(|| MerkleError::InvalidProof("nothing folds to the root".to_string()))?;
+        Ok((computed.is_equal(root), sum))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+    use crate::Fr;
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(users(11, 13, 40)).unwrap()
+    }
+
+    #[test]
+    fn range_sums_match_the_leafs() {
+        let tree = tree();
+        let root = tree.get_root().unwrap();
+        let slots = tree.get_leafs().len();
+        for (start, end) in [(0, 1), (3, 9), (5, 6), (1, 15), (0, 11), (8, 16), (6, 8)] {
+            let proof = tree.get_range_proof(start..end).unwrap();
+            let expected: i32 = tree.get_leafs()[start..end]
+                .iter()
+                .map(|leaf| leaf.get_node().get_value())
+                .sum();
+            assert_eq!(proof.verify(root.clone()).unwrap(), (true, expected));
+            assert_eq!(proof.get_covers().len(), cover(start, end).len());
+        }
+        //[3, 9) is covered by 3, 4..8 and 8, and reaches the root through 2, 9, the
+        //subtrees over 0..2 and 10..12, then 12..16
+        let proof = tree.get_range_proof(3..9).unwrap();
+        assert_eq!(proof.get_covers().len(), 3);
+        assert_eq!(proof.get_siblings().len(), 5);
+
+        let whole = tree.get_range_proof(0..slots).unwrap();
+        assert_eq!(whole.get_covers(), vec![root.clone()]);
+        assert!(whole.get_siblings().is_empty());
+        assert_eq!(
+            whole.verify(root.clone()).unwrap(),
+            (true, root.get_value())
+        );
+    }
+
+    #[test]
+    fn forged_and_malformed_ranges_fail() {
+        let tree = tree();
+        let root = tree.get_root().unwrap();
+        let proof = tree.get_range_proof(2..7).unwrap();
+
+        let mut inflated = proof.clone();
+        inflated.covers[0] = Node::new(inflated.covers[0].get_hash(), 1000);
+        assert!(!inflated.verify(root.clone()).unwrap().0);
+
+        //Claiming another range with the same nodes
+        let mut shifted = proof.clone();
+        shifted.start = 3;
+        shifted.end = 8;
+        assert!(!matches!(shifted.verify(root.clone()), Ok((true, _))));
+
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(matches!(
+            short.verify(root.clone()),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        let mut long = proof.clone();
+        long.siblings.push(Node::new(Fr::ONE, 0));
+        assert!(matches!(
+            long.verify(root.clone()),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        let mut outside = proof;
+        outside.end = 17;
+        assert!(matches!(
+            outside.verify(root),
+            Err(MerkleError::InvalidProof(_))
+        ));
+
+        assert!(matches!(
+            tree.get_range_proof(4..4),
+            Err(MerkleError::IndexOutOfBounds(4))
+        ));
+        assert!(matches!(
+            tree.get_range_proof(0..17),
+            Err(MerkleError::IndexOutOfBounds(17))
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+//Recomputation of every stored node from the leaf layer
+
+use crate::layout::coordinates;
+use crate::progress::finish_all;
+use crate::storage::NodeStore;
+use crate::{CancellationToken, MerkleError, MerkleSumTree, Node, Phase};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildReport {
+    node_count: usize,
+    stored_count: usize,
+    mismatches: Vec<(usize, usize)>,
+}
+
+impl RebuildReport {
+    //Nodes in the recomputed layout
+    pub fn get_node_count(&self) -> usize {
+        self.node_count
+    }
+
+    //Nodes the tree held before the recomputation
+    pub fn get_stored_count(&self) -> usize {
+        self.stored_count
+    }
+
+    //(level, offset) of every stored node that disagreed, missing ones included
+    pub fn get_mismatches(&self) -> Vec<(usize, usize)> {
+        self.mismatches.clone()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.mismatches.is_empty() && self.node_count == self.stored_count
+    }
+}
+
+impl MerkleSumTree {
+    //Recompute every node and report the stored ones that disagree, without mutating
+    pub fn rebuild_verify(&self) -> Result<RebuildReport, MerkleError> {
+        let (nodes, mismatched) = self.reconcile(&self.cancel)?;
+        Ok(self.rebuild_report(&nodes, mismatched))
+    }
+
+    //Recompute every node from the leafs and keep the result
+    pub fn rebuild(&mut self) -> Result<RebuildReport, MerkleError> {
+        let (nodes, mismatched) = self.reconcile(&self.cancel)?;
+        let report = self.rebuild_report(&nodes, mismatched);
+        self.nodes = NodeStore::from_flat(nodes);
+        self.trim_buffers();
+        Ok(report)
+    }
+
+    //Bottom-up pass shared with validate: the recomputed nodes and the flat indices
+    //where the stored ones disagree or are missing
+    pub(crate) fn reconcile(
+        &self,
+        cancel: &CancellationToken,
+    ) -> Result<(Vec<Node>, Vec<usize>), MerkleError> {
+        let levels = self.progress.levels(self.leafs.len());
+        let nodes = Self::build_nodes(&self.leafs, &self.get_hash_params(), &levels, cancel)?;
+        finish_all(&levels);
+        Ok(self.compare_nodes(nodes))
+    }
+
+    //Recomputed nodes paired with the flat indices where the stored ones disagree
+    pub(crate) fn compare_nodes(&self, nodes: Vec<Node>) -> (Vec<Node>, Vec<usize>) {
+        let verification = self
+            .progress
+            .phase(Phase::Verification, Some(nodes.len() as u64));
+        let mut stored = self.nodes.iter();
+        let mismatched = nodes
+            .iter()
+            .enumerate()
+            .inspect(|_| verification.advance(1))
+            .filter(|(_, computed)| stored.next() != Some(*computed))
+            .map(|(index, _)| index)
+            .collect();
+        verification.finish();
+        (nodes, mismatched)
+    }
+
+    fn rebuild_report(&self, nodes: &[Node], mismatched: Vec<usize>) -> RebuildReport {
+        let leaf_count = self.leafs.len();
+        RebuildReport {
+            node_count: nodes.len(),
+            stored_count: self.nodes.len(),
+            mismatches: mismatched
+                .into_iter()
+                .filter_map(|index| coordinates(leaf_count, index))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use crate::Fr;
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, -4, 30])).unwrap()
+    }
+
+    #[test]
+    fn consistent_trees_rebuild_clean() {
+        let mut tree = tree();
+        let before = tree.get_nodes();
+        let report = tree.rebuild_verify().unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.get_node_count(), 7);
+        assert_eq!(tree.rebuild().unwrap(), report);
+        assert_eq!(tree.get_nodes(), before);
+    }
+
+    #[test]
+    fn corrupted_nodes_are_reported_and_repaired() {
+        let clean = tree();
+        let mut tree = clean.clone();
+        //Corrupt the left internal node and the root
+        for (index, hash, value) in [(4, 7u64, 1), (6, 9, 2)] {
+            let at = tree.nodes.locate(index).unwrap();
+            *tree.nodes.get_mut(at).unwrap() = Node::new(Fr::from(hash), value);
+        }
+        assert!(tree.validate().is_err());
+
+        let report = tree.rebuild_verify().unwrap();
+        assert_eq!(report.get_mismatches(), vec![(1, 0), (2, 0)]);
+        assert!(!report.is_clean());
+        assert_ne!(tree.get_nodes(), clean.get_nodes());
+
+        assert_eq!(tree.rebuild().unwrap(), report);
+        assert_eq!(tree.get_nodes(), clean.get_nodes());
+        assert_eq!(tree.validate(), Ok(()));
+        assert!(tree.rebuild_verify().unwrap().is_clean());
+
+        //A truncated node list is refilled
+        tree.nodes = NodeStore::from_flat(tree.get_nodes()[..5].to_vec());
+        let report = tree.rebuild().unwrap();
+        assert_eq!(report.get_stored_count(), 5);
+        assert_eq!(report.get_mismatches(), vec![(1, 1), (2, 0)]);
+        assert_eq!(tree.get_nodes(), clean.get_nodes());
+    }
+}
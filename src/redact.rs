@@ -0,0 +1,118 @@
+//Membership checks that never see the account id
+
+use crate::mimc_sponge::DEFAULT_EXPONENT;
+use crate::{
+    path_index, Fr, InclusionProof, Leaf, MerkleError, MerkleSumTree, Neighbor, Node,
+    RootCommitment,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedactedProof {
+    leaf_hash: Fr,
+    value: i32,
+    path: Vec<Neighbor>,
+}
+
+impl InclusionProof {
+    pub fn redact_id(&self) -> RedactedProof {
+        RedactedProof {
+            leaf_hash: self.leaf.node.hash,
+            value: self.leaf.node.value,
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl RedactedProof {
+    pub fn get_leaf_hash(&self) -> Fr {
+        self.leaf_hash
+    }
+
+    pub fn get_value(&self) -> i32 {
+        self.value
+    }
+
+    pub fn get_path(&self) -> Vec<Neighbor> {
+        self.path.clone()
+    }
+
+    pub fn verify(&self, root: &RootCommitment) -> Result<bool, MerkleError> {
+        MerkleSumTree::verify_membership_by_hash(
+            root,
+            self.leaf_hash,
+            i64::from(self.value),
+            &self.path,
+        )
+    }
+}
+
+impl MerkleSumTree {
+    //Fold path up from a bare leaf hash and value, the id is never re-derived
+    pub fn verify_membership_by_hash(
+        root: &RootCommitment,
+        leaf_hash: Fr,
+        value: i64,
+        path: &[Neighbor],
+    ) -> Result<bool, MerkleError> {
+        let value = match i32::try_from(value) {
+            Ok(value) => value,
+            Err(_) => return Ok(false),
+        };
+        let proof = InclusionProof {
+            leaf: Leaf {
+                id: "".into(),
+                node: Node::new(leaf_hash, value),
+            },
+            index: path_index(path),
+            path: path.to_vec(),
+            root: None,
+            epoch: None,
+            exponent: DEFAULT_EXPONENT,
+        };
+        Ok(root.matches(&proof.compute_root()?))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_and_full_proofs_verify_against_one_root() {
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice-7f3a".to_string(), 10),
+            Leaf::new("bob-91c2".to_string(), -4),
+            Leaf::new("carol-05de".to_string(), 30),
+        ])
+        .unwrap();
+        let root = tree.get_commitment().unwrap();
+        for index in 0..3 {
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            let id = proof.get_leaf().get_id();
+            assert!(tree.verify_proof(proof.clone()).unwrap());
+
+            let redacted = proof.redact_id();
+            assert!(redacted.verify(&root).unwrap());
+            let json = serde_json::to_string(&redacted).unwrap();
+            assert!(!json.contains(&id));
+            assert!(!json.contains("\"id\""));
+            let restored: RedactedProof = serde_json::from_str(&json).unwrap();
+            assert!(restored.verify(&root).unwrap());
+            //The full form does not deserialize without its id
+            assert!(serde_json::from_str::<InclusionProof>(&json).is_err());
+        }
+
+        let proof = tree.get_proof(1).unwrap().unwrap();
+        let hash = proof.get_leaf().get_node().get_hash();
+        let path = proof.get_path();
+        assert!(MerkleSumTree::verify_membership_by_hash(&root, hash, -4, &path).unwrap());
+        assert!(!MerkleSumTree::verify_membership_by_hash(&root, hash, 4, &path).unwrap());
+        assert!(!MerkleSumTree::verify_membership_by_hash(&root, hash, i64::MAX, &path).unwrap());
+        assert!(
+            !MerkleSumTree::verify_membership_by_hash(&root, hash + Fr::from(1u64), -4, &path)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//Salt rotation between reporting periods
+
+use crate::{InclusionProof, MerkleError, MerkleSumTree, RootCommitment, Salt, SaltDeriver};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationProof {
+    old_root: RootCommitment,
+    new_root: RootCommitment,
+    old_count: usize,
+    new_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationOpening {
+    id: String,
+    value: i32,
+    old_salt: Salt,
+    new_salt: Salt,
+    old_proof: InclusionProof,
+    new_proof: InclusionProof,
+}
+
+impl MerkleSumTree {
+    pub fn rotate_salts(
+        &self,
+        new_deriver: &SaltDeriver,
+    ) -> Result<(MerkleSumTree, RotationProof), MerkleError> {
+        let pairs: Vec<(String, i32)> = self
+            .non_empty_leafs(false)
+            .map(|(_, leaf)| (leaf.get_id(), leaf.get_node().get_value()))
+            .collect();
+        let old_count = pairs.len();
+        let rotated = Self::with_derived_salts(pairs, new_deriver)?;
+        let proof = RotationProof {
+            old_root: self.get_commitment().ok_or(MerkleError::EmptyTree)?,
+            new_root: rotated.get_commitment().ok_or(MerkleError::EmptyTree)?,
+            old_count,
+            new_count: rotated.non_empty_leafs(false).count(),
+        };
+        Ok((rotated, proof))
+    }
+
+    //Open id in this tree and its rotation, the caller supplies both of its salts
+    pub fn open_rotation(
+        &self,
+        rotated: &MerkleSumTree,
+        id: &str,
+        old_salt: Salt,
+        new_salt: Salt,
+    ) -> Result<RotationOpening, MerkleError> {
+        let proof_of = |tree: &MerkleSumTree| {
+            let index = tree
+                .get_index_by_id(id)
+                .ok_or_else(|| MerkleError::UnknownId(id.to_string()))?;
+            tree.get_proof(index)?
+                .ok_or(MerkleError::IndexOutOfBounds(index))
+        };
+        let old_proof = proof_of(self)?;
+        let new_proof = proof_of(rotated)?;
+        Ok(RotationOpening {
+            id: id.to_string(),
+            value: old_proof.get_leaf().get_node().get_value(),
+            old_salt,
+            new_salt,
+            old_proof,
+            new_proof,
+        })
+    }
+}
+
+impl RotationProof {
+    pub fn get_old_root(&self) -> RootCommitment {
+        self.old_root
+    }
+
+    pub fn get_new_root(&self) -> RootCommitment {
+        self.new_root
+    }
+
+    pub fn get_old_count(&self) -> usize {
+        self.old_count
+    }
+
+    pub fn get_new_count(&self) -> usize {
+        self.new_count
+    }
+
+    //Both roots are the published ones, sums and occupied counts agree
+    pub fn verify(&self, old: &RootCommitment, new: &RootCommitment) -> bool {
+        let same_root = |carried: &RootCommitment, published: &RootCommitment| {
+            carried.get_hash() == published.get_hash() && carried.get_sum() == published.get_sum()
+        };
+        same_root(&self.old_root, old)
+            && same_root(&self.new_root, new)
+            && self.old_root.get_sum() == self.new_root.get_sum()
+            && self.old_count == self.new_count
+    }
+}
+
+impl RotationOpening {
+    pub fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn get_value(&self) -> i32 {
+        self.value
+    }
+
+    //Both salted leafs are the account's and fold to the roots of the rotation
+    pub fn verify(&self, rotation: &RotationProof) -> Result<bool, MerkleError> {
+        let opens = |salt: &Salt, proof: &InclusionProof, root: &RootCommitment| {
+            Ok::<bool, MerkleError>(
+                salt.leaf(self.id.clone(), self.value) == proof.get_leaf()
+                    && root.matches(&proof.compute_root()?),
+            )
+        };
+        Ok(opens(&self.old_salt, &self.old_proof, &rotation.old_root)?
+            && opens(&self.new_salt, &self.new_proof, &rotation.new_root)?)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    fn pairs() -> Vec<(String, i32)> {
+        vec![
+            ("alice".to_string(), 10),
+            ("bob".to_string(), -4),
+            ("carol".to_string(), 30),
+            ("dave".to_string(), 7),
+            ("erin".to_string(), 0),
+        ]
+    }
+
+    #[test]
+    fn rotation_keeps_the_committed_balances() {
+        let old_deriver = SaltDeriver::new([1; 32]).context(b"epoch-1".to_vec());
+        let new_deriver = SaltDeriver::new([1; 32]).context(b"epoch-2".to_vec());
+        let tree = MerkleSumTree::with_derived_salts(pairs(), &old_deriver).unwrap();
+        let (rotated, proof) = tree.rotate_salts(&new_deriver).unwrap();
+        assert_eq!(
+            rotated.get_root(),
+            MerkleSumTree::with_derived_salts(pairs(), &new_deriver)
+                .unwrap()
+                .get_root()
+        );
+        assert_ne!(rotated.get_root_hash(), tree.get_root_hash());
+        let (old, new) = (
+            tree.get_commitment().unwrap(),
+            rotated.get_commitment().unwrap(),
+        );
+        assert!(proof.verify(&old, &new));
+        assert!(!proof.verify(&new, &old));
+        assert_eq!(proof.get_old_count(), 5);
+
+        for (id, _) in pairs() {
+            let opening = tree
+                .open_rotation(
+                    &rotated,
+                    &id,
+                    old_deriver.derive_salt(&id),
+                    new_deriver.derive_salt(&id),
+                )
+                .unwrap();
+            assert!(opening.verify(&proof).unwrap());
+            let json = serde_json::to_string(&opening).unwrap();
+            let restored: RotationOpening = serde_json::from_str(&json).unwrap();
+            assert!(restored.verify(&proof).unwrap());
+        }
+    }
+
+    #[test]
+    fn changed_balances_fail_the_checks() {
+        let old_deriver = SaltDeriver::new([1; 32]);
+        let new_deriver = SaltDeriver::new([2; 32]);
+        let tree = MerkleSumTree::with_derived_salts(pairs(), &old_deriver).unwrap();
+        let (_, honest) = tree.rotate_salts(&new_deriver).unwrap();
+
+        //A rotation that moved 5 from alice to bob keeps the sum and the count
+        let mut moved = pairs();
+        moved[0].1 -= 5;
+        moved[1].1 += 5;
+        let forged = MerkleSumTree::with_derived_salts(moved, &new_deriver).unwrap();
+        let proof = RotationProof {
+            new_root: forged.get_commitment().unwrap(),
+            ..honest.clone()
+        };
+        assert!(proof.verify(
+            &tree.get_commitment().unwrap(),
+            &forged.get_commitment().unwrap()
+        ));
+        //Only an opening catches it
+        let opening = tree
+            .open_rotation(
+                &forged,
+                "alice",
+                old_deriver.derive_salt("alice"),
+                new_deriver.derive_salt("alice"),
+            )
+            .unwrap();
+        assert!(!opening.verify(&proof).unwrap());
+        let wrong_salt = tree
+            .open_rotation(
+                &forged,
+                "carol",
+                new_deriver.derive_salt("carol"),
+                new_deriver.derive_salt("carol"),
+            )
+            .unwrap();
+        assert!(!wrong_salt.verify(&proof).unwrap());
+
+        //A dropped account changes the count
+        let mut dropped = tree.clone();
+        dropped.remove(4).unwrap();
+        let (rotated, proof) = dropped.rotate_salts(&new_deriver).unwrap();
+        let shrunk = RotationProof {
+            new_root: rotated.get_commitment().unwrap(),
+            new_count: proof.get_new_count(),
+            ..honest
+        };
+        assert!(!shrunk.verify(
+            &tree.get_commitment().unwrap(),
+            &rotated.get_commitment().unwrap()
+        ));
+        assert!(tree
+            .open_rotation(
+                &rotated,
+                "erin",
+                old_deriver.derive_salt("erin"),
+                new_deriver.derive_salt("erin")
+            )
+            .is_err());
+    }
+}
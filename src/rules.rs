@@ -0,0 +1,339 @@
+//Checks on leafs before they enter a tree
+
+use crate::{EmptyLeafSpec, Leaf, MerkleError, MerkleSumTree};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+pub type CharPredicate = Arc<dyn Fn(char) -> bool + Send + Sync>;
+
+//What an id and value must satisfy, see LeafRules::new for the defaults
+#[derive(Clone)]
+pub struct LeafRules {
+    max_id_len: Option<usize>,
+    allowed_chars: Option<CharPredicate>,
+    reserved: BTreeSet<String>,
+    min_value: Option<i64>,
+    max_value: Option<i64>,
+}
+
+//The rule a leaf broke
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeafRule {
+    EmptyId,
+    IdTooLong { len: usize, max: usize },
+    DisallowedChar(char),
+    ReservedId,
+    ValueBelowMin { value: i64, min: i64 },
+    ValueAboveMax { value: i64, max: i64 },
+    //Outside the i32 range every leaf value lives in
+    ValueOutOfRange(i64),
+}
+
+impl Default for LeafRules {
+    fn default() -> Self {
+        LeafRules {
+            max_id_len: None,
+            allowed_chars: None,
+            reserved: BTreeSet::from([EmptyLeafSpec::default().get_id()]),
+            min_value: None,
+            max_value: None,
+        }
+    }
+}
+
+impl fmt::Debug for LeafRules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LeafRules")
+            .field("max_id_len", &self.max_id_len)
+            .field("allowed_chars", &self.allowed_chars.as_ref().map(|_| ".."))
+            .field("reserved", &self.reserved)
+            .field("min_value", &self.min_value)
+            .field("max_value", &self.max_value)
+            .finish()
+    }
+}
+
+impl fmt::Display for LeafRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LeafRule::EmptyId => write!(f, "the id is empty"),
+            LeafRule::IdTooLong { len, max } => {
+                write!(f, "the id is {} bytes, at most {} allowed", len, max)
+            }
+            LeafRule::DisallowedChar(c) => write!(f, "the id contains {:?}", c),
+            LeafRule::ReservedId => write!(f, "the id is reserved"),
+            LeafRule::ValueBelowMin { value, min } => {
+                write!(f, "the value {} is below the minimum {}", value, min)
+            }
+            LeafRule::ValueAboveMax { value, max } => {
+                write!(f, "the value {} is above the maximum {}", value, max)
+            }
+            LeafRule::ValueOutOfRange(value) => {
+                write!(f, "the value {} does not fit in an i32", value)
+            }
+        }
+    }
+}
+
+impl LeafRules {
+    //Non-empty ids other than the default empty leaf id "0", any value
+    pub fn new() -> LeafRules {
+        LeafRules::default()
+    }
+
+    //Longest id accepted, in bytes
+    pub fn max_id_len(mut self, max: Option<usize>) -> Self {
+        self.max_id_len = max;
+        self
+    }
+
+    //Accept only ids whose every char satisfies allowed
+    pub fn allowed_chars<F: Fn(char) -> bool + Send + Sync + 'static>(
+        mut self,
+        allowed: F,
+    ) -> Self {
+        self.allowed_chars = Some(Arc::new(allowed));
+        self
+    }
+
+    //Refuse id, on top of "0"
+    pub fn reserve(mut self, id: String) -> Self {
+        self.reserved.insert(id);
+        self
+    }
+
+    //Drop the reserved ids, "0" included
+    pub fn clear_reserved(mut self) -> Self {
+        self.reserved.clear();
+        self
+    }
+
+    //Inclusive bounds on the value, None leaves that side open
+    pub fn value_bounds(mut self, min: Option<i64>, max: Option<i64>) -> Self {
+        self.min_value = min;
+        self.max_value = max;
+        self
+    }
+
+    pub fn get_max_id_len(&self) -> Option<usize> {
+        self.max_id_len
+    }
+
+    pub fn get_reserved(&self) -> Vec<String> {
+        self.reserved.iter().cloned().collect()
+    }
+
+    pub fn get_value_bounds(&self) -> (Option<i64>, Option<i64>) {
+        (self.min_value, self.max_value)
+    }
+
+    //The first rule id and value break, in the order the rules are declared
+    pub fn check(&self, id: &str, value: i64) -> Result<(), LeafRule> {
+        if id.is_empty() {
+            return Err(LeafRule::EmptyId);
+        }
+        match self.max_id_len {
+            Some(max) if id.len() > max => return Err(LeafRule::IdTooLong { len: id.len(), max }),
+            _ => {}
+        }
+        if let Some(allowed) = &self.allowed_chars {
+            if let Some(c) = id.chars().find(|c| !allowed(*c)) {
+                return Err(LeafRule::DisallowedChar(c));
+            }
+        }
+        if self.reserved.contains(id) {
+            return Err(LeafRule::ReservedId);
+        }
+        match (self.min_value, self.max_value) {
+            (Some(min), _) if value < min => Err(LeafRule::ValueBelowMin { value, min }),
+            (_, Some(max)) if value > max => Err(LeafRule::ValueAboveMax { value, max }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_leaf(&self, leaf: &Leaf) -> Result<(), MerkleError> {
+        self.check(leaf.get_id_str(), i64::from(leaf.node.value))
+            .map_err(|rule| MerkleError::InvalidLeaf {
+                id: leaf.get_id(),
+                rule,
+            })
+    }
+}
+
+impl Leaf {
+    //Leaf::new once id and value pass rules
+    pub fn try_new(id: String, value: i64, rules: &LeafRules) -> Result<Leaf, MerkleError> {
+        let checked = rules
+            .check(&id, value)
+            .and_then(|_| i32::try_from(value).map_err(|_| LeafRule::ValueOutOfRange(value)));
+        match checked {
+            Ok(value) => Ok(Leaf::new(id, value)),
+            Err(rule) => Err(MerkleError::InvalidLeaf { id, rule }),
+        }
+    }
+}
+
+impl MerkleSumTree {
+    pub fn get_leaf_rules(&self) -> Option<LeafRules> {
+        self.rules.clone()
+    }
+
+    //Check every later insertion against rules, the leafs already in place are kept
+    pub fn set_leaf_rules(&mut self, rules: Option<LeafRules>) {
+        self.rules = rules;
+    }
+
+    //Refuse a leaf the tree's rules reject, the empty leaf always passes
+    pub(crate) fn check_rules(&self, leaf: &Leaf) -> Result<(), MerkleError> {
+        match &self.rules {
+            Some(rules) if !self.is_empty_leaf(leaf) => rules.check_leaf(leaf),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    fn rules() -> LeafRules {
+        LeafRules::new()
+            .max_id_len(Some(8))
+            .allowed_chars(|c| c.is_ascii_alphanumeric() || c == '-')
+            .reserve("treasury".to_string())
+            .value_bounds(Some(-100), Some(1000))
+    }
+
+    fn rejected(id: &str, value: i64) -> Option<LeafRule> {
+        match Leaf::try_new(id.to_string(), value, &rules()) {
+            Err(MerkleError::InvalidLeaf { id: refused, rule }) if refused == id => Some(rule),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn each_rule_is_named() {
+        assert_eq!(
+            Leaf::try_new("alice".to_string(), 10, &rules()).unwrap(),
+            Leaf::new("alice".to_string(), 10)
+        );
+        assert_eq!(rejected("", 10), Some(LeafRule::EmptyId));
+        assert_eq!(
+            rejected("alice-and-bob", 10),
+            Some(LeafRule::IdTooLong { len: 13, max: 8 })
+        );
+        assert_eq!(rejected("al ice", 10), Some(LeafRule::DisallowedChar(' ')));
+        assert_eq!(rejected("0", 10), Some(LeafRule::ReservedId));
+        assert_eq!(rejected("treasury", 10), Some(LeafRule::ReservedId));
+        assert_eq!(
+            rejected("alice", -101),
+            Some(LeafRule::ValueBelowMin {
+                value: -101,
+                min: -100
+            })
+        );
+        assert_eq!(
+            rejected("alice", 1001),
+            Some(LeafRule::ValueAboveMax {
+                value: 1001,
+                max: 1000
+            })
+        );
+        assert!(Leaf::try_new("alice".to_string(), 1000, &rules()).is_ok());
+
+        //Without bounds only the i32 range is left
+        let open = LeafRules::new();
+        let huge = i64::from(i32::MAX) + 1;
+        assert!(matches!(
+            Leaf::try_new("alice".to_string(), huge, &open),
+            Err(MerkleError::InvalidLeaf {
+                rule: LeafRule::ValueOutOfRange(value),
+                ..
+            }) if value == huge
+        ));
+        assert!(Leaf::try_new("0".to_string(), 0, &open.clear_reserved()).is_ok());
+    }
+
+    #[test]
+    fn errors_name_the_leaf_and_the_rule() {
+        let err = Leaf::try_new("bob".to_string(), 5000, &rules()).unwrap_err();
+        assert_eq!(err.error_code(), "E_INVALID_LEAF");
+        assert_eq!(
+            err.to_string(),
+            "Invalid leaf bob: the value 5000 is above the maximum 1000"
+        );
+        let err = Leaf::try_new("bob!".to_string(), 5, &rules()).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid leaf bob!: the id contains '!'");
+    }
+
+    #[test]
+    fn trees_check_every_insertion_path() {
+        let leafs = vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ];
+        let mut tree = MerkleSumTree::builder()
+            .leaf_rules(rules())
+            .build(leafs.clone())
+            .unwrap();
+        let before = tree.get_root();
+        let refused =
+            |result: Result<_, MerkleError>| matches!(result, Err(MerkleError::InvalidLeaf { .. }));
+        assert!(refused(
+            tree.push(Leaf::new("a b".to_string(), 1)).map(|_| ())
+        ));
+        assert!(refused(tree.set_leaf(Leaf::new("0".to_string(), 1), 3)));
+        assert!(refused(tree.insert(0, Leaf::new("dave".to_string(), 5000))));
+        assert!(refused(
+            tree.increment_by_id("bob", 2000, false).map(|_| ())
+        ));
+        assert!(refused(tree.increment_by_id("", 1, true).map(|_| ())));
+        //Growing the tree goes through the rules too
+        tree.push(Leaf::new("dave".to_string(), 1)).unwrap();
+        assert!(refused(
+            tree.push(Leaf::new("erin-and-frank".to_string(), 1))
+                .map(|_| ())
+        ));
+        assert_ne!(tree.get_root(), before);
+        assert_eq!(tree.get_leafs().len(), 4);
+
+        //Removal writes the empty leaf, which the rules never refuse
+        tree.remove(0).unwrap();
+        assert!(tree.validate().is_ok());
+
+        //The build refuses the first bad leaf
+        let mut bad = leafs.clone();
+        bad.push(Leaf::new("mallory".to_string(), -500));
+        assert!(matches!(
+            MerkleSumTree::builder().leaf_rules(rules()).build(bad),
+            Err(MerkleError::InvalidLeaf { id, rule: LeafRule::ValueBelowMin { .. } })
+                if id == "mallory"
+        ));
+    }
+
+    #[test]
+    fn loaded_trees_take_their_rules_again() {
+        let tree = MerkleSumTree::builder()
+            .leaf_rules(rules())
+            .build(vec![Leaf::new("alice".to_string(), 10)])
+            .unwrap();
+        let mut loaded: MerkleSumTree =
+            serde_json::from_slice(&serde_json::to_vec(&tree).unwrap()).unwrap();
+        assert!(loaded.get_leaf_rules().is_none());
+        loaded.push(Leaf::new("a b".to_string(), 1)).unwrap();
+        loaded.set_leaf_rules(Some(rules()));
+        assert!(matches!(
+            loaded.push(Leaf::new("c d".to_string(), 1)),
+            Err(MerkleError::InvalidLeaf { .. })
+        ));
+        //Leafs already in place are kept
+        assert_eq!(loaded.get_index_by_id("a b"), Some(1));
+        assert_eq!(
+            loaded.get_leaf_rules().unwrap().get_value_bounds(),
+            (Some(-100), Some(1000))
+        );
+    }
+}
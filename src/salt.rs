@@ -0,0 +1,301 @@
+//Salted leafs and the randomness that feeds them
+
+#[cfg(feature = "random")]
+use crate::mimc_sponge::FrRepr;
+use crate::mimc_sponge::DEFAULT_EXPONENT;
+use crate::progress::ProgressSink;
+use crate::{
+    CancellationToken, EmptyLeafSpec, Fr, Leaf, LeafHashMode, MerkleError, MerkleSumTree,
+    MimcSponge, Node, MAX_HEIGHT,
+};
+use ff::{Field, PrimeField};
+use hkdf::Hkdf;
+#[cfg(feature = "random")]
+use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use std::fmt;
+
+const DOMAIN: &[u8] = b"merkle-sum-tree/salt/v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Salt(Fr);
+
+//Zero-value padding id, 32 hex digits like dummy_padding draws
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DummyId(String);
+
+//Derives salts from a master seed, the seed never leaves it
+#[derive(Clone)]
+pub struct SaltDeriver {
+    master_seed: [u8; 32],
+    context: Vec<u8>,
+}
+
+impl Salt {
+    pub fn new(salt: Fr) -> Salt {
+        Salt(salt)
+    }
+
+    pub fn get_fr(&self) -> Fr {
+        self.0
+    }
+
+    pub fn leaf(&self, id: String, value: i32) -> Leaf {
+        let id_only = Leaf::new(id, value);
+        let hash = MimcSponge::default().single_hash(&[id_only.node.hash, self.0], Fr::ONE);
+        Leaf {
+            id: id_only.id,
+            node: Node::new(hash, value),
+        }
+    }
+
+    //Whether leaf is the one this salt gives its id and value
+    pub fn matches(&self, leaf: &Leaf) -> bool {
+        self.leaf(leaf.get_id(), leaf.node.value) == *leaf
+    }
+
+    #[cfg(feature = "random")]
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Salt {
+        Salt(Fr::random(rng))
+    }
+}
+
+impl DummyId {
+    pub fn new(id: String) -> DummyId {
+        DummyId(id)
+    }
+
+    pub fn get_id(&self) -> String {
+        self.0.clone()
+    }
+
+    pub fn leaf(&self) -> Leaf {
+        Leaf::new(self.0.clone(), 0)
+    }
+
+    #[cfg(feature = "random")]
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> DummyId {
+        DummyId((0..4).map(|_| format!("{:08x}", rng.next_u32())).collect())
+    }
+}
+
+impl SaltDeriver {
+    pub fn new(master_seed: [u8; 32]) -> SaltDeriver {
+        SaltDeriver {
+            master_seed,
+            context: vec![],
+        }
+    }
+
+    //Separate the salts of an epoch, for example b"epoch-42", empty by default
+    pub fn context(mut self, context: Vec<u8>) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn get_context(&self) -> Vec<u8> {
+        self.context.clone()
+    }
+
+    pub fn derive(&self, id: &str) -> Fr {
+        let hkdf = Hkdf::<Sha256>::new(None, &self.master_seed);
+        let length = (self.context.len() as u64).to_le_bytes();
+        let mut okm = [0u8; 64];
+        //64 bytes is far below the HKDF-SHA256 limit of 255 * 32, expand cannot fail
+        hkdf.expand_multi_info(&[DOMAIN, &length, &self.context, id.as_bytes()], &mut okm)
+            .unwrap_or_default();
+        //Big-endian 128-bit chunks folded in, the 512-bit integer mod the modulus
+        let shift = Fr::from_u128(u128::MAX) + Fr::ONE;
+        okm.chunks_exact(16).fold(Fr::ZERO, |acc, chunk| {
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(chunk);
+            acc * shift + Fr::from_u128(u128::from_be_bytes(bytes))
+        })
+    }
+
+    pub fn derive_salt(&self, id: &str) -> Salt {
+        Salt(self.derive(id))
+    }
+}
+
+impl fmt::Debug for SaltDeriver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SaltDeriver")
+            .field("context", &self.context)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "random")]
+impl Fr {
+    //Uniform below the modulus: 255 random bits, redrawn until they are in range
+    pub fn random<R: RngCore + CryptoRng>(rng: &mut R) -> Fr {
+        loop {
+            let mut repr = FrRepr::default();
+            rng.fill_bytes(repr.as_mut());
+            if let Some(top) = repr.as_mut().last_mut() {
+                *top &= 0x7f;
+            }
+            if let Some(fr) = Option::from(Fr::from_repr(repr)) {
+                return fr;
+            }
+        }
+    }
+}
+
+impl MerkleSumTree {
+    //Tree over the pairs, each leaf hashed with the salt at its position
+    pub fn with_salts(
+        pairs: Vec<(String, i32)>,
+        salts: &[Salt],
+    ) -> Result<MerkleSumTree, MerkleError> {
+        if salts.len() != pairs.len() {
+            return Err(MerkleError::InvalidLength {
+                expected: pairs.len(),
+                actual: salts.len(),
+            });
+        }
+        let leafs = pairs
+            .into_iter()
+            .zip(salts)
+            .map(|((id, value), salt)| salt.leaf(id, value))
+            .collect();
+        Self::create_tree(
+            leafs,
+            MAX_HEIGHT,
+            None,
+            BTreeSet::new(),
+            EmptyLeafSpec::default(),
+            LeafHashMode::IdOnly,
+            DEFAULT_EXPONENT,
+            &ProgressSink::default(),
+            &CancellationToken::default(),
+        )
+    }
+
+    //Salt every pair with the salt deriver gives its id, reproducible from the seed
+    pub fn with_derived_salts(
+        pairs: Vec<(String, i32)>,
+        deriver: &SaltDeriver,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        let salts: Vec<Salt> = pairs
+            .iter()
+            .map(|(id, _)| deriver.derive_salt(id))
+            .collect();
+        Self::with_salts(pairs, &salts)
+    }
+
+    //Salt every pair with a fresh draw from rng, return the salts in pair order
+    #[cfg(feature = "random")]
+    pub fn with_random_salts<R: RngCore + CryptoRng>(
+        pairs: Vec<(String, i32)>,
+        rng: &mut R,
+    ) -> Result<(MerkleSumTree, Vec<Salt>), MerkleError> {
+        let salts: Vec<Salt> = pairs.iter().map(|_| Salt::generate(rng)).collect();
+        Ok((Self::with_salts(pairs, &salts)?, salts))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn salted_leafs_hide_the_id_hash() {
+        let salt = Salt::new(Fr::from(7u64));
+        let leaf = salt.leaf("alice".to_string(), 10);
+        assert!(salt.matches(&leaf));
+        assert_ne!(leaf, Leaf::new("alice".to_string(), 10));
+        //Same inputs as an IdAndValue leaf, another key
+        assert_ne!(
+            leaf.get_node().get_hash(),
+            LeafHashMode::IdAndValue
+                .leaf("alice".to_string(), 7)
+                .get_node()
+                .get_hash()
+        );
+        assert!(!Salt::new(Fr::from(8u64)).matches(&leaf));
+        assert_eq!(leaf.get_node().get_value(), 10);
+        assert_eq!(
+            DummyId::new("00000000000000000000000000000001".to_string()).leaf(),
+            Leaf::new("00000000000000000000000000000001".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn derived_salts_are_reproducible() {
+        let deriver = SaltDeriver::new([42; 32]);
+        assert_eq!(
+            deriver.derive("alice").to_string(),
+            "25146841532453066292214650081055399327719786444527178658231811155751276657749"
+        );
+        let epoch = deriver.clone().context(b"epoch-1".to_vec());
+        assert_eq!(
+            epoch.derive("alice").to_string(),
+            "17198104034885901933710001752143037281756744336615420120247196835324272329290"
+        );
+        assert_ne!(epoch.derive("alice"), epoch.derive("bob"));
+        assert!(!format!("{:?}", deriver).contains("42"));
+
+        let pairs = vec![
+            ("alice".to_string(), 10),
+            ("bob".to_string(), 20),
+            ("carol".to_string(), 30),
+        ];
+        for (deriver, root) in [
+            (
+                &deriver,
+                "21059208990863101845858295546042202002173030018655252252072180123886415716987",
+            ),
+            (
+                &epoch,
+                "7446378293213058597105241979079388963707464364099326350308392207008724630253",
+            ),
+        ] {
+            let tree = MerkleSumTree::with_derived_salts(pairs.clone(), deriver).unwrap();
+            assert_eq!(tree.get_root_hash().unwrap().to_string(), root);
+            let proof = tree.get_proof(1).unwrap().unwrap();
+            assert!(deriver.derive_salt("bob").matches(&proof.get_leaf()));
+        }
+    }
+
+    #[cfg(feature = "random")]
+    #[test]
+    fn seeded_draws_pin_the_salted_root() {
+        use rand_chacha::rand_core::SeedableRng;
+        use rand_chacha::ChaCha20Rng;
+
+        let rng = || ChaCha20Rng::from_seed([7; 32]);
+        //The first two draws are above the modulus and redrawn
+        assert_eq!(
+            Fr::random(&mut rng()).to_string(),
+            "3823993545355025941115966249936063477805079600731244460388331744881956979767"
+        );
+        assert_eq!(
+            DummyId::generate(&mut rng()).get_id(),
+            "789200f441f6aa57ba61f5147079b3ac"
+        );
+
+        let pairs = vec![
+            ("alice".to_string(), 10),
+            ("bob".to_string(), 20),
+            ("carol".to_string(), 30),
+        ];
+        let (tree, salts) = MerkleSumTree::with_random_salts(pairs.clone(), &mut rng()).unwrap();
+        assert_eq!(
+            tree.get_root_hash().unwrap().to_string(),
+            "5989335363128376427419739759168297556034463746422330283073565794280210197140"
+        );
+        assert_eq!(tree.get_root_sum(), Some(60));
+        for (index, salt) in salts.iter().enumerate() {
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            assert!(salt.matches(&proof.get_leaf()));
+            assert!(tree.verify_proof(proof).unwrap());
+        }
+        let (again, _) = MerkleSumTree::with_random_salts(pairs, &mut rng()).unwrap();
+        assert_eq!(again.get_root(), tree.get_root());
+    }
+}
@@ -0,0 +1,113 @@
+//The leaf held by unoccupied slots
+
+use crate::Leaf;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EmptyLeafSpec {
+    id: String,
+    value: i32,
+}
+
+impl Default for EmptyLeafSpec {
+    fn default() -> Self {
+        EmptyLeafSpec {
+            id: "0".to_string(),
+            value: 0,
+        }
+    }
+}
+
+impl EmptyLeafSpec {
+    pub fn new(id: String, value: i32) -> EmptyLeafSpec {
+        EmptyLeafSpec { id, value }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn get_value(&self) -> i32 {
+        self.value
+    }
+
+    //The placeholder leaf, hashed like any other
+    pub fn get_leaf(&self) -> Leaf {
+        Leaf::new(self.id.clone(), self.value)
+    }
+
+    pub fn matches(&self, leaf: &Leaf) -> bool {
+        *leaf.id == *self.id && leaf.node.value == self.value
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing, deprecated)]
+mod tests {
+    use super::*;
+    use crate::{MerkleError, MerkleSumTree};
+
+    fn spec() -> EmptyLeafSpec {
+        EmptyLeafSpec::new("<empty>".to_string(), 0)
+    }
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::builder()
+            .empty_leaf(spec())
+            .build(vec![
+                Leaf::new("alice".to_string(), 10),
+                Leaf::new("0".to_string(), 20),
+                Leaf::new("carol".to_string(), 30),
+            ])
+            .unwrap()
+    }
+
+    #[test]
+    fn custom_sentinels_pad_and_free_slots() {
+        let mut tree = tree();
+        assert_eq!(tree.get_empty_leaf(), spec());
+        assert_eq!(tree.get_leaf(3).unwrap(), spec().get_leaf());
+        assert_eq!(tree.get_zero_index(), vec![3]);
+        //"0" is an ordinary account here
+        assert_eq!(tree.get_index_by_id("0"), Some(1));
+        assert!(tree.get_proof(1).unwrap().is_some());
+        assert!(matches!(tree.get_proof(3), Err(MerkleError::EmptySlot(3))));
+
+        tree.remove(0).unwrap();
+        assert_eq!(tree.get_leaf(0).unwrap(), spec().get_leaf());
+        assert_eq!(tree.get_zero_index(), vec![0, 3]);
+        assert_eq!(tree.push(Leaf::new("dave".to_string(), 5)).unwrap(), 0);
+        assert_eq!(tree.non_empty_leafs(false).count(), 3);
+        assert_eq!(tree.validate(), Ok(()));
+
+        //Growth pads with the same sentinel
+        tree.push(Leaf::new("erin".to_string(), 1)).unwrap();
+        tree.push(Leaf::new("frank".to_string(), 2)).unwrap();
+        assert_eq!(tree.get_leaf(7).unwrap(), spec().get_leaf());
+        assert_eq!(tree.get_zero_index(), vec![5, 6, 7]);
+        assert!(tree.empty_subtrees()[11]);
+    }
+
+    #[test]
+    fn custom_sentinels_survive_serialization_and_proofs() {
+        let tree = tree();
+        assert_ne!(
+            tree.get_root(),
+            MerkleSumTree::new(tree.get_leafs()[..3].to_vec())
+                .unwrap()
+                .get_root()
+        );
+        let json = serde_json::to_string(&tree).unwrap();
+        let restored: MerkleSumTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_empty_leaf(), spec());
+        assert_eq!(restored.get_zero_index(), vec![3]);
+        assert_eq!(restored.get_index_by_id("0"), Some(1));
+
+        let proof = tree.get_proof_allow_empty(3).unwrap().unwrap();
+        assert!(tree.verify_proof(proof.clone()).unwrap());
+        assert!(spec().matches(&proof.get_leaf()));
+        assert!(restored
+            .verify_proof(tree.get_proof(1).unwrap().unwrap())
+            .unwrap());
+    }
+}
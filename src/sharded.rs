@@ -0,0 +1,339 @@
+//Two-level trees for datasets built shard by shard
+
+use crate::layout::{level_count, level_size, level_start};
+use crate::mimc_sponge::DEFAULT_EXPONENT;
+use crate::progress::{finish_all, PhaseTracker, ProgressSink};
+use crate::{
+    CancellationToken, EmptyLeafSpec, HashParams, InclusionProof, Leaf, MerkleError, MerkleSumTree,
+    Neighbor, Node, RootCommitment, MAX_HEIGHT,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone)]
+pub struct TopTree {
+    tree: MerkleSumTree,
+    shard_count: usize,
+}
+
+//A shard proof spliced onto the top proof of its shard root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompositeProof {
+    shard_proof: InclusionProof,
+    top_proof: InclusionProof,
+}
+
+impl TopTree {
+    pub fn from_shard_roots(roots: Vec<Node>) -> Result<TopTree, MerkleError> {
+        if roots.is_empty() {
+            return Err(MerkleError::EmptyTree);
+        }
+        let shard_count = roots.len();
+        let leafs = roots
+            .into_iter()
+            .enumerate()
+            .map(|(shard, node)| Leaf {
+                id: format!("shard{}", shard).into(),
+                node,
+            })
+            .collect();
+        Ok(TopTree {
+            tree: MerkleSumTree::new(leafs)?,
+            shard_count,
+        })
+    }
+
+    pub fn get_tree(&self) -> &MerkleSumTree {
+        &self.tree
+    }
+
+    pub fn get_shard_count(&self) -> usize {
+        self.shard_count
+    }
+
+    pub fn get_root(&self) -> Option<Node> {
+        self.tree.get_root()
+    }
+
+    pub fn get_commitment(&self) -> Option<RootCommitment> {
+        self.tree.get_commitment()
+    }
+
+    //Proof of shard's root in the top tree
+    pub fn get_proof(&self, shard: usize) -> Result<InclusionProof, MerkleError> {
+        if shard >= self.shard_count {
+            return Err(MerkleError::IndexOutOfBounds(shard));
+        }
+        self.tree
+            .get_proof_allow_empty(shard)?
+            .ok_or(MerkleError::IndexOutOfBounds(shard))
+    }
+
+    pub fn compose_proof(shard_proof: InclusionProof, top_proof: InclusionProof) -> CompositeProof {
+        CompositeProof {
+            shard_proof,
+            top_proof,
+        }
+    }
+}
+
+impl CompositeProof {
+    pub fn get_shard_proof(&self) -> InclusionProof {
+        self.shard_proof.clone()
+    }
+
+    pub fn get_top_proof(&self) -> InclusionProof {
+        self.top_proof.clone()
+    }
+
+    pub fn get_leaf(&self) -> Leaf {
+        self.shard_proof.get_leaf()
+    }
+
+    //Shard path then top path, leaf to root
+    pub fn get_path(&self) -> Vec<Neighbor> {
+        let mut path = self.shard_proof.get_path();
+        path.extend(self.top_proof.get_path());
+        path
+    }
+
+    //The shard proof must fold to the top leaf, hash and sum, and the top proof to the root
+    pub fn verify(&self, commitment: &RootCommitment) -> Result<bool, MerkleError> {
+        let shard_root = self.shard_proof.compute_root()?;
+        if shard_root != self.top_proof.get_leaf().get_node() {
+            return Ok(false);
+        }
+        Ok(commitment.matches(&self.top_proof.compute_root()?))
+    }
+}
+
+impl MerkleSumTree {
+    //Same tree as new, with the leaf layer hashed in up to shards aligned chunks
+    pub fn build_sharded(leafs: Vec<Leaf>, shards: usize) -> Result<MerkleSumTree, MerkleError> {
+        let empty = EmptyLeafSpec::default();
+        let (leafs, height) = Self::fill_leafs(leafs, MAX_HEIGHT, None, &empty)?;
+        let (nodes, _) = stitched_nodes(
+            &leafs,
+            shards,
+            DEFAULT_EXPONENT,
+            &ProgressSink::default(),
+            &CancellationToken::default(),
+        )?;
+        Ok(Self::assemble(
+            leafs,
+            nodes,
+            height,
+            MAX_HEIGHT,
+            None,
+            BTreeSet::new(),
+            empty,
+        ))
+    }
+}
+
+//Every node over a power-of-two leaf layer, the layer hashed in aligned chunks, and
+//the chunk size. Chunks advance the level counts of the whole tree together, and
+//all stop at their next chunk boundary once cancel is set
+pub(crate) fn stitched_nodes(
+    leafs: &[Leaf],
+    shards: usize,
+    exponent: u64,
+    progress: &ProgressSink,
+    cancel: &CancellationToken,
+) -> Result<(Vec<Node>, usize), MerkleError> {
+    let params = HashParams::mimc(exponent)?;
+    let leaf_count = leafs.len();
+    //Largest power of two not above shards, so every chunk is a whole subtree
+    let chunk_count = match shards {
+        0 => 1,
+        shards => 1usize << (usize::BITS - 1 - shards.leading_zeros()),
+    }
+    .min(leaf_count)
+    .max(1);
+    let chunk_size = leaf_count / chunk_count;
+    let levels = progress.levels(leaf_count);
+    let chunk_levels = level_count(chunk_size);
+    let chunks = build_chunks(
+        leafs.chunks(chunk_size.max(1)).collect(),
+        &params,
+        &levels,
+        cancel,
+    )?;
+
+    let mut nodes = Vec::with_capacity((2 * leaf_count).saturating_sub(1));
+    for level in 0..chunk_levels {
+        let start = level_start(chunk_size, level);
+        let size = level_size(chunk_size, level);
+        for chunk in &chunks {
+            let level_nodes = chunk
+                .get(start..start + size)
+                .ok_or(MerkleError::MissingNode(start))?;
+            nodes.extend_from_slice(level_nodes);
+        }
+    }
+    let roots = chunks
+        .iter()
+        .map(|chunk| chunk.last().cloned().ok_or(MerkleError::EmptyTree))
+        .collect::<Result<Vec<Node>, MerkleError>>()?;
+    //The levels above the chunk roots
+    let top = levels.get(chunk_levels.saturating_sub(1)..).unwrap_or(&[]);
+    nodes.extend(
+        MerkleSumTree::build_levels(roots, &params, top, cancel)?
+            .into_iter()
+            .skip(chunk_count),
+    );
+    finish_all(&levels);
+    Ok((nodes, chunk_size))
+}
+
+#[cfg(feature = "parallel")]
+fn build_chunks(
+    chunks: Vec<&[Leaf]>,
+    params: &HashParams,
+    levels: &[PhaseTracker],
+    cancel: &CancellationToken,
+) -> Result<Vec<Vec<Node>>, MerkleError> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                scope.spawn(move || MerkleSumTree::build_nodes(chunk, params, levels, cancel))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
+
+#[cfg(not(feature = "parallel"))]
+fn build_chunks(
+    chunks: Vec<&[Leaf]>,
+    params: &HashParams,
+    levels: &[PhaseTracker],
+    cancel: &CancellationToken,
+) -> Result<Vec<Vec<Node>>, MerkleError> {
+    chunks
+        .into_iter()
+        .map(|chunk| MerkleSumTree::build_nodes(chunk, params, levels, cancel))
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing, deprecated)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+
+    fn shards() -> Vec<MerkleSumTree> {
+        vec![
+            MerkleSumTree::new(vec![
+                Leaf::new("alice".to_string(), 10),
+                Leaf::new("bob".to_string(), 20),
+                Leaf::new("carol".to_string(), 30),
+            ])
+            .unwrap(),
+            MerkleSumTree::new(vec![Leaf::new("dave".to_string(), -5)]).unwrap(),
+            MerkleSumTree::new(vec![
+                Leaf::new("erin".to_string(), 7),
+                Leaf::new("frank".to_string(), 8),
+            ])
+            .unwrap(),
+        ]
+    }
+
+    #[test]
+    fn composite_proofs_verify_across_shards() {
+        let shards = shards();
+        let top = TopTree::from_shard_roots(
+            shards
+                .iter()
+                .map(|shard| shard.get_root().unwrap())
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(top.get_shard_count(), 3);
+        assert_eq!(top.get_root().unwrap().get_value(), 70);
+        let commitment = top.get_commitment().unwrap();
+
+        for (shard, index) in [(0, 2), (1, 0), (2, 1)] {
+            let shard_proof = shards[shard].get_proof(index).unwrap().unwrap();
+            let proof = TopTree::compose_proof(shard_proof.clone(), top.get_proof(shard).unwrap());
+            assert!(proof.verify(&commitment).unwrap());
+            assert_eq!(proof.get_leaf(), shards[shard].get_leaf(index).unwrap());
+            assert_eq!(
+                proof.get_path().len(),
+                shard_proof.get_path().len() + top.get_tree().get_height() - 1
+            );
+
+            let json = serde_json::to_string(&proof).unwrap();
+            let restored: CompositeProof = serde_json::from_str(&json).unwrap();
+            assert!(restored.verify(&commitment).unwrap());
+        }
+        assert!(top.get_proof(3).is_err());
+    }
+
+    #[test]
+    fn sharded_builds_match_sequential_ones() {
+        for size in [1, 2, 3, 8, 13, 100] {
+            let leafs = users(size, 7, 300);
+            let sequential = MerkleSumTree::new(leafs.clone()).unwrap();
+            for shards in [0, 1, 2, 3, 4, 16, 1000] {
+                let sharded = MerkleSumTree::build_sharded(leafs.clone(), shards).unwrap();
+                assert_eq!(sharded.get_nodes(), sequential.get_nodes());
+                assert_eq!(sharded.get_leafs(), sequential.get_leafs());
+                assert_eq!(sharded.get_zero_index(), sequential.get_zero_index());
+                assert_eq!(sharded.get_height(), sequential.get_height());
+                assert_eq!(sharded.validate(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn mismatched_splices_are_rejected() {
+        let shards = shards();
+        let top = TopTree::from_shard_roots(
+            shards
+                .iter()
+                .map(|shard| shard.get_root().unwrap())
+                .collect(),
+        )
+        .unwrap();
+        let commitment = top.get_commitment().unwrap();
+
+        //A shard proof spliced onto another shard's top proof
+        let wrong_shard = TopTree::compose_proof(
+            shards[0].get_proof(0).unwrap().unwrap(),
+            top.get_proof(2).unwrap(),
+        );
+        assert!(!wrong_shard.verify(&commitment).unwrap());
+
+        //Altered shard balances no longer fold to the committed shard root
+        let mut altered = shards[1].clone();
+        altered
+            .set_leaf(Leaf::new("dave".to_string(), 5), 0)
+            .unwrap();
+        let forged = TopTree::compose_proof(
+            altered.get_proof(0).unwrap().unwrap(),
+            top.get_proof(1).unwrap(),
+        );
+        assert!(!forged.verify(&commitment).unwrap());
+
+        let other_top = TopTree::from_shard_roots(vec![shards[0].get_root().unwrap()]).unwrap();
+        let proof = TopTree::compose_proof(
+            shards[0].get_proof(0).unwrap().unwrap(),
+            other_top.get_proof(0).unwrap(),
+        );
+        assert!(proof.verify(&other_top.get_commitment().unwrap()).unwrap());
+        assert!(!proof.verify(&commitment).unwrap());
+        assert!(matches!(
+            TopTree::from_shard_roots(vec![]),
+            Err(MerkleError::EmptyTree)
+        ));
+    }
+}
@@ -0,0 +1,336 @@
+//Size accounting for capacity planning
+
+use crate::binary::binary_size;
+use crate::mimc_sponge::DEFAULT_EXPONENT;
+use crate::{Fr, InclusionProof, Leaf, MerkleSumTree, Neighbor, Node, Position};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::mem::size_of;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProofFormat {
+    //serde_json::to_string, the form every JSON export in the crate uses
+    #[default]
+    Json,
+    //InclusionProof::to_bytes
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryReport {
+    leafs: usize,
+    leaf_ids: usize,
+    nodes: usize,
+    indexes: usize,
+    caches: usize,
+}
+
+impl InclusionProof {
+    pub fn encoded_size(&self, format: ProofFormat) -> usize {
+        match format {
+            ProofFormat::Json => {
+                let path: usize = self.path.iter().map(json_neighbor).sum();
+                let separators = self.path.len().saturating_sub(1);
+                let root = self.root.as_ref().map_or(0, |root| 8 + json_node(root));
+                let epoch = self.epoch.map_or(0, |epoch| 9 + epoch.to_string().len());
+                let exponent = match self.exponent {
+                    DEFAULT_EXPONENT => 0,
+                    exponent => 12 + exponent.to_string().len(),
+                };
+                let head = 8 + json_leaf(&self.leaf) + 9 + self.index.to_string().len();
+                //{"leaf":…,"index":…,"path":[…]…}
+                head + 8 + 2 + path + separators + root + epoch + exponent + 1
+            }
+            ProofFormat::Binary => binary_size(self.leaf.get_id_str().len(), self.path.len()),
+        }
+    }
+}
+
+impl MerkleSumTree {
+    pub fn memory_usage(&self) -> MemoryReport {
+        MemoryReport {
+            leafs: self.leafs.capacity() * size_of::<Leaf>(),
+            leaf_ids: shared_bytes(self.leafs.iter().map(|leaf| &leaf.id)),
+            nodes: self.nodes.capacity() * size_of::<Node>(),
+            indexes: self.zero_index.capacity() * size_of::<usize>()
+                + set_bytes(&self.retired)
+                + set_bytes(&self.dummies),
+            //The id index is derived from the leafs and rebuilt on load, its keys are
+            //the leafs' own strings
+            caches: self.ids.capacity() * (size_of::<Arc<str>>() + size_of::<BTreeSet<usize>>())
+                + self.ids.values().map(set_bytes).sum::<usize>()
+                + self
+                    .plain
+                    .as_ref()
+                    .map_or(0, |plain| plain.capacity() * size_of::<Fr>())
+                + self
+                    .keccak
+                    .as_ref()
+                    .map_or(0, |keccak| keccak.capacity() * size_of::<[u8; 32]>()),
+        }
+    }
+
+    //Trim every internal collection to its length; equal ids written apart share a string again
+    pub fn shrink_to_fit(&mut self) {
+        self.ids = Self::index_ids(&mut self.leafs, &self.dummies, &self.empty);
+        self.trim_buffers();
+    }
+
+    //Release the spare capacity of the flat buffers, after bulk operations
+    pub(crate) fn trim_buffers(&mut self) {
+        self.leafs.shrink_to_fit();
+        self.nodes.shrink_to_fit();
+        self.zero_index.shrink_to_fit();
+        self.ids.shrink_to_fit();
+        if let Some(plain) = self.plain.as_mut() {
+            plain.shrink_to_fit();
+        }
+        if let Some(keccak) = self.keccak.as_mut() {
+            keccak.shrink_to_fit();
+        }
+    }
+}
+
+impl MemoryReport {
+    //The Vec<Leaf> buffer, id strings excluded
+    pub fn get_leafs(&self) -> usize {
+        self.leafs
+    }
+
+    pub fn get_leaf_ids(&self) -> usize {
+        self.leaf_ids
+    }
+
+    pub fn get_nodes(&self) -> usize {
+        self.nodes
+    }
+
+    //Zero index, retired slots and dummy slots
+    pub fn get_indexes(&self) -> usize {
+        self.indexes
+    }
+
+    //Id index
+    pub fn get_caches(&self) -> usize {
+        self.caches
+    }
+
+    pub fn get_total(&self) -> usize {
+        self.leafs + self.leaf_ids + self.nodes + self.indexes + self.caches
+    }
+}
+
+fn set_bytes(set: &BTreeSet<usize>) -> usize {
+    set.len() * size_of::<usize>()
+}
+
+//Each distinct string once, with the strong and weak counts in front of it
+fn shared_bytes<'a>(ids: impl Iterator<Item = &'a Arc<str>>) -> usize {
+    let mut seen = HashSet::new();
+    ids.filter(|id| seen.insert(Arc::as_ptr(id) as *const u8))
+        .map(|id| 2 * size_of::<usize>() + id.len())
+        .sum()
+}
+
+//Quoted, with serde_json's escapes
+fn json_str(s: &str) -> usize {
+    2 + s
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | '\n' | '\r' | '\t' | '\u{8}' | '\u{c}' => 2,
+            c if (c as u32) < 0x20 => 6,
+            c => c.len_utf8(),
+        })
+        .sum::<usize>()
+}
+
+fn json_fr(fr: &Fr) -> usize {
+    2 + fr.to_string().len()
+}
+
+//{"hash":…,"value":…}
+fn json_node(node: &Node) -> usize {
+    8 + json_fr(&node.hash) + 9 + node.value.to_string().len() + 1
+}
+
+//{"id":…,"node":…}
+fn json_leaf(leaf: &Leaf) -> usize {
+    6 + json_str(&leaf.id) + 8 + json_node(&leaf.node) + 1
+}
+
+//{"position":…,"node":…}
+fn json_neighbor(neighbor: &Neighbor) -> usize {
+    let position = match neighbor.position {
+        Position::Left => 6,
+        Position::Right => 7,
+    };
+    12 + position + 8 + json_node(&neighbor.node) + 1
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_sizes_match_serialization() {
+        for count in [1, 2, 3, 8, 33, 200] {
+            let leafs = (0..count)
+                .map(|i| match i % 4 {
+                    0 => Leaf::new(format!("user{}", i), i * 1000),
+                    1 => Leaf::new(format!("quote\"back\\slash{}", i), -i),
+                    2 => Leaf::new(format!("ctl\n\t\u{1}{}", i), -(1 << 24)),
+                    _ => Leaf::new(format!("ünï{}", i), i32::MAX / 300),
+                })
+                .collect();
+            let mut tree = MerkleSumTree::new(leafs).unwrap();
+            for index in [0, count as usize / 2, count as usize - 1] {
+                let proof = tree.get_proof(index).unwrap().unwrap();
+                assert_eq!(
+                    proof.encoded_size(ProofFormat::Json),
+                    serde_json::to_string(&proof).unwrap().len(),
+                    "height {} index {}",
+                    tree.get_height(),
+                    index
+                );
+                assert_eq!(
+                    proof.encoded_size(ProofFormat::Binary),
+                    proof.to_bytes().unwrap().len()
+                );
+            }
+            tree.push(Leaf::new("late".to_string(), 1)).unwrap();
+            let bound = tree.get_bound_proof(0).unwrap();
+            assert_eq!(
+                bound.encoded_size(ProofFormat::Json),
+                serde_json::to_string(&bound).unwrap().len()
+            );
+        }
+        let seven = MerkleSumTree::builder()
+            .mimc_exponent(7)
+            .build(vec![Leaf::new("alice".to_string(), 1)])
+            .unwrap();
+        let proof = seven.get_proof(0).unwrap().unwrap();
+        assert_eq!(
+            proof.encoded_size(ProofFormat::default()),
+            serde_json::to_string(&proof).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn memory_usage_grows_with_the_tree() {
+        let mut tree = MerkleSumTree::new(vec![Leaf::new("alice".to_string(), 1)]).unwrap();
+        let before = tree.memory_usage();
+        assert!(before.get_leaf_ids() >= 5);
+        for i in 0..100 {
+            tree.push(Leaf::new(format!("account-{:04}", i), i))
+                .unwrap();
+        }
+        let after = tree.memory_usage();
+        assert!(after.get_leafs() >= 128 * size_of::<Leaf>());
+        assert!(after.get_nodes() >= 255 * size_of::<Node>());
+        assert!(after.get_leaf_ids() >= before.get_leaf_ids() + 100 * 12);
+        assert!(after.get_caches() > before.get_caches());
+        assert!(after.get_indexes() >= 27 * size_of::<usize>());
+        assert_eq!(
+            after.get_total(),
+            after.get_leafs()
+                + after.get_leaf_ids()
+                + after.get_nodes()
+                + after.get_indexes()
+                + after.get_caches()
+        );
+        let json = serde_json::to_string(&after).unwrap();
+        assert!(json.contains("\"leaf_ids\""));
+        assert_eq!(serde_json::from_str::<MemoryReport>(&json).unwrap(), after);
+    }
+
+    #[test]
+    fn ids_are_stored_once_however_often_they_are_held() {
+        //4096 accounts split over four slots each, then 3000 slots of padding
+        let id = |i: usize| format!("account-{:05}", i % 4096);
+        let leafs: Vec<Leaf> = (0..13384).map(|i| Leaf::new(id(i), 1)).collect();
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        assert_eq!(tree.leafs.len(), 1 << 14);
+        let header = 2 * size_of::<usize>();
+        let usage = tree.memory_usage();
+        assert_eq!(usage.get_leaf_ids(), 4096 * (header + 13) + header + 1);
+
+        //A String per slot and per index key held nearly twice the bytes
+        let owned: usize = tree.leafs.iter().map(|leaf| leaf.id.len()).sum::<usize>()
+            + tree.ids.keys().map(|id| id.len()).sum::<usize>();
+        assert!(2 * owned > 3 * usage.get_leaf_ids());
+
+        //Writes share the indexed string, proofs share the leaf's
+        tree.set_leaf(Leaf::new(id(7), 2), 0).unwrap();
+        let shared = Arc::clone(tree.ids.get_key_value("account-00007").unwrap().0);
+        assert!(Arc::ptr_eq(&tree.leafs[0].id, &shared));
+        let proof = tree.get_proof(0).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&proof.leaf.id, &shared));
+        assert_eq!(tree.memory_usage().get_leaf_ids(), usage.get_leaf_ids());
+
+        //Serialized ids stay plain strings, and a loaded tree shares them again
+        let json = serde_json::to_string(&tree.leafs[0]).unwrap();
+        assert!(json.starts_with("{\"id\":\"account-00007\",\"node\":"));
+        let loaded: MerkleSumTree =
+            serde_json::from_str(&serde_json::to_string(&tree).unwrap()).unwrap();
+        assert_eq!(loaded.memory_usage().get_leaf_ids(), usage.get_leaf_ids());
+        assert_eq!(
+            loaded.get_indices_by_id("account-00007"),
+            vec![0, 7, 4103, 8199, 12295]
+        );
+    }
+
+    #[test]
+    fn shrink_releases_capacity_after_grow_then_remove() {
+        let mut tree = MerkleSumTree::builder()
+            .plain_root(true)
+            .build(vec![Leaf::new("alice".to_string(), 1)])
+            .unwrap();
+        let accounts: Vec<usize> = (0..100)
+            .map(|i| {
+                tree.push(Leaf::new(format!("account-{:04}", i), i))
+                    .unwrap()
+            })
+            .collect();
+        //The growth rebuilds leave no spare node capacity behind
+        assert_eq!(tree.nodes.capacity(), tree.nodes.len());
+
+        //Removals widen the zero index, pushes narrow it again
+        tree.remove_many(&accounts).unwrap();
+        for i in 0..100 {
+            tree.push(Leaf::new(format!("account-{:04}", i), i))
+                .unwrap();
+        }
+        let grown = tree.memory_usage();
+        let root = tree.get_commitment();
+        tree.shrink_to_fit();
+        let shrunk = tree.memory_usage();
+        assert!(shrunk.get_indexes() < grown.get_indexes());
+        assert_eq!(tree.zero_index.capacity(), tree.zero_index.len());
+        assert_eq!(tree.get_commitment(), root);
+
+        //And the id index after the accounts leave for good
+        tree.remove_many(&accounts).unwrap();
+        let removed = tree.memory_usage();
+        tree.shrink_to_fit();
+        let trimmed = tree.memory_usage();
+        assert!(trimmed.get_caches() < removed.get_caches());
+        assert!(trimmed.get_total() < removed.get_total());
+
+        //Nothing observable changes
+        assert_eq!(tree.get_index_by_id("alice"), Some(0));
+        assert_eq!(tree.get_index_by_id("account-0000"), None);
+        assert_eq!(tree.validate(), Ok(()));
+        tree.push(Leaf::new("late".to_string(), 5)).unwrap();
+        assert_eq!(tree.get_root_sum(), Some(6));
+        let mut fresh = tree.clone();
+        fresh.set_plain_root(true);
+        assert_eq!(tree.get_plain_root(), fresh.get_plain_root());
+
+        tree.rebuild().unwrap();
+        tree.defragment().unwrap();
+        assert_eq!(tree.nodes.capacity(), tree.nodes.len());
+        assert_eq!(tree.leafs.capacity(), tree.leafs.len());
+    }
+}
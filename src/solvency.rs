@@ -0,0 +1,74 @@
+//Proof-of-solvency names over the por workflow
+
+use crate::por::{LiabilitySnapshot, SnapshotOptions};
+use crate::{MerkleError, MerkleSumTree};
+
+pub use crate::por::{Publication as LiabilityCommitment, UserPackage as UserStatement};
+
+//Build the tree over (id, balance) entries and the commitment to publish, ids must be
+//unique and balances not negative
+pub fn build_commitment(
+    entries: &[(String, i32)],
+) -> Result<(MerkleSumTree, LiabilityCommitment), MerkleError> {
+    let snapshot = LiabilitySnapshot::build(entries.to_vec(), SnapshotOptions::default())?;
+    Ok((snapshot.get_tree(), snapshot.get_publication()))
+}
+
+//Statement of id's balance in a tree from build_commitment
+pub fn user_statement(
+    tree: &MerkleSumTree,
+    commitment: &LiabilityCommitment,
+    id: &str,
+) -> Result<UserStatement, MerkleError> {
+    UserStatement::for_id(tree, commitment, id)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_user_and_auditor_agree() {
+        let entries = vec![
+            ("alice".to_string(), 100),
+            ("bob".to_string(), 250),
+            ("carol".to_string(), 0),
+        ];
+        //Exchange side
+        let (tree, commitment) = build_commitment(&entries).unwrap();
+        assert_eq!(commitment.get_root_sum(), 350);
+        assert_eq!(commitment.get_leaf_count(), 4);
+        let statements: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|(id, _)| {
+                user_statement(&tree, &commitment, id)
+                    .unwrap()
+                    .to_bytes()
+                    .unwrap()
+            })
+            .collect();
+
+        //User side
+        for ((id, value), bytes) in entries.iter().zip(&statements) {
+            let statement = UserStatement::from_bytes(bytes).unwrap();
+            assert!(statement.verify(id, *value).unwrap());
+            assert!(!statement.verify(id, value + 1).unwrap());
+            assert_eq!(statement.get_publication(), commitment);
+        }
+
+        //Auditor side
+        assert!(tree.validate().is_ok());
+        assert!(commitment
+            .get_commitment()
+            .matches(&tree.get_root().unwrap()));
+        assert!(matches!(
+            user_statement(&tree, &commitment, "mallory"),
+            Err(MerkleError::UnknownId(_))
+        ));
+        assert!(matches!(
+            build_commitment(&[("mallory".to_string(), -1)]),
+            Err(MerkleError::NegativeValue { .. })
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+//Accounts split across several leafs so no single leaf reveals the balance
+
+use crate::{InclusionProof, Leaf, MerkleError, MerkleSumTree, Node};
+use rand::Rng;
+
+impl MerkleSumTree {
+    //Insert value as shares random parts, return their indices in share order
+    pub fn split_insert<R: Rng>(
+        &mut self,
+        id: &str,
+        value: i64,
+        shares: usize,
+        rng: &mut R,
+    ) -> Result<Vec<usize>, MerkleError> {
+        if !self.split_indices(id).is_empty() {
+            return Err(MerkleError::DuplicateId(id.to_string()));
+        }
+        let parts = split_value(value, shares, rng)?;
+        let mut staged = self.staged();
+        let mut indices = vec![];
+        for (share, part) in parts.into_iter().enumerate() {
+            indices.push(staged.push(Leaf::new(share_id(id, share), part))?);
+        }
+        self.commit_staged(staged, &indices)?;
+        Ok(indices)
+    }
+
+    //Redistribute a new balance over the existing shares, keeping their slots
+    pub fn split_update<R: Rng>(
+        &mut self,
+        id: &str,
+        value: i64,
+        rng: &mut R,
+    ) -> Result<Vec<usize>, MerkleError> {
+        let indices = self.split_indices(id);
+        if indices.is_empty() {
+            return Err(MerkleError::UnknownId(id.to_string()));
+        }
+        let parts = split_value(value, indices.len(), rng)?;
+        let mut updates: Vec<(usize, usize, i32)> = indices
+            .iter()
+            .zip(parts)
+            .enumerate()
+            .map(|(share, (index, part))| (share, *index, part))
+            .collect();
+        //Shrink shares before growing others so intermediate sums stay in range
+        updates.sort_by_key(|(_, index, part)| {
+            let current = self.leafs.get(*index).map_or(0, |leaf| leaf.node.value);
+            i64::from(*part) - i64::from(current)
+        });
+        let mut staged = self.staged();
+        for (share, index, part) in updates {
+            staged.set_leaf(Leaf::new(share_id(id, share), part), index)?;
+        }
+        self.commit_staged(staged, &indices)?;
+        Ok(indices)
+    }
+
+    //Remove every share of a split account, return the freed indices
+    pub fn split_remove(&mut self, id: &str) -> Result<Vec<usize>, MerkleError> {
+        let indices = self.split_indices(id);
+        if indices.is_empty() {
+            return Err(MerkleError::UnknownId(id.to_string()));
+        }
+        let mut staged = self.staged();
+        for index in &indices {
+            staged.remove(*index)?;
+        }
+        self.commit_staged(staged, &indices)?;
+        Ok(indices)
+    }
+
+    //Proofs of every share, in share order
+    pub fn get_split_proofs(&self, id: &str) -> Result<Vec<InclusionProof>, MerkleError> {
+        let indices = self.split_indices(id);
+        if indices.is_empty() {
+            return Err(MerkleError::UnknownId(id.to_string()));
+        }
+        let mut proofs = vec![];
+        for index in indices {
+            proofs.push(
+                self.get_proof(index)?
+                    .ok_or(MerkleError::IndexOutOfBounds(index))?,
+            );
+        }
+        Ok(proofs)
+    }
+
+    //Check the proofs are shares 0..n of id, all under root, summing to balance
+    pub fn verify_split_proofs(
+        proofs: &[InclusionProof],
+        id: &str,
+        balance: i64,
+        root: &Node,
+    ) -> Result<bool, MerkleError> {
+        if proofs.is_empty() {
+            return Ok(false);
+        }
+        let mut total: i64 = 0;
+        for (share, proof) in proofs.iter().enumerate() {
+            let leaf = proof.get_leaf();
+            if leaf.get_id() != share_id(id, share) || !proof.compute_root()?.is_equal(root.clone())
+            {
+                return Ok(false);
+            }
+            total += i64::from(leaf.get_node().get_value());
+        }
+        Ok(total == balance)
+    }
+
+    //Indices of the shares of id, ordered by share number
+    fn split_indices(&self, id: &str) -> Vec<usize> {
+        let mut shares: Vec<(usize, usize)> = self
+            .leafs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, leaf)| share_number(id, &leaf.id).map(|share| (share, index)))
+            .collect();
+        shares.sort();
+        shares.into_iter().map(|(_, index)| index).collect()
+    }
+}
+
+fn share_id(id: &str, share: usize) -> String {
+    format!("{}#{}", id, share)
+}
+
+//Inverse of share_id, only canonical share numbers match
+fn share_number(id: &str, sub_id: &str) -> Option<usize> {
+    let digits = sub_id.strip_prefix(id)?.strip_prefix('#')?;
+    let share = digits.parse::<usize>().ok()?;
+    match share.to_string() == digits {
+        true => Some(share),
+        false => None,
+    }
+}
+
+//Random cut points over the magnitude, so the parts sum exactly to value
+fn split_value<R: Rng>(value: i64, shares: usize, rng: &mut R) -> Result<Vec<i32>, MerkleError> {
+    if shares == 0 {
+        return Err(MerkleError::InvalidShareCount(shares));
+    }
+    i32::try_from(value).map_err(|_| MerkleError::Overflow)?;
+    let magnitude = value.abs();
+    let mut cuts: Vec<i64> = (1..shares)
+        .map(|_| rng.gen_range(0, magnitude + 1))
+        .collect();
+    cuts.push(magnitude);
+    cuts.sort();
+    let mut previous = 0;
+    let mut parts = vec![];
+    for cut in cuts {
+        let part = match value < 0 {
+            true => previous - cut,
+            false => cut - previous,
+        };
+        parts.push(i32::try_from(part).map_err(|_| MerkleError::Overflow)?);
+        previous = cut;
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, XorShiftRng};
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([1, 2, 3, 4])
+    }
+
+    #[test]
+    fn shares_sum_to_the_balance() {
+        let mut rng = rng();
+        for (value, shares) in [(100, 4), (7, 7), (0, 3), (-50, 2), (1, 1)] {
+            let parts = split_value(value, shares, &mut rng).unwrap();
+            assert_eq!(parts.len(), shares);
+            assert_eq!(
+                parts.iter().map(|part| i64::from(*part)).sum::<i64>(),
+                value
+            );
+        }
+        assert!(matches!(
+            split_value(10, 0, &mut rng),
+            Err(MerkleError::InvalidShareCount(0))
+        ));
+        assert!(matches!(
+            split_value(i64::from(i32::MAX) + 1, 2, &mut rng),
+            Err(MerkleError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn split_accounts_prove_their_balance() {
+        let mut rng = rng();
+        let mut tree = MerkleSumTree::new(vec![Leaf::new("bob".to_string(), 20)]).unwrap();
+        let indices = tree.split_insert("alice", 1000, 3, &mut rng).unwrap();
+        assert_eq!(indices.len(), 3);
+        assert_eq!(tree.get_root_sum(), Some(1020));
+        assert_eq!(tree.get_leaf(indices[2]).unwrap().get_id(), "alice#2");
+
+        let root = tree.get_root().unwrap();
+        let proofs = tree.get_split_proofs("alice").unwrap();
+        assert!(MerkleSumTree::verify_split_proofs(&proofs, "alice", 1000, &root).unwrap());
+        assert!(!MerkleSumTree::verify_split_proofs(&proofs, "alice", 999, &root).unwrap());
+        assert!(!MerkleSumTree::verify_split_proofs(&proofs[1..], "alice", 1000, &root).unwrap());
+        let doubled = vec![proofs[0].clone(), proofs[0].clone(), proofs[2].clone()];
+        assert!(!MerkleSumTree::verify_split_proofs(&doubled, "alice", 1000, &root).unwrap());
+
+        assert!(matches!(
+            tree.split_insert("alice", 5, 2, &mut rng),
+            Err(MerkleError::DuplicateId(_))
+        ));
+        assert!(matches!(
+            tree.get_split_proofs("carol"),
+            Err(MerkleError::UnknownId(_))
+        ));
+    }
+
+    #[test]
+    fn split_updates_and_removals_are_atomic() {
+        let mut rng = rng();
+        let leafs = vec![Leaf::new("bob".to_string(), i32::MAX - 100)];
+        let mut tree = MerkleSumTree::new(leafs).unwrap();
+        let indices = tree.split_insert("alice", 60, 3, &mut rng).unwrap();
+
+        let before = tree.clone();
+        assert!(tree.split_update("alice", 200, &mut rng).is_err());
+        assert_eq!(tree.get_nodes(), before.get_nodes());
+        assert!(tree.split_insert("carol", 50, 2, &mut rng).is_err());
+        assert_eq!(tree.get_leafs(), before.get_leafs());
+
+        assert_eq!(tree.split_update("alice", 90, &mut rng).unwrap(), indices);
+        assert_eq!(tree.get_root_sum(), Some(i32::MAX - 10));
+
+        assert_eq!(tree.split_remove("alice").unwrap(), indices);
+        assert_eq!(tree.get_root_sum(), Some(i32::MAX - 100));
+        assert!(tree.get_split_proofs("alice").is_err());
+        assert_eq!(tree.validate(), Ok(()));
+    }
+}
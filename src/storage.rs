@@ -0,0 +1,186 @@
+//Level-major node storage
+
+use crate::layout::NodeIndex;
+use crate::Node;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+//One level of the tree, left to right
+pub(crate) type Level = Vec<Node>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct NodeStore {
+    levels: Vec<Level>,
+}
+
+impl NodeStore {
+    //Split a flat layout into its levels, the bottom level half of it rounded up
+    pub(crate) fn from_flat(mut nodes: Vec<Node>) -> NodeStore {
+        let mut sizes = vec![];
+        let (mut remaining, mut size) = (nodes.len(), nodes.len().div_ceil(2));
+        while remaining > 0 {
+            let taken = size.min(remaining).max(1);
+            sizes.push(taken);
+            remaining -= taken;
+            size /= 2;
+        }
+        let mut levels = Vec::with_capacity(sizes.len());
+        for size in sizes.iter().rev() {
+            let level = nodes.split_off(nodes.len() - size);
+            levels.push(level);
+        }
+        levels.reverse();
+        NodeStore { levels }
+    }
+
+    pub(crate) fn from_levels(levels: Vec<Level>) -> NodeStore {
+        NodeStore { levels }
+    }
+
+    //The flat layout, levels bottom-up
+    pub(crate) fn to_flat(&self) -> Vec<Node> {
+        self.iter().cloned().collect()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Node> + '_ {
+        self.levels.iter().flatten()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.levels.iter().map(Vec::len).sum()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.levels.iter().map(Vec::capacity).sum()
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.levels.shrink_to_fit();
+        for level in self.levels.iter_mut() {
+            level.shrink_to_fit();
+        }
+    }
+
+    pub(crate) fn root(&self) -> Option<&Node> {
+        self.levels.last().and_then(|level| level.last())
+    }
+
+    pub(crate) fn level(&self, level: usize) -> Option<&[Node]> {
+        self.levels.get(level).map(Vec::as_slice)
+    }
+
+    pub(crate) fn get(&self, at: NodeIndex) -> Option<&Node> {
+        self.levels.get(at.level())?.get(at.offset())
+    }
+
+    pub(crate) fn get_mut(&mut self, at: NodeIndex) -> Option<&mut Node> {
+        self.levels.get_mut(at.level())?.get_mut(at.offset())
+    }
+
+    //Typed index of a flat one, None past the end
+    pub(crate) fn locate(&self, index: usize) -> Option<NodeIndex> {
+        let mut offset = index;
+        for (level, nodes) in self.levels.iter().enumerate() {
+            if offset < nodes.len() {
+                return Some(NodeIndex::new(level, offset));
+            }
+            offset -= nodes.len();
+        }
+        None
+    }
+
+    //Flat index of a typed one
+    pub(crate) fn flat(&self, at: NodeIndex) -> usize {
+        let below: usize = self.levels.iter().take(at.level()).map(Vec::len).sum();
+        below + at.offset()
+    }
+
+    pub(crate) fn get_flat(&self, index: usize) -> Option<&Node> {
+        self.locate(index).and_then(|at| self.get(at))
+    }
+}
+
+impl Serialize for NodeStore {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeStore {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<Node>::deserialize(deserializer).map(NodeStore::from_flat)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::layout::flat_index;
+    use crate::{Fr, Leaf, MerkleSumTree};
+    use ff::Field;
+
+    fn nodes(count: u64) -> Vec<Node> {
+        (0..count)
+            .map(|i| Node::new(Fr::from(i), i as i32))
+            .collect()
+    }
+
+    #[test]
+    fn full_layouts_split_into_their_levels() {
+        for leaf_count in [1usize, 2, 4, 8, 64] {
+            let flat = nodes(2 * leaf_count as u64 - 1);
+            let store = NodeStore::from_flat(flat.clone());
+            assert_eq!(store.to_flat(), flat);
+            assert_eq!(store.len(), flat.len());
+            assert_eq!(store.root(), flat.last());
+            for (index, node) in flat.iter().enumerate() {
+                let at = store.locate(index).unwrap();
+                assert_eq!(flat_index(leaf_count, at.level(), at.offset()), Some(index));
+                assert_eq!(store.flat(at), index);
+                assert_eq!(store.get(at), Some(node));
+            }
+            assert_eq!(store.level(0).unwrap().len(), leaf_count);
+            assert_eq!(store.locate(flat.len()), None);
+        }
+    }
+
+    #[test]
+    fn malformed_layouts_keep_their_flat_addresses() {
+        for count in [0, 2, 5, 6, 14, 16] {
+            let flat = nodes(count);
+            let mut store = NodeStore::from_flat(flat.clone());
+            assert_eq!(store.to_flat(), flat);
+            for (index, node) in flat.iter().enumerate() {
+                assert_eq!(store.get_flat(index), Some(node));
+            }
+            assert_eq!(store.get_flat(flat.len()), None);
+            if let Some(last) = count.checked_sub(1) {
+                let at = store.locate(last as usize).unwrap();
+                *store.get_mut(at).unwrap() = Node::new(Fr::ZERO, -1);
+                assert_eq!(store.root().unwrap().get_value(), -1);
+            }
+        }
+    }
+
+    #[test]
+    fn trees_serialize_their_nodes_flat() {
+        let tree = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        let json = serde_json::to_value(&tree).unwrap();
+        let flat: Vec<Node> = serde_json::from_value(json["nodes"].clone()).unwrap();
+        assert_eq!(flat, tree.get_nodes());
+        assert_eq!(flat.len(), 7);
+        let loaded: MerkleSumTree = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.nodes, tree.nodes);
+    }
+}
@@ -0,0 +1,222 @@
+//Subtrees extracted as independent trees, for sharded processing
+
+use crate::history::RootHistory;
+use crate::layout::{flat_index, level_count, path_from, NodeIndex};
+use crate::metrics::MetricsSink;
+use crate::progress::ProgressSink;
+use crate::storage::NodeStore;
+use crate::wal::Logger;
+use crate::watch::Watches;
+use crate::{
+    CancellationToken, MerkleError, MerkleSumTree, Neighbor, Node, Position, RootCommitment,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+//Evidence that a node sits at (level, offset) under a root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubtreeProof {
+    level: usize,
+    offset: usize,
+    node: Node,
+    path: Vec<Neighbor>,
+}
+
+impl MerkleSumTree {
+    pub fn get_subtree(&self, level: usize, offset: usize) -> Result<MerkleSumTree, MerkleError> {
+        let leaf_count = self.leafs.len();
+        flat_index(leaf_count, level, offset)
+            .ok_or(MerkleError::InvalidCoordinates { level, offset })?;
+        let width = 1usize << level;
+        let first = offset * width;
+        let mut leafs = self
+            .leafs
+            .get(first..first + width)
+            .ok_or(MerkleError::MissingNode(first))?
+            .to_vec();
+        let mut levels = vec![];
+        for below in 0..=level {
+            let start = NodeIndex::new(below, offset << (level - below));
+            let size = width >> below;
+            let level_nodes = self
+                .nodes
+                .level(below)
+                .and_then(|nodes| nodes.get(start.offset()..start.offset() + size))
+                .ok_or_else(|| MerkleError::MissingNode(self.nodes.flat(start)))?;
+            levels.push(level_nodes.to_vec());
+        }
+        let zero_index = (0..width)
+            .filter(|index| matches!(leafs.get(*index), Some(leaf) if self.is_empty_leaf(leaf)))
+            .collect();
+        let retired: BTreeSet<usize> = self
+            .retired
+            .range(first..first + width)
+            .map(|index| index - first)
+            .collect();
+        let next_slot = self.next_slot.clamp(first, first + width) - first;
+        let ids = Self::index_ids(&mut leafs, &BTreeSet::new(), &self.empty);
+        Ok(MerkleSumTree {
+            leafs,
+            nodes: NodeStore::from_levels(levels),
+            height: level + 1,
+            zero_index,
+            append_only: self.append_only,
+            next_slot,
+            retired,
+            max_height: self.max_height,
+            epoch: 0,
+            shuffle_seed: None,
+            dummy_seed: None,
+            dummies: BTreeSet::new(),
+            empty: self.empty.clone(),
+            max_leaf_value: self.max_leaf_value,
+            non_negative: self.non_negative,
+            leaf_hash_mode: self.leaf_hash_mode,
+            mimc_exponent: self.mimc_exponent,
+            canonical: false,
+            allocation: self.allocation,
+            rules: self.rules.clone(),
+            history: RootHistory::default(),
+            plain: None,
+            keccak: None,
+            ids,
+            watches: Watches::default(),
+            log: Logger::default(),
+            metrics: MetricsSink::default(),
+            progress: ProgressSink::default(),
+            cancel: CancellationToken::default(),
+        })
+    }
+
+    //Path from the internal node at (level, offset) to the root
+    pub fn prove_subtree(&self, level: usize, offset: usize) -> Result<SubtreeProof, MerkleError> {
+        self.require_default_exponent()?;
+        let leaf_count = self.leafs.len();
+        flat_index(leaf_count, level, offset)
+            .ok_or(MerkleError::InvalidCoordinates { level, offset })?;
+        let mut path = vec![];
+        for step in path_from(leaf_count, level, offset) {
+            path.push(Neighbor {
+                position: step.position,
+                node: self.node_at(step.sibling)?,
+            });
+        }
+        Ok(SubtreeProof {
+            level,
+            offset,
+            node: self.node_at(NodeIndex::new(level, offset))?,
+            path,
+        })
+    }
+}
+
+impl SubtreeProof {
+    pub fn get_level(&self) -> usize {
+        self.level
+    }
+
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn get_node(&self) -> Node {
+        self.node.clone()
+    }
+
+    pub fn get_path(&self) -> Vec<Neighbor> {
+        self.path.clone()
+    }
+
+    pub fn compute_root(&self) -> Result<Node, MerkleError> {
+        let mut node = self.node.clone();
+        for neighbor in &self.path {
+            node = match neighbor.position {
+                Position::Right => MerkleSumTree::build_parent(node, neighbor.node.clone())?,
+                Position::Left => MerkleSumTree::build_parent(neighbor.node.clone(), node)?,
+            };
+        }
+        Ok(node)
+    }
+
+    //The path must walk up from offset and fold to the committed root
+    pub fn verify(&self, commitment: &RootCommitment) -> Result<bool, MerkleError> {
+        let walks_from_offset = self.path.iter().enumerate().all(|(depth, neighbor)| {
+            let left_child = self.offset.checked_shr(depth as u32).unwrap_or(0) % 2 == 0;
+            (neighbor.position == Position::Right) == left_child
+        });
+        let reaches_the_top = self.offset.checked_shr(self.path.len() as u32).unwrap_or(0) == 0;
+        Ok(walks_from_offset && reaches_the_top && commitment.matches(&self.compute_root()?))
+    }
+
+    //The extracted tree has the proven node as its root
+    pub fn verify_subtree(
+        &self,
+        subtree: &MerkleSumTree,
+        commitment: &RootCommitment,
+    ) -> Result<bool, MerkleError> {
+        let shaped = level_count(subtree.leafs.len()) == self.level + 1;
+        Ok(shaped && subtree.get_root() == Some(self.node.clone()) && self.verify(commitment)?)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing, deprecated)]
+mod tests {
+    use super::*;
+    use crate::test_support::users;
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(users(6, 10, 15)).unwrap()
+    }
+
+    #[test]
+    fn subtrees_keep_the_internal_node_as_root() {
+        let tree = tree();
+        for (level, offset) in [(0, 3), (1, 2), (2, 0), (2, 1), (3, 0)] {
+            let subtree = tree.get_subtree(level, offset).unwrap();
+            let index = crate::layout::flat_index(8, level, offset).unwrap();
+            assert_eq!(subtree.get_root(), tree.get_node(index));
+            assert_eq!(subtree.get_height(), level + 1);
+            assert_eq!(subtree.validate(), Ok(()));
+        }
+
+        let right = tree.get_subtree(2, 1).unwrap();
+        assert_eq!(right.get_leaf(1).unwrap().get_id(), "user5");
+        assert_eq!(right.get_zero_index(), vec![2, 3]);
+        assert_eq!(right.get_index_by_id("user4"), Some(0));
+
+        for (level, offset) in [(1, 4), (4, 0), (0, 8)] {
+            assert!(matches!(
+                tree.get_subtree(level, offset),
+                Err(MerkleError::InvalidCoordinates { .. })
+            ));
+            assert!(tree.prove_subtree(level, offset).is_err());
+        }
+    }
+
+    #[test]
+    fn subtree_proofs_verify_against_the_parent_root() {
+        let tree = tree();
+        let commitment = tree.get_commitment().unwrap();
+        let subtree = tree.get_subtree(1, 2).unwrap();
+        let proof = tree.prove_subtree(1, 2).unwrap();
+        assert_eq!(proof.get_path().len(), 2);
+        assert!(proof.verify(&commitment).unwrap());
+        assert!(proof.verify_subtree(&subtree, &commitment).unwrap());
+
+        let other = tree.get_subtree(1, 1).unwrap();
+        assert!(!proof.verify_subtree(&other, &commitment).unwrap());
+
+        //Claiming another offset breaks the walk
+        let mut moved = proof.clone();
+        moved.offset = 3;
+        assert!(!moved.verify(&commitment).unwrap());
+        let mut moved = proof;
+        moved.offset = 6;
+        assert!(!moved.verify(&commitment).unwrap());
+
+        let root_proof = tree.prove_subtree(3, 0).unwrap();
+        assert!(root_proof.get_path().is_empty());
+        assert!(root_proof.verify(&commitment).unwrap());
+    }
+}
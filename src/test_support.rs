@@ -0,0 +1,19 @@
+//Fixtures shared by the test modules
+
+use crate::Leaf;
+
+//count leafs user0, user1..., user i holding i * step - offset
+pub(crate) fn users(count: i32, step: i32, offset: i32) -> Vec<Leaf> {
+    (0..count)
+        .map(|i| Leaf::new(format!("user{}", i), i * step - offset))
+        .collect()
+}
+
+//One leaf per value, for alice, bob, carol, dave and erin in that order
+pub(crate) fn accounts(values: &[i32]) -> Vec<Leaf> {
+    ["alice", "bob", "carol", "dave", "erin"]
+        .iter()
+        .zip(values)
+        .map(|(id, value)| Leaf::new(id.to_string(), *value))
+        .collect()
+}
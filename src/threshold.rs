@@ -0,0 +1,185 @@
+//Circuit inputs for proving a committed balance meets a threshold
+
+use crate::{
+    fr_to_sum, fr_to_value, sum_to_fr, value_to_fr, Fr, MerkleError, MerkleSumTree, Node, Position,
+};
+use ff::Field;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThresholdInputs {
+    leaf_hash: Fr,
+    leaf_value: Fr,
+    path_elements: Vec<Fr>,
+    path_sums: Vec<Fr>,
+    path_indices: Vec<Fr>,
+    root_hash: Fr,
+    root_sum: Fr,
+    threshold: Fr,
+}
+
+impl MerkleSumTree {
+    pub fn threshold_statement_inputs(
+        &self,
+        index: usize,
+        threshold: i64,
+    ) -> Result<ThresholdInputs, MerkleError> {
+        self.require_default_exponent()?;
+        let proof = self
+            .get_proof(index)?
+            .ok_or(MerkleError::IndexOutOfBounds(index))?;
+        let root = self.get_root().ok_or(MerkleError::EmptyTree)?;
+        let leaf = proof.get_leaf().get_node();
+        let path = proof.get_path();
+        Ok(ThresholdInputs {
+            leaf_hash: leaf.hash,
+            leaf_value: value_to_fr(leaf.value),
+            path_elements: path.iter().map(|neighbor| neighbor.node.hash).collect(),
+            path_sums: path
+                .iter()
+                .map(|neighbor| value_to_fr(neighbor.node.value))
+                .collect(),
+            path_indices: path
+                .iter()
+                .map(|neighbor| match neighbor.position {
+                    Position::Left => Fr::ONE,
+                    Position::Right => Fr::ZERO,
+                })
+                .collect(),
+            root_hash: root.hash,
+            root_sum: value_to_fr(root.value),
+            threshold: sum_to_fr(threshold),
+        })
+    }
+}
+
+impl ThresholdInputs {
+    pub fn get_threshold(&self) -> Fr {
+        self.threshold
+    }
+
+    pub fn get_root(&self) -> Option<Node> {
+        Some(Node::new(self.root_hash, fr_to_value(self.root_sum)?))
+    }
+
+    //The balance reaches the threshold and the path folds to the root
+    pub fn check(&self) -> Result<bool, MerkleError> {
+        let (value, threshold) = match (fr_to_value(self.leaf_value), fr_to_sum(self.threshold)) {
+            (Some(value), Some(threshold)) => (value, threshold),
+            _ => return Ok(false),
+        };
+        if i64::from(value) < threshold
+            || self.path_sums.len() != self.path_elements.len()
+            || self.path_indices.len() != self.path_elements.len()
+        {
+            return Ok(false);
+        }
+        let mut node = Node::new(self.leaf_hash, value);
+        let levels = self
+            .path_elements
+            .iter()
+            .zip(&self.path_sums)
+            .zip(&self.path_indices);
+        for ((hash, sum), index) in levels {
+            let sibling = match fr_to_value(*sum) {
+                Some(sum) => Node::new(*hash, sum),
+                None => return Ok(false),
+            };
+            node = match *index {
+                index if index == Fr::ZERO => MerkleSumTree::build_parent(node, sibling)?,
+                index if index == Fr::ONE => MerkleSumTree::build_parent(sibling, node)?,
+                _ => return Ok(false),
+            };
+        }
+        Ok(Some(node) == self.get_root())
+    }
+
+    //Circom input JSON, refused when the statement does not hold
+    pub fn to_json(&self) -> Result<String, MerkleError> {
+        if !self.check()? {
+            return Err(MerkleError::StatementNotSatisfied);
+        }
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use serde_json::Value;
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[100, -40, 30])).unwrap()
+    }
+
+    fn fr(value: &Value) -> Fr {
+        value.as_str().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn exported_inputs_fold_to_the_root() {
+        let tree = tree();
+        let inputs = tree.threshold_statement_inputs(0, 100).unwrap();
+        assert!(inputs.check().unwrap());
+        let json: Value = serde_json::from_str(&inputs.to_json().unwrap()).unwrap();
+        for signal in [
+            "leafHash",
+            "leafValue",
+            "pathElements",
+            "pathSums",
+            "pathIndices",
+            "rootHash",
+            "rootSum",
+            "threshold",
+        ] {
+            assert!(json.get(signal).is_some(), "missing {}", signal);
+        }
+        assert_eq!(json["leafValue"], "100");
+        assert_eq!(json["threshold"], "100");
+        assert_eq!(json["rootSum"], "90");
+
+        //Recompute the root from the exported strings alone
+        let mut node = Node::new(fr(&json["leafHash"]), 100);
+        for level in 0..2 {
+            let sum = fr_to_value(fr(&json["pathSums"][level])).unwrap();
+            let sibling = Node::new(fr(&json["pathElements"][level]), sum);
+            node = match json["pathIndices"][level].as_str().unwrap() {
+                "0" => MerkleSumTree::build_parent(node, sibling).unwrap(),
+                _ => MerkleSumTree::build_parent(sibling, node).unwrap(),
+            };
+        }
+        assert_eq!(Some(node), tree.get_root());
+        assert_eq!(fr(&json["rootHash"]), tree.get_root_hash().unwrap());
+    }
+
+    #[test]
+    fn unsatisfiable_statements_are_not_exported() {
+        let tree = tree();
+        let inputs = tree.threshold_statement_inputs(0, 101).unwrap();
+        assert!(!inputs.check().unwrap());
+        assert!(matches!(
+            inputs.to_json(),
+            Err(MerkleError::StatementNotSatisfied)
+        ));
+
+        //Negative balances against negative thresholds
+        assert!(tree
+            .threshold_statement_inputs(1, -40)
+            .unwrap()
+            .check()
+            .unwrap());
+        let bob = tree.threshold_statement_inputs(1, -39).unwrap();
+        assert!(!bob.check().unwrap());
+        assert_eq!(bob.get_threshold(), -Fr::from(39u64));
+
+        //Tampered inputs no longer fold to the root
+        let mut inputs = tree.threshold_statement_inputs(2, 0).unwrap();
+        assert!(inputs.check().unwrap());
+        inputs.leaf_value = value_to_fr(300);
+        assert!(!inputs.check().unwrap());
+        assert!(tree.threshold_statement_inputs(3, 0).is_err());
+    }
+}
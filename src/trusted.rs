@@ -0,0 +1,215 @@
+//Construction from leafs the caller has already checked and hashed
+
+use crate::{
+    CancellationToken, EmptyLeafSpec, HashParams, IntegrityViolation, Leaf, LeafHashMode,
+    MerkleError, MerkleSumTree, DEFAULT_EXPONENT, MAX_HEIGHT,
+};
+use std::collections::BTreeSet;
+
+//Parameters of the tree and the checks to run anyway, all off by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrustedBuildOpts {
+    leaf_hash_mode: LeafHashMode,
+    mimc_exponent: u64,
+    max_leaf_value: Option<i64>,
+    canonical: bool,
+    check_leaf_hashes: bool,
+    check_values: bool,
+}
+
+impl Default for TrustedBuildOpts {
+    fn default() -> Self {
+        TrustedBuildOpts {
+            leaf_hash_mode: LeafHashMode::default(),
+            mimc_exponent: DEFAULT_EXPONENT,
+            max_leaf_value: None,
+            canonical: false,
+            check_leaf_hashes: false,
+            check_values: false,
+        }
+    }
+}
+
+impl TrustedBuildOpts {
+    pub fn new() -> TrustedBuildOpts {
+        TrustedBuildOpts::default()
+    }
+
+    //Mode the leaf hashes were derived under, IdOnly by default
+    pub fn leaf_hash_mode(mut self, mode: LeafHashMode) -> Self {
+        self.leaf_hash_mode = mode;
+        self
+    }
+
+    pub fn mimc_exponent(mut self, exponent: u64) -> Self {
+        self.mimc_exponent = exponent;
+        self
+    }
+
+    //Cap enforced on every later mutation, and at build only with check_values
+    pub fn max_leaf_value(mut self, cap: Option<i64>) -> Self {
+        self.max_leaf_value = cap;
+        self
+    }
+
+    //The leafs come in canonical order, flag the tree canonical without sorting
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    //Reject leafs whose hash the mode would not derive
+    pub fn check_leaf_hashes(mut self, check: bool) -> Self {
+        self.check_leaf_hashes = check;
+        self
+    }
+
+    //Reject leafs above max_leaf_value
+    pub fn check_values(mut self, check: bool) -> Self {
+        self.check_values = check;
+        self
+    }
+}
+
+impl MerkleSumTree {
+    //new over leafs whose hashes are already set, skipping the passes opts trusts
+    pub fn from_leaves_prehashed(
+        leaves: Vec<Leaf>,
+        opts: TrustedBuildOpts,
+    ) -> Result<MerkleSumTree, MerkleError> {
+        let params = HashParams::mimc(opts.mimc_exponent)?;
+        let mode = opts.leaf_hash_mode;
+        if opts.check_leaf_hashes {
+            let violations: Vec<IntegrityViolation> = leaves
+                .iter()
+                .enumerate()
+                .filter(|(_, leaf)| !mode.accepts(leaf))
+                .map(|(index, _)| IntegrityViolation::LeafHashMismatch(index))
+                .collect();
+            if !violations.is_empty() {
+                return Err(MerkleError::InvalidTree(violations));
+            }
+        }
+        let filled = leaves.len();
+        let empty = EmptyLeafSpec::default();
+        let (mut leafs, height) = Self::fill_leafs(leaves, MAX_HEIGHT, None, &empty)?;
+        for padding in leafs.iter_mut().skip(filled) {
+            *padding = mode.normalize(padding.clone());
+        }
+        let nodes = Self::build_nodes(&leafs, &params, &[], &CancellationToken::default())?;
+        let mut tree = Self::assemble(
+            leafs,
+            nodes,
+            height,
+            MAX_HEIGHT,
+            None,
+            BTreeSet::new(),
+            empty,
+        );
+        tree.leaf_hash_mode = mode;
+        tree.mimc_exponent = opts.mimc_exponent;
+        tree.max_leaf_value = opts.max_leaf_value;
+        tree.canonical = opts.canonical;
+        if opts.check_values {
+            let violations = tree.value_violations();
+            if !violations.is_empty() {
+                return Err(MerkleError::InvalidTree(violations));
+            }
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    fn leafs(mode: LeafHashMode) -> Vec<Leaf> {
+        (0..300)
+            .map(|i| mode.leaf(format!("account-{}", i), (i * 37) % 1000 - 200))
+            .collect()
+    }
+
+    #[test]
+    fn prehashed_builds_match_new() {
+        for mode in [LeafHashMode::IdOnly, LeafHashMode::IdAndValue] {
+            for exponent in [5, 7] {
+                let expected = MerkleSumTree::builder()
+                    .leaf_hash_mode(mode)
+                    .mimc_exponent(exponent)
+                    .build(leafs(mode))
+                    .unwrap();
+                let opts = TrustedBuildOpts::new()
+                    .leaf_hash_mode(mode)
+                    .mimc_exponent(exponent);
+                let tree = MerkleSumTree::from_leaves_prehashed(leafs(mode), opts).unwrap();
+                assert_eq!(tree.get_root(), expected.get_root());
+                assert_eq!(tree.nodes, expected.nodes);
+                assert_eq!(tree.get_leaf_hash_mode(), mode);
+                assert_eq!(tree.get_index_by_id("account-299"), Some(299));
+                assert_eq!(tree.validate(), Ok(()));
+            }
+        }
+        let empty = MerkleSumTree::from_leaves_prehashed(vec![], TrustedBuildOpts::new());
+        assert_eq!(
+            empty.unwrap().get_root(),
+            MerkleSumTree::new(vec![]).unwrap().get_root()
+        );
+    }
+
+    #[test]
+    fn skipped_checks_are_left_to_validate() {
+        let mode = LeafHashMode::IdAndValue;
+        let mut forged = leafs(mode);
+        forged[3] = LeafHashMode::IdOnly.leaf("account-3".to_string(), 5);
+        forged[8] = mode.leaf("account-8".to_string(), 5000);
+        let opts = TrustedBuildOpts::new()
+            .leaf_hash_mode(mode)
+            .max_leaf_value(Some(1000));
+
+        //Trusted, the tree builds and only validate notices
+        let tree = MerkleSumTree::from_leaves_prehashed(forged.clone(), opts).unwrap();
+        let violations = tree.validate().unwrap_err();
+        assert!(violations.contains(&IntegrityViolation::LeafHashMismatch(3)));
+        assert!(violations.contains(&IntegrityViolation::ValueAboveCap {
+            index: 8,
+            value: 5000,
+            cap: 1000
+        }));
+
+        //Checked, the construction refuses them
+        assert!(matches!(
+            MerkleSumTree::from_leaves_prehashed(forged.clone(), opts.check_leaf_hashes(true)),
+            Err(MerkleError::InvalidTree(violations))
+                if violations == vec![IntegrityViolation::LeafHashMismatch(3)]
+        ));
+        forged[3] = mode.leaf("account-3".to_string(), 5);
+        assert!(matches!(
+            MerkleSumTree::from_leaves_prehashed(forged, opts.check_values(true)),
+            Err(MerkleError::InvalidTree(violations))
+                if violations == vec![IntegrityViolation::ValueAboveCap {
+                    index: 8,
+                    value: 5000,
+                    cap: 1000
+                }]
+        ));
+    }
+
+    #[test]
+    fn canonical_order_is_trusted_not_sorted() {
+        let sorted = MerkleSumTree::new_canonical(leafs(LeafHashMode::IdOnly)).unwrap();
+        let ordered: Vec<Leaf> = sorted
+            .non_empty_leafs(false)
+            .map(|(_, leaf)| leaf.clone())
+            .collect();
+        let opts = TrustedBuildOpts::new().canonical(true);
+        let mut tree = MerkleSumTree::from_leaves_prehashed(ordered, opts).unwrap();
+        assert!(tree.is_canonical());
+        assert_eq!(tree.get_root(), sorted.get_root());
+        tree.push(Leaf::new("late".to_string(), 1)).unwrap();
+        let mut expected = sorted;
+        expected.push(Leaf::new("late".to_string(), 1)).unwrap();
+        assert_eq!(tree.get_root(), expected.get_root());
+    }
+}
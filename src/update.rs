@@ -0,0 +1,265 @@
+//Witness of a single-leaf update, for state-transition circuits
+
+use crate::{
+    default_exponent, is_default_exponent, path_index, InclusionProof, Leaf, MerkleError,
+    MerkleSumTree, Neighbor, RootCommitment,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeafUpdateProof {
+    old_leaf: Leaf,
+    new_leaf: Leaf,
+    path: Vec<Neighbor>,
+    old_root: RootCommitment,
+    new_root: RootCommitment,
+    #[serde(
+        default = "default_exponent",
+        skip_serializing_if = "is_default_exponent"
+    )]
+    exponent: u64,
+}
+
+impl MerkleSumTree {
+    //set_leaf, returning the witness of the transition
+    pub fn set_leaf_with_proofs(
+        &mut self,
+        leaf: Leaf,
+        index: usize,
+    ) -> Result<LeafUpdateProof, MerkleError> {
+        self.with_update_proof(index, |tree| tree.set_leaf(leaf, index))
+    }
+
+    //remove, returning the witness of the transition to the empty leaf
+    pub fn remove_with_proofs(&mut self, index: usize) -> Result<LeafUpdateProof, MerkleError> {
+        self.with_update_proof(index, |tree| tree.remove(index))
+    }
+
+    //push into a free slot, returning the slot and the witness. A push that would grow
+    //the tree or, in a canonical tree, shift leafs changes more than one leaf and is
+    //refused before writing
+    pub fn push_with_proofs(
+        &mut self,
+        leaf: Leaf,
+    ) -> Result<(usize, LeafUpdateProof), MerkleError> {
+        let slot = match (self.canonical, self.append_only) {
+            (true, _) => None,
+            (false, true) => Some(self.next_slot).filter(|slot| *slot < self.leafs.len()),
+            (false, false) => self.allocate(),
+        };
+        let index = slot.ok_or_else(|| {
+            MerkleError::InvalidProof("push would change more than one leaf".to_string())
+        })?;
+        let update = self.with_update_proof(index, |tree| tree.push(leaf).map(|_| ()))?;
+        Ok((index, update))
+    }
+
+    //Capture the path before write changes the leaf at index, every sibling stays
+    fn with_update_proof<F>(
+        &mut self,
+        index: usize,
+        write: F,
+    ) -> Result<LeafUpdateProof, MerkleError>
+    where
+        F: FnOnce(&mut MerkleSumTree) -> Result<(), MerkleError>,
+    {
+        let before = self.get_bound_proof(index)?;
+        let old_root = self.get_commitment().ok_or(MerkleError::EmptyTree)?;
+        write(self)?;
+        Ok(LeafUpdateProof {
+            old_leaf: before.leaf,
+            new_leaf: self
+                .get_leaf(index)
+                .ok_or(MerkleError::IndexOutOfBounds(index))?,
+            path: before.path,
+            old_root,
+            new_root: self.get_commitment().ok_or(MerkleError::EmptyTree)?,
+            exponent: self.mimc_exponent,
+        })
+    }
+}
+
+impl LeafUpdateProof {
+    pub fn get_old_leaf(&self) -> Leaf {
+        self.old_leaf.clone()
+    }
+
+    pub fn get_new_leaf(&self) -> Leaf {
+        self.new_leaf.clone()
+    }
+
+    pub fn get_path(&self) -> Vec<Neighbor> {
+        self.path.clone()
+    }
+
+    pub fn get_old_root(&self) -> RootCommitment {
+        self.old_root
+    }
+
+    pub fn get_new_root(&self) -> RootCommitment {
+        self.new_root
+    }
+
+    pub fn get_exponent(&self) -> u64 {
+        self.exponent
+    }
+
+    //Inclusion proof of the old leaf against the old root
+    pub fn old_proof(&self) -> InclusionProof {
+        self.proof(&self.old_leaf)
+    }
+
+    //Inclusion proof of the new leaf against the new root
+    pub fn new_proof(&self) -> InclusionProof {
+        self.proof(&self.new_leaf)
+    }
+
+    //Both leafs fold along the path to their roots, one epoch apart
+    pub fn verify(&self) -> Result<bool, MerkleError> {
+        Ok(
+            self.new_root.get_epoch() == self.old_root.get_epoch().wrapping_add(1)
+                && self.old_root.matches(&self.old_proof().compute_root()?)
+                && self.new_root.matches(&self.new_proof().compute_root()?),
+        )
+    }
+
+    fn proof(&self, leaf: &Leaf) -> InclusionProof {
+        InclusionProof {
+            leaf: leaf.clone(),
+            index: path_index(&self.path),
+            path: self.path.clone(),
+            root: None,
+            epoch: None,
+            exponent: self.exponent,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+
+    fn tree(exponent: u64) -> MerkleSumTree {
+        MerkleSumTree::builder()
+            .mimc_exponent(exponent)
+            .build(accounts(&[10, 20, 30]))
+            .unwrap()
+    }
+
+    #[test]
+    fn both_leafs_fold_along_one_path() {
+        for exponent in [5, 7] {
+            let mut tree = tree(exponent);
+            let before = tree.get_commitment().unwrap();
+            let update = tree
+                .set_leaf_with_proofs(Leaf::new("bob".to_string(), 25), 1)
+                .unwrap();
+            assert!(update.verify().unwrap());
+            assert_eq!(update.get_old_root(), before);
+            assert_eq!(update.get_new_root(), tree.get_commitment().unwrap());
+            assert_eq!(update.get_old_leaf(), Leaf::new("bob".to_string(), 20));
+            assert_eq!(
+                update.get_path(),
+                tree.get_proof(1).unwrap().unwrap().get_path()
+            );
+            assert!(tree.verify_proof(update.new_proof()).unwrap());
+
+            let json = serde_json::to_string(&update).unwrap();
+            assert_eq!(json.contains("exponent"), exponent != 5);
+            let restored: LeafUpdateProof = serde_json::from_str(&json).unwrap();
+            assert!(restored.verify().unwrap());
+        }
+
+        //A write into an empty slot is an update from the empty leaf
+        let mut tree = tree(5);
+        let update = tree
+            .set_leaf_with_proofs(Leaf::new("dave".to_string(), 1), 3)
+            .unwrap();
+        assert!(update.old_proof().is_empty_slot());
+        assert!(update.verify().unwrap());
+    }
+
+    #[test]
+    fn tampered_updates_fail() {
+        let mut tree = tree(5);
+        let update = tree
+            .set_leaf_with_proofs(Leaf::new("carol".to_string(), 31), 2)
+            .unwrap();
+        let other = tree
+            .set_leaf_with_proofs(Leaf::new("carol".to_string(), 32), 2)
+            .unwrap();
+        for forged in [
+            LeafUpdateProof {
+                old_root: other.get_old_root(),
+                ..update.clone()
+            },
+            LeafUpdateProof {
+                new_root: other.get_new_root(),
+                ..update.clone()
+            },
+            LeafUpdateProof {
+                new_leaf: Leaf::new("carol".to_string(), 40),
+                ..update.clone()
+            },
+            LeafUpdateProof {
+                new_root: RootCommitment::new(
+                    update.get_new_root().get_hash(),
+                    update.get_new_root().get_sum(),
+                    update.get_new_root().get_epoch() + 1,
+                ),
+                ..update.clone()
+            },
+            LeafUpdateProof {
+                exponent: 7,
+                ..update.clone()
+            },
+        ] {
+            assert!(!forged.verify().unwrap());
+        }
+        assert!(tree
+            .set_leaf_with_proofs(Leaf::new("erin".to_string(), 1), 4)
+            .is_err());
+    }
+
+    #[test]
+    fn pushes_and_removals_carry_witnesses() {
+        let mut tree = tree(5);
+        let update = tree.remove_with_proofs(0).unwrap();
+        assert!(update.verify().unwrap());
+        assert!(update.new_proof().is_empty_slot());
+        assert_eq!(update.get_old_leaf(), Leaf::new("alice".to_string(), 10));
+
+        let (index, update) = tree
+            .push_with_proofs(Leaf::new("dave".to_string(), 4))
+            .unwrap();
+        assert_eq!(index, 0);
+        assert!(update.verify().unwrap());
+        assert!(update.old_proof().is_empty_slot());
+        assert_eq!(update.get_new_root(), tree.get_commitment().unwrap());
+        let (index, update) = tree
+            .push_with_proofs(Leaf::new("erin".to_string(), 5))
+            .unwrap();
+        assert_eq!(index, 3);
+        assert!(update.verify().unwrap());
+
+        //Full, the push would grow the tree and is refused untouched
+        let before = tree.get_commitment();
+        assert!(matches!(
+            tree.push_with_proofs(Leaf::new("frank".to_string(), 6)),
+            Err(MerkleError::InvalidProof(_))
+        ));
+        assert_eq!(tree.get_commitment(), before);
+
+        let mut canonical = MerkleSumTree::new_canonical(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), 20),
+            Leaf::new("carol".to_string(), 30),
+        ])
+        .unwrap();
+        assert!(canonical
+            .push_with_proofs(Leaf::new("dave".to_string(), 4))
+            .is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//Depth-first walks steered by a visitor
+
+use crate::layout::{flat_index, level_count, NodeIndex};
+use crate::{MerkleSumTree, Node};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitControl {
+    Descend,
+    SkipChildren,
+    Stop,
+}
+
+pub trait TreeVisitor {
+    fn visit(&mut self, level: usize, offset: usize, node: &Node) -> VisitControl;
+}
+
+//Collects the coordinates of every node whose sum reaches threshold
+struct SumThreshold {
+    threshold: i64,
+    found: Vec<(usize, usize)>,
+}
+
+impl TreeVisitor for SumThreshold {
+    fn visit(&mut self, level: usize, offset: usize, node: &Node) -> VisitControl {
+        if i64::from(node.get_value()) >= self.threshold {
+            self.found.push((level, offset));
+        }
+        //Negative balances let a light subtree hold a heavy one, so never prune on the sum
+        VisitControl::Descend
+    }
+}
+
+impl MerkleSumTree {
+    pub fn visit<V: TreeVisitor>(&self, visitor: &mut V) {
+        let leaf_count = self.leafs.len();
+        let empty = self.empty_subtrees();
+        let mut stack = match level_count(leaf_count) {
+            0 => vec![],
+            levels => vec![(levels - 1, 0)],
+        };
+        while let Some((level, offset)) = stack.pop() {
+            let index = match flat_index(leaf_count, level, offset) {
+                Some(index) => index,
+                None => continue,
+            };
+            let node = match self.nodes.get(NodeIndex::new(level, offset)) {
+                Some(node) => node,
+                None => continue,
+            };
+            match visitor.visit(level, offset, node) {
+                VisitControl::Stop => return,
+                VisitControl::SkipChildren => {}
+                VisitControl::Descend => {
+                    if level > 0 && !empty.get(index).copied().unwrap_or(false) {
+                        stack.push((level - 1, 2 * offset + 1));
+                        stack.push((level - 1, 2 * offset));
+                    }
+                }
+            }
+        }
+    }
+
+    //(level, offset) of every node summing to at least threshold, in visit order
+    pub fn find_subtrees_with_sum_at_least(&self, threshold: i64) -> Vec<(usize, usize)> {
+        let mut visitor = SumThreshold {
+            threshold,
+            found: vec![],
+        };
+        self.visit(&mut visitor);
+        visitor.found
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+
+    struct Recorder {
+        seen: Vec<(usize, usize)>,
+        control: fn(usize, usize) -> VisitControl,
+    }
+
+    impl TreeVisitor for Recorder {
+        fn visit(&mut self, level: usize, offset: usize, _node: &Node) -> VisitControl {
+            self.seen.push((level, offset));
+            (self.control)(level, offset)
+        }
+    }
+
+    fn walk(
+        tree: &MerkleSumTree,
+        control: fn(usize, usize) -> VisitControl,
+    ) -> Vec<(usize, usize)> {
+        let mut recorder = Recorder {
+            seen: vec![],
+            control,
+        };
+        tree.visit(&mut recorder);
+        recorder.seen
+    }
+
+    //Five accounts over eight slots, slots 5 to 7 are padding
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, -4, 30, 7, 1])).unwrap()
+    }
+
+    #[test]
+    fn walks_honor_the_control_value() {
+        let tree = tree();
+        let all = walk(&tree, |_, _| VisitControl::Descend);
+        //The padding pair under (1, 3) is seen once
+        assert_eq!(
+            all,
+            vec![
+                (3, 0),
+                (2, 0),
+                (1, 0),
+                (0, 0),
+                (0, 1),
+                (1, 1),
+                (0, 2),
+                (0, 3),
+                (2, 1),
+                (1, 2),
+                (0, 4),
+                (0, 5),
+                (1, 3),
+            ]
+        );
+
+        let pruned = walk(&tree, |level, _| match level {
+            2 => VisitControl::SkipChildren,
+            _ => VisitControl::Descend,
+        });
+        assert_eq!(pruned, vec![(3, 0), (2, 0), (2, 1)]);
+
+        let stopped = walk(&tree, |level, offset| match (level, offset) {
+            (0, 1) => VisitControl::Stop,
+            _ => VisitControl::Descend,
+        });
+        assert_eq!(stopped, vec![(3, 0), (2, 0), (1, 0), (0, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn finds_heavy_subtrees() {
+        let tree = tree();
+        assert_eq!(
+            tree.find_subtrees_with_sum_at_least(30),
+            vec![(3, 0), (2, 0), (1, 1), (0, 2)]
+        );
+        //Bob's debt drops (1, 0) below the threshold alice alone reaches, so nothing is pruned
+        assert_eq!(
+            tree.find_subtrees_with_sum_at_least(10),
+            vec![(3, 0), (2, 0), (0, 0), (1, 1), (0, 2)]
+        );
+        assert!(tree.find_subtrees_with_sum_at_least(100).is_empty());
+    }
+}
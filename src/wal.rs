@@ -0,0 +1,525 @@
+//Write-ahead log of tree mutations
+
+use crate::container::{read_container, write_container, KIND_LOG_ENTRY};
+use crate::{Leaf, MerkleError, MerkleSumTree, Node, RootCommitment};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogEntry {
+    Push {
+        leaf: Leaf,
+        pre_root: RootCommitment,
+        post_root: RootCommitment,
+    },
+    SetLeaf {
+        index: usize,
+        leaf: Leaf,
+        pre_root: RootCommitment,
+        post_root: RootCommitment,
+    },
+    Remove {
+        index: usize,
+        pre_root: RootCommitment,
+        post_root: RootCommitment,
+    },
+    Insert {
+        index: usize,
+        leaf: Leaf,
+        pre_root: RootCommitment,
+        post_root: RootCommitment,
+    },
+    Batch {
+        entries: Vec<LogEntry>,
+        pre_root: RootCommitment,
+        post_root: RootCommitment,
+    },
+}
+
+//Append-only sink for log entries
+pub struct MutationLog {
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+//Iterates the entries of a log stream, checking the root chain
+pub struct MutationLogReader<R: Read> {
+    reader: R,
+    previous: Option<RootCommitment>,
+    position: usize,
+    done: bool,
+}
+
+//Where a tree sends its entries, a clone starts without a log
+#[derive(Default)]
+pub(crate) enum Logger {
+    #[default]
+    Off,
+    Sink(MutationLog),
+    Record(Vec<LogEntry>),
+}
+
+//Which single-leaf entry a write is logged as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogKind {
+    Push,
+    SetLeaf,
+    Remove,
+}
+
+impl LogEntry {
+    pub fn get_pre_root(&self) -> RootCommitment {
+        match self {
+            LogEntry::Push { pre_root, .. }
+            | LogEntry::SetLeaf { pre_root, .. }
+            | LogEntry::Remove { pre_root, .. }
+            | LogEntry::Insert { pre_root, .. }
+            | LogEntry::Batch { pre_root, .. } => *pre_root,
+        }
+    }
+
+    pub fn get_post_root(&self) -> RootCommitment {
+        match self {
+            LogEntry::Push { post_root, .. }
+            | LogEntry::SetLeaf { post_root, .. }
+            | LogEntry::Remove { post_root, .. }
+            | LogEntry::Insert { post_root, .. }
+            | LogEntry::Batch { post_root, .. } => *post_root,
+        }
+    }
+}
+
+impl MutationLog {
+    pub fn new<W: Write + Send + 'static>(sink: W) -> MutationLog {
+        MutationLog {
+            sink: Mutex::new(Box::new(sink)),
+        }
+    }
+
+    fn append(&mut self, entry: &LogEntry) -> Result<(), MerkleError> {
+        let payload = serde_json::to_vec(entry)?;
+        let sink = self
+            .sink
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        write_container(sink, KIND_LOG_ENTRY, &payload)
+    }
+}
+
+impl fmt::Debug for MutationLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MutationLog").finish_non_exhaustive()
+    }
+}
+
+impl<R: Read> MutationLogReader<R> {
+    pub fn new(reader: R) -> MutationLogReader<R> {
+        MutationLogReader {
+            reader,
+            previous: None,
+            position: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for MutationLogReader<R> {
+    type Item = Result<LogEntry, MerkleError>;
+
+    //Stops after the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = match read_container(&mut self.reader, KIND_LOG_ENTRY) {
+            Ok(Some(payload)) => serde_json::from_slice::<LogEntry>(&payload)
+                .map_err(MerkleError::from)
+                .and_then(|entry| match self.previous {
+                    Some(previous) if previous != entry.get_pre_root() => {
+                        Err(MerkleError::LogChainBroken(self.position))
+                    }
+                    _ => Ok(entry),
+                }),
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(err) => Err(err),
+        };
+        match &entry {
+            Ok(entry) => self.previous = Some(entry.get_post_root()),
+            Err(_) => self.done = true,
+        }
+        self.position += 1;
+        Some(entry)
+    }
+}
+
+impl Clone for Logger {
+    fn clone(&self) -> Self {
+        Logger::Off
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Logger::Off => f.write_str("Off"),
+            Logger::Sink(_) => f.write_str("Sink"),
+            Logger::Record(entries) => f.debug_tuple("Record").field(&entries.len()).finish(),
+        }
+    }
+}
+
+impl Logger {
+    pub(crate) fn append(&mut self, entry: LogEntry) -> Result<(), MerkleError> {
+        match self {
+            Logger::Off => Ok(()),
+            Logger::Sink(log) => log.append(&entry),
+            Logger::Record(entries) => {
+                entries.push(entry);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl MerkleSumTree {
+    //Log every later mutation to log, replacing any previous one
+    pub fn set_mutation_log(&mut self, log: MutationLog) {
+        self.log = Logger::Sink(log);
+    }
+
+    //Detach the log, later mutations go unlogged
+    pub fn take_mutation_log(&mut self) -> Option<MutationLog> {
+        match std::mem::take(&mut self.log) {
+            Logger::Sink(log) => Some(log),
+            _ => None,
+        }
+    }
+
+    //Log a single-leaf write whose root becomes post, before it is applied
+    pub(crate) fn log_leaf(
+        &mut self,
+        kind: LogKind,
+        index: usize,
+        leaf: &Leaf,
+        post: &Node,
+    ) -> Result<(), MerkleError> {
+        if matches!(self.log, Logger::Off) {
+            return Ok(());
+        }
+        let pre_root = self.get_commitment().ok_or(MerkleError::EmptyTree)?;
+        let post_root = RootCommitment::from_node(post, self.epoch + 1);
+        let entry = match kind {
+            LogKind::Push => LogEntry::Push {
+                leaf: leaf.clone(),
+                pre_root,
+                post_root,
+            },
+            LogKind::SetLeaf => LogEntry::SetLeaf {
+                index,
+                leaf: leaf.clone(),
+                pre_root,
+                post_root,
+            },
+            LogKind::Remove => LogEntry::Remove {
+                index,
+                pre_root,
+                post_root,
+            },
+        };
+        self.log.append(entry)
+    }
+
+    //Copy for a batch to mutate, recording its steps when this tree logs
+    pub(crate) fn staged(&self) -> MerkleSumTree {
+        let mut staged = self.clone();
+        if !matches!(self.log, Logger::Off) {
+            staged.log = Logger::Record(vec![]);
+        }
+        staged
+    }
+
+    //Log a staged batch as one entry, failing before anything is applied
+    pub(crate) fn log_batch(&mut self, staged: &mut MerkleSumTree) -> Result<(), MerkleError> {
+        let entries = match std::mem::take(&mut staged.log) {
+            Logger::Record(entries) => entries,
+            _ => return Ok(()),
+        };
+        let pre_root = self.get_commitment().ok_or(MerkleError::EmptyTree)?;
+        let post_root = staged.get_commitment().ok_or(MerkleError::EmptyTree)?;
+        self.log.append(LogEntry::Batch {
+            entries,
+            pre_root,
+            post_root,
+        })
+    }
+}
+
+impl MerkleSumTree {
+    //Apply every entry of log to initial, checking each recorded root
+    pub fn replay<I>(initial: MerkleSumTree, log: I) -> Result<MerkleSumTree, MerkleError>
+    where
+        I: IntoIterator<Item = LogEntry>,
+    {
+        Self::replay_until(initial, log, u64::MAX)
+    }
+
+    //Replay the entries whose post-root epoch is at most epoch, for point-in-time state
+    pub fn replay_until<I>(
+        initial: MerkleSumTree,
+        log: I,
+        epoch: u64,
+    ) -> Result<MerkleSumTree, MerkleError>
+    where
+        I: IntoIterator<Item = LogEntry>,
+    {
+        let mut tree = initial;
+        for (at_entry, entry) in log.into_iter().enumerate() {
+            if entry.get_post_root().get_epoch() > epoch {
+                break;
+            }
+            tree.cancel.check()?;
+            tree.check_root(at_entry, entry.get_pre_root())?;
+            tree.apply_entry(&entry)?;
+            tree.check_root(at_entry, entry.get_post_root())?;
+        }
+        Ok(tree)
+    }
+
+    fn apply_entry(&mut self, entry: &LogEntry) -> Result<(), MerkleError> {
+        match entry {
+            LogEntry::Push { leaf, .. } => self.push(leaf.clone()).map(|_| ()),
+            LogEntry::SetLeaf { index, leaf, .. } => self.set_leaf(leaf.clone(), *index),
+            LogEntry::Remove { index, .. } => self.remove(*index),
+            LogEntry::Insert { index, leaf, .. } => self.insert(*index, leaf.clone()),
+            LogEntry::Batch { entries, .. } => {
+                for entry in entries {
+                    self.apply_entry(entry)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn check_root(&self, at_entry: usize, expected: RootCommitment) -> Result<(), MerkleError> {
+        let actual = self.get_commitment();
+        if actual != Some(expected) {
+            return Err(MerkleError::ReplayDiverged {
+                at_entry,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing, deprecated)]
+mod tests {
+    use super::*;
+    use crate::container::HEADER_BYTES;
+    use crate::test_support::accounts;
+    use rand::{SeedableRng, XorShiftRng};
+    use std::io;
+    use std::sync::Arc;
+
+    //A cloneable in-memory sink
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl Write for Failing {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, 20])).unwrap()
+    }
+
+    #[test]
+    fn insertions_are_logged_as_one_entry() {
+        let shared = Shared::default();
+        let initial = tree();
+        let mut tree = initial.clone();
+        tree.set_mutation_log(MutationLog::new(shared.clone()));
+        tree.insert(1, Leaf::new("carol".to_string(), 5)).unwrap();
+        tree.insert(0, Leaf::new("dave".to_string(), 1)).unwrap();
+
+        let bytes = shared.0.lock().unwrap().clone();
+        let entries: Vec<LogEntry> = MutationLogReader::new(bytes.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(entries[0], LogEntry::Insert { index: 1, .. }));
+        assert_eq!(entries[1].get_post_root(), tree.get_commitment().unwrap());
+        let replayed = MerkleSumTree::replay(initial, entries).unwrap();
+        assert_eq!(replayed.get_leafs(), tree.get_leafs());
+    }
+
+    #[test]
+    fn mutations_are_logged_with_chained_roots() {
+        let shared = Shared::default();
+        let mut tree = tree();
+        let start = tree.get_commitment().unwrap();
+        tree.set_mutation_log(MutationLog::new(shared.clone()));
+
+        tree.set_leaf(Leaf::new("bob".to_string(), 25), 1).unwrap();
+        tree.push(Leaf::new("carol".to_string(), 5)).unwrap();
+        tree.remove(0).unwrap();
+        tree.push(Leaf::new("dave".to_string(), 1)).unwrap();
+        tree.split_insert("erin", 40, 2, &mut XorShiftRng::from_seed([1, 2, 3, 4]))
+            .unwrap();
+
+        let bytes = shared.0.lock().unwrap().clone();
+        let entries: Vec<LogEntry> = MutationLogReader::new(bytes.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].get_pre_root(), start);
+        assert!(matches!(entries[1], LogEntry::Push { .. }));
+        assert!(matches!(entries[2], LogEntry::Remove { index: 0, .. }));
+        //The push reuses the freed slot, it is still logged as a push
+        assert!(matches!(entries[3], LogEntry::Push { .. }));
+        match &entries[4] {
+            LogEntry::Batch { entries, .. } => assert_eq!(entries.len(), 2),
+            _ => panic!("expected a batch"),
+        }
+        assert_eq!(entries[4].get_post_root(), tree.get_commitment().unwrap());
+
+        let detached = tree.take_mutation_log();
+        assert!(detached.is_some());
+        tree.remove(1).unwrap();
+        assert_eq!(shared.0.lock().unwrap().len(), bytes.len());
+    }
+
+    #[test]
+    fn write_failures_fail_the_mutation() {
+        let mut tree = tree();
+        tree.set_mutation_log(MutationLog::new(Failing));
+        let before = tree.get_commitment();
+        assert!(matches!(
+            tree.set_leaf(Leaf::new("bob".to_string(), 25), 1),
+            Err(MerkleError::Io(_))
+        ));
+        assert!(tree.push(Leaf::new("carol".to_string(), 5)).is_err());
+        assert!(tree.remove(0).is_err());
+        assert!(tree
+            .split_insert("erin", 40, 2, &mut XorShiftRng::from_seed([1, 2, 3, 4]))
+            .is_err());
+        assert_eq!(tree.get_commitment(), before);
+        assert_eq!(tree.get_leafs().len(), 2);
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    fn logged_history() -> (MerkleSumTree, MerkleSumTree, Vec<LogEntry>) {
+        let shared = Shared::default();
+        let initial = tree();
+        let mut live = initial.clone();
+        live.set_mutation_log(MutationLog::new(shared.clone()));
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        live.set_leaf(Leaf::new("bob".to_string(), 25), 1).unwrap();
+        live.push(Leaf::new("carol".to_string(), 5)).unwrap();
+        live.split_insert("dave", 90, 3, &mut rng).unwrap();
+        live.remove(0).unwrap();
+        live.add_to_leaf(2, -3).unwrap();
+        live.split_update("dave", 60, &mut rng).unwrap();
+        live.push(Leaf::new("erin".to_string(), 7)).unwrap();
+        let bytes = shared.0.lock().unwrap().clone();
+        let entries = MutationLogReader::new(bytes.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        (initial, live, entries)
+    }
+
+    #[test]
+    fn clean_replays_reproduce_the_live_tree() {
+        let (initial, live, entries) = logged_history();
+        assert_eq!(entries.len(), 7);
+        let replayed = MerkleSumTree::replay(initial.clone(), entries.clone()).unwrap();
+        assert_eq!(replayed.get_commitment(), live.get_commitment());
+        assert_eq!(replayed.get_leafs(), live.get_leafs());
+        assert_eq!(replayed.get_nodes(), live.get_nodes());
+        assert_eq!(replayed.get_zero_index(), live.get_zero_index());
+        assert_eq!(replayed.get_epoch(), live.get_epoch());
+
+        //Point in time, right after the first split insert
+        let epoch = entries[2].get_post_root().get_epoch();
+        let past = MerkleSumTree::replay_until(initial.clone(), entries.clone(), epoch).unwrap();
+        assert_eq!(past.get_commitment(), Some(entries[2].get_post_root()));
+        let start = MerkleSumTree::replay_until(initial, entries, 0).unwrap();
+        assert_eq!(start.get_epoch(), 0);
+    }
+
+    #[test]
+    fn corrupted_entries_stop_the_replay() {
+        let (initial, _, mut entries) = logged_history();
+        if let LogEntry::SetLeaf { leaf, .. } = &mut entries[4] {
+            *leaf = Leaf::new(leaf.get_id(), 1000);
+        } else {
+            panic!("expected a set_leaf");
+        }
+        match MerkleSumTree::replay(initial.clone(), entries.clone()) {
+            Err(MerkleError::ReplayDiverged {
+                at_entry,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(at_entry, 4);
+                assert_eq!(expected, entries[4].get_post_root());
+                assert_ne!(actual, Some(expected));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        //Replaying onto the wrong starting tree fails on the first pre-root
+        let mut wrong_start = initial;
+        wrong_start.remove(0).unwrap();
+        assert!(matches!(
+            MerkleSumTree::replay(wrong_start, entries),
+            Err(MerkleError::ReplayDiverged { at_entry: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn readers_reject_broken_chains() {
+        let shared = Shared::default();
+        let mut tree = tree();
+        tree.set_mutation_log(MutationLog::new(shared.clone()));
+        tree.set_leaf(Leaf::new("bob".to_string(), 25), 1).unwrap();
+        tree.set_leaf(Leaf::new("bob".to_string(), 30), 1).unwrap();
+        let bytes = shared.0.lock().unwrap().clone();
+
+        //Swapping the entries breaks the chain at the second one
+        let mut length = [0u8; 8];
+        length.copy_from_slice(&bytes[7..15]);
+        let first_len = HEADER_BYTES + u64::from_le_bytes(length) as usize;
+        let mut swapped = bytes[first_len..].to_vec();
+        swapped.extend_from_slice(&bytes[..first_len]);
+        let results: Vec<_> = MutationLogReader::new(swapped.as_slice()).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(MerkleError::LogChainBroken(1))));
+    }
+}
@@ -0,0 +1,281 @@
+//Callbacks fired when watched leafs change
+
+use crate::{EmptyLeafSpec, Leaf, MerkleError, MerkleSumTree, RootCommitment};
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WatchId(u64);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WatchTarget {
+    Index(usize),
+    Id(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafChange {
+    index: usize,
+    old: Leaf,
+    new: Leaf,
+    commitment: RootCommitment,
+}
+
+pub type WatchCallback = Box<dyn FnMut(LeafChange) + Send>;
+
+struct Watch {
+    id: WatchId,
+    target: WatchTarget,
+    callback: WatchCallback,
+}
+
+//The mutex only makes the callbacks Sync, every access goes through &mut
+#[derive(Default)]
+pub(crate) struct Watches {
+    next: u64,
+    watches: Mutex<Vec<Watch>>,
+}
+
+impl From<usize> for WatchTarget {
+    fn from(index: usize) -> Self {
+        WatchTarget::Index(index)
+    }
+}
+
+impl From<&str> for WatchTarget {
+    fn from(id: &str) -> Self {
+        WatchTarget::Id(id.to_string())
+    }
+}
+
+impl From<String> for WatchTarget {
+    fn from(id: String) -> Self {
+        WatchTarget::Id(id)
+    }
+}
+
+impl WatchTarget {
+    fn matches(&self, index: usize, old: &Leaf, new: &Leaf, empty: &EmptyLeafSpec) -> bool {
+        match self {
+            WatchTarget::Index(watched) => *watched == index,
+            WatchTarget::Id(id) => {
+                (!empty.matches(old) && *old.id == **id) || (!empty.matches(new) && *new.id == **id)
+            }
+        }
+    }
+}
+
+impl LeafChange {
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_old(&self) -> Leaf {
+        self.old.clone()
+    }
+
+    pub fn get_new(&self) -> Leaf {
+        self.new.clone()
+    }
+
+    //Root and epoch right after the mutation
+    pub fn get_commitment(&self) -> RootCommitment {
+        self.commitment
+    }
+}
+
+//Callbacks cannot be cloned, a clone starts unwatched
+impl Clone for Watches {
+    fn clone(&self) -> Self {
+        Watches::default()
+    }
+}
+
+impl fmt::Debug for Watches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watches").field("next", &self.next).finish()
+    }
+}
+
+impl Watches {
+    fn entries(&mut self) -> &mut Vec<Watch> {
+        self.watches
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl MerkleSumTree {
+    //Call back after every mutation writing the slot, or a slot holding the id
+    pub fn watch_leaf<T: Into<WatchTarget>>(
+        &mut self,
+        target: T,
+        callback: WatchCallback,
+    ) -> WatchId {
+        let id = WatchId(self.watches.next);
+        self.watches.next += 1;
+        self.watches.entries().push(Watch {
+            id,
+            target: target.into(),
+            callback,
+        });
+        id
+    }
+
+    //Drop a watch, return whether it was registered
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        let watches = self.watches.entries();
+        let before = watches.len();
+        watches.retain(|watch| watch.id != id);
+        watches.len() != before
+    }
+
+    //Runs after every completed mutation: record the root, then fire the watches
+    //matching the written slots, given with their old leafs
+    pub(crate) fn notify(&mut self, changes: Vec<(usize, Leaf)>) {
+        let root = self.get_commitment();
+        self.history.record(root, self.height);
+        if self.watches.entries().is_empty() {
+            return;
+        }
+        let commitment = match self.get_commitment() {
+            Some(commitment) => commitment,
+            None => return,
+        };
+        let mut fired = vec![];
+        for (index, old) in changes {
+            let new = match self.leafs.get(index) {
+                Some(new) => new.clone(),
+                None => continue,
+            };
+            fired.push(LeafChange {
+                index,
+                old,
+                new,
+                commitment,
+            });
+        }
+        for watch in self.watches.entries() {
+            for change in &fired {
+                if watch
+                    .target
+                    .matches(change.index, &change.old, &change.new, &self.empty)
+                {
+                    (watch.callback)(change.clone());
+                }
+            }
+        }
+    }
+
+    //Log and swap in a staged copy, keeping the watches and firing once per written slot
+    pub(crate) fn commit_staged(
+        &mut self,
+        mut staged: MerkleSumTree,
+        indices: &[usize],
+    ) -> Result<(), MerkleError> {
+        self.log_batch(&mut staged)?;
+        let changes = indices
+            .iter()
+            .map(|index| {
+                let old = self.get_leaf(*index);
+                (*index, old.unwrap_or_else(|| self.empty.get_leaf()))
+            })
+            .collect();
+        let watches = std::mem::take(&mut self.watches);
+        let log = std::mem::take(&mut self.log);
+        *self = staged;
+        self.watches = watches;
+        self.log = log;
+        self.notify(changes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::accounts;
+    use rand::{SeedableRng, XorShiftRng};
+    use std::sync::{Arc, Mutex};
+
+    type Log = Arc<Mutex<Vec<LeafChange>>>;
+
+    fn recorder() -> (Log, WatchCallback) {
+        let log: Log = Arc::default();
+        let sink = log.clone();
+        (
+            log,
+            Box::new(move |change: LeafChange| sink.lock().unwrap().push(change)),
+        )
+    }
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, 20])).unwrap()
+    }
+
+    #[test]
+    fn watches_fire_once_per_mutation() {
+        let mut tree = tree();
+        let (by_index, callback) = recorder();
+        let index_watch = tree.watch_leaf(1usize, callback);
+        let (by_id, callback) = recorder();
+        tree.watch_leaf("carol", callback);
+
+        tree.set_leaf(Leaf::new("bob".to_string(), 25), 1).unwrap();
+        tree.set_leaf(Leaf::new("alice".to_string(), 5), 0).unwrap();
+        assert_eq!(by_index.lock().unwrap().len(), 1);
+        let change = by_index.lock().unwrap()[0].clone();
+        assert_eq!(change.get_index(), 1);
+        assert_eq!(change.get_old().get_node().get_value(), 20);
+        assert_eq!(change.get_new().get_node().get_value(), 25);
+        assert_eq!(change.get_commitment().get_epoch(), 1);
+
+        //Full tree, the push grows it and the watch follows the id
+        assert_eq!(tree.increment_by_id("carol", 7, true).unwrap(), 7);
+        assert_eq!(tree.add_to_leaf(2, 1).unwrap(), 8);
+        tree.remove(2).unwrap();
+        let changes = by_id.lock().unwrap().clone();
+        assert_eq!(changes.len(), 3);
+        assert!(changes[0].get_old().is_none());
+        assert_eq!(changes[1].get_new().get_node().get_value(), 8);
+        assert!(changes[2].get_new().is_none());
+        assert_eq!(Some(changes[2].get_commitment()), tree.get_commitment());
+
+        assert!(tree.unwatch(index_watch));
+        assert!(!tree.unwatch(index_watch));
+        tree.remove(1).unwrap();
+        assert_eq!(by_index.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn batches_fire_on_commit_only() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut tree =
+            MerkleSumTree::new(vec![Leaf::new("bob".to_string(), i32::MAX - 100)]).unwrap();
+        let (log, callback) = recorder();
+        tree.watch_leaf("alice#1", callback);
+
+        let indices = tree.split_insert("alice", 60, 3, &mut rng).unwrap();
+        assert_eq!(log.lock().unwrap().len(), 1);
+        assert_eq!(log.lock().unwrap()[0].get_index(), indices[1]);
+
+        //Rolled back, nothing fires and the watch survives
+        assert!(tree.split_update("alice", 200, &mut rng).is_err());
+        assert_eq!(log.lock().unwrap().len(), 1);
+
+        tree.split_update("alice", 90, &mut rng).unwrap();
+        tree.split_remove("alice").unwrap();
+        let changes = log.lock().unwrap().clone();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(
+            changes[1].get_commitment().get_sum(),
+            i64::from(i32::MAX - 10)
+        );
+        assert!(changes[2].get_new().is_none());
+
+        let mut copy = tree.clone();
+        copy.push(Leaf::new("alice#1".to_string(), 1)).unwrap();
+        assert_eq!(log.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,349 @@
+//Inclusion proofs flattened into circuit private inputs
+
+use crate::compress::empty_subtree_roots;
+use crate::mimc_sponge::DEFAULT_EXPONENT;
+use crate::{
+    fr_to_value, path_index, value_to_fr, Fr, InclusionProof, Leaf, MerkleError, Neighbor, Node,
+    Position,
+};
+use ff::Field;
+use serde::Serialize;
+
+//snarkjs input signals, every one a decimal string
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CircomInputs {
+    leaf_hash: Fr,
+    leaf_value: Fr,
+    path_elements: Vec<Fr>,
+    path_values: Vec<Fr>,
+    path_indices: Vec<Fr>,
+    root: Fr,
+    root_sum: Fr,
+}
+
+//Field elements before the first level
+const LEAF_WIDTH: usize = 2;
+//Field elements per level
+const LEVEL_WIDTH: usize = 3;
+
+impl InclusionProof {
+    pub fn to_flat_witness(&self, depth: usize) -> Result<Vec<Fr>, MerkleError> {
+        if self.path.len() > depth {
+            return Err(MerkleError::InvalidLength {
+                expected: depth,
+                actual: self.path.len(),
+            });
+        }
+        let mut witness = Vec::with_capacity(LEAF_WIDTH + LEVEL_WIDTH * depth);
+        witness.push(self.leaf.node.hash);
+        witness.push(value_to_fr(self.leaf.node.value));
+        for neighbor in &self.path {
+            let dir = match neighbor.position {
+                Position::Left => Fr::ONE,
+                Position::Right => Fr::ZERO,
+            };
+            witness.extend([neighbor.node.hash, value_to_fr(neighbor.node.value), dir]);
+        }
+        let padding = empty_subtree_roots(DEFAULT_EXPONENT, depth)?;
+        for node in padding.iter().skip(self.path.len()) {
+            witness.extend([node.hash, Fr::ZERO, Fr::ZERO]);
+        }
+        Ok(witness)
+    }
+
+    //Input JSON of a circom inclusion circuit, padded to depth levels when given. The
+    //root is the one the padded path folds to, not a stamped one
+    pub fn to_circom_inputs(&self, depth: Option<usize>) -> Result<String, MerkleError> {
+        if self.exponent != DEFAULT_EXPONENT {
+            return Err(MerkleError::UnsupportedExponent(self.exponent));
+        }
+        let depth = depth.unwrap_or(self.path.len());
+        if self.path.len() > depth {
+            return Err(MerkleError::InvalidLength {
+                expected: depth,
+                actual: self.path.len(),
+            });
+        }
+        let mut padded = self.clone();
+        for node in empty_subtree_roots(DEFAULT_EXPONENT, depth)?
+            .into_iter()
+            .skip(self.path.len())
+        {
+            padded.path.push(Neighbor::new(Position::Right, node));
+        }
+        let root = padded.compute_root()?;
+        let inputs = CircomInputs {
+            leaf_hash: self.leaf.node.hash,
+            leaf_value: value_to_fr(self.leaf.node.value),
+            path_elements: padded.sibling_hashes(),
+            path_values: padded
+                .sibling_values()
+                .into_iter()
+                .map(value_to_fr)
+                .collect(),
+            path_indices: padded
+                .path_indices()
+                .into_iter()
+                .map(|bit| Fr::from(u64::from(bit)))
+                .collect(),
+            root: root.hash,
+            root_sum: value_to_fr(root.value),
+        };
+        Ok(serde_json::to_string(&inputs)?)
+    }
+
+    //Rebuild the proof of id from the first path_len levels of a witness, the rest
+    //must be padding
+    pub fn from_flat_witness(
+        id: String,
+        witness: &[Fr],
+        path_len: usize,
+    ) -> Result<InclusionProof, MerkleError> {
+        let malformed = MerkleError::InvalidLength {
+            expected: LEAF_WIDTH + LEVEL_WIDTH * path_len,
+            actual: witness.len(),
+        };
+        let (hash, value, levels) = match witness {
+            [hash, value, levels @ ..] if levels.len() % LEVEL_WIDTH == 0 => {
+                (*hash, *value, levels)
+            }
+            _ => return Err(malformed),
+        };
+        let depth = levels.len() / LEVEL_WIDTH;
+        if path_len > depth {
+            return Err(malformed);
+        }
+        let padding = empty_subtree_roots(DEFAULT_EXPONENT, depth)?;
+        let mut path = vec![];
+        for (level, (chunk, empty)) in levels.chunks_exact(LEVEL_WIDTH).zip(&padding).enumerate() {
+            let (hash, value, dir) = match chunk {
+                [hash, value, dir] => (*hash, *value, *dir),
+                _ => return Err(MerkleError::MissingNode(level)),
+            };
+            if level >= path_len {
+                if hash != empty.hash || value != Fr::ZERO || dir != Fr::ZERO {
+                    return Err(MerkleError::InvalidFieldElement(format!(
+                        "level {} is not empty-subtree padding",
+                        level
+                    )));
+                }
+                continue;
+            }
+            let position = match dir {
+                dir if dir == Fr::ONE => Position::Left,
+                dir if dir == Fr::ZERO => Position::Right,
+                dir => {
+                    return Err(MerkleError::InvalidFieldElement(format!(
+                        "direction {} at level {}",
+                        dir, level
+                    )))
+                }
+            };
+            path.push(Neighbor::new(
+                position,
+                Node::new(hash, witness_value(value)?),
+            ));
+        }
+        Ok(InclusionProof {
+            leaf: Leaf {
+                id: id.into(),
+                node: Node::new(hash, witness_value(value)?),
+            },
+            index: path_index(&path),
+            path,
+            root: None,
+            epoch: None,
+            exponent: DEFAULT_EXPONENT,
+        })
+    }
+}
+
+fn witness_value(value: Fr) -> Result<i32, MerkleError> {
+    fr_to_value(value)
+        .ok_or_else(|| MerkleError::InvalidFieldElement(format!("{} is not an i32 value", value)))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::test_support::{accounts, users};
+    use crate::MerkleSumTree;
+
+    fn tree() -> MerkleSumTree {
+        MerkleSumTree::new(accounts(&[10, -4])).unwrap()
+    }
+
+    #[test]
+    fn flat_witness_layout_fixture() {
+        let proof = tree().get_proof(1).unwrap().unwrap();
+        let witness: Vec<String> = proof
+            .to_flat_witness(3)
+            .unwrap()
+            .iter()
+            .map(Fr::to_string)
+            .collect();
+        assert_eq!(
+            witness,
+            vec![
+                //Leaf: bob, -4
+                "15304630271063698513",
+                "28948022309329048855892746252171976963363056481941647379679742748393362948093",
+                //Level 0: alice on the left
+                "200341070530370144",
+                "10",
+                "1",
+                //Level 1: empty pair
+                "22122121163127692601040977824042691137073709259780268058970818763835109135359",
+                "0",
+                "0",
+                //Level 2: empty quad
+                "9577381579138472660640687876808526267294443674721706486194556629472463835227",
+                "0",
+                "0",
+            ]
+        );
+
+        //Read back in full, the padding folds to the root of the tree grown to depth 3
+        let padded = InclusionProof::from_flat_witness(
+            "bob".to_string(),
+            &proof.to_flat_witness(3).unwrap(),
+            3,
+        )
+        .unwrap();
+        let root = padded.compute_root().unwrap();
+        assert_eq!(
+            root.get_hash().to_string(),
+            "4564118577634216951553541551749483544245025458973040891459514328025366708315"
+        );
+        let mut grown = MerkleSumTree::new(vec![
+            Leaf::new("alice".to_string(), 10),
+            Leaf::new("bob".to_string(), -4),
+            Leaf::new("carol".to_string(), 1),
+            Leaf::new("dave".to_string(), 2),
+            Leaf::new("erin".to_string(), 3),
+        ])
+        .unwrap();
+        for index in 2..5 {
+            grown.remove(index).unwrap();
+        }
+        assert_eq!(grown.get_root(), Some(root));
+    }
+
+    #[test]
+    fn flat_witnesses_round_trip() {
+        let leafs = users(5, 7, 10);
+        let tree = MerkleSumTree::new(leafs).unwrap();
+        for index in 0..5 {
+            let proof = tree.get_proof(index).unwrap().unwrap();
+            for depth in [3, 6] {
+                let witness = proof.to_flat_witness(depth).unwrap();
+                assert_eq!(witness.len(), 2 + 3 * depth);
+                let restored =
+                    InclusionProof::from_flat_witness(proof.get_leaf().get_id(), &witness, 3)
+                        .unwrap();
+                assert_eq!(restored, proof);
+                assert!(tree.verify_proof(restored).unwrap());
+            }
+        }
+
+        for value in [0, 1, -1, i32::MAX, i32::MIN] {
+            assert_eq!(fr_to_value(value_to_fr(value)), Some(value));
+        }
+        assert_eq!(fr_to_value(Fr::from(1u64 << 31)), None);
+        assert_eq!(fr_to_value(-Fr::from((1u64 << 31) + 1)), None);
+        assert_eq!(fr_to_value(Fr::from(u64::MAX)), None);
+
+        let proof = tree.get_proof(0).unwrap().unwrap();
+        assert!(matches!(
+            proof.to_flat_witness(2),
+            Err(MerkleError::InvalidLength {
+                expected: 2,
+                actual: 3
+            })
+        ));
+        let witness = proof.to_flat_witness(4).unwrap();
+        assert!(InclusionProof::from_flat_witness("user0".to_string(), &witness[1..], 3).is_err());
+        assert!(InclusionProof::from_flat_witness("user0".to_string(), &witness, 5).is_err());
+        let mut bad_dir = witness.clone();
+        bad_dir[4] = Fr::from(2u64);
+        assert!(InclusionProof::from_flat_witness("user0".to_string(), &bad_dir, 3).is_err());
+        let mut bad_padding = witness;
+        bad_padding[11] = Fr::ONE;
+        assert!(InclusionProof::from_flat_witness("user0".to_string(), &bad_padding, 3).is_err());
+    }
+
+    #[test]
+    fn circom_inputs_match_the_flat_witness() {
+        let proof = tree().get_proof(1).unwrap().unwrap();
+        let strings = |value: &serde_json::Value| -> Vec<String> {
+            value
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| s.as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let unpadded: serde_json::Value =
+            serde_json::from_str(&proof.to_circom_inputs(None).unwrap()).unwrap();
+        let root = tree().get_root().unwrap();
+        assert_eq!(unpadded["leafHash"], "15304630271063698513");
+        assert_eq!(
+            unpadded["leafValue"],
+            "28948022309329048855892746252171976963363056481941647379679742748393362948093"
+        );
+        assert_eq!(
+            strings(&unpadded["pathElements"]),
+            vec!["200341070530370144"]
+        );
+        assert_eq!(strings(&unpadded["pathValues"]), vec!["10"]);
+        assert_eq!(strings(&unpadded["pathIndices"]), vec!["1"]);
+        assert_eq!(unpadded["root"], root.get_hash().to_string());
+        assert_eq!(unpadded["rootSum"], "6");
+
+        //Padded, the signals are the flat witness regrouped and the root the grown one
+        let padded: serde_json::Value =
+            serde_json::from_str(&proof.to_circom_inputs(Some(3)).unwrap()).unwrap();
+        let witness: Vec<String> = proof
+            .to_flat_witness(3)
+            .unwrap()
+            .iter()
+            .map(Fr::to_string)
+            .collect();
+        for (column, signal) in ["pathElements", "pathValues", "pathIndices"]
+            .iter()
+            .enumerate()
+        {
+            let expected: Vec<String> = witness[2..]
+                .iter()
+                .skip(column)
+                .step_by(3)
+                .cloned()
+                .collect();
+            assert_eq!(strings(&padded[signal]), expected);
+        }
+        assert_eq!(
+            padded["root"],
+            "4564118577634216951553541551749483544245025458973040891459514328025366708315"
+        );
+        assert_eq!(padded["rootSum"], "6");
+
+        assert!(matches!(
+            proof.to_circom_inputs(Some(0)),
+            Err(MerkleError::InvalidLength {
+                expected: 0,
+                actual: 1
+            })
+        ));
+        let seven = MerkleSumTree::builder()
+            .mimc_exponent(7)
+            .build(vec![Leaf::new("alice".to_string(), 1)])
+            .unwrap();
+        assert!(matches!(
+            seven.get_proof(0).unwrap().unwrap().to_circom_inputs(None),
+            Err(MerkleError::UnsupportedExponent(7))
+        ));
+    }
+}